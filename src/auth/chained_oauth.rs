@@ -1,33 +1,528 @@
-use crate::config::OAuthConfig;
+use crate::auth::client_auth;
+use crate::auth::sessions::UpstreamTokens;
+use crate::config::{OAuthConfig, ScopePolicy, SubjectTokenType};
+use crate::oauth::codes::now_secs;
+use crate::AppState;
+use serde::{Deserialize, Serialize};
 
+/// A successful response from the provider's token endpoint (RFC 6749 §5.1),
+/// as parsed from the callback's code exchange and from refreshes. It is
+/// sealed into authorization codes and proxy access tokens as the
+/// [`crate::oauth::codes::DownstreamTokens::ChainedOAuth`] payload.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_expires_in",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expires_in: Option<u64>,
+    /// The scope the provider granted. Absent when it equals the requested
+    /// scope (RFC 6749 §5.1).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// OpenID Connect ID token, when `openid` was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// The scopes in a scope string. Providers separate them with spaces or
+/// (like GitHub) commas.
+fn scope_tokens(scope: &str) -> impl Iterator<Item = &str> {
+    scope
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+}
+
+/// A provider scope string in the space-delimited form of RFC 6749 §3.3.
+pub fn normalize_scope(scope: &str) -> String {
+    scope_tokens(scope).collect::<Vec<_>>().join(" ")
+}
+
+/// Scopes in `required` that are missing from the `granted` scope. A
+/// response without a scope grants what was requested.
+pub fn missing_scopes<'a>(required: Option<&'a str>, granted: Option<&str>) -> Vec<&'a str> {
+    let (Some(required), Some(granted)) = (required, granted) else {
+        return Vec::new();
+    };
+    scope_tokens(required)
+        .filter(|s| !scope_tokens(granted).any(|g| g == *s))
+        .collect()
+}
+
+/// Apply `oauth_scope_policy` to a token response: log when the user granted
+/// fewer scopes than configured, and refuse it under `reject`.
+pub fn check_granted_scope(
+    ds_name: &str,
+    oauth: &OAuthConfig,
+    granted: Option<&str>,
+) -> Result<(), String> {
+    let missing = missing_scopes(oauth.oauth_scopes.as_deref(), granted);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let missing = missing.join(" ");
+    tracing::warn!(
+        downstream = %ds_name,
+        missing = %missing,
+        granted = granted.unwrap_or_default(),
+        "Provider granted fewer scopes than oauth_scopes"
+    );
+    match oauth.oauth_scope_policy {
+        ScopePolicy::Warn => Ok(()),
+        ScopePolicy::Reject => Err(format!("required scopes were not granted: {missing}")),
+    }
+}
+
+/// Form-encoded responses carry every value as a string, and some JSON
+/// providers quote `expires_in` too.
+fn deserialize_expires_in<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::Number(n)) => n
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom("expires_in must be a positive integer")),
+        Some(serde_json::Value::String(s)) if s.is_empty() => Ok(None),
+        Some(serde_json::Value::String(s)) => s.parse().map(Some).map_err(serde::de::Error::custom),
+        Some(other) => Err(serde::de::Error::custom(format!(
+            "expires_in must be an integer, got {other}"
+        ))),
+    }
+}
+
+/// Why a request to the provider's token endpoint failed.
+#[derive(Debug)]
+pub enum TokenError {
+    /// The provider refused the grant with an OAuth error or a 4xx status.
+    Rejected(String),
+    /// The provider answered, but not with a usable token response.
+    Invalid(String),
+    /// The provider could not be reached or failed with a 5xx status, or the
+    /// request could not be built. Retrying later may succeed.
+    Unavailable(String),
+}
+
+impl TokenError {
+    /// The same error with `prefix` in front of its message.
+    fn context(self, prefix: &str) -> Self {
+        match self {
+            TokenError::Rejected(e) => TokenError::Rejected(format!("{prefix}: {e}")),
+            TokenError::Invalid(e) => TokenError::Invalid(format!("{prefix}: {e}")),
+            TokenError::Unavailable(e) => TokenError::Unavailable(format!("{prefix}: {e}")),
+        }
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Rejected(e) | TokenError::Invalid(e) | TokenError::Unavailable(e) => {
+                f.write_str(e)
+            }
+        }
+    }
+}
+
+/// POST `form_params` to the provider's token endpoint, adding the client
+/// authentication configured for the downstream.
 pub async fn post_downstream_token(
     client: &reqwest::Client,
     oauth: &OAuthConfig,
     form_params: &[(&str, &str)],
-) -> Result<serde_json::Value, String> {
-    let resp = client
-        .post(&oauth.oauth_token_url)
-        .header("Accept", &oauth.oauth_token_accept)
-        .form(form_params)
+) -> Result<TokenResponse, TokenError> {
+    let token_url = oauth.token_url().ok_or_else(|| {
+        TokenError::Unavailable("no token endpoint configured or discovered".to_string())
+    })?;
+    let request = client_auth::authenticated_form(client, oauth, &token_url, form_params)
+        .map_err(TokenError::Unavailable)?
+        .header("Accept", &oauth.oauth_token_accept);
+    post_token_request(request).await
+}
+
+const TOKEN_EXCHANGE_GRANT: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// With `oauth_token_exchange_url`, trade the provider's token in `body` for
+/// one issued by the security token service (RFC 8693) and put it in place of
+/// the provider's access token. The provider's refresh token, scope and ID
+/// token are kept, so refreshing the grant refreshes with the provider and
+/// then exchanges again. Without it, `body` is returned unchanged.
+///
+/// The STS is a different server from the provider, so the request carries
+/// the `oauth_token_exchange_client_*` credentials, if any, and never the
+/// provider's.
+pub async fn apply_token_exchange(
+    client: &reqwest::Client,
+    oauth: &OAuthConfig,
+    body: TokenResponse,
+) -> Result<TokenResponse, TokenError> {
+    let Some(url) = &oauth.oauth_token_exchange_url else {
+        return Ok(body);
+    };
+    let subject_type = oauth.oauth_token_exchange_subject;
+    let subject_token = match subject_type {
+        SubjectTokenType::AccessToken => Some(&body.access_token),
+        SubjectTokenType::IdToken => body.id_token.as_ref(),
+    }
+    .ok_or_else(|| TokenError::Invalid("no ID token to exchange".to_string()))?;
+
+    let mut params = vec![
+        ("grant_type", TOKEN_EXCHANGE_GRANT),
+        ("subject_token", subject_token.as_str()),
+        ("subject_token_type", subject_type.urn()),
+        ("requested_token_type", ACCESS_TOKEN_TYPE),
+    ];
+    if let Some(audience) = &oauth.oauth_token_exchange_audience {
+        params.push(("audience", audience));
+    }
+    if let Some(scopes) = &oauth.oauth_token_exchange_scopes {
+        params.push(("scope", scopes));
+    }
+
+    let request = client_auth::token_exchange_form(client, oauth, url, &params)
+        .map_err(TokenError::Unavailable)
+        .map_err(|e| e.context("Token exchange"))?
+        .header("Accept", "application/json");
+    let exchanged = post_token_request(request)
+        .await
+        .map_err(|e| e.context("Token exchange"))?;
+    Ok(TokenResponse {
+        access_token: exchanged.access_token,
+        token_type: exchanged.token_type,
+        expires_in: exchanged.expires_in,
+        ..body
+    })
+}
+
+async fn post_token_request(request: reqwest::RequestBuilder) -> Result<TokenResponse, TokenError> {
+    let resp = request
         .send()
         .await
-        .map_err(|e| format!("HTTP request failed: {e}"))?;
+        .map_err(|e| TokenError::Unavailable(format!("HTTP request failed: {e}")))?;
 
     let status = resp.status();
-    let body: serde_json::Value = resp
-        .json::<serde_json::Value>()
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let body = resp
+        .bytes()
         .await
-        .map_err(|e| format!("Failed to parse token response: {e}"))?;
+        .map_err(|e| TokenError::Unavailable(format!("Failed to read token response: {e}")))?;
+
+    parse_token_response(status, content_type.as_deref(), &body)
+}
 
+/// Interpret a token endpoint response according to its Content-Type. Some
+/// providers ignore `Accept` and answer form-encoded, and some report errors
+/// with HTTP 200, so an `error` field wins over the status code. Only
+/// `server_error`, `temporarily_unavailable` and 5xx statuses without an
+/// error body are transient.
+fn parse_token_response(
+    status: reqwest::StatusCode,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<TokenResponse, TokenError> {
+    let fields = parse_fields(content_type, body);
+
+    if let Some(fields) = &fields {
+        if let Some(error) = fields["error"].as_str() {
+            let err_desc = fields["error_description"].as_str().unwrap_or(error);
+            let message = format!("Downstream token endpoint returned {status}: {err_desc}");
+            return Err(match error {
+                "server_error" | "temporarily_unavailable" => TokenError::Unavailable(message),
+                _ => TokenError::Rejected(message),
+            });
+        }
+    }
     if !status.is_success() {
-        let err_desc = body["error_description"]
-            .as_str()
-            .or_else(|| body["error"].as_str())
-            .unwrap_or("unknown error");
-        return Err(format!(
-            "Downstream token endpoint returned {status}: {err_desc}"
-        ));
+        let message = format!("Downstream token endpoint returned {status}: unknown error");
+        return Err(if status.is_server_error() {
+            TokenError::Unavailable(message)
+        } else {
+            TokenError::Rejected(message)
+        });
+    }
+
+    let fields = fields.ok_or_else(|| {
+        TokenError::Invalid(format!(
+            "Failed to parse token response ({})",
+            content_type.unwrap_or("no Content-Type")
+        ))
+    })?;
+    serde_json::from_value(fields)
+        .map_err(|e| TokenError::Invalid(format!("Invalid token response: {e}")))
+}
+
+/// The response's fields as a JSON object. Bodies without a recognised
+/// Content-Type are tried as JSON, then as a form.
+fn parse_fields(content_type: Option<&str>, body: &[u8]) -> Option<serde_json::Value> {
+    let mime = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|m| m.trim().to_ascii_lowercase());
+    let is_json = |m: &str| m == "application/json" || m.ends_with("+json");
+
+    match mime.as_deref() {
+        Some(m) if is_json(m) => parse_json_fields(body),
+        Some("application/x-www-form-urlencoded") => parse_form_fields(body),
+        _ => parse_json_fields(body).or_else(|| parse_form_fields(body)),
     }
+}
 
-    Ok(body)
+fn parse_json_fields(body: &[u8]) -> Option<serde_json::Value> {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .filter(serde_json::Value::is_object)
+}
+
+fn parse_form_fields(body: &[u8]) -> Option<serde_json::Value> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_bytes(body).ok()?;
+    if pairs.is_empty() {
+        return None;
+    }
+    Some(serde_json::Value::Object(
+        pairs
+            .into_iter()
+            .map(|(k, v)| (k, serde_json::Value::String(v)))
+            .collect(),
+    ))
+}
+
+/// Ask the provider to revoke an upstream token (RFC 7009). A no-op when the
+/// downstream has no revocation endpoint, configured or discovered.
+pub async fn revoke_upstream_token(
+    client: &reqwest::Client,
+    oauth: &OAuthConfig,
+    token: &str,
+    token_type_hint: &str,
+) -> Result<(), String> {
+    let Some(url) = oauth.revocation_url() else {
+        return Ok(());
+    };
+
+    let resp = client_auth::authenticated_form(
+        client,
+        oauth,
+        &url,
+        &[("token", token), ("token_type_hint", token_type_hint)],
+    )?
+    .send()
+    .await
+    .map_err(|e| format!("HTTP request failed: {e}"))?;
+
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("Downstream revocation endpoint returned {status}"));
+    }
+
+    Ok(())
+}
+
+/// Refresh proactively when the upstream access token has less than this many
+/// seconds left.
+const UPSTREAM_EXPIRY_SKEW_SECS: u64 = 60;
+
+/// Return usable upstream tokens for a proxy-managed grant.
+///
+/// `known` are the upstream tokens embedded in the proxy token the client
+/// presented. The session cache wins if it has newer ones; if the newest
+/// access token is expired (or about to be), the proxy refreshes against the
+/// provider, remembering a rotated refresh token for the next caller, and
+/// applies any configured token exchange.
+pub async fn fresh_upstream_tokens(
+    state: &AppState,
+    ds_name: &str,
+    oauth: &OAuthConfig,
+    grant_id: &str,
+    known: UpstreamTokens,
+) -> Result<UpstreamTokens, TokenError> {
+    let sessions = &state.upstream_sessions;
+
+    let current = sessions.get(grant_id).await.unwrap_or(known);
+    if current.is_fresh(UPSTREAM_EXPIRY_SKEW_SECS) {
+        return Ok(current);
+    }
+
+    let _guard = sessions.lock(grant_id).await;
+
+    // Another request may have refreshed while we waited for the lock.
+    let current = sessions.get(grant_id).await.unwrap_or(current);
+    if current.is_fresh(UPSTREAM_EXPIRY_SKEW_SECS) {
+        return Ok(current);
+    }
+
+    let refresh_token = current
+        .refresh_token
+        .ok_or_else(|| TokenError::Rejected("no upstream refresh token for grant".to_string()))?;
+
+    let body = post_downstream_token(
+        &state.http_client,
+        oauth,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+        ],
+    )
+    .await?;
+
+    check_granted_scope(ds_name, oauth, body.scope.as_deref()).map_err(TokenError::Rejected)?;
+
+    let now = now_secs().map_err(TokenError::Unavailable)?;
+    let until = now + state.config.server.refresh_token_ttl;
+    // Providers that rotate return a new refresh token; others keep the old one valid.
+    let refresh_token = Some(body.refresh_token.clone().unwrap_or(refresh_token));
+    // An omitted scope is unchanged (RFC 6749 §6).
+    let scope = body.scope.clone().or(current.scope);
+
+    let body = match apply_token_exchange(&state.http_client, oauth, body).await {
+        Ok(body) => body,
+        Err(e) => {
+            // Remember a rotated refresh token, with nothing usable to send,
+            // so the next request refreshes again instead of failing for good.
+            let pending = UpstreamTokens {
+                access_token: String::new(),
+                refresh_token,
+                expires_at: Some(0),
+                scope,
+            };
+            sessions.put(grant_id, pending, until).await;
+            return Err(e);
+        }
+    };
+
+    let tokens = UpstreamTokens {
+        access_token: body.access_token,
+        refresh_token,
+        expires_at: body.expires_in.map(|ei| now + ei),
+        scope,
+    };
+
+    sessions.put(grant_id, tokens.clone(), until).await;
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn parse(
+        status: u16,
+        content_type: Option<&str>,
+        body: &str,
+    ) -> Result<TokenResponse, TokenError> {
+        parse_token_response(
+            StatusCode::from_u16(status).unwrap(),
+            content_type,
+            body.as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_parse_json_and_form_responses() {
+        let json = parse(
+            200,
+            Some("application/json; charset=utf-8"),
+            r#"{"access_token":"at","token_type":"bearer","expires_in":3600,"refresh_token":"rt"}"#,
+        )
+        .unwrap();
+        assert_eq!(json.access_token, "at");
+        assert_eq!(json.refresh_token.as_deref(), Some("rt"));
+        assert_eq!(json.expires_in, Some(3600));
+        assert_eq!(json.token_type.as_deref(), Some("bearer"));
+
+        let form = parse(
+            200,
+            Some("application/x-www-form-urlencoded"),
+            "access_token=a%2Bt&token_type=bearer&expires_in=3600&scope=repo%2Cuser",
+        )
+        .unwrap();
+        assert_eq!(form.access_token, "a+t");
+        assert_eq!(form.refresh_token, None);
+        assert_eq!(form.expires_in, Some(3600));
+        assert_eq!(form.scope.as_deref(), Some("repo,user"));
+
+        // Quoted or empty expires_in, and an unlabelled form body.
+        let quoted = parse(200, None, r#"{"access_token":"at","expires_in":"60"}"#).unwrap();
+        assert_eq!(quoted.expires_in, Some(60));
+        let plain = parse(200, Some("text/plain"), "access_token=at&expires_in=").unwrap();
+        assert_eq!(
+            (plain.access_token.as_str(), plain.expires_in),
+            ("at", None)
+        );
+    }
+
+    #[test]
+    fn test_missing_scopes() {
+        assert!(missing_scopes(Some("repo user"), None).is_empty());
+        assert!(missing_scopes(None, Some("repo")).is_empty());
+        assert!(missing_scopes(Some("repo user"), Some("user,repo,gist")).is_empty());
+        assert_eq!(missing_scopes(Some("repo user"), Some("user")), ["repo"]);
+        assert_eq!(
+            missing_scopes(Some("repo,user"), Some("")),
+            ["repo", "user"]
+        );
+        assert_eq!(normalize_scope("repo,user  gist"), "repo user gist");
+    }
+
+    #[test]
+    fn test_errors_with_http_200_are_rejections() {
+        let err = parse(
+            200,
+            Some("application/x-www-form-urlencoded"),
+            "error=bad_verification_code&error_description=The+code+is+incorrect",
+        )
+        .unwrap_err();
+        assert!(matches!(&err, TokenError::Rejected(e) if e.contains("The code is incorrect")));
+
+        let err = parse(
+            200,
+            Some("application/json"),
+            r#"{"error":"invalid_grant"}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(&err, TokenError::Rejected(e) if e.contains("invalid_grant")));
+    }
+
+    #[test]
+    fn test_unusable_responses() {
+        // A failed status without an OAuth error body is a rejection, unless
+        // the provider itself failed.
+        let err = parse(400, Some("text/html"), "<html>Bad Request</html>").unwrap_err();
+        assert!(matches!(&err, TokenError::Rejected(e) if e.contains("400")));
+        let err = parse(502, Some("text/html"), "<html>Bad Gateway</html>").unwrap_err();
+        assert!(matches!(&err, TokenError::Unavailable(e) if e.contains("502")));
+        let err = parse(
+            503,
+            Some("application/json"),
+            r#"{"error":"temporarily_unavailable"}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, TokenError::Unavailable(_)));
+
+        let err = parse(200, Some("application/json"), "not json").unwrap_err();
+        assert!(matches!(err, TokenError::Invalid(_)));
+
+        let err = parse(200, Some("application/json"), r#"{"token_type":"bearer"}"#).unwrap_err();
+        assert!(matches!(&err, TokenError::Invalid(e) if e.contains("access_token")));
+
+        let err = parse(
+            200,
+            Some("application/json"),
+            r#"{"access_token":"at","expires_in":-1}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, TokenError::Invalid(_)));
+    }
 }
@@ -0,0 +1,172 @@
+//! Client authentication towards upstream providers.
+//!
+//! Code exchange, refresh and revocation requests all authenticate the same
+//! way, chosen per downstream with `oauth_client_auth_method`. Requests to an
+//! RFC 8693 security token service use the `oauth_token_exchange_client_*`
+//! settings instead, so the provider's credentials never reach it.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Serialize;
+
+use crate::config::{ClientAuthMethod, OAuthConfig};
+use crate::oauth::codes::{now_secs, random_id};
+
+const JWT_BEARER_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Lifetime of `private_key_jwt` assertions (seconds).
+const ASSERTION_TTL: u64 = 60;
+
+#[derive(Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    jti: String,
+    iat: u64,
+    exp: u64,
+}
+
+/// Build a form POST of `params` to the provider endpoint `url`, carrying the
+/// downstream's client authentication.
+pub fn authenticated_form(
+    client: &reqwest::Client,
+    oauth: &OAuthConfig,
+    url: &str,
+    params: &[(&str, &str)],
+) -> Result<reqwest::RequestBuilder, String> {
+    let client_id = oauth.oauth_client_id.as_str();
+    let mut form: Vec<(&str, String)> = params.iter().map(|(k, v)| (*k, v.to_string())).collect();
+    let mut req = client.post(url);
+
+    match oauth.oauth_client_auth_method {
+        ClientAuthMethod::PrivateKeyJwt => {
+            form.push(("client_id", client_id.to_string()));
+            form.push((
+                "client_assertion_type",
+                JWT_BEARER_ASSERTION_TYPE.to_string(),
+            ));
+            form.push(("client_assertion", client_assertion(oauth, url)?));
+        }
+        method => {
+            req = with_client_secret(
+                req,
+                &mut form,
+                method,
+                client_id,
+                &oauth.oauth_client_secret,
+            );
+        }
+    }
+
+    Ok(req.form(&form))
+}
+
+/// Build a form POST of `params` to the security token service `url`. It
+/// carries the `oauth_token_exchange_client_*` credentials, or none without
+/// `oauth_token_exchange_client_id`.
+pub fn token_exchange_form(
+    client: &reqwest::Client,
+    oauth: &OAuthConfig,
+    url: &str,
+    params: &[(&str, &str)],
+) -> Result<reqwest::RequestBuilder, String> {
+    let mut form: Vec<(&str, String)> = params.iter().map(|(k, v)| (*k, v.to_string())).collect();
+    let mut req = client.post(url);
+
+    if let Some(client_id) = &oauth.oauth_token_exchange_client_id {
+        match oauth
+            .oauth_token_exchange_client_auth_method
+            .unwrap_or_default()
+        {
+            ClientAuthMethod::PrivateKeyJwt => {
+                return Err("private_key_jwt is not supported for token exchange".to_string());
+            }
+            method => {
+                req = with_client_secret(
+                    req,
+                    &mut form,
+                    method,
+                    client_id,
+                    &oauth.oauth_token_exchange_client_secret,
+                );
+            }
+        }
+    }
+
+    Ok(req.form(&form))
+}
+
+/// Add `client_id`, and `client_secret` where `method` uses one, to a request.
+fn with_client_secret(
+    req: reqwest::RequestBuilder,
+    form: &mut Vec<(&str, String)>,
+    method: ClientAuthMethod,
+    client_id: &str,
+    client_secret: &str,
+) -> reqwest::RequestBuilder {
+    match method {
+        ClientAuthMethod::ClientSecretPost => {
+            form.push(("client_id", client_id.to_string()));
+            form.push(("client_secret", client_secret.to_string()));
+            req
+        }
+        ClientAuthMethod::ClientSecretBasic => {
+            req.header("Authorization", basic_credentials(client_id, client_secret))
+        }
+        ClientAuthMethod::PrivateKeyJwt | ClientAuthMethod::None => {
+            form.push(("client_id", client_id.to_string()));
+            req
+        }
+    }
+}
+
+/// `Basic` credentials per RFC 6749 §2.3.1: both parts are form-encoded
+/// before being joined, so IDs and secrets containing `:` survive.
+fn basic_credentials(client_id: &str, client_secret: &str) -> String {
+    let pair = format!(
+        "{}:{}",
+        urlencoding::encode(client_id),
+        urlencoding::encode(client_secret)
+    );
+    format!("Basic {}", STANDARD.encode(pair))
+}
+
+/// A signed `private_key_jwt` assertion for a request to `audience`.
+fn client_assertion(oauth: &OAuthConfig, audience: &str) -> Result<String, String> {
+    let key = oauth
+        .client_key
+        .get()
+        .ok_or_else(|| "private_key_jwt client key is not loaded".to_string())?;
+    let mut header = jsonwebtoken::Header::new(oauth.oauth_client_key_alg.algorithm());
+    header.kid = oauth.oauth_client_key_id.clone();
+
+    let now = now_secs()?;
+    let claims = AssertionClaims {
+        iss: &oauth.oauth_client_id,
+        sub: &oauth.oauth_client_id,
+        aud: audience,
+        jti: random_id(),
+        iat: now,
+        exp: now + ASSERTION_TTL,
+    };
+    jsonwebtoken::encode(&header, &claims, key)
+        .map_err(|e| format!("Failed to sign client assertion: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_credentials_are_form_encoded() {
+        assert_eq!(
+            basic_credentials("client", "secret"),
+            format!("Basic {}", STANDARD.encode("client:secret"))
+        );
+        assert_eq!(
+            basic_credentials("a:b", "c d/e"),
+            format!("Basic {}", STANDARD.encode("a%3Ab:c%20d%2Fe"))
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Service tokens for `strategy = "client_credentials"` downstreams.
+//!
+//! The proxy obtains one token per downstream from the provider's token
+//! endpoint with its own client credentials (RFC 6749 §4.4) and sends it on
+//! every proxied request, whichever user's proxy token authorized it. Users
+//! never see the service token; codes and proxy tokens carry only
+//! [`crate::oauth::codes::DownstreamTokens::ProxyCredential`].
+//!
+//! The token is cached per process and replaced shortly before it expires.
+//! Fetches are serialized per downstream, so concurrent requests that find
+//! the token stale wait for a single call to the provider.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::chained_oauth::{check_granted_scope, post_downstream_token};
+use crate::config::OAuthConfig;
+use crate::oauth::codes::now_secs;
+use crate::AppState;
+
+/// Replace a token this many seconds before it expires, or halfway through
+/// its lifetime if that is shorter.
+const EXPIRY_SKEW_SECS: u64 = 60;
+
+/// How long to use a token whose response did not include `expires_in`.
+const UNKNOWN_LIFETIME_SECS: u64 = 300;
+
+#[derive(Clone)]
+struct ServiceToken {
+    access_token: String,
+    /// Fetch a new token from this time on.
+    refresh_at: u64,
+}
+
+/// When a token issued at `now` should be replaced.
+fn refresh_at(now: u64, expires_in: Option<u64>) -> u64 {
+    match expires_in {
+        Some(lifetime) => now + lifetime - EXPIRY_SKEW_SECS.min(lifetime / 2),
+        None => now + UNKNOWN_LIFETIME_SECS,
+    }
+}
+
+type Slot = Arc<tokio::sync::Mutex<Option<ServiceToken>>>;
+
+/// The current service token of each client credentials downstream, and the
+/// installation token of each GitHub App downstream.
+#[derive(Default)]
+pub struct ServiceTokens {
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+impl ServiceTokens {
+    fn slot(&self, downstream: &str) -> Slot {
+        self.slots
+            .lock()
+            .unwrap()
+            .entry(downstream.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// The cached token for `downstream`, or a new one from `fetch`, which
+    /// returns the access token and its `expires_in`. The slot stays locked
+    /// while fetching, so callers arriving meanwhile reuse the result.
+    pub async fn get_or_fetch<F, Fut>(&self, downstream: &str, fetch: F) -> Result<String, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(String, Option<u64>), String>>,
+    {
+        let slot = self.slot(downstream);
+        let mut cached = slot.lock().await;
+        let now = now_secs()?;
+        if let Some(token) = cached.as_ref().filter(|t| now < t.refresh_at) {
+            return Ok(token.access_token.clone());
+        }
+
+        let (access_token, expires_in) = fetch().await?;
+        *cached = Some(ServiceToken {
+            access_token: access_token.clone(),
+            refresh_at: refresh_at(now_secs()?, expires_in),
+        });
+        Ok(access_token)
+    }
+}
+
+/// The service token to send to a client credentials downstream, fetching a
+/// new one from the provider when the cached one is missing or due for
+/// replacement.
+pub async fn service_token(
+    state: &AppState,
+    ds_name: &str,
+    oauth: &OAuthConfig,
+) -> Result<String, String> {
+    state
+        .service_tokens
+        .get_or_fetch(ds_name, || async {
+            let mut params = vec![("grant_type", "client_credentials")];
+            if let Some(scopes) = &oauth.oauth_scopes {
+                params.push(("scope", scopes.as_str()));
+            }
+            let body = post_downstream_token(&state.http_client, oauth, &params)
+                .await
+                .map_err(|e| e.to_string())?;
+            check_granted_scope(ds_name, oauth, body.scope.as_deref())?;
+            tracing::info!(downstream = %ds_name, expires_in = ?body.expires_in, "Service token issued");
+            Ok((body.access_token, body.expires_in))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_refresh_at() {
+        assert_eq!(refresh_at(1000, Some(3600)), 1000 + 3600 - 60);
+        assert_eq!(refresh_at(1000, Some(30)), 1000 + 15);
+        assert_eq!(refresh_at(1000, Some(0)), 1000);
+        assert_eq!(refresh_at(1000, None), 1000 + UNKNOWN_LIFETIME_SECS);
+    }
+
+    #[tokio::test]
+    async fn test_single_fetch_under_concurrency() {
+        let tokens = Arc::new(ServiceTokens::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let (tokens, calls) = (tokens.clone(), calls.clone());
+                tokio::spawn(async move {
+                    tokens
+                        .get_or_fetch("svc", || async {
+                            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                            tokio::task::yield_now().await;
+                            Ok((format!("token-{n}"), Some(3600)))
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), "token-1");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Other downstreams have their own token.
+        let other = tokens
+            .get_or_fetch("other", || async { Ok(("other-1".to_string(), None)) })
+            .await;
+        assert_eq!(other.unwrap(), "other-1");
+    }
+
+    #[tokio::test]
+    async fn test_stale_tokens_and_failures_refetch() {
+        let tokens = ServiceTokens::default();
+
+        // Due for replacement as soon as it is issued.
+        let first = tokens
+            .get_or_fetch("svc", || async { Ok(("token-1".to_string(), Some(0))) })
+            .await;
+        assert_eq!(first.unwrap(), "token-1");
+
+        let failed = tokens
+            .get_or_fetch("svc", || async { Err("provider down".to_string()) })
+            .await;
+        assert_eq!(failed.unwrap_err(), "provider down");
+
+        let second = tokens
+            .get_or_fetch("svc", || async { Ok(("token-2".to_string(), Some(3600))) })
+            .await;
+        assert_eq!(second.unwrap(), "token-2");
+        let cached = tokens
+            .get_or_fetch("svc", || async { Ok(("token-3".to_string(), Some(3600))) })
+            .await;
+        assert_eq!(cached.unwrap(), "token-2");
+    }
+}
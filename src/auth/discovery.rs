@@ -0,0 +1,187 @@
+//! Provider metadata discovery for chained OAuth and client credentials
+//! downstreams and the `[server.login]` provider (OpenID Connect Discovery and RFC 8414).
+//!
+//! A provider with `oauth_issuer` gets its endpoints from the provider's
+//! metadata document instead of (or in addition to) configuring each URL.
+//! The document is fetched once by `load_config`, which fails if it cannot be
+//! retrieved, and then refreshed periodically so endpoint changes on the
+//! provider's side are picked up without a restart. A failed refresh keeps
+//! the last good metadata.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{Config, OAuthConfig, ProviderMetadata, StrategyConfig};
+
+/// Metadata document URLs for `issuer`, in the order they are tried: OpenID
+/// Connect (`<issuer>/.well-known/openid-configuration`), then RFC 8414, which
+/// inserts the well-known segment between the host and the issuer's path.
+fn metadata_urls(issuer: &str) -> Vec<String> {
+    let issuer = issuer.trim_end_matches('/');
+    let mut urls = vec![format!("{issuer}/.well-known/openid-configuration")];
+
+    let (scheme, rest) = issuer.split_once("://").unwrap_or(("https", issuer));
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    urls.push(format!(
+        "{scheme}://{host}/.well-known/oauth-authorization-server{path}"
+    ));
+    urls
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<ProviderMetadata, String> {
+    let resp = client
+        .get(url)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| format!("HTTP request failed: {e}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("returned {status}"));
+    }
+    resp.json()
+        .await
+        .map_err(|e| format!("invalid metadata document: {e}"))
+}
+
+/// Fetch the metadata for `issuer`. The document must name the same issuer,
+/// so a misconfigured or spoofed document is not trusted.
+pub async fn discover(client: &reqwest::Client, issuer: &str) -> Result<ProviderMetadata, String> {
+    let mut errors = Vec::new();
+    for url in metadata_urls(issuer) {
+        match fetch(client, &url).await {
+            Ok(metadata) if metadata.issuer == issuer => return Ok(metadata),
+            Ok(metadata) => errors.push(format!(
+                "{url}: issuer '{}' does not match",
+                metadata.issuer
+            )),
+            Err(e) => errors.push(format!("{url}: {e}")),
+        }
+    }
+    Err(errors.join("; "))
+}
+
+/// A provider configured by `oauth_issuer`, with the label used in errors and
+/// logs.
+struct IssuerProvider<'a> {
+    label: String,
+    oauth: &'a OAuthConfig,
+    issuer: &'a str,
+    /// Whether users are sent to its authorization endpoint (client
+    /// credentials and JWT bearer downstreams only use the token endpoint).
+    authorizes: bool,
+}
+
+/// Providers configured by `oauth_issuer`: the login provider and chained
+/// OAuth, client credentials and JWT bearer downstreams.
+fn issuer_providers(config: &Config) -> impl Iterator<Item = IssuerProvider<'_>> {
+    let login = config
+        .server
+        .login
+        .as_ref()
+        .map(|login| ("server.login".to_string(), &login.oauth, true));
+    let downstreams = config
+        .downstream
+        .iter()
+        .filter_map(|(name, ds)| match &ds.strategy {
+            StrategyConfig::ChainedOauth { oauth } => {
+                Some((format!("downstream '{name}'"), oauth, true))
+            }
+            StrategyConfig::ClientCredentials { oauth, .. }
+            | StrategyConfig::JwtBearer { oauth, .. } => {
+                Some((format!("downstream '{name}'"), oauth, false))
+            }
+            _ => None,
+        });
+    login
+        .into_iter()
+        .chain(downstreams)
+        .filter_map(|(label, oauth, authorizes)| {
+            oauth.oauth_issuer.as_deref().map(|issuer| IssuerProvider {
+                label,
+                oauth,
+                issuer,
+                authorizes,
+            })
+        })
+}
+
+/// Discover metadata for every provider with `oauth_issuer`, failing if a
+/// document cannot be fetched or lacks an endpoint the proxy needs.
+pub async fn discover_all(config: &Config, client: &reqwest::Client) -> Result<(), String> {
+    for IssuerProvider {
+        label,
+        oauth,
+        issuer,
+        authorizes,
+    } in issuer_providers(config)
+    {
+        let metadata = discover(client, issuer)
+            .await
+            .map_err(|e| format!("{label}: discovery for {issuer} failed: {e}"))?;
+        oauth.discovered.set(metadata);
+
+        if authorizes && oauth.authorize_url().is_none() {
+            return Err(format!(
+                "{label}: {issuer} metadata has no authorization_endpoint; set oauth_authorize_url"
+            ));
+        }
+        if oauth.token_url().is_none() {
+            return Err(format!(
+                "{label}: {issuer} metadata has no token_endpoint; set oauth_token_url"
+            ));
+        }
+        tracing::info!(provider = %label, issuer = %issuer, "Provider metadata discovered");
+    }
+    Ok(())
+}
+
+/// Re-fetch provider metadata every `server.discovery_refresh_interval`
+/// seconds. A no-op when no provider uses `oauth_issuer`.
+pub fn spawn_refresh(config: Arc<Config>, client: reqwest::Client) {
+    if issuer_providers(&config).next().is_none() {
+        return;
+    }
+    let interval = Duration::from_secs(config.server.discovery_refresh_interval);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            for provider in issuer_providers(&config) {
+                match discover(&client, provider.issuer).await {
+                    Ok(metadata) => provider.oauth.discovered.set(metadata),
+                    Err(e) => tracing::warn!(
+                        provider = %provider.label,
+                        error = %e,
+                        "Provider metadata refresh failed, keeping cached metadata"
+                    ),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_urls() {
+        assert_eq!(
+            metadata_urls("https://accounts.example.com"),
+            [
+                "https://accounts.example.com/.well-known/openid-configuration",
+                "https://accounts.example.com/.well-known/oauth-authorization-server",
+            ]
+        );
+        assert_eq!(
+            metadata_urls("https://example.com/tenant/v2/"),
+            [
+                "https://example.com/tenant/v2/.well-known/openid-configuration",
+                "https://example.com/.well-known/oauth-authorization-server/tenant/v2",
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Installation tokens for `strategy = "github_app"` downstreams.
+//!
+//! The proxy authenticates as the GitHub App with a short-lived RS256 JWT
+//! signed by the app's private key, and trades it for an installation access
+//! token at `POST /app/installations/{id}/access_tokens`. Installation tokens
+//! last an hour; they are cached with the client credentials service tokens
+//! ([`crate::auth::client_credentials::ServiceTokens`]) and replaced shortly
+//! before `expires_at`. Codes and proxy tokens carry only
+//! [`crate::oauth::codes::DownstreamTokens::ProxyCredential`].
+//!
+//! With `gate = "github"`, users sign in through the app's user-to-server
+//! OAuth flow instead. The callback looks the user up with the user-to-server
+//! token and requires the app's installation among those the user can access;
+//! that token is used for nothing else.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{GithubAppConfig, OAuthConfig};
+use crate::oauth::codes::{now_secs, UserIdentity};
+use crate::AppState;
+
+/// GitHub rejects API requests without a `User-Agent`.
+const USER_AGENT: &str = concat!("mcp-oauth-proxy/", env!("CARGO_PKG_VERSION"));
+
+/// Backdate app JWTs against clock drift, as GitHub recommends.
+const APP_JWT_BACKDATE_SECS: u64 = 60;
+
+/// App JWT lifetime from `iat`; GitHub allows at most ten minutes.
+const APP_JWT_TTL_SECS: u64 = 540;
+
+#[derive(Serialize)]
+struct AppClaims<'a> {
+    iss: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+/// A JWT authenticating as the app, issued by its client ID.
+fn app_jwt(client_id: &str, app: &GithubAppConfig) -> Result<String, String> {
+    let key = app
+        .github_private_key
+        .get()
+        .ok_or_else(|| "GitHub App private key is not loaded".to_string())?;
+    let iat = now_secs()? - APP_JWT_BACKDATE_SECS;
+    let claims = AppClaims {
+        iss: client_id,
+        iat,
+        exp: iat + APP_JWT_TTL_SECS,
+    };
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+        &claims,
+        key,
+    )
+    .map_err(|e| format!("Failed to sign GitHub App JWT: {e}"))
+}
+
+fn api_request(
+    client: &reqwest::Client,
+    method: reqwest::Method,
+    url: &str,
+    bearer: &str,
+) -> reqwest::RequestBuilder {
+    client
+        .request(method, url)
+        .bearer_auth(bearer)
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", USER_AGENT)
+        .header("X-GitHub-Api-Version", "2022-11-28")
+}
+
+/// Seconds since the Unix epoch of a GitHub `YYYY-MM-DDTHH:MM:SSZ`
+/// timestamp.
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let format =
+        time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]Z");
+    let time = time::PrimitiveDateTime::parse(s, format).ok()?;
+    u64::try_from(time.assume_utc().unix_timestamp()).ok()
+}
+
+/// The installation token to send to a GitHub App downstream, requesting a
+/// new one when the cached one is missing or due for replacement.
+pub async fn installation_token(
+    state: &AppState,
+    ds_name: &str,
+    oauth: &OAuthConfig,
+    app: &GithubAppConfig,
+) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct InstallationToken {
+        token: String,
+        expires_at: Option<String>,
+    }
+
+    state
+        .service_tokens
+        .get_or_fetch(ds_name, || async {
+            let url = format!(
+                "{}/app/installations/{}/access_tokens",
+                app.github_api_url, app.github_installation_id
+            );
+            let jwt = app_jwt(&oauth.oauth_client_id, app)?;
+            let resp = api_request(&state.http_client, reqwest::Method::POST, &url, &jwt)
+                .send()
+                .await
+                .map_err(|e| format!("HTTP request failed: {e}"))?;
+            let status = resp.status();
+            if !status.is_success() {
+                return Err(format!("GitHub installation token endpoint returned {status}"));
+            }
+            let body: InstallationToken = resp
+                .json()
+                .await
+                .map_err(|e| format!("invalid installation token response: {e}"))?;
+            let now = now_secs()?;
+            let expires_in = body
+                .expires_at
+                .as_deref()
+                .and_then(parse_timestamp)
+                .map(|at| at.saturating_sub(now));
+            tracing::info!(downstream = %ds_name, expires_in = ?expires_in, "Installation token issued");
+            Ok((body.token, expires_in))
+        })
+        .await
+}
+
+/// The GitHub user a user-to-server token belongs to, by numeric account ID.
+pub async fn user(
+    client: &reqwest::Client,
+    app: &GithubAppConfig,
+    token: &str,
+) -> Result<UserIdentity, String> {
+    #[derive(Deserialize)]
+    struct User {
+        id: u64,
+        email: Option<String>,
+    }
+
+    let url = format!("{}/user", app.github_api_url);
+    let resp = api_request(client, reqwest::Method::GET, &url, token)
+        .send()
+        .await
+        .map_err(|e| format!("HTTP request failed: {e}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("GitHub user endpoint returned {status}"));
+    }
+    let user: User = resp
+        .json()
+        .await
+        .map_err(|e| format!("invalid user response: {e}"))?;
+    Ok(UserIdentity {
+        sub: user.id.to_string(),
+        email: user.email,
+    })
+}
+
+/// Whether the app's installation is among those the user-to-server token's
+/// user can access.
+pub async fn can_access_installation(
+    client: &reqwest::Client,
+    app: &GithubAppConfig,
+    token: &str,
+) -> Result<bool, String> {
+    #[derive(Deserialize)]
+    struct Installations {
+        installations: Vec<Installation>,
+    }
+    #[derive(Deserialize)]
+    struct Installation {
+        id: u64,
+    }
+
+    let url = format!("{}/user/installations?per_page=100", app.github_api_url);
+    let resp = api_request(client, reqwest::Method::GET, &url, token)
+        .send()
+        .await
+        .map_err(|e| format!("HTTP request failed: {e}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("GitHub installations endpoint returned {status}"));
+    }
+    let body: Installations = resp
+        .json()
+        .await
+        .map_err(|e| format!("invalid installations response: {e}"))?;
+    Ok(body
+        .installations
+        .iter()
+        .any(|i| i.id == app.github_installation_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2016-07-11T22:14:10Z"), Some(1_468_275_250));
+        assert_eq!(parse_timestamp("2024-02-29T12:00:00Z"), Some(1_709_208_000));
+        assert_eq!(parse_timestamp("2016-07-11T22:14:10"), None);
+        assert_eq!(parse_timestamp("2016-13-11T22:14:10Z"), None);
+        assert_eq!(parse_timestamp("2016-07-11 22:14:10Z"), None);
+        assert_eq!(parse_timestamp("not a time"), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! Tokens for `strategy = "jwt_bearer"` downstreams.
+//!
+//! The proxy signs a JWT assertion with the configured key and presents it
+//! to the provider's token endpoint with the RFC 7523 §2.1 grant type, the
+//! way Google service accounts and similar APIs authenticate. The resulting
+//! token is cached with the client credentials service tokens
+//! ([`crate::auth::client_credentials::ServiceTokens`]) and replaced shortly
+//! before it expires.
+//!
+//! With `jwt_impersonate`, the assertion's `sub` is the user recorded in the
+//! proxy token when they signed in, and tokens are cached per user.
+
+use serde::Serialize;
+
+use crate::auth::chained_oauth::{check_granted_scope, post_downstream_token};
+use crate::config::{Impersonation, JwtBearerConfig, OAuthConfig};
+use crate::oauth::codes::{now_secs, random_id, UserIdentity};
+use crate::AppState;
+
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Lifetime of assertions (seconds).
+const ASSERTION_TTL: u64 = 300;
+
+#[derive(Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<&'a str>,
+    jti: String,
+    iat: u64,
+    exp: u64,
+}
+
+/// The assertion `sub`: the impersonated user, `jwt_subject`, or the issuer.
+fn subject<'a>(
+    assertion: &'a JwtBearerConfig,
+    issuer: &'a str,
+    user: Option<&'a UserIdentity>,
+) -> Result<&'a str, String> {
+    let Some(impersonation) = assertion.jwt_impersonate else {
+        return Ok(assertion.jwt_subject.as_deref().unwrap_or(issuer));
+    };
+    let user = user.ok_or_else(|| "no signed-in user to impersonate".to_string())?;
+    match impersonation {
+        Impersonation::Sub => Ok(&user.sub),
+        Impersonation::Email => user
+            .email
+            .as_deref()
+            .ok_or_else(|| format!("user {} has no verified email to impersonate", user.sub)),
+    }
+}
+
+/// A signed assertion from `iss` for `sub`, addressed to `token_url` unless
+/// `jwt_audience` is set.
+fn sign_assertion(
+    oauth: &OAuthConfig,
+    assertion: &JwtBearerConfig,
+    iss: &str,
+    sub: &str,
+    token_url: &str,
+) -> Result<String, String> {
+    let key = assertion
+        .jwt_key
+        .get()
+        .ok_or_else(|| "jwt_bearer key is not loaded".to_string())?;
+    let mut header = jsonwebtoken::Header::new(assertion.jwt_key_alg.algorithm());
+    header.kid = assertion.jwt_key_id.clone();
+
+    let now = now_secs()?;
+    let claims = AssertionClaims {
+        iss,
+        sub,
+        aud: assertion.jwt_audience.as_deref().unwrap_or(token_url),
+        scope: oauth
+            .oauth_scopes
+            .as_deref()
+            .filter(|_| assertion.jwt_scope_claim),
+        jti: random_id(),
+        iat: now,
+        exp: now + ASSERTION_TTL,
+    };
+    jsonwebtoken::encode(&header, &claims, key)
+        .map_err(|e| format!("Failed to sign JWT bearer assertion: {e}"))
+}
+
+/// The token to send to a JWT bearer downstream on behalf of `user` (the
+/// proxy token's signed-in user, if any), requesting a new one when the
+/// cached one is missing or due for replacement.
+pub async fn access_token(
+    state: &AppState,
+    ds_name: &str,
+    oauth: &OAuthConfig,
+    assertion: &JwtBearerConfig,
+    user: Option<&UserIdentity>,
+) -> Result<String, String> {
+    let issuer = assertion
+        .jwt_issuer
+        .as_deref()
+        .unwrap_or(&oauth.oauth_client_id);
+    let sub = subject(assertion, issuer, user)?;
+    // Downstream names cannot contain ':', so per-user keys never collide
+    // with another downstream's.
+    let cache_key = match assertion.jwt_impersonate {
+        Some(_) => format!("{ds_name}:{sub}"),
+        None => ds_name.to_string(),
+    };
+
+    state
+        .service_tokens
+        .get_or_fetch(&cache_key, || async {
+            let token_url = oauth
+                .token_url()
+                .ok_or_else(|| "no token endpoint configured or discovered".to_string())?;
+            let signed = sign_assertion(oauth, assertion, issuer, sub, &token_url)?;
+            let mut params = vec![
+                ("grant_type", JWT_BEARER_GRANT),
+                ("assertion", signed.as_str()),
+            ];
+            if let Some(scopes) = oauth.oauth_scopes.as_deref() {
+                if !assertion.jwt_scope_claim {
+                    params.push(("scope", scopes));
+                }
+            }
+            let body = post_downstream_token(&state.http_client, oauth, &params)
+                .await
+                .map_err(|e| e.to_string())?;
+            check_granted_scope(ds_name, oauth, body.scope.as_deref())?;
+            tracing::info!(downstream = %ds_name, expires_in = ?body.expires_in, "JWT bearer token issued");
+            Ok((body.access_token, body.expires_in))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assertion(extra: &str) -> JwtBearerConfig {
+        toml::from_str(&format!("jwt_key_path = \"key.pem\"\n{extra}")).unwrap()
+    }
+
+    #[test]
+    fn test_subject() {
+        let user = UserIdentity {
+            sub: "user-123".to_string(),
+            email: Some("ada@example.com".to_string()),
+        };
+
+        assert_eq!(subject(&assertion(""), "svc", Some(&user)), Ok("svc"));
+        assert_eq!(
+            subject(&assertion("jwt_subject = \"robot\""), "svc", None),
+            Ok("robot")
+        );
+        let by_email = assertion("jwt_impersonate = \"email\"");
+        assert_eq!(
+            subject(&by_email, "svc", Some(&user)),
+            Ok("ada@example.com")
+        );
+        assert_eq!(
+            subject(&assertion("jwt_impersonate = \"sub\""), "svc", Some(&user)),
+            Ok("user-123")
+        );
+
+        assert!(subject(&by_email, "svc", None).is_err());
+        let no_email = UserIdentity {
+            email: None,
+            ..user
+        };
+        assert_eq!(
+            subject(&by_email, "svc", Some(&no_email)).unwrap_err(),
+            "user user-123 has no verified email to impersonate"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! `[server.login]`: single sign-on in front of passthrough downstreams.
+//!
+//! Without a session, `/authorize` redirects the browser to the login
+//! provider. `/login/callback` validates the provider's ID token, stores the
+//! user in an encrypted session cookie, and sends the browser back to the
+//! original `/authorize` URL. The form and its POST then check the session
+//! against the downstream's `allowed_email_domains` and `allowed_groups`, and
+//! the signed-in user is embedded in the authorization code.
+//!
+//! Both the cookie and the provider `state` are AES-256-GCM blobs under their
+//! own derived keys ([`KeyPurpose::Cookie`], [`KeyPurpose::Login`]), so
+//! nothing is stored server-side. The `state` also carries a random value
+//! that the redirect sets in a short-lived [`BINDING_COOKIE_NAME`] cookie;
+//! the callback refuses a `state` arriving in any other browser, so an
+//! attacker cannot sign a victim in as themselves (login CSRF).
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::oidc;
+use crate::config::DownstreamConfig;
+use crate::oauth::codes::{self, UserIdentity};
+use crate::oauth::keys::{KeyPurpose, Keyring};
+use crate::oauth::pkce;
+use crate::AppState;
+
+/// Name of the session cookie.
+pub const COOKIE_NAME: &str = "mcp_proxy_login";
+
+/// Name of the cookie binding a sign-in to the browser that started it.
+pub const BINDING_COOKIE_NAME: &str = "mcp_proxy_login_state";
+
+/// How long a sign-in at the provider may take (seconds).
+const LOGIN_STATE_TTL_SECS: u64 = 600;
+
+/// A signed-in user, kept in the session cookie.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginSession {
+    pub user: UserIdentity,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    pub exp: u64,
+}
+
+/// The `state` sent to the login provider.
+#[derive(Serialize, Deserialize)]
+pub struct LoginState {
+    /// The `/authorize` URL to resume once signed in.
+    pub return_to: String,
+    /// The ID token must echo this back.
+    pub nonce: String,
+    /// PKCE verifier towards the login provider.
+    pub verifier: String,
+    /// Also set in the [`BINDING_COOKIE_NAME`] cookie; the callback requires
+    /// both to match.
+    pub binding: String,
+    pub exp: u64,
+}
+
+/// Whether a browser may proceed to a passthrough downstream's form.
+pub enum Access {
+    /// `[server.login]` is not configured.
+    Anonymous,
+    /// Signed in and allowed.
+    User(UserIdentity),
+    /// No valid session; the browser must sign in first.
+    LoginRequired,
+    /// Signed in, but not allowed at this downstream.
+    Denied(String),
+}
+
+/// Check the request's session against the downstream's access rules.
+pub fn check(state: &AppState, ds: &DownstreamConfig, headers: &HeaderMap) -> Access {
+    if state.config.server.login.is_none() {
+        return Access::Anonymous;
+    }
+    let Some(session) = session(headers, state.keys()) else {
+        return Access::LoginRequired;
+    };
+    match check_allowed(ds, &session) {
+        Ok(()) => Access::User(session.user),
+        Err(e) => Access::Denied(e),
+    }
+}
+
+/// The value of cookie `name` in the request, if any.
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+}
+
+/// The valid session in the request's cookies, if any.
+fn session(headers: &HeaderMap, keys: &Keyring) -> Option<LoginSession> {
+    let value = cookie(headers, COOKIE_NAME)?;
+    let session: LoginSession = codes::decrypt_payload(value, KeyPurpose::Cookie, keys).ok()?;
+    (session.exp > codes::now_secs().ok()?).then_some(session)
+}
+
+/// Allow users with a verified email in `allowed_email_domains` or a group in
+/// `allowed_groups`, or anyone signed in when both are empty.
+fn check_allowed(ds: &DownstreamConfig, session: &LoginSession) -> Result<(), String> {
+    if ds.allowed_email_domains.is_empty() && ds.allowed_groups.is_empty() {
+        return Ok(());
+    }
+    let domain_allowed = session
+        .user
+        .email
+        .as_deref()
+        .and_then(|email| email.rsplit_once('@'))
+        .is_some_and(|(_, domain)| {
+            ds.allowed_email_domains
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+        });
+    let group_allowed = session.groups.iter().any(|g| ds.allowed_groups.contains(g));
+    if domain_allowed || group_allowed {
+        return Ok(());
+    }
+    let who = session.user.email.as_deref().unwrap_or(&session.user.sub);
+    Err(format!(
+        "{who} is not allowed to authorize {}",
+        ds.display_name
+    ))
+}
+
+/// The redirect URI registered with the login provider.
+pub fn callback_url(public_url: &str) -> String {
+    format!("{public_url}/login/callback")
+}
+
+/// The provider URL that signs the user in and comes back to `return_to`,
+/// and the `Set-Cookie` value binding the sign-in to this browser.
+pub fn login_url(state: &AppState, return_to: &str) -> Result<(String, String), String> {
+    let login = state
+        .config
+        .server
+        .login
+        .as_ref()
+        .ok_or_else(|| "[server.login] is not configured".to_string())?;
+    let authorize_url = login
+        .oauth
+        .authorize_url()
+        .ok_or_else(|| "no authorization endpoint configured or discovered".to_string())?;
+
+    let login_state = LoginState {
+        return_to: return_to.to_string(),
+        nonce: codes::random_id(),
+        verifier: pkce::generate_verifier(),
+        binding: codes::random_id(),
+        exp: codes::now_secs()? + LOGIN_STATE_TTL_SECS,
+    };
+    let sealed = codes::encrypt_payload(&login_state, KeyPurpose::Login, state.keys())?;
+    let binding = format!(
+        "{BINDING_COOKIE_NAME}={}; Path=/login/callback; Max-Age={LOGIN_STATE_TTL_SECS}; \
+         HttpOnly; SameSite=Lax{}",
+        login_state.binding,
+        secure_attr(&state.config.server.public_url),
+    );
+
+    let url = format!(
+        "{authorize_url}?response_type=code&client_id={}&redirect_uri={}&state={}\
+         &code_challenge={}&code_challenge_method=S256&scope={}&nonce={}",
+        urlencoding::encode(&login.oauth.oauth_client_id),
+        urlencoding::encode(&callback_url(&state.config.server.public_url)),
+        urlencoding::encode(&sealed),
+        pkce::s256_challenge(&login_state.verifier),
+        urlencoding::encode(&oidc::openid_scope(login.oauth.oauth_scopes.as_deref())),
+        login_state.nonce,
+    );
+    Ok((url, binding))
+}
+
+/// Open the `state` returned by the login provider.
+pub fn open_state(sealed: &str, keys: &Keyring) -> Option<LoginState> {
+    let login_state: LoginState = codes::decrypt_payload(sealed, KeyPurpose::Login, keys).ok()?;
+    (login_state.exp > codes::now_secs().ok()?).then_some(login_state)
+}
+
+/// Whether `login_state` came back to the browser that started the sign-in.
+pub fn started_here(headers: &HeaderMap, login_state: &LoginState) -> bool {
+    cookie(headers, BINDING_COOKIE_NAME).is_some_and(|value| value == login_state.binding)
+}
+
+/// A `Set-Cookie` value removing the binding cookie once the sign-in is done.
+pub fn clear_binding_cookie(public_url: &str) -> String {
+    format!(
+        "{BINDING_COOKIE_NAME}=; Path=/login/callback; Max-Age=0; HttpOnly; SameSite=Lax{}",
+        secure_attr(public_url)
+    )
+}
+
+/// The `Secure` cookie attribute when the proxy is served over HTTPS.
+fn secure_attr(public_url: &str) -> &'static str {
+    if public_url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    }
+}
+
+/// A `Set-Cookie` value holding `session` for `ttl` seconds.
+pub fn session_cookie(
+    session: &LoginSession,
+    ttl: u64,
+    public_url: &str,
+    keys: &Keyring,
+) -> Result<String, String> {
+    let sealed = codes::encrypt_payload(session, KeyPurpose::Cookie, keys)?;
+    Ok(format!(
+        "{COOKIE_NAME}={sealed}; Path=/; Max-Age={ttl}; HttpOnly; SameSite=Lax{}",
+        secure_attr(public_url)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn downstream(rules: &str) -> DownstreamConfig {
+        toml::from_str(&format!(
+            r#"
+display_name = "Linear"
+strategy = "passthrough"
+downstream_url = "https://mcp.linear.app/mcp"
+{rules}
+"#
+        ))
+        .unwrap()
+    }
+
+    fn signed_in(email: Option<&str>, groups: &[&str]) -> LoginSession {
+        LoginSession {
+            user: UserIdentity {
+                sub: "user-123".to_string(),
+                email: email.map(String::from),
+            },
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            exp: 0,
+        }
+    }
+
+    #[test]
+    fn test_check_allowed() {
+        let open = downstream("");
+        assert!(check_allowed(&open, &signed_in(None, &[])).is_ok());
+
+        let rules = downstream(
+            "allowed_email_domains = [\"example.com\"]\nallowed_groups = [\"engineering\"]",
+        );
+        assert!(check_allowed(&rules, &signed_in(Some("ada@Example.COM"), &[])).is_ok());
+        assert!(check_allowed(&rules, &signed_in(None, &["sales", "engineering"])).is_ok());
+
+        let err = check_allowed(&rules, &signed_in(Some("eve@example.com.evil"), &["sales"]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "eve@example.com.evil is not allowed to authorize Linear"
+        );
+        assert!(check_allowed(&rules, &signed_in(None, &[])).is_err());
+    }
+}
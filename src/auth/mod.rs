@@ -1 +1,10 @@
 pub mod chained_oauth;
+pub mod client_auth;
+pub mod client_credentials;
+pub mod discovery;
+pub mod github_app;
+pub mod jwt_bearer;
+pub mod login;
+pub mod oidc;
+pub mod sessions;
+pub mod vault;
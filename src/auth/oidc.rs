@@ -0,0 +1,338 @@
+//! OpenID Connect ID token validation for chained OAuth downstreams.
+//!
+//! With `oauth_oidc = true` the proxy requests the `openid` scope, sends a
+//! nonce (kept in the signed `state`), and validates the provider's
+//! `id_token`: the signature against the provider's JWKS, then `iss`, `aud`
+//! (and `azp` when there are several audiences), `exp` and `nonce`
+//! (OIDC Core §3.1.3.7). The verified `sub` and `email` become the grant's
+//! [`UserIdentity`].
+//!
+//! JWKS documents are cached per URL. An unknown `kid` triggers a refetch, so
+//! provider key rotation is picked up without a restart, but at most once per
+//! [`JWKS_MIN_REFETCH`] to keep forged tokens from hammering the provider.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::Jwk;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+use crate::config::OAuthConfig;
+use crate::oauth::codes::UserIdentity;
+
+/// How long a fetched JWKS is used before it is fetched again.
+const JWKS_TTL: Duration = Duration::from_secs(3600);
+
+/// Minimum time between fetches of the same JWKS.
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(30);
+
+/// Signature algorithms accepted for ID tokens. Symmetric algorithms would
+/// need the client secret as the key, and `none` is never acceptable.
+const ALLOWED_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    /// A string or an array; `jsonwebtoken` has already checked it names us.
+    #[serde(default)]
+    aud: serde_json::Value,
+    azp: Option<String>,
+    nonce: Option<String>,
+    email: Option<String>,
+    /// Some providers send `"true"`/`"false"` strings.
+    email_verified: Option<serde_json::Value>,
+    /// A list of group names, or a single name.
+    groups: Option<serde_json::Value>,
+}
+
+/// What a validated ID token says about the user.
+#[derive(Debug)]
+pub struct IdToken {
+    pub user: UserIdentity,
+    /// The `groups` claim, used by `[server.login]` access rules.
+    pub groups: Vec<String>,
+}
+
+struct CachedJwks {
+    keys: Vec<Jwk>,
+    fetched_at: Instant,
+}
+
+/// JWKS documents by URL.
+#[derive(Default)]
+pub struct JwksCache {
+    sets: Mutex<HashMap<String, CachedJwks>>,
+}
+
+impl JwksCache {
+    /// Keys from `url` that may have signed a token with key ID `kid`,
+    /// fetching the document when it is missing, stale, or lacks `kid`.
+    async fn keys(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        kid: Option<&str>,
+    ) -> Result<Vec<Jwk>, String> {
+        let refetch = {
+            let sets = self.sets.lock().unwrap();
+            match sets.get(url) {
+                None => true,
+                Some(cached) => {
+                    let age = cached.fetched_at.elapsed();
+                    age > JWKS_TTL
+                        || (age > JWKS_MIN_REFETCH && matching(&cached.keys, kid).is_empty())
+                }
+            }
+        };
+
+        if refetch {
+            let keys = fetch_jwks(client, url).await?;
+            self.sets.lock().unwrap().insert(
+                url.to_string(),
+                CachedJwks {
+                    keys,
+                    fetched_at: Instant::now(),
+                },
+            );
+        }
+
+        let sets = self.sets.lock().unwrap();
+        Ok(sets
+            .get(url)
+            .map(|cached| matching(&cached.keys, kid))
+            .unwrap_or_default())
+    }
+}
+
+/// Signing keys with ID `kid`, or all signing keys when the token names none.
+fn matching(keys: &[Jwk], kid: Option<&str>) -> Vec<Jwk> {
+    keys.iter()
+        .filter(|k| {
+            !matches!(
+                k.common.public_key_use,
+                Some(jsonwebtoken::jwk::PublicKeyUse::Encryption)
+            )
+        })
+        .filter(|k| kid.is_none() || k.common.key_id.as_deref() == kid)
+        .cloned()
+        .collect()
+}
+
+/// Fetch a JWKS, skipping keys of types this proxy cannot use rather than
+/// rejecting the whole document.
+async fn fetch_jwks(client: &reqwest::Client, url: &str) -> Result<Vec<Jwk>, String> {
+    #[derive(Deserialize)]
+    struct RawJwks {
+        keys: Vec<serde_json::Value>,
+    }
+
+    let resp = client
+        .get(url)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| format!("JWKS request failed: {e}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("JWKS endpoint returned {status}"));
+    }
+    let raw: RawJwks = resp
+        .json()
+        .await
+        .map_err(|e| format!("invalid JWKS document: {e}"))?;
+    Ok(raw
+        .keys
+        .into_iter()
+        .filter_map(|k| serde_json::from_value(k).ok())
+        .collect())
+}
+
+/// Validate `id_token` for the downstream and return who it identifies.
+///
+/// `nonce` is the value sent with the authorization request; refreshed ID
+/// tokens are checked with `None` and may omit it (OIDC Core §12.2).
+pub async fn validate_id_token(
+    client: &reqwest::Client,
+    jwks: &JwksCache,
+    oauth: &OAuthConfig,
+    id_token: &str,
+    nonce: Option<&str>,
+) -> Result<UserIdentity, String> {
+    verify_id_token(client, jwks, oauth, id_token, nonce)
+        .await
+        .map(|token| token.user)
+}
+
+/// Like [`validate_id_token`], also returning the user's groups.
+pub async fn verify_id_token(
+    client: &reqwest::Client,
+    jwks: &JwksCache,
+    oauth: &OAuthConfig,
+    id_token: &str,
+    nonce: Option<&str>,
+) -> Result<IdToken, String> {
+    let issuer = oauth
+        .oauth_issuer
+        .as_deref()
+        .ok_or_else(|| "oauth_oidc requires oauth_issuer".to_string())?;
+    let jwks_url = oauth
+        .jwks_url()
+        .ok_or_else(|| "no JWKS URL configured or discovered".to_string())?;
+
+    let header =
+        jsonwebtoken::decode_header(id_token).map_err(|e| format!("malformed ID token: {e}"))?;
+    if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+        return Err(format!(
+            "ID token algorithm {:?} is not allowed",
+            header.alg
+        ));
+    }
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[&oauth.oauth_client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let candidates = jwks.keys(client, &jwks_url, header.kid.as_deref()).await?;
+    if candidates.is_empty() {
+        return Err(format!(
+            "no signing key {} in the provider's JWKS",
+            header.kid.as_deref().unwrap_or("(no kid)")
+        ));
+    }
+
+    let mut last_error = String::new();
+    let claims = candidates
+        .iter()
+        .find_map(|jwk| {
+            let key = DecodingKey::from_jwk(jwk)
+                .map_err(|e| last_error = format!("unusable JWKS key: {e}"))
+                .ok()?;
+            jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+                .map_err(|e| last_error = format!("ID token rejected: {e}"))
+                .ok()
+        })
+        .ok_or(last_error)?
+        .claims;
+
+    // With several audiences, the token must have been issued to us.
+    if claims.aud.as_array().is_some_and(|aud| aud.len() > 1)
+        && claims.azp.as_deref() != Some(oauth.oauth_client_id.as_str())
+    {
+        return Err("ID token azp does not name this client".to_string());
+    }
+
+    match (nonce, claims.nonce.as_deref()) {
+        (Some(expected), Some(actual)) if expected == actual => {}
+        (Some(_), _) => return Err("ID token nonce does not match".to_string()),
+        (None, _) => {}
+    }
+
+    let verified = email_verified(claims.email_verified.as_ref());
+
+    let groups = match claims.groups {
+        Some(serde_json::Value::Array(groups)) => groups
+            .into_iter()
+            .filter_map(|g| g.as_str().map(String::from))
+            .collect(),
+        Some(serde_json::Value::String(group)) => vec![group],
+        _ => Vec::new(),
+    };
+
+    Ok(IdToken {
+        user: UserIdentity {
+            sub: claims.sub,
+            email: claims.email.filter(|_| verified),
+        },
+        groups,
+    })
+}
+
+/// Whether the `email_verified` claim vouches for the email. Only `true`
+/// (or the string `"true"` some providers send) does; a missing claim does not.
+fn email_verified(claim: Option<&serde_json::Value>) -> bool {
+    match claim {
+        Some(serde_json::Value::Bool(verified)) => *verified,
+        Some(serde_json::Value::String(s)) => s == "true",
+        _ => false,
+    }
+}
+
+/// The scope to request: `oauth_scopes`, plus `openid` when OIDC is enabled.
+pub fn request_scope(oauth: &OAuthConfig) -> Option<String> {
+    if !oauth.oauth_oidc {
+        return oauth.oauth_scopes.clone();
+    }
+    Some(openid_scope(oauth.oauth_scopes.as_deref()))
+}
+
+/// `scopes` with `openid` added unless already present.
+pub fn openid_scope(scopes: Option<&str>) -> String {
+    let scopes = scopes.unwrap_or_default();
+    if scopes.split_whitespace().any(|s| s == "openid") {
+        return scopes.to_string();
+    }
+    format!("openid {scopes}").trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwk(kid: Option<&str>, use_: &str) -> Jwk {
+        let mut value = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+            "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+            "use": use_,
+        });
+        if let Some(kid) = kid {
+            value["kid"] = serde_json::json!(kid);
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_matching_keys() {
+        let keys = [
+            jwk(Some("a"), "sig"),
+            jwk(Some("b"), "sig"),
+            jwk(Some("c"), "enc"),
+        ];
+        let ids = |kid| {
+            matching(&keys, kid)
+                .into_iter()
+                .map(|k| k.common.key_id.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(Some("b")), ["b"]);
+        assert!(ids(Some("c")).is_empty());
+        assert!(ids(Some("z")).is_empty());
+        assert_eq!(ids(None), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_email_verified() {
+        use serde_json::json;
+
+        assert!(email_verified(Some(&json!(true))));
+        assert!(email_verified(Some(&json!("true"))));
+        assert!(!email_verified(None));
+        assert!(!email_verified(Some(&json!(false))));
+        assert!(!email_verified(Some(&json!("false"))));
+        assert!(!email_verified(Some(&json!(1))));
+    }
+}
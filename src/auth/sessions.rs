@@ -0,0 +1,246 @@
+//! Latest upstream tokens for proxy-managed chained OAuth grants.
+//!
+//! With `oauth_refresh_mode = "proxy"` the upstream tokens travel inside the
+//! proxy's own encrypted access and refresh tokens, but a provider that
+//! rotates refresh tokens invalidates the copy embedded in tokens the client
+//! already holds. This cache remembers the newest upstream tokens per grant ID
+//! so that whichever path refreshes first (the MCP proxy or `/token`) leaves
+//! the other one a usable refresh token. It also serializes refreshes per
+//! grant, so concurrent requests on one instance trigger a single upstream
+//! call.
+//!
+//! With `oauth_refresh_mode = "client"` the client holds the provider's own
+//! refresh token, which says nothing about whom it was issued to. The cache
+//! also remembers the [`GrantBinding`] behind each such token, so a refresh
+//! keeps the user and client of the original grant.
+//!
+//! Entries live in the configured [`Store`], sealed with AES-256-GCM
+//! ([`KeyPurpose::UpstreamSession`]) and dropped after the grant's refresh
+//! token lifetime. A rotated refresh token therefore survives a restart, and
+//! with a shared backend every replica sees it. If the store cannot be read,
+//! the proxy falls back to the refresh token embedded in the client's token.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::oauth::codes::{self, now_secs, GrantBinding};
+use crate::oauth::keys::{KeyPurpose, Keyring};
+use crate::storage::Store;
+
+/// Upstream tokens for one grant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Absolute expiry of `access_token`, if the provider reported one.
+    pub expires_at: Option<u64>,
+    /// Scope granted to the grant, if the provider reported one.
+    pub scope: Option<String>,
+}
+
+impl UpstreamTokens {
+    /// Whether the access token is still usable for at least `skew` seconds.
+    pub fn is_fresh(&self, skew: u64) -> bool {
+        match self.expires_at {
+            Some(exp) => now_secs().map(|now| now + skew < exp).unwrap_or(false),
+            None => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    /// The key the entry was stored under, so a copy under another key in
+    /// the backend is rejected.
+    key: String,
+    value: T,
+}
+
+fn key(id: &str) -> String {
+    format!("upstream:{id}")
+}
+
+fn binding_key(refresh_token: &str) -> String {
+    format!(
+        "grant:{}",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(refresh_token.as_bytes()))
+    )
+}
+
+pub struct UpstreamSessions {
+    store: Arc<dyn Store>,
+    keys: Arc<Keyring>,
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl UpstreamSessions {
+    pub fn new(store: Arc<dyn Store>, keys: Arc<Keyring>) -> Self {
+        Self {
+            store,
+            keys,
+            locks: Mutex::default(),
+        }
+    }
+
+    /// Hold this guard while refreshing a grant so concurrent callers wait for
+    /// the first refresh instead of racing it with a soon-to-be-stale token.
+    pub async fn lock(&self, grant_id: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|_, l| Arc::strong_count(l) > 1);
+            locks.entry(grant_id.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    pub async fn get(&self, grant_id: &str) -> Option<UpstreamTokens> {
+        self.load(&key(grant_id)).await
+    }
+
+    pub async fn remove(&self, grant_id: &str) -> Option<UpstreamTokens> {
+        let tokens = self.get(grant_id).await;
+        if let Err(e) = self.store.delete(&key(grant_id)).await {
+            tracing::warn!(error = %e, "Failed to delete upstream session");
+        }
+        tokens
+    }
+
+    /// Remember `tokens` until `until`. A failure is logged rather than
+    /// returned: the tokens are still good for the current request.
+    pub async fn put(&self, grant_id: &str, tokens: UpstreamTokens, until: u64) {
+        self.save(&key(grant_id), &tokens, until).await;
+    }
+
+    /// The grant a client-mode upstream refresh token was issued under.
+    pub async fn binding(&self, refresh_token: &str) -> Option<GrantBinding> {
+        self.load(&binding_key(refresh_token)).await
+    }
+
+    /// Remember that `refresh_token` was issued under `binding`, until `until`.
+    pub async fn put_binding(&self, refresh_token: &str, binding: &GrantBinding, until: u64) {
+        self.save(&binding_key(refresh_token), binding, until).await;
+    }
+
+    async fn load<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let bytes = match self.store.get(key).await {
+            Ok(bytes) => bytes?,
+            Err(e) => {
+                tracing::warn!(error = %e, "Upstream session lookup failed");
+                return None;
+            }
+        };
+        let sealed = String::from_utf8_lossy(&bytes);
+        let entry: Entry<T> =
+            match codes::decrypt_payload(&sealed, KeyPurpose::UpstreamSession, &self.keys) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    tracing::warn!(error = ?e, "Unreadable upstream session");
+                    return None;
+                }
+            };
+        (entry.key == key).then_some(entry.value)
+    }
+
+    async fn save<T: Serialize>(&self, key: &str, value: &T, until: u64) {
+        let entry = Entry {
+            key: key.to_string(),
+            value,
+        };
+        let stored = match codes::encrypt_payload(&entry, KeyPurpose::UpstreamSession, &self.keys) {
+            Ok(sealed) => self.store.set(key, sealed.as_bytes(), Some(until)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            tracing::error!(error = %e, "Failed to store upstream session");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    fn sessions(store: Arc<dyn Store>) -> UpstreamSessions {
+        UpstreamSessions::new(store, Arc::new(Keyring::single(&[1; 32])))
+    }
+
+    fn tokens(access: &str, expires_at: Option<u64>) -> UpstreamTokens {
+        UpstreamTokens {
+            access_token: access.to_string(),
+            refresh_token: Some(format!("{access}-refresh")),
+            expires_at,
+            scope: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_get_and_expiry() {
+        let sessions = sessions(Arc::new(MemoryStore::default()));
+        let now = now_secs().unwrap();
+
+        sessions.put("g1", tokens("a1", None), now + 60).await;
+        sessions.put("g2", tokens("a2", None), 1).await;
+
+        assert_eq!(sessions.get("g1").await.unwrap().access_token, "a1");
+        assert!(sessions.get("g2").await.is_none());
+        assert!(sessions.get("missing").await.is_none());
+
+        assert_eq!(sessions.remove("g1").await.unwrap().access_token, "a1");
+        assert!(sessions.get("g1").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_sealed_and_bound_to_their_id() {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::default());
+        let sessions = sessions(store.clone());
+        let later = now_secs().unwrap() + 60;
+        sessions.put("g1", tokens("a1", None), later).await;
+
+        let sealed = store.get("upstream:g1").await.unwrap().unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("a1-refresh"));
+
+        store
+            .set("upstream:g2", &sealed, Some(later))
+            .await
+            .unwrap();
+        assert!(sessions.get("g2").await.is_none());
+
+        // Another keyring cannot open them.
+        let other = UpstreamSessions::new(store, Arc::new(Keyring::single(&[2; 32])));
+        assert!(other.get("g1").await.is_none());
+    }
+
+    #[test]
+    fn test_freshness_uses_skew() {
+        let now = now_secs().unwrap();
+        assert!(tokens("a", None).is_fresh(60));
+        assert!(tokens("a", Some(now + 600)).is_fresh(60));
+        assert!(!tokens("a", Some(now + 30)).is_fresh(60));
+    }
+
+    #[tokio::test]
+    async fn test_lock_serializes_per_grant() {
+        let sessions = Arc::new(sessions(Arc::new(MemoryStore::default())));
+        let guard = sessions.lock("g1").await;
+
+        // A different grant is not blocked.
+        drop(sessions.lock("g2").await);
+
+        let waiter = {
+            let sessions = sessions.clone();
+            tokio::spawn(async move { drop(sessions.lock("g1").await) })
+        };
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        waiter.await.unwrap();
+    }
+}
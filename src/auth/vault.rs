@@ -0,0 +1,187 @@
+//! Per-user credential vault for `strategy = "vault"` downstreams.
+//!
+//! A signed-in user's downstream credential is kept in the configured
+//! [`Store`], sealed with AES-256-GCM under a per-downstream key
+//! ([`KeyPurpose::Vault`]). Codes and tokens for vault grants carry only the
+//! user, and the MCP proxy looks the credential up on every request, so
+//! updating or deleting it takes effect immediately.
+//!
+//! Each entry also records whose it is, so an entry copied to another user's
+//! key in the backend is rejected. Entries sealed under an older
+//! `state_secrets` key are resealed under the current key when read; once that
+//! key is dropped, any entry not yet resealed reads as absent and the user is
+//! asked for the credential again.
+
+use serde::{Deserialize, Serialize};
+
+use crate::oauth::codes::{self, UserIdentity};
+use crate::oauth::keys::{KeyPurpose, Keyring};
+use crate::storage::Store;
+
+#[derive(Serialize, Deserialize)]
+struct VaultEntry {
+    sub: String,
+    credential: String,
+}
+
+fn key(downstream: &str, user: &UserIdentity) -> String {
+    format!("vault:{downstream}:{}", user.sub)
+}
+
+/// The user's stored credential for `downstream`, if any.
+pub async fn get(
+    store: &dyn Store,
+    keys: &Keyring,
+    downstream: &str,
+    user: &UserIdentity,
+) -> Result<Option<String>, String> {
+    let Some(bytes) = store.get(&key(downstream, user)).await? else {
+        return Ok(None);
+    };
+    let sealed = String::from_utf8_lossy(&bytes);
+    let entry: VaultEntry =
+        match codes::decrypt_payload(&sealed, KeyPurpose::Vault(downstream), keys) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!(downstream, user = %user.sub, error = ?e, "Unreadable vault entry");
+                return Ok(None);
+            }
+        };
+    if entry.sub != user.sub {
+        tracing::warn!(downstream, user = %user.sub, "Vault entry belongs to another user");
+        return Ok(None);
+    }
+
+    if !sealed.starts_with(&format!("{}.", keys.current().header())) {
+        put(store, keys, downstream, user, &entry.credential).await?;
+    }
+    Ok(Some(entry.credential))
+}
+
+/// Store `credential` as the user's credential for `downstream`, replacing
+/// any previous one.
+pub async fn put(
+    store: &dyn Store,
+    keys: &Keyring,
+    downstream: &str,
+    user: &UserIdentity,
+    credential: &str,
+) -> Result<(), String> {
+    let entry = VaultEntry {
+        sub: user.sub.clone(),
+        credential: credential.to_string(),
+    };
+    let sealed = codes::encrypt_payload(&entry, KeyPurpose::Vault(downstream), keys)?;
+    store
+        .set(&key(downstream, user), sealed.as_bytes(), None)
+        .await
+}
+
+/// Forget the user's credential for `downstream`.
+pub async fn delete(
+    store: &dyn Store,
+    downstream: &str,
+    user: &UserIdentity,
+) -> Result<(), String> {
+    store.delete(&key(downstream, user)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth::keys::Key;
+    use crate::storage::MemoryStore;
+
+    fn user(sub: &str) -> UserIdentity {
+        UserIdentity {
+            sub: sub.to_string(),
+            email: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_get_delete() {
+        let store = MemoryStore::default();
+        let keys = Keyring::single(&[1; 32]);
+        let ada = user("ada");
+
+        assert_eq!(get(&store, &keys, "linear", &ada).await.unwrap(), None);
+        put(&store, &keys, "linear", &ada, "lin_api_1")
+            .await
+            .unwrap();
+        put(&store, &keys, "linear", &ada, "lin_api_2")
+            .await
+            .unwrap();
+        assert_eq!(
+            get(&store, &keys, "linear", &ada).await.unwrap().as_deref(),
+            Some("lin_api_2")
+        );
+        assert_eq!(get(&store, &keys, "notion", &ada).await.unwrap(), None);
+        assert_eq!(
+            get(&store, &keys, "linear", &user("eve")).await.unwrap(),
+            None
+        );
+
+        delete(&store, "linear", &ada).await.unwrap();
+        assert_eq!(get(&store, &keys, "linear", &ada).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_bound_to_user_and_downstream() {
+        let store = MemoryStore::default();
+        let keys = Keyring::single(&[1; 32]);
+        put(&store, &keys, "linear", &user("ada"), "secret")
+            .await
+            .unwrap();
+        let sealed = store.get("vault:linear:ada").await.unwrap().unwrap();
+
+        store.set("vault:linear:eve", &sealed, None).await.unwrap();
+        assert_eq!(
+            get(&store, &keys, "linear", &user("eve")).await.unwrap(),
+            None
+        );
+
+        store.set("vault:notion:ada", &sealed, None).await.unwrap();
+        assert_eq!(
+            get(&store, &keys, "notion", &user("ada")).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reseal_after_rotation() {
+        let store = MemoryStore::default();
+        let old = Key {
+            id: "old".to_string(),
+            secret: vec![1; 32],
+        };
+        let new = Key {
+            id: "new".to_string(),
+            secret: vec![2; 32],
+        };
+        let ada = user("ada");
+        let before = Keyring::new(vec![old.clone()]).unwrap();
+        put(&store, &before, "linear", &ada, "secret")
+            .await
+            .unwrap();
+
+        let rotated = Keyring::new(vec![new.clone(), old]).unwrap();
+        assert_eq!(
+            get(&store, &rotated, "linear", &ada)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("secret")
+        );
+
+        // Resealed under the new key, so dropping the old one loses nothing.
+        let after = Keyring::new(vec![new]).unwrap();
+        assert_eq!(
+            get(&store, &after, "linear", &ada)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("secret")
+        );
+    }
+}
@@ -1,12 +1,19 @@
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::proxy::headers::HeaderTemplate;
+use crate::proxy::sigv4::{AwsCredentials, SigningScope};
 
 /// Top-level configuration parsed from TOML.
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
     pub downstream: HashMap<String, DownstreamConfig>,
 }
 
@@ -21,12 +28,74 @@ pub struct ServerConfig {
     /// Secret key used for HMAC-signing state parameters (chained OAuth)
     /// and AES-256-GCM encrypting stateless authorization codes.
     /// Stored as base64 in TOML, parsed into raw bytes during config loading.
-    #[serde(deserialize_with = "deserialize_base64_secret")]
-    pub state_secret: Vec<u8>,
+    /// Equivalent to a single `state_secrets` entry with ID `default`.
+    #[serde(default, deserialize_with = "deserialize_optional_base64_secret")]
+    pub state_secret: Option<Vec<u8>>,
+    /// Secrets with key IDs, for rotation. The first entry signs everything
+    /// new; all entries are accepted when verifying.
+    #[serde(default)]
+    pub state_secrets: Vec<StateSecretConfig>,
     /// TTL for encrypted authorization codes (seconds). The expiry is embedded
     /// inside the encrypted code itself — no server-side storage required.
     #[serde(default = "default_auth_code_ttl")]
     pub auth_code_ttl: u64,
+    /// Remember redeemed authorization codes until they expire so each can be
+    /// exchanged only once.
+    #[serde(default = "default_single_use_auth_codes")]
+    pub single_use_auth_codes: bool,
+    /// Maximum lifetime of proxy-issued access tokens (seconds). Chained OAuth
+    /// tokens are capped at the downstream token's own `expires_in`.
+    #[serde(default = "default_access_token_ttl")]
+    pub access_token_ttl: u64,
+    /// Lifetime of proxy-issued refresh tokens (seconds), used by chained OAuth
+    /// downstreams with `oauth_refresh_mode = "proxy"`.
+    #[serde(default = "default_refresh_token_ttl")]
+    pub refresh_token_ttl: u64,
+    /// Reject `/authorize` and `/token` requests whose `client_id` was not
+    /// issued by the dynamic client registration endpoint.
+    #[serde(default)]
+    pub require_client_registration: bool,
+    /// Redirect URIs of clients the operator trusts, such as Claude's own
+    /// callback. A signed-in user is sent straight back to them from an
+    /// SSO-gated downstream, without confirming on a page.
+    #[serde(default)]
+    pub trusted_redirect_uris: Vec<String>,
+    /// Bearer credential for the token introspection endpoint. Introspection
+    /// is disabled when unset.
+    pub introspection_secret: Option<String>,
+    /// How often provider metadata for `oauth_issuer` downstreams is fetched
+    /// again (seconds).
+    #[serde(default = "default_discovery_refresh_interval")]
+    pub discovery_refresh_interval: u64,
+    /// OpenID provider users must sign in with before the passthrough form.
+    pub login: Option<LoginConfig>,
+}
+
+/// `[server.login]`: single sign-on in front of passthrough downstreams.
+#[derive(Debug, Deserialize)]
+pub struct LoginConfig {
+    /// The provider, configured like a chained OAuth downstream. It must be an
+    /// OpenID provider with an `oauth_issuer`; `openid` is always requested.
+    #[serde(flatten)]
+    pub oauth: OAuthConfig,
+    /// How long a sign-in is remembered by the browser (seconds).
+    #[serde(default = "default_login_session_ttl")]
+    pub session_ttl: u64,
+}
+
+/// One entry of `server.state_secrets`.
+#[derive(Debug, Deserialize)]
+pub struct StateSecretConfig {
+    pub id: String,
+    #[serde(deserialize_with = "deserialize_base64_secret")]
+    pub secret: Vec<u8>,
+}
+
+fn deserialize_optional_base64_secret<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_base64_secret(deserializer).map(Some)
 }
 
 fn deserialize_base64_secret<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
@@ -50,6 +119,48 @@ fn default_auth_code_ttl() -> u64 {
     300
 }
 
+fn default_single_use_auth_codes() -> bool {
+    true
+}
+
+fn default_access_token_ttl() -> u64 {
+    30 * 24 * 3600
+}
+
+fn default_refresh_token_ttl() -> u64 {
+    90 * 24 * 3600
+}
+
+fn default_discovery_refresh_interval() -> u64 {
+    3600
+}
+
+fn default_login_session_ttl() -> u64 {
+    8 * 3600
+}
+
+/// Where shared state (revocations, used authorization codes) is kept,
+/// discriminated by the `backend` field in TOML.
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// Per-process memory; nothing is shared or persisted.
+    #[default]
+    Memory,
+    /// An SQLite database file, created if missing.
+    Sqlite { path: PathBuf },
+    /// A Redis server, shared by every replica.
+    Redis {
+        url: String,
+        #[serde(default = "default_redis_key_prefix")]
+        key_prefix: String,
+    },
+}
+
+fn default_redis_key_prefix() -> String {
+    "mcp-oauth-proxy:".to_string()
+}
+
 /// Configuration for a single downstream MCP server.
 #[derive(Debug, Deserialize)]
 pub struct DownstreamConfig {
@@ -58,6 +169,14 @@ pub struct DownstreamConfig {
     #[serde(default = "default_auth_header_format")]
     pub auth_header_format: String,
     pub scopes: Option<String>,
+    /// With `[server.login]`, restrict authorization to users whose verified
+    /// email is in one of these domains or who are in one of `allowed_groups`.
+    /// Any signed-in user may authorize when both are empty.
+    #[serde(default)]
+    pub allowed_email_domains: Vec<String>,
+    /// Groups, from the ID token's `groups` claim, allowed to authorize.
+    #[serde(default)]
+    pub allowed_groups: Vec<String>,
     #[serde(flatten)]
     pub strategy: StrategyConfig,
 }
@@ -65,27 +184,413 @@ pub struct DownstreamConfig {
 /// Strategy-specific configuration, discriminated by the `strategy` field in TOML.
 #[derive(Debug, Deserialize, Clone, PartialEq)]
 #[serde(tag = "strategy", rename_all = "snake_case")]
+// Parsed once at startup; boxing the variants would only complicate matching.
+#[allow(clippy::large_enum_variant)]
 pub enum StrategyConfig {
     Passthrough {
         auth_hint: Option<String>,
+        /// Fields the form asks for instead of a single token, such as a
+        /// username and password.
+        #[serde(default)]
+        credential_fields: Vec<CredentialField>,
+        /// The headers sent downstream, by name, built from
+        /// `credential_fields`; replaces `auth_header_format`.
+        #[serde(default)]
+        credential_headers: BTreeMap<String, HeaderTemplate>,
     },
     ChainedOauth {
         #[serde(flatten)]
         oauth: OAuthConfig,
     },
+    /// Like passthrough, but the credential is kept per signed-in user in the
+    /// storage backend and the form is only shown when none is stored.
+    /// Requires `[server.login]`.
+    Vault { auth_hint: Option<String> },
+    /// One team-wide credential, held only by the proxy. Users pass `gate`
+    /// to get a proxy token; the MCP proxy sends `credential` downstream.
+    StaticCredential {
+        /// Override with `MCP_PROXY_<NAME>_CREDENTIAL`.
+        #[serde(default)]
+        credential: String,
+        #[serde(default)]
+        gate: Gate,
+        /// The shared password for `gate = "password"`. Override with
+        /// `MCP_PROXY_<NAME>_GATE_PASSWORD`.
+        gate_password: Option<String>,
+        auth_hint: Option<String>,
+    },
+    /// A service token the proxy obtains with its own client credentials
+    /// (RFC 6749 §4.4), sent downstream for every user. Users pass `gate` as
+    /// with `static_credential`.
+    ClientCredentials {
+        #[serde(flatten)]
+        oauth: OAuthConfig,
+        #[serde(default)]
+        gate: Gate,
+        /// Override with `MCP_PROXY_<NAME>_GATE_PASSWORD`.
+        gate_password: Option<String>,
+        auth_hint: Option<String>,
+    },
+    /// A GitHub App installation token, obtained with an app JWT and sent
+    /// downstream for every user. `oauth_client_id` is the app's client ID;
+    /// with `gate = "github"` users sign in through the app's user-to-server
+    /// OAuth flow, configured by the other `oauth_*` fields.
+    GithubApp {
+        #[serde(flatten)]
+        oauth: OAuthConfig,
+        #[serde(flatten)]
+        app: GithubAppConfig,
+        #[serde(default)]
+        gate: Gate,
+        /// Override with `MCP_PROXY_<NAME>_GATE_PASSWORD`.
+        gate_password: Option<String>,
+        auth_hint: Option<String>,
+    },
+    /// A token the proxy obtains with a self-signed JWT assertion (RFC 7523
+    /// §2.1), such as a service account's, sent downstream for every user or,
+    /// with `jwt_impersonate`, per signed-in user. Users pass `gate` as with
+    /// `static_credential`.
+    JwtBearer {
+        #[serde(flatten)]
+        oauth: OAuthConfig,
+        #[serde(flatten)]
+        assertion: JwtBearerConfig,
+        #[serde(default)]
+        gate: Gate,
+        /// Override with `MCP_PROXY_<NAME>_GATE_PASSWORD`.
+        gate_password: Option<String>,
+        auth_hint: Option<String>,
+    },
+    /// Requests signed with AWS Signature Version 4, as API Gateway with IAM
+    /// authorization requires. With `aws_access_key_id` the proxy holds the
+    /// credentials and users pass `gate` as with `static_credential`;
+    /// otherwise users enter their own on the authorize form.
+    AwsSigv4 {
+        #[serde(flatten)]
+        aws: AwsSigv4Config,
+        /// Only with `aws_access_key_id`, where it defaults to `"sso"`.
+        gate: Option<Gate>,
+        /// Override with `MCP_PROXY_<NAME>_GATE_PASSWORD`.
+        gate_password: Option<String>,
+        auth_hint: Option<String>,
+    },
+}
+
+impl StrategyConfig {
+    /// The `strategy` value this was configured with.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StrategyConfig::Passthrough { .. } => "passthrough",
+            StrategyConfig::ChainedOauth { .. } => "chained_oauth",
+            StrategyConfig::Vault { .. } => "vault",
+            StrategyConfig::StaticCredential { .. } => "static_credential",
+            StrategyConfig::ClientCredentials { .. } => "client_credentials",
+            StrategyConfig::GithubApp { .. } => "github_app",
+            StrategyConfig::JwtBearer { .. } => "jwt_bearer",
+            StrategyConfig::AwsSigv4 { .. } => "aws_sigv4",
+        }
+    }
+
+    /// How users get a proxy token for a downstream whose credential the
+    /// proxy holds; `None` where users supply their own.
+    pub fn gate(&self) -> Option<Gate> {
+        match self {
+            StrategyConfig::StaticCredential { gate, .. }
+            | StrategyConfig::ClientCredentials { gate, .. }
+            | StrategyConfig::GithubApp { gate, .. }
+            | StrategyConfig::JwtBearer { gate, .. } => Some(*gate),
+            StrategyConfig::AwsSigv4 { aws, gate, .. } => aws
+                .aws_access_key_id
+                .as_ref()
+                .map(|_| gate.unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+/// How users prove they may use a downstream whose credential the proxy
+/// holds (`static_credential`, `client_credentials`, `github_app`,
+/// `jwt_bearer` and `aws_sigv4` with configured credentials).
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Gate {
+    /// Sign in through `[server.login]`.
+    #[default]
+    Sso,
+    /// Enter `gate_password` on the authorize page.
+    Password,
+    /// Sign in with GitHub through the app and have access to its
+    /// installation (`github_app` only).
+    Github,
+}
+
+/// The app installation of a `github_app` downstream.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GithubAppConfig {
+    pub github_installation_id: u64,
+    /// PEM private key the app JWTs are signed with (RS256).
+    pub github_private_key_path: PathBuf,
+    /// The key read from `github_private_key_path` by [`load_config`].
+    #[serde(skip)]
+    pub github_private_key: ClientKey,
+    /// REST API base URL; GitHub Enterprise Server uses
+    /// `https://<host>/api/v3`.
+    #[serde(default = "default_github_api_url")]
+    pub github_api_url: String,
 }
 
 #[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct OAuthConfig {
-    pub oauth_authorize_url: String,
-    pub oauth_token_url: String,
+    /// Provider issuer URL. When set, endpoints are discovered from its
+    /// metadata document; explicitly configured URLs take precedence.
+    pub oauth_issuer: Option<String>,
+    pub oauth_authorize_url: Option<String>,
+    pub oauth_token_url: Option<String>,
     pub oauth_client_id: String,
+    /// Required by the `client_secret_*` authentication methods.
+    #[serde(default)]
     pub oauth_client_secret: String,
+    /// How the proxy authenticates to the provider's token and revocation
+    /// endpoints.
+    #[serde(default)]
+    pub oauth_client_auth_method: ClientAuthMethod,
+    /// PEM private key for `private_key_jwt`.
+    pub oauth_client_key_path: Option<PathBuf>,
+    #[serde(default)]
+    pub oauth_client_key_alg: ClientKeyAlg,
+    /// `kid` header of `private_key_jwt` assertions, for providers that hold
+    /// more than one key for the client.
+    pub oauth_client_key_id: Option<String>,
+    /// The key read from `oauth_client_key_path` by [`load_config`].
+    #[serde(skip)]
+    pub client_key: ClientKey,
     pub oauth_scopes: Option<String>,
+    /// Request `openid` and validate the provider's ID token, recording the
+    /// user it identifies in issued codes and tokens. Requires `oauth_issuer`.
+    #[serde(default)]
+    pub oauth_oidc: bool,
+    /// What to do when the provider grants fewer scopes than `oauth_scopes`.
+    #[serde(default)]
+    pub oauth_scope_policy: ScopePolicy,
     #[serde(default)]
     pub oauth_supports_refresh: bool,
     #[serde(default = "default_oauth_token_accept")]
     pub oauth_token_accept: String,
+    #[serde(default)]
+    pub oauth_refresh_mode: RefreshMode,
+    /// Provider's RFC 7009 revocation endpoint. When set, `/revoke` forwards
+    /// revocation of upstream tokens to it.
+    pub oauth_revocation_url: Option<String>,
+    pub oauth_userinfo_url: Option<String>,
+    pub oauth_jwks_url: Option<String>,
+    /// RFC 8693 security token service. When set, the provider's token is
+    /// exchanged here after authorization and every refresh, and the
+    /// exchanged token is what the downstream receives.
+    pub oauth_token_exchange_url: Option<String>,
+    /// `audience` of the exchange request.
+    pub oauth_token_exchange_audience: Option<String>,
+    /// `scope` of the exchange request.
+    pub oauth_token_exchange_scopes: Option<String>,
+    /// Which of the provider's tokens is exchanged.
+    #[serde(default)]
+    pub oauth_token_exchange_subject: SubjectTokenType,
+    /// The proxy's client at the STS. Without it, exchange requests carry no
+    /// client authentication.
+    pub oauth_token_exchange_client_id: Option<String>,
+    #[serde(default)]
+    pub oauth_token_exchange_client_secret: String,
+    /// `client_secret_post` unless set; `private_key_jwt` is not supported.
+    pub oauth_token_exchange_client_auth_method: Option<ClientAuthMethod>,
+    /// Metadata discovered from `oauth_issuer`, filled in by [`load_config`]
+    /// and kept current in the background.
+    #[serde(skip)]
+    pub discovered: DiscoveredMetadata,
+}
+
+impl OAuthConfig {
+    /// The provider's authorization endpoint, configured or discovered.
+    pub fn authorize_url(&self) -> Option<String> {
+        self.endpoint(&self.oauth_authorize_url, |m| &m.authorization_endpoint)
+    }
+
+    pub fn token_url(&self) -> Option<String> {
+        self.endpoint(&self.oauth_token_url, |m| &m.token_endpoint)
+    }
+
+    pub fn revocation_url(&self) -> Option<String> {
+        self.endpoint(&self.oauth_revocation_url, |m| &m.revocation_endpoint)
+    }
+
+    pub fn userinfo_url(&self) -> Option<String> {
+        self.endpoint(&self.oauth_userinfo_url, |m| &m.userinfo_endpoint)
+    }
+
+    pub fn jwks_url(&self) -> Option<String> {
+        self.endpoint(&self.oauth_jwks_url, |m| &m.jwks_uri)
+    }
+
+    fn endpoint(
+        &self,
+        configured: &Option<String>,
+        discovered: impl Fn(&ProviderMetadata) -> &Option<String>,
+    ) -> Option<String> {
+        configured
+            .clone()
+            .or_else(|| self.discovered.get().and_then(|m| discovered(&m).clone()))
+    }
+}
+
+/// The parts of an OpenID Connect / RFC 8414 provider metadata document the
+/// proxy uses.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: Option<String>,
+}
+
+/// Latest discovered provider metadata, shared between the config and the
+/// refresh task.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveredMetadata(Arc<RwLock<Option<ProviderMetadata>>>);
+
+impl DiscoveredMetadata {
+    pub fn get(&self) -> Option<ProviderMetadata> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, metadata: ProviderMetadata) {
+        *self.0.write().unwrap() = Some(metadata);
+    }
+}
+
+impl PartialEq for DiscoveredMetadata {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+/// Client authentication towards the provider (RFC 6749 §2.3, OIDC Core §9).
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuthMethod {
+    /// `client_id` and `client_secret` in the form body.
+    #[default]
+    ClientSecretPost,
+    /// `client_id` and `client_secret` in an HTTP Basic `Authorization` header.
+    ClientSecretBasic,
+    /// A JWT assertion signed with `oauth_client_key_path` (RFC 7523).
+    PrivateKeyJwt,
+    /// Only `client_id`, for public clients.
+    None,
+}
+
+/// Signing algorithm for `private_key_jwt` and `jwt_bearer` assertions.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum ClientKeyAlg {
+    #[default]
+    RS256,
+    ES256,
+}
+
+impl ClientKeyAlg {
+    pub fn algorithm(self) -> jsonwebtoken::Algorithm {
+        match self {
+            ClientKeyAlg::RS256 => jsonwebtoken::Algorithm::RS256,
+            ClientKeyAlg::ES256 => jsonwebtoken::Algorithm::ES256,
+        }
+    }
+}
+
+/// Private key for `private_key_jwt`, GitHub App JWTs or `jwt_bearer`
+/// assertions, loaded at startup.
+#[derive(Clone, Default)]
+pub struct ClientKey(Option<Arc<jsonwebtoken::EncodingKey>>);
+
+impl ClientKey {
+    /// Read a PEM key for `alg`. EC keys must be PKCS#8
+    /// (`BEGIN PRIVATE KEY`); RSA keys may also be PKCS#1.
+    pub fn load(path: &Path, alg: ClientKeyAlg) -> Result<Self, String> {
+        let pem = std::fs::read(path)
+            .map_err(|e| format!("Failed to read client key '{}': {e}", path.display()))?;
+        let key = match alg {
+            ClientKeyAlg::RS256 => jsonwebtoken::EncodingKey::from_rsa_pem(&pem),
+            ClientKeyAlg::ES256 => jsonwebtoken::EncodingKey::from_ec_pem(&pem),
+        }
+        .map_err(|e| format!("Invalid {alg:?} client key '{}': {e}", path.display()))?;
+        Ok(Self(Some(Arc::new(key))))
+    }
+
+    pub fn get(&self) -> Option<&jsonwebtoken::EncodingKey> {
+        self.0.as_deref()
+    }
+}
+
+impl std::fmt::Debug for ClientKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("ClientKey(<redacted>)"),
+            None => f.write_str("ClientKey(None)"),
+        }
+    }
+}
+
+impl PartialEq for ClientKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Handling of token responses that grant fewer scopes than configured.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScopePolicy {
+    /// Log a warning and carry on with the narrower grant.
+    #[default]
+    Warn,
+    /// Fail the authorization or refresh.
+    Reject,
+}
+
+/// The provider token presented as the `subject_token` of a token exchange.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectTokenType {
+    #[default]
+    AccessToken,
+    /// Requires `oauth_oidc`.
+    IdToken,
+}
+
+impl SubjectTokenType {
+    /// The RFC 8693 §3 token type identifier.
+    pub fn urn(self) -> &'static str {
+        match self {
+            SubjectTokenType::AccessToken => "urn:ietf:params:oauth:token-type:access_token",
+            SubjectTokenType::IdToken => "urn:ietf:params:oauth:token-type:id_token",
+        }
+    }
+}
+
+/// Who holds the upstream refresh token for a chained OAuth downstream.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshMode {
+    /// The client receives the upstream refresh token and `/token` forwards
+    /// refresh requests to the provider as-is.
+    #[default]
+    Client,
+    /// The proxy wraps the upstream refresh token in its own encrypted refresh
+    /// token and refreshes against the provider itself, including on the MCP
+    /// path when the upstream access token expires.
+    Proxy,
 }
 
 fn default_auth_header_format() -> String {
@@ -96,8 +601,120 @@ fn default_oauth_token_accept() -> String {
     "application/json".to_string()
 }
 
-/// Load and validate config from a TOML file, applying environment variable overrides.
-pub fn load_config(path: &Path) -> Result<Config, String> {
+/// The assertion of a `jwt_bearer` downstream.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct JwtBearerConfig {
+    /// `iss` claim; defaults to `oauth_client_id`.
+    pub jwt_issuer: Option<String>,
+    /// `sub` claim; defaults to the issuer.
+    pub jwt_subject: Option<String>,
+    /// `aud` claim; defaults to the token endpoint URL.
+    pub jwt_audience: Option<String>,
+    /// Send `oauth_scopes` as a `scope` claim of the assertion rather than a
+    /// request parameter, as Google expects.
+    #[serde(default)]
+    pub jwt_scope_claim: bool,
+    /// PEM private key the assertions are signed with.
+    pub jwt_key_path: PathBuf,
+    #[serde(default)]
+    pub jwt_key_alg: ClientKeyAlg,
+    /// `kid` header of the assertions.
+    pub jwt_key_id: Option<String>,
+    /// The key read from `jwt_key_path` by [`load_config`].
+    #[serde(skip)]
+    pub jwt_key: ClientKey,
+    /// Make the signed-in user the `sub`, fetching a token per user.
+    /// Requires `gate = "sso"`.
+    pub jwt_impersonate: Option<Impersonation>,
+}
+
+/// One field of a multi-field passthrough form.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct CredentialField {
+    /// Referenced as `{name}` in `credential_headers`.
+    pub name: String,
+    pub label: String,
+    #[serde(default, rename = "type")]
+    pub kind: FieldKind,
+    /// Help text shown below the input.
+    pub hint: Option<String>,
+    #[serde(default = "default_field_required")]
+    pub required: bool,
+    /// A regular expression the whole value must match.
+    pub regex: Option<String>,
+}
+
+fn default_field_required() -> bool {
+    true
+}
+
+/// How a credential field is shown on the form.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    /// Masked input, for secrets.
+    #[default]
+    Password,
+    /// Plain input, for usernames, workspace IDs and the like.
+    Text,
+}
+
+/// The signing scope and optional credentials of an `aws_sigv4` downstream.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct AwsSigv4Config {
+    pub aws_region: String,
+    /// Signing name of the service; API Gateway's is `execute-api`.
+    #[serde(default = "default_aws_service")]
+    pub aws_service: String,
+    /// Sign with these credentials rather than ones users enter.
+    pub aws_access_key_id: Option<String>,
+    /// Override with `MCP_PROXY_<NAME>_AWS_SECRET_ACCESS_KEY`.
+    #[serde(default)]
+    pub aws_secret_access_key: String,
+    /// For temporary credentials. Override with
+    /// `MCP_PROXY_<NAME>_AWS_SESSION_TOKEN`.
+    pub aws_session_token: Option<String>,
+}
+
+impl AwsSigv4Config {
+    /// The configured credentials, when the proxy holds them.
+    pub fn credentials(&self) -> Option<AwsCredentials> {
+        Some(AwsCredentials {
+            access_key_id: self.aws_access_key_id.clone()?,
+            secret_access_key: self.aws_secret_access_key.clone(),
+            session_token: self.aws_session_token.clone(),
+        })
+    }
+
+    pub fn scope(&self) -> SigningScope<'_> {
+        SigningScope {
+            region: &self.aws_region,
+            service: &self.aws_service,
+        }
+    }
+}
+
+fn default_aws_service() -> String {
+    "execute-api".to_string()
+}
+
+/// Which part of the signed-in user a `jwt_bearer` assertion impersonates.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Impersonation {
+    /// The verified email, e.g. for Google domain-wide delegation.
+    Email,
+    /// The login provider's subject identifier.
+    Sub,
+}
+
+fn default_github_api_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+/// Load and validate config from a TOML file, applying environment variable
+/// overrides and discovering endpoints for downstreams with `oauth_issuer`.
+pub async fn load_config(path: &Path) -> Result<Config, String> {
     let content = std::fs::read_to_string(path)
         .map_err(|e| format!("Failed to read config file '{}': {}", path.display(), e))?;
 
@@ -106,27 +723,181 @@ pub fn load_config(path: &Path) -> Result<Config, String> {
 
     apply_env_overrides(&mut config)?;
     validate(&config)?;
+    load_client_keys(&mut config)?;
+
+    let login_issuer = config
+        .server
+        .login
+        .as_ref()
+        .is_some_and(|login| login.oauth.oauth_issuer.is_some());
+    let downstream_issuer = config.downstream.values().any(|ds| {
+        matches!(
+            &ds.strategy,
+            StrategyConfig::ChainedOauth { oauth }
+            | StrategyConfig::ClientCredentials { oauth, .. }
+            | StrategyConfig::JwtBearer { oauth, .. }
+                if oauth.oauth_issuer.is_some()
+        )
+    });
+    if login_issuer || downstream_issuer {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client for discovery: {e}"))?;
+        crate::auth::discovery::discover_all(&config, &client).await?;
+    }
 
     Ok(config)
 }
 
+/// Read the `private_key_jwt` signing keys of the login provider and the
+/// downstreams that talk to a token endpoint, the GitHub App private keys and
+/// the `jwt_bearer` assertion keys.
+fn load_client_keys(config: &mut Config) -> Result<(), String> {
+    if let Some(login) = &mut config.server.login {
+        load_client_key(&mut login.oauth).map_err(|e| format!("server.login: {e}"))?;
+    }
+    for (name, ds) in &mut config.downstream {
+        if let StrategyConfig::ChainedOauth { oauth }
+        | StrategyConfig::ClientCredentials { oauth, .. }
+        | StrategyConfig::GithubApp { oauth, .. }
+        | StrategyConfig::JwtBearer { oauth, .. } = &mut ds.strategy
+        {
+            load_client_key(oauth).map_err(|e| format!("downstream '{name}': {e}"))?;
+        }
+        match &mut ds.strategy {
+            StrategyConfig::GithubApp { app, .. } => {
+                app.github_private_key =
+                    ClientKey::load(&app.github_private_key_path, ClientKeyAlg::RS256)
+                        .map_err(|e| format!("downstream '{name}': {e}"))?;
+            }
+            StrategyConfig::JwtBearer { assertion, .. } => {
+                assertion.jwt_key = ClientKey::load(&assertion.jwt_key_path, assertion.jwt_key_alg)
+                    .map_err(|e| format!("downstream '{name}': {e}"))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn load_client_key(oauth: &mut OAuthConfig) -> Result<(), String> {
+    if oauth.oauth_client_auth_method != ClientAuthMethod::PrivateKeyJwt {
+        return Ok(());
+    }
+    if let Some(path) = &oauth.oauth_client_key_path {
+        oauth.client_key = ClientKey::load(path, oauth.oauth_client_key_alg)?;
+    }
+    Ok(())
+}
+
 /// Apply environment variable overrides.
 fn apply_env_overrides(config: &mut Config) -> Result<(), String> {
     if let Ok(val) = std::env::var("MCP_PROXY_STATE_SECRET") {
-        config.server.state_secret =
+        config.server.state_secret = Some(
             base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &val)
-                .map_err(|e| format!("MCP_PROXY_STATE_SECRET is not valid base64: {e}"))?;
+                .map_err(|e| format!("MCP_PROXY_STATE_SECRET is not valid base64: {e}"))?,
+        );
+    }
+
+    // `id:base64,id:base64`, newest first. Replaces both secret settings.
+    if let Ok(val) = std::env::var("MCP_PROXY_STATE_SECRETS") {
+        config.server.state_secret = None;
+        config.server.state_secrets = val
+            .split(',')
+            .map(|entry| {
+                let (id, secret) = entry.trim().split_once(':').ok_or_else(|| {
+                    "MCP_PROXY_STATE_SECRETS entries must look like id:base64secret".to_string()
+                })?;
+                let secret =
+                    base64::Engine::decode(&base64::engine::general_purpose::STANDARD, secret)
+                        .map_err(|e| {
+                            format!("MCP_PROXY_STATE_SECRETS key '{id}' is not valid base64: {e}")
+                        })?;
+                Ok(StateSecretConfig {
+                    id: id.to_string(),
+                    secret,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+    }
+
+    if let Ok(val) = std::env::var("MCP_PROXY_INTROSPECTION_SECRET") {
+        config.server.introspection_secret = Some(val);
+    }
+
+    if let Ok(val) = std::env::var("MCP_PROXY_LOGIN_CLIENT_SECRET") {
+        if let Some(login) = &mut config.server.login {
+            login.oauth.oauth_client_secret = val;
+        }
+    }
+
+    if let Ok(val) = std::env::var("MCP_PROXY_REDIS_URL") {
+        if let StorageConfig::Redis { url, .. } = &mut config.storage {
+            *url = val;
+        }
     }
 
     for (name, ds) in &mut config.downstream {
-        let env_name = format!(
-            "MCP_PROXY_{}_CLIENT_SECRET",
-            name.to_uppercase().replace('-', "_")
-        );
-        if let Ok(val) = std::env::var(&env_name) {
-            if let StrategyConfig::ChainedOauth { oauth } = &mut ds.strategy {
-                oauth.oauth_client_secret = val;
+        let env_prefix = format!("MCP_PROXY_{}", name.to_uppercase().replace('-', "_"));
+        match &mut ds.strategy {
+            StrategyConfig::ChainedOauth { oauth } => {
+                if let Ok(val) = std::env::var(format!("{env_prefix}_CLIENT_SECRET")) {
+                    oauth.oauth_client_secret = val;
+                }
+                if let Ok(val) = std::env::var(format!("{env_prefix}_TOKEN_EXCHANGE_CLIENT_SECRET"))
+                {
+                    oauth.oauth_token_exchange_client_secret = val;
+                }
+            }
+            StrategyConfig::StaticCredential {
+                credential,
+                gate_password,
+                ..
+            } => {
+                if let Ok(val) = std::env::var(format!("{env_prefix}_CREDENTIAL")) {
+                    *credential = val;
+                }
+                if let Ok(val) = std::env::var(format!("{env_prefix}_GATE_PASSWORD")) {
+                    *gate_password = Some(val);
+                }
+            }
+            StrategyConfig::ClientCredentials {
+                oauth,
+                gate_password,
+                ..
             }
+            | StrategyConfig::GithubApp {
+                oauth,
+                gate_password,
+                ..
+            }
+            | StrategyConfig::JwtBearer {
+                oauth,
+                gate_password,
+                ..
+            } => {
+                if let Ok(val) = std::env::var(format!("{env_prefix}_CLIENT_SECRET")) {
+                    oauth.oauth_client_secret = val;
+                }
+                if let Ok(val) = std::env::var(format!("{env_prefix}_GATE_PASSWORD")) {
+                    *gate_password = Some(val);
+                }
+            }
+            StrategyConfig::AwsSigv4 {
+                aws, gate_password, ..
+            } => {
+                if let Ok(val) = std::env::var(format!("{env_prefix}_AWS_SECRET_ACCESS_KEY")) {
+                    aws.aws_secret_access_key = val;
+                }
+                if let Ok(val) = std::env::var(format!("{env_prefix}_AWS_SESSION_TOKEN")) {
+                    aws.aws_session_token = Some(val);
+                }
+                if let Ok(val) = std::env::var(format!("{env_prefix}_GATE_PASSWORD")) {
+                    *gate_password = Some(val);
+                }
+            }
+            StrategyConfig::Passthrough { .. } | StrategyConfig::Vault { .. } => {}
         }
     }
 
@@ -136,7 +907,54 @@ fn apply_env_overrides(config: &mut Config) -> Result<(), String> {
 /// Validate the entire configuration. Returns an error string on failure.
 fn validate(config: &Config) -> Result<(), String> {
     validate_server(&config.server)?;
+    validate_storage(&config.storage)?;
     validate_downstreams(&config.downstream)?;
+
+    for (name, ds) in &config.downstream {
+        if ds.allowed_email_domains.is_empty() && ds.allowed_groups.is_empty() {
+            continue;
+        }
+        if config.server.login.is_none() {
+            return Err(format!(
+                "downstream '{name}': allowed_email_domains and allowed_groups require [server.login]"
+            ));
+        }
+        if !matches!(
+            ds.strategy,
+            StrategyConfig::Passthrough { .. }
+                | StrategyConfig::Vault { .. }
+                | StrategyConfig::AwsSigv4 { gate: None, .. }
+        ) && ds.strategy.gate() != Some(Gate::Sso)
+        {
+            return Err(format!(
+                "downstream '{name}': allowed_email_domains and allowed_groups only apply to passthrough, vault and SSO-gated downstreams"
+            ));
+        }
+    }
+
+    for (name, ds) in &config.downstream {
+        match &ds.strategy {
+            StrategyConfig::Vault { .. } => {
+                if config.server.login.is_none() {
+                    return Err(format!(
+                        "downstream '{name}': the vault strategy requires [server.login]"
+                    ));
+                }
+                if matches!(config.storage, StorageConfig::Memory) {
+                    tracing::warn!(
+                        downstream = %name,
+                        "Vault credentials are kept in memory and lost on restart; configure [storage]"
+                    );
+                }
+            }
+            _ if ds.strategy.gate() == Some(Gate::Sso) && config.server.login.is_none() => {
+                return Err(format!(
+                    "downstream '{name}': gate = \"sso\" requires [server.login]"
+                ));
+            }
+            _ => {}
+        }
+    }
     Ok(())
 }
 
@@ -159,16 +977,108 @@ fn validate_server(server: &ServerConfig) -> Result<(), String> {
         );
     }
 
-    if server.state_secret.len() < 32 {
-        return Err(format!(
-            "server.state_secret must be at least 32 bytes (got {} bytes). Generate with: openssl rand -base64 32",
-            server.state_secret.len()
-        ));
+    validate_state_secrets(server)?;
+
+    if server.access_token_ttl == 0 {
+        return Err("server.access_token_ttl must be greater than 0".to_string());
+    }
+
+    if server.refresh_token_ttl == 0 {
+        return Err("server.refresh_token_ttl must be greater than 0".to_string());
+    }
+
+    if server.discovery_refresh_interval == 0 {
+        return Err("server.discovery_refresh_interval must be greater than 0".to_string());
+    }
+
+    if let Some(secret) = &server.introspection_secret {
+        if secret.len() < 16 {
+            return Err("server.introspection_secret must be at least 16 characters".to_string());
+        }
+    }
+
+    if let Some(login) = &server.login {
+        if login.oauth.oauth_issuer.is_none() {
+            return Err("server.login: oauth_issuer is required".to_string());
+        }
+        validate_oauth_client("server.login", &login.oauth, true)?;
+        validate_token_exchange("server.login", &login.oauth, false)?;
+        if login.session_ttl == 0 {
+            return Err("server.login: session_ttl must be greater than 0".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_state_secrets(server: &ServerConfig) -> Result<(), String> {
+    let secrets: Vec<(String, &[u8])> = match (&server.state_secret, &server.state_secrets[..]) {
+        (Some(_), [_, ..]) => {
+            return Err(
+                "server.state_secret and server.state_secrets are mutually exclusive".to_string(),
+            );
+        }
+        (Some(secret), []) => vec![("server.state_secret".to_string(), secret)],
+        (None, []) => {
+            return Err(
+                "server.state_secret is required. Generate with: openssl rand -base64 32"
+                    .to_string(),
+            );
+        }
+        (None, entries) => entries
+            .iter()
+            .map(|k| {
+                (
+                    format!("server.state_secrets key '{}'", k.id),
+                    k.secret.as_slice(),
+                )
+            })
+            .collect(),
+    };
+
+    let id_regex = regex_lite::Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+    let mut seen = std::collections::HashSet::new();
+    for k in &server.state_secrets {
+        if !id_regex.is_match(&k.id) {
+            return Err(format!(
+                "server.state_secrets: key id '{}' must match ^[A-Za-z0-9_-]+$",
+                k.id
+            ));
+        }
+        if !seen.insert(k.id.as_str()) {
+            return Err(format!("server.state_secrets: duplicate key id '{}'", k.id));
+        }
+    }
+
+    for (name, secret) in secrets {
+        if secret.len() < 32 {
+            return Err(format!(
+                "{name} must be at least 32 bytes (got {} bytes). Generate with: openssl rand -base64 32",
+                secret.len()
+            ));
+        }
     }
 
     Ok(())
 }
 
+fn validate_storage(storage: &StorageConfig) -> Result<(), String> {
+    match storage {
+        StorageConfig::Memory => {}
+        StorageConfig::Sqlite { path } => {
+            if path.as_os_str().is_empty() {
+                return Err("storage.path is required for the sqlite backend".to_string());
+            }
+        }
+        StorageConfig::Redis { url, .. } => {
+            if !url.starts_with("redis://") && !url.starts_with("rediss://") {
+                return Err("storage.url must be a redis:// or rediss:// URL".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
 fn validate_downstreams(downstreams: &HashMap<String, DownstreamConfig>) -> Result<(), String> {
     if downstreams.is_empty() {
         return Err("At least one [downstream.*] entry is required".to_string());
@@ -198,13 +1108,182 @@ fn validate_downstreams(downstreams: &HashMap<String, DownstreamConfig>) -> Resu
             ));
         }
 
+        if let StrategyConfig::Passthrough {
+            credential_fields,
+            credential_headers,
+            ..
+        } = &ds.strategy
+        {
+            validate_credential_fields(name, credential_fields, credential_headers)?;
+        }
+
         if let StrategyConfig::ChainedOauth { oauth } = &ds.strategy {
-            if oauth.oauth_client_secret.is_empty() {
+            validate_oauth_client(&format!("downstream '{name}'"), oauth, true)?;
+            if oauth.oauth_oidc && oauth.oauth_issuer.is_none() {
                 return Err(format!(
-                    "downstream '{}': oauth_client_secret must not be empty",
+                    "downstream '{}': oauth_oidc = true requires oauth_issuer",
                     name
                 ));
             }
+            validate_token_exchange(&format!("downstream '{name}'"), oauth, true)?;
+            if oauth.oauth_refresh_mode == RefreshMode::Proxy && !oauth.oauth_supports_refresh {
+                return Err(format!(
+                    "downstream '{}': oauth_refresh_mode = \"proxy\" requires oauth_supports_refresh = true",
+                    name
+                ));
+            }
+        }
+
+        if let StrategyConfig::StaticCredential { credential, .. } = &ds.strategy {
+            if credential.is_empty() {
+                return Err(format!(
+                    "downstream '{name}': credential is required (or set MCP_PROXY_{}_CREDENTIAL)",
+                    name.to_uppercase().replace('-', "_")
+                ));
+            }
+        }
+
+        if let StrategyConfig::ClientCredentials { oauth, .. } = &ds.strategy {
+            validate_oauth_client(&format!("downstream '{name}'"), oauth, false)?;
+            validate_token_exchange(&format!("downstream '{name}'"), oauth, false)?;
+            if oauth.oauth_client_auth_method == ClientAuthMethod::None {
+                return Err(format!(
+                    "downstream '{name}': the client_credentials strategy requires client authentication"
+                ));
+            }
+            if oauth.oauth_oidc {
+                return Err(format!(
+                    "downstream '{name}': oauth_oidc does not apply to the client_credentials strategy"
+                ));
+            }
+        }
+
+        if let StrategyConfig::GithubApp {
+            oauth, app, gate, ..
+        } = &ds.strategy
+        {
+            if oauth.oauth_issuer.is_some() {
+                return Err(format!(
+                    "downstream '{name}': GitHub publishes no OAuth metadata; set oauth_authorize_url and oauth_token_url instead of oauth_issuer"
+                ));
+            }
+            if *gate == Gate::Github {
+                validate_oauth_client(&format!("downstream '{name}'"), oauth, true)?;
+            }
+            validate_token_exchange(&format!("downstream '{name}'"), oauth, false)?;
+            if oauth.oauth_oidc {
+                return Err(format!(
+                    "downstream '{name}': oauth_oidc does not apply to the github_app strategy"
+                ));
+            }
+            if !app.github_api_url.starts_with("https://")
+                && !app.github_api_url.starts_with("http://")
+            {
+                return Err(format!(
+                    "downstream '{name}': github_api_url must be an http(s) URL"
+                ));
+            }
+        } else if ds.strategy.gate() == Some(Gate::Github) {
+            return Err(format!(
+                "downstream '{name}': gate = \"github\" only applies to github_app downstreams"
+            ));
+        }
+
+        if let StrategyConfig::JwtBearer {
+            oauth,
+            assertion,
+            gate,
+            ..
+        } = &ds.strategy
+        {
+            validate_oauth_client(&format!("downstream '{name}'"), oauth, false)?;
+            validate_token_exchange(&format!("downstream '{name}'"), oauth, false)?;
+            if oauth.oauth_oidc {
+                return Err(format!(
+                    "downstream '{name}': oauth_oidc does not apply to the jwt_bearer strategy"
+                ));
+            }
+            if assertion.jwt_impersonate.is_some() {
+                if *gate != Gate::Sso {
+                    return Err(format!(
+                        "downstream '{name}': jwt_impersonate requires gate = \"sso\""
+                    ));
+                }
+                if assertion.jwt_subject.is_some() {
+                    return Err(format!(
+                        "downstream '{name}': set jwt_subject or jwt_impersonate, not both"
+                    ));
+                }
+            }
+        }
+
+        if let StrategyConfig::AwsSigv4 {
+            aws,
+            gate,
+            gate_password,
+            ..
+        } = &ds.strategy
+        {
+            let region_chars = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-';
+            if aws.aws_region.is_empty() || !aws.aws_region.bytes().all(region_chars) {
+                return Err(format!(
+                    "downstream '{name}': aws_region must be a region name such as \"us-east-1\""
+                ));
+            }
+            if aws.aws_service.is_empty() {
+                return Err(format!(
+                    "downstream '{name}': aws_service must not be empty"
+                ));
+            }
+            if aws.aws_access_key_id.is_some() {
+                if aws.aws_secret_access_key.is_empty() {
+                    return Err(format!(
+                        "downstream '{name}': aws_access_key_id requires aws_secret_access_key (or set MCP_PROXY_{}_AWS_SECRET_ACCESS_KEY)",
+                        name.to_uppercase().replace('-', "_")
+                    ));
+                }
+            } else if !aws.aws_secret_access_key.is_empty()
+                || aws.aws_session_token.is_some()
+                || gate.is_some()
+                || gate_password.is_some()
+            {
+                return Err(format!(
+                    "downstream '{name}': aws_secret_access_key, aws_session_token and gate require aws_access_key_id"
+                ));
+            }
+        }
+
+        if let StrategyConfig::StaticCredential {
+            gate: Gate::Password,
+            gate_password,
+            ..
+        }
+        | StrategyConfig::ClientCredentials {
+            gate: Gate::Password,
+            gate_password,
+            ..
+        }
+        | StrategyConfig::GithubApp {
+            gate: Gate::Password,
+            gate_password,
+            ..
+        }
+        | StrategyConfig::JwtBearer {
+            gate: Gate::Password,
+            gate_password,
+            ..
+        }
+        | StrategyConfig::AwsSigv4 {
+            gate: Some(Gate::Password),
+            gate_password,
+            ..
+        } = &ds.strategy
+        {
+            if gate_password.as_ref().is_none_or(|p| p.len() < 16) {
+                return Err(format!(
+                    "downstream '{name}': gate = \"password\" requires a gate_password of at least 16 characters"
+                ));
+            }
         }
 
         let valid_formats = ["Bearer", "token", "Basic", "X-API-Key"];
@@ -221,6 +1300,177 @@ fn validate_downstreams(downstreams: &HashMap<String, DownstreamConfig>) -> Resu
     Ok(())
 }
 
+/// Check that a multi-field passthrough form has uniquely named, well-formed
+/// fields and headers built only from them.
+fn validate_credential_fields(
+    name: &str,
+    fields: &[CredentialField],
+    headers: &BTreeMap<String, HeaderTemplate>,
+) -> Result<(), String> {
+    if fields.is_empty() != headers.is_empty() {
+        return Err(format!(
+            "downstream '{name}': credential_fields and credential_headers must be set together"
+        ));
+    }
+
+    let field_regex = regex_lite::Regex::new(r"^[a-z0-9_]+$").unwrap();
+    let mut seen = std::collections::HashSet::new();
+    for field in fields {
+        if !field_regex.is_match(&field.name) {
+            return Err(format!(
+                "downstream '{name}': credential field name '{}' must match ^[a-z0-9_]+$",
+                field.name
+            ));
+        }
+        if !seen.insert(field.name.as_str()) {
+            return Err(format!(
+                "downstream '{name}': duplicate credential field '{}'",
+                field.name
+            ));
+        }
+        if field.label.is_empty() {
+            return Err(format!(
+                "downstream '{name}': credential field '{}' needs a label",
+                field.name
+            ));
+        }
+        if let Some(regex) = &field.regex {
+            if let Err(e) = regex_lite::Regex::new(regex) {
+                return Err(format!(
+                    "downstream '{name}': credential field '{}' has an invalid regex: {e}",
+                    field.name
+                ));
+            }
+        }
+    }
+
+    for (header, template) in headers {
+        if axum::http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+            return Err(format!(
+                "downstream '{name}': '{header}' is not a valid header name"
+            ));
+        }
+        if let Some(unknown) = template.fields().into_iter().find(|f| !seen.contains(f)) {
+            return Err(format!(
+                "downstream '{name}': credential header '{header}' uses unknown field '{unknown}'"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check a provider's endpoints and the credentials its client
+/// authentication method needs. `context` prefixes error messages; users are
+/// sent to the authorization endpoint only when `authorize` is set.
+fn validate_oauth_client(
+    context: &str,
+    oauth: &OAuthConfig,
+    authorize: bool,
+) -> Result<(), String> {
+    match &oauth.oauth_issuer {
+        Some(issuer) => {
+            if !issuer.starts_with("https://") && !issuer.starts_with("http://") {
+                return Err(format!("{context}: oauth_issuer must be an http(s) URL"));
+            }
+        }
+        None if !authorize => {
+            if oauth.oauth_token_url.is_none() {
+                return Err(format!("{context}: set oauth_issuer or oauth_token_url"));
+            }
+        }
+        None => {
+            if oauth.oauth_authorize_url.is_none() || oauth.oauth_token_url.is_none() {
+                return Err(format!(
+                    "{context}: set oauth_issuer, or both oauth_authorize_url and oauth_token_url"
+                ));
+            }
+        }
+    }
+    match oauth.oauth_client_auth_method {
+        ClientAuthMethod::ClientSecretPost | ClientAuthMethod::ClientSecretBasic => {
+            if oauth.oauth_client_secret.is_empty() {
+                return Err(format!("{context}: oauth_client_secret must not be empty"));
+            }
+        }
+        ClientAuthMethod::PrivateKeyJwt => {
+            if oauth.oauth_client_key_path.is_none() {
+                return Err(format!(
+                    "{context}: oauth_client_auth_method = \"private_key_jwt\" requires oauth_client_key_path"
+                ));
+            }
+        }
+        ClientAuthMethod::None => {}
+    }
+    Ok(())
+}
+
+/// Check the `oauth_token_exchange_*` fields, which only chained OAuth
+/// downstreams (`allowed`) may use.
+fn validate_token_exchange(
+    context: &str,
+    oauth: &OAuthConfig,
+    allowed: bool,
+) -> Result<(), String> {
+    let Some(url) = &oauth.oauth_token_exchange_url else {
+        if oauth.oauth_token_exchange_audience.is_some()
+            || oauth.oauth_token_exchange_scopes.is_some()
+            || oauth.oauth_token_exchange_subject != SubjectTokenType::AccessToken
+            || oauth.oauth_token_exchange_client_id.is_some()
+            || !oauth.oauth_token_exchange_client_secret.is_empty()
+            || oauth.oauth_token_exchange_client_auth_method.is_some()
+        {
+            return Err(format!(
+                "{context}: oauth_token_exchange_* fields require oauth_token_exchange_url"
+            ));
+        }
+        return Ok(());
+    };
+    if !allowed {
+        return Err(format!(
+            "{context}: token exchange only applies to chained_oauth downstreams"
+        ));
+    }
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(format!(
+            "{context}: oauth_token_exchange_url must be an http(s) URL"
+        ));
+    }
+    if oauth.oauth_token_exchange_subject == SubjectTokenType::IdToken && !oauth.oauth_oidc {
+        return Err(format!(
+            "{context}: oauth_token_exchange_subject = \"id_token\" requires oauth_oidc = true"
+        ));
+    }
+    if oauth.oauth_token_exchange_client_id.is_none() {
+        if !oauth.oauth_token_exchange_client_secret.is_empty()
+            || oauth.oauth_token_exchange_client_auth_method.is_some()
+        {
+            return Err(format!(
+                "{context}: oauth_token_exchange_client_secret and oauth_token_exchange_client_auth_method require oauth_token_exchange_client_id"
+            ));
+        }
+        return Ok(());
+    }
+    match oauth
+        .oauth_token_exchange_client_auth_method
+        .unwrap_or_default()
+    {
+        ClientAuthMethod::ClientSecretPost | ClientAuthMethod::ClientSecretBasic => {
+            if oauth.oauth_token_exchange_client_secret.is_empty() {
+                return Err(format!(
+                    "{context}: oauth_token_exchange_client_secret must not be empty"
+                ));
+            }
+        }
+        ClientAuthMethod::PrivateKeyJwt => {
+            return Err(format!(
+                "{context}: oauth_token_exchange_client_auth_method does not support private_key_jwt"
+            ));
+        }
+        ClientAuthMethod::None => {}
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -307,6 +1557,611 @@ downstream_url = "https://downstream.example.com/mcp"
         );
     }
 
+    #[test]
+    fn test_chained_oauth_needs_issuer_or_endpoints() {
+        let parse = |endpoints: &str| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+
+[downstream.test]
+display_name = "Test"
+strategy = "chained_oauth"
+downstream_url = "https://downstream.example.com/mcp"
+oauth_client_id = "my-client"
+oauth_client_secret = "my-secret"
+{endpoints}
+"#
+            ))
+            .unwrap();
+            validate_downstreams(&config.downstream)
+        };
+
+        assert!(parse("oauth_issuer = \"https://accounts.example.com\"").is_ok());
+        assert!(parse(
+            "oauth_authorize_url = \"https://p.com/authorize\"\noauth_token_url = \"https://p.com/token\""
+        )
+        .is_ok());
+        assert!(parse("oauth_authorize_url = \"https://p.com/authorize\"")
+            .unwrap_err()
+            .contains("set oauth_issuer"));
+        assert!(parse("oauth_issuer = \"accounts.example.com\"").is_err());
+    }
+
+    #[test]
+    fn test_client_auth_method_requirements() {
+        let parse = |auth: &str| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+
+[downstream.test]
+display_name = "Test"
+strategy = "chained_oauth"
+downstream_url = "https://downstream.example.com/mcp"
+oauth_issuer = "https://accounts.example.com"
+oauth_client_id = "my-client"
+{auth}
+"#
+            ))
+            .unwrap();
+            validate_downstreams(&config.downstream)
+        };
+
+        assert!(parse("oauth_client_secret = \"s\"").is_ok());
+        assert!(parse("oauth_client_auth_method = \"client_secret_basic\"")
+            .unwrap_err()
+            .contains("oauth_client_secret"));
+        assert!(parse("oauth_client_auth_method = \"none\"").is_ok());
+        assert!(parse("oauth_client_auth_method = \"private_key_jwt\"")
+            .unwrap_err()
+            .contains("oauth_client_key_path"));
+        assert!(parse(
+            "oauth_client_auth_method = \"private_key_jwt\"\noauth_client_key_path = \"key.pem\"\noauth_client_key_alg = \"ES256\""
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_login_requirements() {
+        let parse = |login: &str, rules: &str, strategy: &str| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+{login}
+
+[downstream.test]
+display_name = "Test"
+downstream_url = "https://downstream.example.com/mcp"
+{strategy}
+{rules}
+"#
+            ))
+            .unwrap();
+            validate(&config)
+        };
+        let login = "[server.login]\noauth_issuer = \"https://login.example.com\"\noauth_client_id = \"proxy\"\noauth_client_secret = \"s\"";
+        let passthrough = "strategy = \"passthrough\"";
+        let domains = "allowed_email_domains = [\"example.com\"]";
+
+        assert!(parse(login, domains, passthrough).is_ok());
+        assert!(parse("", domains, passthrough)
+            .unwrap_err()
+            .contains("require [server.login]"));
+        assert!(parse(
+            login,
+            domains,
+            "strategy = \"chained_oauth\"\noauth_issuer = \"https://a.example.com\"\noauth_client_id = \"c\"\noauth_client_secret = \"s\""
+        )
+        .unwrap_err()
+        .contains("only apply to passthrough"));
+        assert!(parse(
+            "[server.login]\noauth_authorize_url = \"https://login.example.com/a\"\noauth_token_url = \"https://login.example.com/t\"\noauth_client_id = \"proxy\"\noauth_client_secret = \"s\"",
+            "",
+            passthrough
+        )
+        .unwrap_err()
+        .contains("server.login: oauth_issuer is required"));
+        assert!(parse(
+            "[server.login]\noauth_issuer = \"https://login.example.com\"\noauth_client_id = \"proxy\"",
+            "",
+            passthrough
+        )
+        .unwrap_err()
+        .contains("server.login: oauth_client_secret"));
+
+        let vault = "strategy = \"vault\"";
+        assert!(parse(login, domains, vault).is_ok());
+        assert!(parse("", "", vault)
+            .unwrap_err()
+            .contains("the vault strategy requires [server.login]"));
+    }
+
+    #[test]
+    fn test_static_credential_requirements() {
+        let parse = |login: &str, downstream: &str| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+{login}
+
+[downstream.team]
+display_name = "Team"
+strategy = "static_credential"
+downstream_url = "https://downstream.example.com/mcp"
+{downstream}
+"#
+            ))
+            .unwrap();
+            validate(&config)
+        };
+        let login = "[server.login]\noauth_issuer = \"https://login.example.com\"\noauth_client_id = \"proxy\"\noauth_client_secret = \"s\"";
+
+        assert!(parse(login, "credential = \"team-key\"").is_ok());
+        assert!(parse(login, "")
+            .unwrap_err()
+            .contains("MCP_PROXY_TEAM_CREDENTIAL"));
+        assert!(parse("", "credential = \"team-key\"")
+            .unwrap_err()
+            .contains("gate = \"sso\" requires [server.login]"));
+
+        let password = "credential = \"team-key\"\ngate = \"password\"";
+        assert!(parse(
+            "",
+            &format!("{password}\ngate_password = \"correct horse battery\"")
+        )
+        .is_ok());
+        assert!(parse("", &format!("{password}\ngate_password = \"short\""))
+            .unwrap_err()
+            .contains("at least 16 characters"));
+        assert!(parse(
+            login,
+            &format!(
+                "{password}\ngate_password = \"correct horse battery\"\nallowed_groups = [\"eng\"]"
+            )
+        )
+        .unwrap_err()
+        .contains("SSO-gated"));
+    }
+
+    #[test]
+    fn test_client_credentials_requirements() {
+        let parse = |downstream: &str| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+
+[downstream.svc]
+display_name = "Service"
+strategy = "client_credentials"
+downstream_url = "https://downstream.example.com/mcp"
+oauth_client_id = "proxy"
+gate = "password"
+gate_password = "correct horse battery"
+{downstream}
+"#
+            ))
+            .unwrap();
+            validate(&config)
+        };
+        let token_url = "oauth_token_url = \"https://idp.example.com/token\"";
+
+        // No authorization endpoint is needed.
+        assert!(parse(&format!("{token_url}\noauth_client_secret = \"s\"")).is_ok());
+        assert!(parse("oauth_client_secret = \"s\"")
+            .unwrap_err()
+            .contains("set oauth_issuer or oauth_token_url"));
+        assert!(parse(token_url)
+            .unwrap_err()
+            .contains("oauth_client_secret must not be empty"));
+        assert!(
+            parse(&format!("{token_url}\noauth_client_auth_method = \"none\""))
+                .unwrap_err()
+                .contains("requires client authentication")
+        );
+        assert!(parse(&format!(
+            "{token_url}\noauth_client_secret = \"s\"\noauth_oidc = true"
+        ))
+        .unwrap_err()
+        .contains("oauth_oidc does not apply"));
+    }
+
+    #[test]
+    fn test_github_app_requirements() {
+        let config = |downstream: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+[server.login]
+oauth_issuer = "https://idp.example.com"
+oauth_client_id = "proxy-login"
+oauth_client_secret = "s"
+
+[downstream.repo]
+display_name = "Repositories"
+strategy = "github_app"
+downstream_url = "https://api.githubcopilot.com/mcp/"
+oauth_client_id = "Iv23liExample"
+github_installation_id = 42
+github_private_key_path = "app.pem"
+{downstream}
+"#
+            ))
+            .unwrap()
+        };
+        let parse = |downstream: &str| validate(&config(downstream));
+
+        // Signing in through [server.login] and the public API by default.
+        let StrategyConfig::GithubApp { app, gate, .. } = &config("").downstream["repo"].strategy
+        else {
+            panic!("expected github_app");
+        };
+        assert_eq!(app.github_api_url, "https://api.github.com");
+        assert_eq!(*gate, Gate::Sso);
+        assert!(parse("").is_ok());
+
+        let password = "gate = \"password\"\ngate_password = \"correct horse battery\"";
+        assert!(parse(password).is_ok());
+        assert!(parse("gate = \"github\"")
+            .unwrap_err()
+            .contains("set oauth_issuer, or both oauth_authorize_url and oauth_token_url"));
+        assert!(parse(
+            "gate = \"github\"\n\
+             oauth_authorize_url = \"https://github.com/login/oauth/authorize\"\n\
+             oauth_token_url = \"https://github.com/login/oauth/access_token\"\n\
+             oauth_client_secret = \"s\""
+        )
+        .is_ok());
+        assert!(parse(&format!(
+            "{password}\noauth_issuer = \"https://github.com\""
+        ))
+        .unwrap_err()
+        .contains("no OAuth metadata"));
+        assert!(
+            parse(&format!("{password}\ngithub_api_url = \"api.github.com\""))
+                .unwrap_err()
+                .contains("github_api_url must be an http(s) URL")
+        );
+    }
+
+    #[test]
+    fn test_jwt_bearer_requirements() {
+        let config = |downstream: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+[server.login]
+oauth_issuer = "https://idp.example.com"
+oauth_client_id = "proxy-login"
+oauth_client_secret = "s"
+
+[downstream.drive]
+display_name = "Drive"
+strategy = "jwt_bearer"
+downstream_url = "https://drive.example.com/mcp"
+oauth_client_id = "svc@project.iam.example.com"
+oauth_client_auth_method = "none"
+jwt_key_path = "svc.pem"
+{downstream}
+"#
+            ))
+            .unwrap()
+        };
+        let parse = |downstream: &str| validate(&config(downstream));
+        let token_url = "oauth_token_url = \"https://oauth2.example.com/token\"";
+
+        // RS256, no impersonation and the SSO gate by default.
+        let StrategyConfig::JwtBearer {
+            assertion, gate, ..
+        } = &config(token_url).downstream["drive"].strategy
+        else {
+            panic!("expected jwt_bearer");
+        };
+        assert_eq!(assertion.jwt_key_alg, ClientKeyAlg::RS256);
+        assert_eq!(assertion.jwt_impersonate, None);
+        assert!(!assertion.jwt_scope_claim);
+        assert_eq!(*gate, Gate::Sso);
+        assert!(parse(token_url).is_ok());
+
+        assert!(parse("")
+            .unwrap_err()
+            .contains("set oauth_issuer or oauth_token_url"));
+        assert!(parse(&format!("{token_url}\noauth_oidc = true"))
+            .unwrap_err()
+            .contains("oauth_oidc does not apply"));
+        assert!(parse(&format!(
+            "{token_url}\njwt_impersonate = \"email\"\njwt_key_alg = \"ES256\""
+        ))
+        .is_ok());
+        assert!(parse(&format!(
+            "{token_url}\njwt_impersonate = \"email\"\n\
+             gate = \"password\"\ngate_password = \"correct horse battery\""
+        ))
+        .unwrap_err()
+        .contains("jwt_impersonate requires gate = \"sso\""));
+        assert!(parse(&format!(
+            "{token_url}\njwt_impersonate = \"sub\"\njwt_subject = \"robot\""
+        ))
+        .unwrap_err()
+        .contains("not both"));
+        assert!(parse(&format!("{token_url}\ngate = \"github\""))
+            .unwrap_err()
+            .contains("only applies to github_app"));
+    }
+
+    #[test]
+    fn test_aws_sigv4_requirements() {
+        let config = |downstream: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+[server.login]
+oauth_issuer = "https://idp.example.com"
+oauth_client_id = "proxy-login"
+oauth_client_secret = "s"
+
+[downstream.api]
+display_name = "Internal API"
+strategy = "aws_sigv4"
+downstream_url = "https://abc123.execute-api.us-east-1.amazonaws.com/prod/mcp"
+aws_region = "us-east-1"
+{downstream}
+"#
+            ))
+            .unwrap()
+        };
+        let parse = |downstream: &str| validate(&config(downstream));
+        let keys = "aws_access_key_id = \"AKIDEXAMPLE\"\naws_secret_access_key = \"secret\"";
+
+        // Users enter credentials unless the proxy holds them; API Gateway
+        // is the default service.
+        let form = config("");
+        let StrategyConfig::AwsSigv4 { aws, .. } = &form.downstream["api"].strategy else {
+            panic!("expected aws_sigv4");
+        };
+        assert_eq!(aws.aws_service, "execute-api");
+        assert_eq!(aws.credentials(), None);
+        assert_eq!(form.downstream["api"].strategy.gate(), None);
+        assert!(parse("").is_ok());
+        assert!(parse("allowed_email_domains = [\"example.com\"]").is_ok());
+
+        let held = config(keys);
+        assert_eq!(held.downstream["api"].strategy.gate(), Some(Gate::Sso));
+        assert!(parse(keys).is_ok());
+        assert!(parse(&format!(
+            "{keys}\ngate = \"password\"\ngate_password = \"correct horse battery\""
+        ))
+        .is_ok());
+        assert!(parse(&format!("{keys}\ngate = \"password\""))
+            .unwrap_err()
+            .contains("gate_password of at least 16 characters"));
+        assert!(parse(&format!("{keys}\ngate = \"github\""))
+            .unwrap_err()
+            .contains("only applies to github_app"));
+
+        assert!(parse("aws_access_key_id = \"AKIDEXAMPLE\"")
+            .unwrap_err()
+            .contains("MCP_PROXY_API_AWS_SECRET_ACCESS_KEY"));
+        assert!(parse("gate = \"sso\"")
+            .unwrap_err()
+            .contains("require aws_access_key_id"));
+        assert!(parse("aws_session_token = \"t\"")
+            .unwrap_err()
+            .contains("require aws_access_key_id"));
+        let mut bad_region = config("");
+        if let StrategyConfig::AwsSigv4 { aws, .. } =
+            &mut bad_region.downstream.get_mut("api").unwrap().strategy
+        {
+            aws.aws_region = "US East".to_string();
+        }
+        assert!(validate(&bad_region).unwrap_err().contains("region name"));
+        assert!(parse("aws_service = \"\"")
+            .unwrap_err()
+            .contains("aws_service must not be empty"));
+    }
+
+    #[test]
+    fn test_credential_fields_requirements() {
+        let parse = |extra: &str| -> Result<(), String> {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+
+[downstream.jira]
+display_name = "Jira"
+strategy = "passthrough"
+downstream_url = "https://jira.example.com/mcp"
+{extra}
+"#
+            ))
+            .map_err(|e| e.to_string())?;
+            validate(&config)
+        };
+        let fields = r#"
+[[downstream.jira.credential_fields]]
+name = "user"
+label = "Email"
+type = "text"
+[[downstream.jira.credential_fields]]
+name = "password"
+label = "API token"
+"#;
+        let headers = |template: &str| {
+            format!("{fields}[downstream.jira.credential_headers]\nAuthorization = \"{template}\"")
+        };
+
+        assert!(parse(&headers("Basic {base64({user}:{password})}")).is_ok());
+        assert!(parse(fields).unwrap_err().contains("must be set together"));
+        assert!(parse(&headers("Bearer {token}"))
+            .unwrap_err()
+            .contains("uses unknown field 'token'"));
+        assert!(parse(&headers("Basic {base64({user}"))
+            .unwrap_err()
+            .contains("unclosed base64("));
+        assert!(parse(&format!(
+            "{}\n[[downstream.jira.credential_fields]]\nname = \"user\"\nlabel = \"Again\"",
+            headers("{user}")
+        ))
+        .unwrap_err()
+        .contains("duplicate credential field 'user'"));
+        assert!(parse(&format!(
+            "{}\n[[downstream.jira.credential_fields]]\nname = \"Site\"\nlabel = \"Site\"",
+            headers("{user}")
+        ))
+        .unwrap_err()
+        .contains("must match ^[a-z0-9_]+$"));
+        assert!(parse(&format!(
+            "{}\n[[downstream.jira.credential_fields]]\nname = \"site\"\nlabel = \"Site\"\nregex = \"(\"",
+            headers("{user}")
+        ))
+        .unwrap_err()
+        .contains("has an invalid regex"));
+        assert!(parse(&format!(
+            "{fields}[downstream.jira.credential_headers]\n\"Bad Header\" = \"{{user}}\""
+        ))
+        .unwrap_err()
+        .contains("is not a valid header name"));
+    }
+
+    #[test]
+    fn test_token_exchange_requirements() {
+        let parse = |extra: &str| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+[server]
+public_url = "https://example.com"
+state_secret = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+
+[downstream.internal]
+display_name = "Internal"
+strategy = "chained_oauth"
+downstream_url = "https://downstream.example.com/mcp"
+oauth_issuer = "https://idp.example.com"
+oauth_client_id = "proxy"
+oauth_client_secret = "s"
+{extra}
+"#
+            ))
+            .unwrap();
+            validate(&config)
+        };
+        let sts = "oauth_token_exchange_url = \"https://sts.example.com/token\"";
+
+        assert!(parse(&format!(
+            "{sts}\noauth_token_exchange_audience = \"internal-mcp\""
+        ))
+        .is_ok());
+        assert!(parse("oauth_token_exchange_audience = \"internal-mcp\"")
+            .unwrap_err()
+            .contains("require oauth_token_exchange_url"));
+        assert!(parse(&format!(
+            "{sts}\noauth_token_exchange_subject = \"id_token\""
+        ))
+        .unwrap_err()
+        .contains("requires oauth_oidc = true"));
+        assert!(parse(&format!(
+            "{sts}\noauth_token_exchange_subject = \"id_token\"\noauth_oidc = true"
+        ))
+        .is_ok());
+
+        // The STS client is configured separately from the provider's.
+        let sts_client = format!("{sts}\noauth_token_exchange_client_id = \"proxy-at-sts\"");
+        assert!(parse(&format!(
+            "{sts_client}\noauth_token_exchange_client_secret = \"s\""
+        ))
+        .is_ok());
+        assert!(parse(&sts_client)
+            .unwrap_err()
+            .contains("oauth_token_exchange_client_secret must not be empty"));
+        assert!(parse(&format!(
+            "{sts_client}\noauth_token_exchange_client_auth_method = \"none\""
+        ))
+        .is_ok());
+        assert!(parse(&format!(
+            "{sts_client}\noauth_token_exchange_client_auth_method = \"private_key_jwt\""
+        ))
+        .unwrap_err()
+        .contains("does not support private_key_jwt"));
+        assert!(parse(&format!(
+            "{sts}\noauth_token_exchange_client_secret = \"s\""
+        ))
+        .unwrap_err()
+        .contains("require oauth_token_exchange_client_id"));
+    }
+
+    #[test]
+    fn test_explicit_endpoints_override_discovered() {
+        let oauth = OAuthConfig {
+            oauth_issuer: Some("https://accounts.example.com".to_string()),
+            oauth_authorize_url: None,
+            oauth_token_url: Some("https://override.example.com/token".to_string()),
+            oauth_client_id: "c".to_string(),
+            oauth_client_secret: "s".to_string(),
+            oauth_client_auth_method: ClientAuthMethod::ClientSecretPost,
+            oauth_client_key_path: None,
+            oauth_client_key_alg: ClientKeyAlg::RS256,
+            oauth_client_key_id: None,
+            client_key: ClientKey::default(),
+            oauth_scopes: None,
+            oauth_oidc: false,
+            oauth_scope_policy: ScopePolicy::Warn,
+            oauth_supports_refresh: false,
+            oauth_token_accept: default_oauth_token_accept(),
+            oauth_refresh_mode: RefreshMode::Client,
+            oauth_revocation_url: None,
+            oauth_userinfo_url: None,
+            oauth_jwks_url: None,
+            oauth_token_exchange_url: None,
+            oauth_token_exchange_audience: None,
+            oauth_token_exchange_scopes: None,
+            oauth_token_exchange_subject: SubjectTokenType::AccessToken,
+            oauth_token_exchange_client_id: None,
+            oauth_token_exchange_client_secret: String::new(),
+            oauth_token_exchange_client_auth_method: None,
+            discovered: DiscoveredMetadata::default(),
+        };
+        assert_eq!(oauth.authorize_url(), None);
+
+        oauth.discovered.set(ProviderMetadata {
+            issuer: "https://accounts.example.com".to_string(),
+            authorization_endpoint: Some("https://accounts.example.com/auth".to_string()),
+            token_endpoint: Some("https://accounts.example.com/token".to_string()),
+            jwks_uri: Some("https://accounts.example.com/jwks".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            oauth.authorize_url().as_deref(),
+            Some("https://accounts.example.com/auth")
+        );
+        assert_eq!(
+            oauth.token_url().as_deref(),
+            Some("https://override.example.com/token")
+        );
+        assert_eq!(
+            oauth.jwks_url().as_deref(),
+            Some("https://accounts.example.com/jwks")
+        );
+        assert_eq!(oauth.revocation_url(), None);
+    }
+
     #[test]
     fn test_invalid_name_format() {
         let toml_str = r#"
@@ -324,4 +2179,73 @@ downstream_url = "https://example.com"
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("must match"));
     }
+
+    #[test]
+    fn test_state_secrets() {
+        let parse = |server: &str| {
+            let config: Config = toml::from_str(&format!(
+                "[server]\npublic_url = \"https://example.com\"\n{server}"
+            ))
+            .unwrap();
+            validate_server(&config.server)
+        };
+        let key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+        assert!(parse(&format!(
+            "[[server.state_secrets]]\nid = \"k2\"\nsecret = \"{key}\"\n\
+             [[server.state_secrets]]\nid = \"k1\"\nsecret = \"{key}\""
+        ))
+        .is_ok());
+
+        assert!(parse("").unwrap_err().contains("required"));
+        assert!(parse(&format!(
+            "state_secret = \"{key}\"\n[[server.state_secrets]]\nid = \"k1\"\nsecret = \"{key}\""
+        ))
+        .unwrap_err()
+        .contains("mutually exclusive"));
+        assert!(parse(&format!(
+            "[[server.state_secrets]]\nid = \"k1\"\nsecret = \"{key}\"\n\
+             [[server.state_secrets]]\nid = \"k1\"\nsecret = \"{key}\""
+        ))
+        .unwrap_err()
+        .contains("duplicate"));
+        assert!(parse(&format!(
+            "[[server.state_secrets]]\nid = \"k.1\"\nsecret = \"{key}\""
+        ))
+        .is_err());
+        assert!(
+            parse("[[server.state_secrets]]\nid = \"k1\"\nsecret = \"c2hvcnQ=\"")
+                .unwrap_err()
+                .contains("key 'k1' must be at least 32 bytes")
+        );
+    }
+
+    #[test]
+    fn test_storage() {
+        let parse = |storage: &str| {
+            let config: Config = toml::from_str(&format!(
+                "[server]\npublic_url = \"https://example.com\"\n{storage}"
+            ))
+            .unwrap();
+            validate_storage(&config.storage).map(|_| config.storage)
+        };
+
+        assert_eq!(parse("").unwrap(), StorageConfig::Memory);
+        assert_eq!(
+            parse("[storage]\nbackend = \"sqlite\"\npath = \"/var/lib/proxy.db\"").unwrap(),
+            StorageConfig::Sqlite {
+                path: PathBuf::from("/var/lib/proxy.db")
+            }
+        );
+        assert_eq!(
+            parse("[storage]\nbackend = \"redis\"\nurl = \"redis://cache:6379/0\"").unwrap(),
+            StorageConfig::Redis {
+                url: "redis://cache:6379/0".to_string(),
+                key_prefix: "mcp-oauth-proxy:".to_string(),
+            }
+        );
+
+        assert!(parse("[storage]\nbackend = \"sqlite\"\npath = \"\"").is_err());
+        assert!(parse("[storage]\nbackend = \"redis\"\nurl = \"http://cache\"").is_err());
+    }
 }
@@ -3,6 +3,7 @@ pub mod config;
 pub mod oauth;
 pub mod proxy;
 pub mod routes;
+pub mod storage;
 
 use axum::extract::DefaultBodyLimit;
 use axum::routing::{get, post};
@@ -14,18 +15,60 @@ use tower_http::trace::TraceLayer;
 pub struct AppState {
     pub config: Arc<config::Config>,
     pub http_client: reqwest::Client,
+    pub(crate) upstream_sessions: Arc<auth::sessions::UpstreamSessions>,
+    pub(crate) service_tokens: Arc<auth::client_credentials::ServiceTokens>,
+    pub(crate) revoked: Arc<oauth::revocation::Denylist>,
+    pub(crate) store: Arc<dyn storage::Store>,
+    pub(crate) jwks: Arc<auth::oidc::JwksCache>,
+    keys: Arc<oauth::keys::Keyring>,
 }
 
 impl AppState {
-    pub fn new(config: config::Config, http_client: reqwest::Client) -> Self {
+    /// Build the state, opening the `[storage]` backend.
+    pub fn try_new(config: config::Config, http_client: reqwest::Client) -> Result<Self, String> {
+        let store = storage::open(&config.storage)?;
+        Ok(Self::with_store(config, http_client, store))
+    }
+
+    /// Build the state around an already opened store, ignoring `[storage]`.
+    pub fn with_store(
+        config: config::Config,
+        http_client: reqwest::Client,
+        store: Arc<dyn storage::Store>,
+    ) -> Self {
+        let keys = Arc::new(
+            oauth::keys::Keyring::from_config(&config.server)
+                .expect("server secrets are checked by config validation"),
+        );
         Self {
             config: Arc::new(config),
             http_client,
+            upstream_sessions: Arc::new(auth::sessions::UpstreamSessions::new(
+                store.clone(),
+                keys.clone(),
+            )),
+            service_tokens: Arc::default(),
+            revoked: Arc::new(oauth::revocation::Denylist::new(store.clone())),
+            store,
+            jwks: Arc::default(),
+            keys,
         }
     }
 
-    pub fn state_secret(&self) -> &[u8] {
-        &self.config.server.state_secret
+    /// Start keeping discovered provider metadata current in the background.
+    pub fn spawn_discovery_refresh(&self) {
+        auth::discovery::spawn_refresh(self.config.clone(), self.http_client.clone());
+    }
+
+    /// Server secrets for sealing codes and tokens and signing state.
+    pub fn keys(&self) -> &oauth::keys::Keyring {
+        &self.keys
+    }
+
+    /// The MCP endpoint URL of a downstream, used as its RFC 8707 resource
+    /// identifier.
+    pub fn resource_url(&self, name: &str) -> String {
+        format!("{}/mcp/{}", self.config.server.public_url, name)
     }
 
     pub fn find_downstream(&self, name: &str) -> Option<&config::DownstreamConfig> {
@@ -37,6 +80,19 @@ async fn health() -> &'static str {
     "OK"
 }
 
+/// The per-request span. `user` and `email` are empty until a handler
+/// authenticates a token carrying an OIDC identity and records them.
+fn request_span(request: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        user = tracing::field::Empty,
+        email = tracing::field::Empty,
+    )
+}
+
 pub fn build_router(state: AppState) -> Router {
     Router::new()
         .route("/health", get(health))
@@ -53,12 +109,23 @@ pub fn build_router(state: AppState) -> Router {
             get(routes::authorize::authorize_get).post(routes::authorize::authorize_post),
         )
         .route("/callback/mcp/{name}", get(routes::authorize::callback))
+        .route("/login/callback", get(routes::login::callback))
+        .route(
+            "/vault/mcp/{name}",
+            get(routes::vault::manage_get).post(routes::vault::manage_post),
+        )
+        .route("/register/mcp/{name}", post(routes::register::register))
         .route("/token/mcp/{name}", post(routes::token::token))
+        .route("/revoke/mcp/{name}", post(routes::revoke::revoke))
+        .route(
+            "/introspect/mcp/{name}",
+            post(routes::introspect::introspect),
+        )
         .route(
             "/mcp/{name}",
             get(routes::mcp_proxy::mcp_sse).post(routes::mcp_proxy::mcp_post),
         )
         .layer(DefaultBodyLimit::max(10 * 1_048_576))
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
         .with_state(state)
 }
@@ -29,7 +29,7 @@ async fn main() {
 
     let cli = Cli::parse();
 
-    let mut cfg = match config::load_config(&cli.config) {
+    let mut cfg = match config::load_config(&cli.config).await {
         Ok(c) => c,
         Err(e) => {
             tracing::error!("Configuration error: {e}");
@@ -48,7 +48,7 @@ async fn main() {
     for (name, ds) in &cfg.downstream {
         tracing::info!(
             name = %name,
-            strategy = ?ds.strategy,
+            strategy = ds.strategy.name(),
             downstream_url = %ds.downstream_url,
             "  Downstream configured"
         );
@@ -63,7 +63,15 @@ async fn main() {
         .build()
         .expect("failed to build HTTP client");
 
-    let state = AppState::new(cfg, http_client);
+    let state = match AppState::try_new(cfg, http_client) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Storage error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    state.spawn_discovery_refresh();
 
     let app = build_router(state);
 
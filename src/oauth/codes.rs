@@ -3,29 +3,45 @@
 //! Instead of an in-memory store, the authorization code itself is an AES-256-GCM
 //! encrypted blob containing the downstream token, PKCE challenge, redirect URI,
 //! and expiry. On `/token`, the proxy decrypts the code, verifies PKCE and expiry,
-//! and returns the embedded token. Fully stateless — no HashMap, no sweeper task,
-//! no concerns about multi-instance deployments.
+//! and wraps the embedded token in a proxy access token (see [`super::tokens`]).
+//! The code itself needs no server-side storage; each one carries a random
+//! nonce so `/token` can enforce single use (see [`super::replay`]).
 //!
-//! Format:  base64url( nonce || ciphertext || tag )
+//! Format:  "v1." kid "." base64url( nonce || ciphertext || tag )
+//!
+//! `kid` names the server key the code was sealed with; the AES key is derived
+//! from it for this purpose and downstream (see [`super::keys`]). The `v1.kid`
+//! header is authenticated as associated data.
 //!
 //! The plaintext is JSON:
 //! ```json
 //! {
+//!   "downstream": "github",
+//!   "client_id": "...",
+//!   "resource": "https://proxy.example.com/mcp/github",
+//!   "user": { "sub": "...", "email": "..." },
 //!   "downstream_tokens": { ... },
 //!   "pkce_challenge": "...",
 //!   "redirect_uri": "...",
+//!   "nonce": "...",
 //!   "exp": 1234567890
 //! }
 //! ```
 
-use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
 use aes_gcm::{AeadCore, Aes256Gcm, Nonce};
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use super::keys::{EnvelopeError, KeyPurpose, Keyring};
+use crate::auth::chained_oauth::TokenResponse;
+use crate::proxy::sigv4::AwsCredentials;
+
 const NONCE_SIZE: usize = 12;
 
 /// Tokens embedded inside the encrypted authorization code.
@@ -34,121 +50,264 @@ const NONCE_SIZE: usize = 12;
 pub enum DownstreamTokens {
     #[serde(rename = "passthrough")]
     Passthrough { access_token: String },
+    /// The provider's token response, including granted scope and ID token.
     #[serde(rename = "chained_oauth")]
-    ChainedOAuth {
-        access_token: String,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        refresh_token: Option<String>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        expires_in: Option<u64>,
-    },
+    ChainedOAuth(TokenResponse),
+    /// No credential: it is looked up in the user's vault on each request
+    /// (see [`crate::auth::vault`]).
+    #[serde(rename = "vault")]
+    Vault,
+    /// No credential: the proxy supplies the downstream's own, a configured
+    /// static credential or a client credentials service token.
+    #[serde(rename = "proxy_credential")]
+    ProxyCredential,
+    /// AWS credentials the user entered, used to sign each request rather
+    /// than sent as a header.
+    #[serde(rename = "aws_sigv4")]
+    AwsSigv4(AwsCredentials),
+    /// The values of a multi-field passthrough form, by field name, turned
+    /// into headers by the downstream's `credential_headers`.
+    #[serde(rename = "credential_fields")]
+    CredentialFields { fields: BTreeMap<String, String> },
+}
+
+impl DownstreamTokens {
+    /// The credential presented to the downstream MCP server; empty for
+    /// vault grants, credentials the proxy holds, AWS credentials and form
+    /// fields.
+    pub fn access_token(&self) -> &str {
+        match self {
+            DownstreamTokens::Passthrough { access_token } => access_token,
+            DownstreamTokens::ChainedOAuth(tokens) => &tokens.access_token,
+            DownstreamTokens::Vault
+            | DownstreamTokens::ProxyCredential
+            | DownstreamTokens::AwsSigv4(_)
+            | DownstreamTokens::CredentialFields { .. } => "",
+        }
+    }
+}
+
+/// Who a code or token was issued to: the downstream, the OAuth client, and
+/// the RFC 8707 resource the client asked for. Carried by authorization codes,
+/// signed OAuth states, and proxy-issued tokens so none of them can be
+/// redeemed at another downstream or by another client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GrantBinding {
+    pub downstream: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+    /// The person who authorized, when the provider proved it with a
+    /// validated OpenID Connect ID token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserIdentity>,
+}
+
+/// Identity taken from a validated ID token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserIdentity {
+    /// The provider's stable subject identifier.
+    pub sub: String,
+    /// Included only when the provider marks it verified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl GrantBinding {
+    /// Check that a redemption happens at the downstream the grant was issued
+    /// for and, if the grant names a client, by that client.
+    pub fn check(&self, downstream: &str, client_id: Option<&str>) -> Result<(), &'static str> {
+        if self.downstream != downstream {
+            return Err("grant was issued for a different downstream");
+        }
+        if self.client_id.is_some() && self.client_id.as_deref() != client_id {
+            return Err("grant was issued to a different client");
+        }
+        Ok(())
+    }
 }
 
 /// The plaintext payload encrypted inside the authorization code.
 #[derive(Debug, Serialize, Deserialize)]
 struct AuthCodePayload {
+    #[serde(flatten)]
+    binding: GrantBinding,
     downstream_tokens: DownstreamTokens,
     pkce_challenge: String,
     redirect_uri: String,
+    /// Random per-code value used for replay detection.
+    nonce: String,
     exp: u64,
 }
 
-/// Derive a 256-bit AES key from the server's state_secret using SHA-256.
-/// The state_secret is already validated to be ≥32 bytes when base64-decoded,
-/// but we hash it to get a clean 32-byte key regardless of input length.
-fn derive_key(state_secret: &[u8]) -> [u8; 32] {
-    let hash = Sha256::digest(state_secret);
-    hash.into()
-}
-
 /// Create an encrypted authorization code containing the given grant data.
 ///
 /// The returned string is safe to use as a URL query parameter (base64url, no padding).
 pub fn create_auth_code(
     downstream_tokens: DownstreamTokens,
+    binding: GrantBinding,
     pkce_challenge: &str,
     redirect_uri: &str,
     ttl_seconds: u64,
-    state_secret: &[u8],
+    keys: &Keyring,
 ) -> Result<String, String> {
-    let exp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| format!("system time error: {e}"))?
-        .as_secs()
-        + ttl_seconds;
-
     let payload = AuthCodePayload {
+        binding,
         downstream_tokens,
         pkce_challenge: pkce_challenge.to_string(),
         redirect_uri: redirect_uri.to_string(),
-        exp,
+        nonce: random_id(),
+        exp: now_secs()? + ttl_seconds,
     };
 
+    let purpose = KeyPurpose::AuthCode(&payload.binding.downstream);
+    encrypt_payload(&payload, purpose, keys)
+}
+
+/// Current UNIX time in seconds.
+pub(crate) fn now_secs() -> Result<u64, String> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|e| format!("system time error: {e}"))
+}
+
+/// A random 128-bit identifier, base64url-encoded.
+pub(crate) fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Serialize `payload` as JSON and seal it with AES-256-GCM under the current
+/// server key, derived for `purpose`. Shared by authorization codes and
+/// proxy-issued tokens.
+pub(crate) fn encrypt_payload<T: Serialize>(
+    payload: &T,
+    purpose: KeyPurpose,
+    keys: &Keyring,
+) -> Result<String, String> {
     let plaintext =
-        serde_json::to_vec(&payload).map_err(|e| format!("failed to serialize payload: {e}"))?;
+        serde_json::to_vec(payload).map_err(|e| format!("failed to serialize payload: {e}"))?;
 
-    let key = derive_key(state_secret);
-    let cipher =
-        Aes256Gcm::new_from_slice(&key).map_err(|e| format!("failed to create cipher: {e}"))?;
+    let signing_key = keys.current();
+    let header = signing_key.header();
+    let cipher = Aes256Gcm::new_from_slice(&signing_key.derive(purpose))
+        .map_err(|e| format!("failed to create cipher: {e}"))?;
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
     let ciphertext = cipher
-        .encrypt(&nonce, plaintext.as_ref())
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &plaintext,
+                aad: header.as_bytes(),
+            },
+        )
         .map_err(|e| format!("encryption failed: {e}"))?;
 
     let mut blob = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
     blob.extend_from_slice(&nonce);
     blob.extend_from_slice(&ciphertext);
 
-    Ok(URL_SAFE_NO_PAD.encode(&blob))
+    Ok(format!("{header}.{}", URL_SAFE_NO_PAD.encode(&blob)))
 }
 
-/// Result of decrypting and validating an authorization code.
+/// Why an encrypted blob could not be opened by [`decrypt_payload`].
 #[derive(Debug)]
-pub struct ValidatedGrant {
-    pub downstream_tokens: DownstreamTokens,
-    pub pkce_challenge: String,
-    pub redirect_uri: String,
+pub(crate) enum DecryptError {
+    /// Not a `v1` envelope, e.g. a blob from an older release.
+    Version,
+    /// Not base64url, or too short to hold a nonce and tag.
+    Malformed,
+    /// Authentication failed: wrong purpose or unknown key, or modified
+    /// ciphertext.
+    Tampered,
+    /// Decrypted fine but the JSON does not match the expected payload type.
+    Corrupt,
 }
 
-/// Decrypt and validate an authorization code.
-///
-/// Returns the embedded grant data if the code is valid, not expired,
-/// and decrypts successfully. Returns an error description otherwise.
-pub fn validate_auth_code(code: &str, state_secret: &[u8]) -> Result<ValidatedGrant, &'static str> {
+/// Reverse of [`encrypt_payload`]: decode, decrypt and deserialize the blob.
+pub(crate) fn decrypt_payload<T: DeserializeOwned>(
+    blob: &str,
+    purpose: KeyPurpose,
+    keys: &Keyring,
+) -> Result<T, DecryptError> {
+    let (key, blob) = keys.open(blob, 1).map_err(|e| match e {
+        EnvelopeError::Version => DecryptError::Version,
+        EnvelopeError::Malformed => DecryptError::Malformed,
+        EnvelopeError::UnknownKey => DecryptError::Tampered,
+    })?;
     let blob = URL_SAFE_NO_PAD
-        .decode(code)
-        .map_err(|_| "invalid authorization code encoding")?;
+        .decode(blob)
+        .map_err(|_| DecryptError::Malformed)?;
 
     if blob.len() < NONCE_SIZE + 1 {
-        return Err("authorization code too short");
+        return Err(DecryptError::Malformed);
     }
 
     let (nonce_bytes, ciphertext) = blob.split_at(NONCE_SIZE);
     let nonce = Nonce::from_slice(nonce_bytes);
 
-    let key = derive_key(state_secret);
-    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| "internal cipher error")?;
+    let cipher =
+        Aes256Gcm::new_from_slice(&key.derive(purpose)).map_err(|_| DecryptError::Tampered)?;
     let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|_| "authorization code is invalid or tampered")?;
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad: key.header().as_bytes(),
+            },
+        )
+        .map_err(|_| DecryptError::Tampered)?;
+
+    serde_json::from_slice(&plaintext).map_err(|_| DecryptError::Corrupt)
+}
+
+/// Result of decrypting and validating an authorization code.
+#[derive(Debug)]
+pub struct ValidatedGrant {
+    pub binding: GrantBinding,
+    pub downstream_tokens: DownstreamTokens,
+    pub pkce_challenge: String,
+    pub redirect_uri: String,
+    /// Identifies this code for single-use enforcement.
+    pub nonce: String,
+    /// When the code expires (UNIX seconds).
+    pub exp: u64,
+}
 
-    let payload: AuthCodePayload =
-        serde_json::from_slice(&plaintext).map_err(|_| "authorization code payload corrupt")?;
+/// Decrypt and validate an authorization code presented at `downstream`.
+///
+/// Returns the embedded grant data if the code is valid, not expired,
+/// and decrypts successfully. Returns an error description otherwise.
+pub fn validate_auth_code(
+    code: &str,
+    downstream: &str,
+    keys: &Keyring,
+) -> Result<ValidatedGrant, &'static str> {
+    let purpose = KeyPurpose::AuthCode(downstream);
+    let payload: AuthCodePayload = decrypt_payload(code, purpose, keys).map_err(|e| match e {
+        DecryptError::Version => "authorization code format is not supported",
+        DecryptError::Malformed => "invalid authorization code encoding",
+        DecryptError::Tampered => "authorization code is invalid or tampered",
+        DecryptError::Corrupt => "authorization code payload corrupt",
+    })?;
 
     // Check expiry
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|_| "system time error")?
-        .as_secs();
+    let now = now_secs().map_err(|_| "system time error")?;
 
     if now > payload.exp {
         return Err("authorization code expired");
     }
 
     Ok(ValidatedGrant {
+        binding: payload.binding,
         downstream_tokens: payload.downstream_tokens,
         pkce_challenge: payload.pkce_challenge,
         redirect_uri: payload.redirect_uri,
+        nonce: payload.nonce,
+        exp: payload.exp,
     })
 }
 
@@ -156,8 +315,17 @@ pub fn validate_auth_code(code: &str, state_secret: &[u8]) -> Result<ValidatedGr
 mod tests {
     use super::*;
 
-    fn test_secret() -> Vec<u8> {
-        vec![0xAA; 32]
+    fn test_secret() -> Keyring {
+        Keyring::single(&[0xAA; 32])
+    }
+
+    fn binding() -> GrantBinding {
+        GrantBinding {
+            downstream: "github".to_string(),
+            client_id: Some("client-1".to_string()),
+            resource: None,
+            user: None,
+        }
     }
 
     #[test]
@@ -167,6 +335,7 @@ mod tests {
             DownstreamTokens::Passthrough {
                 access_token: "my-api-key".to_string(),
             },
+            binding(),
             "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
             "http://localhost:9999/callback",
             300,
@@ -174,7 +343,8 @@ mod tests {
         )
         .unwrap();
 
-        let grant = validate_auth_code(&code, &secret).unwrap();
+        let grant = validate_auth_code(&code, "github", &secret).unwrap();
+        assert_eq!(grant.binding, binding());
         assert_eq!(grant.redirect_uri, "http://localhost:9999/callback");
         assert_eq!(
             grant.pkce_challenge,
@@ -192,11 +362,15 @@ mod tests {
     fn test_round_trip_chained_oauth() {
         let secret = test_secret();
         let code = create_auth_code(
-            DownstreamTokens::ChainedOAuth {
+            DownstreamTokens::ChainedOAuth(TokenResponse {
                 access_token: "gh-access".to_string(),
+                token_type: Some("bearer".to_string()),
                 refresh_token: Some("gh-refresh".to_string()),
                 expires_in: Some(28800),
-            },
+                scope: Some("repo,user".to_string()),
+                id_token: None,
+            }),
+            binding(),
             "challenge123",
             "https://claude.ai/callback",
             300,
@@ -204,21 +378,42 @@ mod tests {
         )
         .unwrap();
 
-        let grant = validate_auth_code(&code, &secret).unwrap();
+        let grant = validate_auth_code(&code, "github", &secret).unwrap();
         match grant.downstream_tokens {
-            DownstreamTokens::ChainedOAuth {
-                access_token,
-                refresh_token,
-                expires_in,
-            } => {
-                assert_eq!(access_token, "gh-access");
-                assert_eq!(refresh_token.unwrap(), "gh-refresh");
-                assert_eq!(expires_in.unwrap(), 28800);
+            DownstreamTokens::ChainedOAuth(tokens) => {
+                assert_eq!(tokens.access_token, "gh-access");
+                assert_eq!(tokens.token_type.as_deref(), Some("bearer"));
+                assert_eq!(tokens.refresh_token.unwrap(), "gh-refresh");
+                assert_eq!(tokens.expires_in.unwrap(), 28800);
+                assert_eq!(tokens.scope.as_deref(), Some("repo,user"));
+                assert_eq!(tokens.id_token, None);
             }
             _ => panic!("expected ChainedOAuth variant"),
         }
     }
 
+    #[test]
+    fn test_binding_check() {
+        let bound = binding();
+        assert!(bound.check("github", Some("client-1")).is_ok());
+        assert_eq!(
+            bound.check("linear", Some("client-1")).unwrap_err(),
+            "grant was issued for a different downstream"
+        );
+        assert_eq!(
+            bound.check("github", Some("client-2")).unwrap_err(),
+            "grant was issued to a different client"
+        );
+        assert!(bound.check("github", None).is_err());
+
+        // Grants issued without a client_id can be redeemed by any client.
+        let unbound = GrantBinding {
+            client_id: None,
+            ..binding()
+        };
+        assert!(unbound.check("github", Some("anyone")).is_ok());
+    }
+
     #[test]
     fn test_wrong_secret_fails() {
         let secret = test_secret();
@@ -226,6 +421,7 @@ mod tests {
             DownstreamTokens::Passthrough {
                 access_token: "token".to_string(),
             },
+            binding(),
             "challenge",
             "http://localhost/cb",
             300,
@@ -233,8 +429,8 @@ mod tests {
         )
         .unwrap();
 
-        let wrong_secret = vec![0xBB; 32];
-        let result = validate_auth_code(&code, &wrong_secret);
+        let wrong_secret = Keyring::single(&[0xBB; 32]);
+        let result = validate_auth_code(&code, "github", &wrong_secret);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err(),
@@ -242,6 +438,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rotated_keys() {
+        use crate::oauth::keys::Key;
+
+        let key = |id: &str, byte: u8| Key {
+            id: id.to_string(),
+            secret: vec![byte; 32],
+        };
+        let old = Keyring::new(vec![key("old", 0xAA)]).unwrap();
+        let rotated = Keyring::new(vec![key("new", 0xBB), key("old", 0xAA)]).unwrap();
+        let create = |keys: &Keyring| {
+            create_auth_code(
+                DownstreamTokens::Passthrough {
+                    access_token: "token".to_string(),
+                },
+                binding(),
+                "challenge",
+                "http://localhost/cb",
+                300,
+                keys,
+            )
+            .unwrap()
+        };
+
+        // Codes name their key, and old codes survive a rotation.
+        let code = create(&old);
+        assert!(code.starts_with("v1.old."));
+        assert!(validate_auth_code(&code, "github", &rotated).is_ok());
+
+        // New codes use the first key, which the old keyring does not know.
+        let code = create(&rotated);
+        assert!(code.starts_with("v1.new."));
+        assert!(validate_auth_code(&code, "github", &old).is_err());
+    }
+
+    #[test]
+    fn test_codes_are_scoped_to_downstream_and_format() {
+        let secret = test_secret();
+        let code = create_auth_code(
+            DownstreamTokens::Passthrough {
+                access_token: "token".to_string(),
+            },
+            binding(),
+            "challenge",
+            "http://localhost/cb",
+            300,
+            &secret,
+        )
+        .unwrap();
+
+        // Sealed under the github subkey, so no other downstream can open it.
+        assert_eq!(
+            validate_auth_code(&code, "linear", &secret).unwrap_err(),
+            "authorization code is invalid or tampered"
+        );
+
+        // Pre-versioning formats are rejected outright.
+        let unversioned = code.strip_prefix("v1.").unwrap();
+        assert_eq!(
+            validate_auth_code(unversioned, "github", &secret).unwrap_err(),
+            "authorization code format is not supported"
+        );
+        let (_, bare) = unversioned.split_once('.').unwrap();
+        assert!(validate_auth_code(bare, "github", &secret).is_err());
+    }
+
     #[test]
     fn test_expired_code_fails() {
         let secret = test_secret();
@@ -250,6 +512,7 @@ mod tests {
             DownstreamTokens::Passthrough {
                 access_token: "token".to_string(),
             },
+            binding(),
             "challenge",
             "http://localhost/cb",
             0,
@@ -260,7 +523,7 @@ mod tests {
         // Sleep briefly to ensure we're past expiry
         std::thread::sleep(std::time::Duration::from_millis(1100));
 
-        let result = validate_auth_code(&code, &secret);
+        let result = validate_auth_code(&code, "github", &secret);
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "authorization code expired");
     }
@@ -272,6 +535,7 @@ mod tests {
             DownstreamTokens::Passthrough {
                 access_token: "token".to_string(),
             },
+            binding(),
             "challenge",
             "http://localhost/cb",
             300,
@@ -285,15 +549,15 @@ mod tests {
         tampered[mid] = if tampered[mid] == b'A' { b'B' } else { b'A' };
         let tampered = String::from_utf8(tampered).unwrap();
 
-        let result = validate_auth_code(&tampered, &secret);
+        let result = validate_auth_code(&tampered, "github", &secret);
         assert!(result.is_err());
     }
 
     #[test]
     fn test_garbage_input_fails() {
         let secret = test_secret();
-        assert!(validate_auth_code("not-a-valid-code", &secret).is_err());
-        assert!(validate_auth_code("", &secret).is_err());
-        assert!(validate_auth_code("AAAA", &secret).is_err());
+        assert!(validate_auth_code("not-a-valid-code", "github", &secret).is_err());
+        assert!(validate_auth_code("", "github", &secret).is_err());
+        assert!(validate_auth_code("AAAA", "github", &secret).is_err());
     }
 }
@@ -0,0 +1,254 @@
+//! Server secrets and the keys derived from them.
+//!
+//! Secrets carry key IDs so `state_secret` can be rotated without invalidating
+//! in-flight codes, states and tokens. New blobs always use the current key
+//! (the first one configured); any configured key is accepted when verifying.
+//!
+//! Secrets are never used directly. Each use gets its own key via HKDF-SHA256
+//! with a distinct info label (see [`KeyPurpose`]), and downstream-scoped
+//! blobs use a subkey per downstream, so a blob minted for one purpose or
+//! downstream cannot be opened as another.
+//!
+//! Every sealed or signed blob is wrapped in a versioned envelope,
+//! `v1.<kid>.<body>`. Blobs in any other format, including those from before
+//! versioning, are rejected rather than parsed.
+//!
+//! Rotation: append the new key (so every replica accepts it), then move it to
+//! the front once deployed everywhere, and drop the old key after the longest
+//! token lifetime has passed.
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::config::ServerConfig;
+
+/// Key ID given to a bare `server.state_secret`.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Envelope format version; bump when the body format or key schedule changes.
+const FORMAT_VERSION: &str = "v1";
+
+/// What a derived key is used for. Each purpose has its own HKDF info label.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyPurpose<'a> {
+    /// HMAC over chained OAuth `state` parameters, per downstream.
+    State(&'a str),
+    /// HMAC over registered client IDs.
+    ClientId,
+    /// AES-256-GCM for authorization codes, per downstream.
+    AuthCode(&'a str),
+    /// AES-256-GCM for proxy access and refresh tokens, per downstream.
+    Token(&'a str),
+    /// AES-256-GCM for the upstream PKCE verifier carried in `state`, per
+    /// downstream.
+    UpstreamPkce(&'a str),
+    /// AES-256-GCM for browser cookies.
+    Cookie,
+    /// AES-256-GCM for the `state` of `[server.login]` sign-ins.
+    Login,
+    /// AES-256-GCM for credentials stored in the vault, per downstream.
+    Vault(&'a str),
+    /// AES-256-GCM for upstream tokens remembered across refreshes.
+    UpstreamSession,
+}
+
+impl KeyPurpose<'_> {
+    fn info(&self) -> String {
+        let (label, downstream) = match self {
+            KeyPurpose::State(ds) => ("state-hmac", Some(ds)),
+            KeyPurpose::ClientId => ("client-id-hmac", None),
+            KeyPurpose::AuthCode(ds) => ("auth-code-aes", Some(ds)),
+            KeyPurpose::Token(ds) => ("token-aes", Some(ds)),
+            KeyPurpose::UpstreamPkce(ds) => ("upstream-pkce-aes", Some(ds)),
+            KeyPurpose::Cookie => ("cookie-aes", None),
+            KeyPurpose::Login => ("login-aes", None),
+            KeyPurpose::Vault(ds) => ("vault-aes", Some(ds)),
+            KeyPurpose::UpstreamSession => ("upstream-session-aes", None),
+        };
+        match downstream {
+            Some(ds) => format!("mcp-oauth-proxy/{FORMAT_VERSION}/{label}/{ds}"),
+            None => format!("mcp-oauth-proxy/{FORMAT_VERSION}/{label}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub id: String,
+    pub secret: Vec<u8>,
+}
+
+impl Key {
+    /// Derive the 256-bit key for `purpose` from this secret.
+    pub fn derive(&self, purpose: KeyPurpose) -> [u8; 32] {
+        let mut okm = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &self.secret)
+            .expand(purpose.info().as_bytes(), &mut okm)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        okm
+    }
+
+    /// The `v1.<kid>` envelope header for blobs made with this key.
+    pub(crate) fn header(&self) -> String {
+        format!("{FORMAT_VERSION}.{}", self.id)
+    }
+}
+
+/// Why an envelope could not be opened by [`Keyring::open`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum EnvelopeError {
+    /// Not `v1.<kid>.<body>`: an older or unknown format.
+    Version,
+    /// The body has the wrong number of segments.
+    Malformed,
+    /// The key ID is not configured (any more).
+    UnknownKey,
+}
+
+/// Ordered set of server secrets; the first one signs.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    keys: Vec<Key>,
+}
+
+impl Keyring {
+    pub fn new(keys: Vec<Key>) -> Result<Self, String> {
+        if keys.is_empty() {
+            return Err("at least one server secret is required".to_string());
+        }
+        Ok(Self { keys })
+    }
+
+    /// A keyring holding just `secret`, under [`DEFAULT_KEY_ID`].
+    pub fn single(secret: &[u8]) -> Self {
+        Self {
+            keys: vec![Key {
+                id: DEFAULT_KEY_ID.to_string(),
+                secret: secret.to_vec(),
+            }],
+        }
+    }
+
+    /// Build the keyring from `server.state_secrets`, falling back to the
+    /// single `server.state_secret`.
+    pub fn from_config(server: &ServerConfig) -> Result<Self, String> {
+        if !server.state_secrets.is_empty() {
+            return Self::new(
+                server
+                    .state_secrets
+                    .iter()
+                    .map(|k| Key {
+                        id: k.id.clone(),
+                        secret: k.secret.clone(),
+                    })
+                    .collect(),
+            );
+        }
+        match &server.state_secret {
+            Some(secret) => Ok(Self::single(secret)),
+            None => Err("server.state_secret or server.state_secrets is required".to_string()),
+        }
+    }
+
+    /// The key new codes, states and tokens are produced with.
+    pub fn current(&self) -> &Key {
+        &self.keys[0]
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Key> {
+        self.keys.iter().find(|k| k.id == id)
+    }
+
+    /// Split a `v1.<kid>.<body>` envelope whose body has `parts`
+    /// dot-separated segments, returning the key it names and the body.
+    pub(crate) fn open<'a>(
+        &self,
+        envelope: &'a str,
+        parts: usize,
+    ) -> Result<(&Key, &'a str), EnvelopeError> {
+        let (version, rest) = envelope.split_once('.').ok_or(EnvelopeError::Version)?;
+        if version != FORMAT_VERSION {
+            return Err(EnvelopeError::Version);
+        }
+        let (kid, body) = rest.split_once('.').ok_or(EnvelopeError::Malformed)?;
+        if body.split('.').count() != parts {
+            return Err(EnvelopeError::Malformed);
+        }
+        let key = self.get(kid).ok_or(EnvelopeError::UnknownKey)?;
+        Ok((key, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str, byte: u8) -> Key {
+        Key {
+            id: id.to_string(),
+            secret: vec![byte; 32],
+        }
+    }
+
+    #[test]
+    fn test_current_and_lookup() {
+        let keys = Keyring::new(vec![key("new", 1), key("old", 2)]).unwrap();
+        assert_eq!(keys.current().id, "new");
+        assert_eq!(keys.get("old").unwrap().secret, [2; 32]);
+        assert!(keys.get("gone").is_none());
+
+        assert!(Keyring::new(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_purposes_get_distinct_keys() {
+        let k = key("k1", 1);
+        let derived = [
+            k.derive(KeyPurpose::State("github")),
+            k.derive(KeyPurpose::State("linear")),
+            k.derive(KeyPurpose::ClientId),
+            k.derive(KeyPurpose::AuthCode("github")),
+            k.derive(KeyPurpose::Token("github")),
+            k.derive(KeyPurpose::UpstreamPkce("github")),
+            k.derive(KeyPurpose::Cookie),
+            k.derive(KeyPurpose::Login),
+            k.derive(KeyPurpose::Vault("github")),
+        ];
+        for (i, a) in derived.iter().enumerate() {
+            assert_ne!(a.as_slice(), k.secret.as_slice());
+            for b in &derived[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+
+        // Deterministic, and different secrets give different keys.
+        assert_eq!(k.derive(KeyPurpose::Cookie), k.derive(KeyPurpose::Cookie));
+        assert_ne!(
+            k.derive(KeyPurpose::Cookie),
+            key("k1", 2).derive(KeyPurpose::Cookie)
+        );
+    }
+
+    #[test]
+    fn test_open_envelope() {
+        let keys = Keyring::new(vec![key("k1", 1)]).unwrap();
+        let (k, body) = keys.open("v1.k1.blob", 1).unwrap();
+        assert_eq!((k.id.as_str(), body), ("k1", "blob"));
+        assert_eq!(keys.open("v1.k1.a.b", 2).unwrap().1, "a.b");
+
+        assert_eq!(keys.open("blob", 1).unwrap_err(), EnvelopeError::Version);
+        assert_eq!(keys.open("k1.blob", 1).unwrap_err(), EnvelopeError::Version);
+        assert_eq!(
+            keys.open("v2.k1.blob", 1).unwrap_err(),
+            EnvelopeError::Version
+        );
+        assert_eq!(
+            keys.open("v1.k1.a.b", 1).unwrap_err(),
+            EnvelopeError::Malformed
+        );
+        assert_eq!(
+            keys.open("v1.k9.blob", 1).unwrap_err(),
+            EnvelopeError::UnknownKey
+        );
+    }
+}
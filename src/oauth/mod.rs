@@ -1,3 +1,8 @@
 pub mod codes;
+pub mod keys;
 pub mod pkce;
+pub mod registration;
+pub mod replay;
+pub mod revocation;
 pub mod state;
+pub mod tokens;
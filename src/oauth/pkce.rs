@@ -1,3 +1,5 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
 use sha2::{Digest, Sha256};
@@ -7,9 +9,20 @@ use sha2::{Digest, Sha256};
 /// Computes `base64url_no_pad(sha256(code_verifier))` and compares
 /// it to the stored challenge.
 pub fn verify_pkce(code_verifier: &str, stored_challenge: &str) -> bool {
-    let hash = Sha256::digest(code_verifier.as_bytes());
-    let computed = URL_SAFE_NO_PAD.encode(hash);
-    computed == stored_challenge
+    s256_challenge(code_verifier) == stored_challenge
+}
+
+/// A fresh code_verifier for the proxy's own requests to upstream providers:
+/// 32 random bytes, base64url-encoded (43 characters).
+pub fn generate_verifier() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The S256 code_challenge for `code_verifier`.
+pub fn s256_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
 }
 
 #[cfg(test)]
@@ -33,4 +46,16 @@ mod tests {
             ""
         ));
     }
+
+    #[test]
+    fn test_generated_verifier() {
+        let verifier = generate_verifier();
+        // RFC 7636 §4.1: 43–128 unreserved characters.
+        assert_eq!(verifier.len(), 43);
+        assert!(verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_ne!(verifier, generate_verifier());
+        assert!(verify_pkce(&verifier, &s256_challenge(&verifier)));
+    }
 }
@@ -0,0 +1,249 @@
+//! Stateless OAuth 2.0 Dynamic Client Registration (RFC 7591).
+//!
+//! A registered client ID is an HMAC-signed blob (see [`super::state`]) that
+//! carries the client's metadata, so registration needs no storage:
+//!
+//! ```json
+//! {
+//!   "typ": "client",
+//!   "downstream": "github",
+//!   "client_name": "Claude",
+//!   "redirect_uris": ["https://claude.ai/api/mcp/auth_callback"],
+//!   "iat": 1234567890
+//! }
+//! ```
+//!
+//! `/authorize` and `/token` verify the signature and only accept redirect
+//! URIs listed in the client ID. IDs are scoped to the downstream they were
+//! registered with.
+
+use serde::{Deserialize, Serialize};
+
+use super::codes::now_secs;
+use super::keys::{KeyPurpose, Keyring};
+use super::state;
+
+/// Marker distinguishing client IDs from OAuth state blobs signed with the
+/// same key.
+const CLIENT_TYPE: &str = "client";
+
+/// Every redirect URI is signed into the client ID, which travels in each
+/// authorize URL, so registrations are kept small.
+const MAX_REDIRECT_URIS: usize = 10;
+const MAX_REDIRECT_URI_LEN: usize = 2048;
+
+/// Metadata bound into a registered client ID.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisteredClient {
+    typ: String,
+    pub downstream: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub iat: u64,
+}
+
+/// Validate the requested redirect URIs and mint a client ID binding them.
+///
+/// Returns the client ID together with the metadata it encodes.
+pub fn register_client(
+    downstream: &str,
+    client_name: Option<String>,
+    redirect_uris: Vec<String>,
+    keys: &Keyring,
+) -> Result<(String, RegisteredClient), String> {
+    if redirect_uris.is_empty() {
+        return Err("at least one redirect_uri is required".to_string());
+    }
+    if redirect_uris.len() > MAX_REDIRECT_URIS {
+        return Err(format!(
+            "at most {MAX_REDIRECT_URIS} redirect_uris may be registered"
+        ));
+    }
+    for uri in &redirect_uris {
+        validate_redirect_uri(uri)?;
+    }
+
+    let client = RegisteredClient {
+        typ: CLIENT_TYPE.to_string(),
+        downstream: downstream.to_string(),
+        client_name,
+        redirect_uris,
+        iat: now_secs()?,
+    };
+    let payload = serde_json::to_value(&client)
+        .map_err(|e| format!("failed to serialize client metadata: {e}"))?;
+
+    Ok((
+        state::sign_state(&payload, KeyPurpose::ClientId, keys),
+        client,
+    ))
+}
+
+/// Verify a client ID and return its metadata. Returns `None` for anything
+/// that is not a client ID issued by this proxy.
+pub fn verify_client_id(client_id: &str, keys: &Keyring) -> Option<RegisteredClient> {
+    let payload = state::verify_state(client_id, KeyPurpose::ClientId, keys)?;
+    let client: RegisteredClient = serde_json::from_value(payload).ok()?;
+    (client.typ == CLIENT_TYPE).then_some(client)
+}
+
+/// Check the `client_id` / `redirect_uri` pair of an authorization or token
+/// request against the registration bound into the client ID.
+///
+/// Unregistered client IDs (or none at all) are accepted unless
+/// `require_registration` is set, so clients that skip registration keep
+/// working by default.
+pub fn check_client(
+    client_id: Option<&str>,
+    downstream: &str,
+    redirect_uri: &str,
+    require_registration: bool,
+    keys: &Keyring,
+) -> Result<Option<RegisteredClient>, &'static str> {
+    let client = client_id.and_then(|id| verify_client_id(id, keys));
+
+    let Some(client) = client else {
+        if require_registration {
+            return Err("client_id is not registered");
+        }
+        return Ok(None);
+    };
+
+    if client.downstream != downstream {
+        return Err("client_id was registered for a different downstream");
+    }
+
+    if !client.redirect_uris.iter().any(|u| u == redirect_uri) {
+        return Err("redirect_uri is not registered for this client");
+    }
+
+    Ok(Some(client))
+}
+
+/// Redirect URIs must be absolute, carry no fragment (RFC 6749 §3.1.2), and
+/// use HTTPS unless they point at a loopback address. Private-use schemes for
+/// native apps are allowed.
+fn validate_redirect_uri(uri: &str) -> Result<(), String> {
+    if uri.len() > MAX_REDIRECT_URI_LEN {
+        return Err(format!(
+            "redirect_uri must be at most {MAX_REDIRECT_URI_LEN} bytes"
+        ));
+    }
+
+    let parsed =
+        reqwest::Url::parse(uri).map_err(|_| format!("redirect_uri is not a valid URL: {uri}"))?;
+
+    if parsed.fragment().is_some() {
+        return Err(format!("redirect_uri must not contain a fragment: {uri}"));
+    }
+
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" => match parsed.host_str() {
+            Some("localhost" | "127.0.0.1" | "[::1]") => Ok(()),
+            _ => Err(format!(
+                "redirect_uri must use https unless it is a loopback address: {uri}"
+            )),
+        },
+        "javascript" | "data" | "file" => Err(format!("redirect_uri scheme not allowed: {uri}")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CLAUDE_CALLBACK: &str = "https://claude.ai/api/mcp/auth_callback";
+
+    fn secret() -> Keyring {
+        Keyring::single(&[0xCC; 32])
+    }
+
+    fn register(uris: &[&str]) -> Result<String, String> {
+        register_client(
+            "github",
+            Some("Claude".to_string()),
+            uris.iter().map(|u| u.to_string()).collect(),
+            &secret(),
+        )
+        .map(|(id, _)| id)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let id = register(&[CLAUDE_CALLBACK, "http://localhost:9999/callback"]).unwrap();
+        let client = verify_client_id(&id, &secret()).unwrap();
+        assert_eq!(client.downstream, "github");
+        assert_eq!(client.client_name.as_deref(), Some("Claude"));
+        assert_eq!(client.redirect_uris.len(), 2);
+
+        assert!(verify_client_id(&id, &Keyring::single(&[0xDD; 32])).is_none());
+        assert!(verify_client_id("claude-desktop", &secret()).is_none());
+    }
+
+    #[test]
+    fn test_rejects_bad_redirect_uris() {
+        assert!(register(&[]).is_err());
+        assert!(register(&["not a url"]).is_err());
+        assert!(register(&["http://evil.example.com/cb"]).is_err());
+        assert!(register(&["https://claude.ai/cb#frag"]).is_err());
+        assert!(register(&["javascript:alert(1)"]).is_err());
+        let long = format!("https://claude.ai/{}", "a".repeat(MAX_REDIRECT_URI_LEN));
+        assert!(register(&[&long]).is_err());
+        let many: Vec<String> = (0..=MAX_REDIRECT_URIS)
+            .map(|i| format!("https://claude.ai/cb/{i}"))
+            .collect();
+        assert!(register(&many.iter().map(String::as_str).collect::<Vec<_>>()).is_err());
+
+        assert!(register(&["http://127.0.0.1:3000/cb"]).is_ok());
+        assert!(register(&["cursor://anysphere.cursor-mcp/oauth/callback"]).is_ok());
+    }
+
+    #[test]
+    fn test_state_blobs_are_not_client_ids() {
+        let signed = state::sign_state(
+            &json!({"downstream": "github", "redirect_uris": [CLAUDE_CALLBACK], "iat": 0}),
+            KeyPurpose::ClientId,
+            &secret(),
+        );
+        assert!(verify_client_id(&signed, &secret()).is_none());
+    }
+
+    #[test]
+    fn test_check_client() {
+        let id = register(&[CLAUDE_CALLBACK]).unwrap();
+
+        assert!(
+            check_client(Some(&id), "github", CLAUDE_CALLBACK, true, &secret())
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            check_client(
+                Some(&id),
+                "github",
+                "https://evil.example.com/cb",
+                false,
+                &secret()
+            )
+            .unwrap_err(),
+            "redirect_uri is not registered for this client"
+        );
+        assert!(check_client(Some(&id), "linear", CLAUDE_CALLBACK, false, &secret()).is_err());
+
+        // Unregistered clients only pass when registration is optional.
+        assert!(check_client(
+            Some("anything"),
+            "github",
+            CLAUDE_CALLBACK,
+            false,
+            &secret()
+        )
+        .unwrap()
+        .is_none());
+        assert!(check_client(None, "github", CLAUDE_CALLBACK, true, &secret()).is_err());
+    }
+}
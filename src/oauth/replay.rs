@@ -0,0 +1,76 @@
+//! Replay protection for authorization codes and proxy refresh tokens.
+//!
+//! Authorization codes are stateless (see [`super::codes`]), so on their own
+//! they could be redeemed any number of times before they expire. OAuth 2.1
+//! requires them to be single-use, so each code carries a random nonce and
+//! `/token` records it in the configured [`Store`] on first redemption; a
+//! nonce that is already recorded means the code was used before.
+//!
+//! Proxy refresh tokens are rotated the same way: each refresh records the
+//! presented token's `jti` and issues a new one. A recorded `jti` presented
+//! again means the token leaked, and `/token` revokes its grant.
+//!
+//! With the default in-memory store a code or refresh token could still be
+//! used once per replica, so multi-replica deployments should use a shared
+//! backend.
+
+use crate::storage::Store;
+
+/// Record the code `nonce` as used until the UNIX time `until`. Returns `true`
+/// if this is its first use and `false` if it was already recorded.
+pub async fn first_use(store: &dyn Store, nonce: &str, until: u64) -> Result<bool, String> {
+    store
+        .set_if_absent(&format!("used-code:{nonce}"), b"", Some(until))
+        .await
+}
+
+/// Record the proxy refresh token `jti` as used until the UNIX time `until`.
+/// Returns `true` if this is its first use and `false` if it was already
+/// recorded.
+pub async fn first_refresh(store: &dyn Store, jti: &str, until: u64) -> Result<bool, String> {
+    store
+        .set_if_absent(&format!("used-refresh:{jti}"), b"", Some(until))
+        .await
+}
+
+/// Whether the proxy refresh token `jti` has been used.
+pub async fn is_refresh_used(store: &dyn Store, jti: &str) -> Result<bool, String> {
+    Ok(store.get(&format!("used-refresh:{jti}")).await?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth::codes::now_secs;
+    use crate::storage::MemoryStore;
+
+    #[tokio::test]
+    async fn test_second_use_is_rejected() {
+        let store = MemoryStore::default();
+        let until = now_secs().unwrap() + 60;
+
+        assert!(first_use(&store, "a", until).await.unwrap());
+        assert!(!first_use(&store, "a", until).await.unwrap());
+        assert!(first_use(&store, "b", until).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens_are_single_use() {
+        let store = MemoryStore::default();
+        let until = now_secs().unwrap() + 60;
+
+        assert!(!is_refresh_used(&store, "r1").await.unwrap());
+        assert!(first_refresh(&store, "r1", until).await.unwrap());
+        assert!(is_refresh_used(&store, "r1").await.unwrap());
+        assert!(!first_refresh(&store, "r1", until).await.unwrap());
+        // Codes and refresh tokens do not share IDs.
+        assert!(first_use(&store, "r1", until).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_expired_nonces_can_be_reused() {
+        let store = MemoryStore::default();
+        assert!(first_use(&store, "old", 0).await.unwrap());
+        assert!(first_use(&store, "old", 0).await.unwrap());
+    }
+}
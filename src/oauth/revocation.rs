@@ -0,0 +1,106 @@
+//! Denylist for revoked proxy-issued tokens (RFC 7009).
+//!
+//! Proxy tokens are self-contained, so revoking one means remembering it until
+//! it would have expired anyway. Access tokens are revoked by their `jti`;
+//! revoking a proxy refresh token revokes its whole grant, which also covers
+//! every access token issued under it.
+//!
+//! Entries live in the configured [`Store`]. With the default in-memory store
+//! a revocation only takes effect on the instance that received it; a shared
+//! backend makes it visible to every replica.
+
+use std::sync::Arc;
+
+use crate::storage::Store;
+
+pub struct Denylist {
+    store: Arc<dyn Store>,
+}
+
+impl Denylist {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        Self { store }
+    }
+
+    /// Revoke a single access token.
+    pub async fn revoke_token(&self, jti: &str, until: u64) -> Result<(), String> {
+        self.store
+            .set(&format!("revoked:jti:{jti}"), b"", Some(until))
+            .await
+    }
+
+    /// Revoke every token belonging to a proxy-managed grant.
+    pub async fn revoke_grant(&self, grant_id: &str, until: u64) -> Result<(), String> {
+        self.store
+            .set(&format!("revoked:grant:{grant_id}"), b"", Some(until))
+            .await
+    }
+
+    pub async fn is_token_revoked(&self, jti: &str) -> bool {
+        self.contains(&format!("revoked:jti:{jti}")).await
+    }
+
+    pub async fn is_grant_revoked(&self, grant_id: &str) -> bool {
+        self.contains(&format!("revoked:grant:{grant_id}")).await
+    }
+
+    /// Whether an access token was revoked, by itself or through its grant.
+    pub async fn is_access_token_revoked(&self, jti: &str, grant_id: Option<&str>) -> bool {
+        if self.is_token_revoked(jti).await {
+            return true;
+        }
+        match grant_id {
+            Some(grant_id) => self.is_grant_revoked(grant_id).await,
+            None => false,
+        }
+    }
+
+    /// Fails closed: if the store cannot be read, the token counts as revoked.
+    async fn contains(&self, key: &str) -> bool {
+        match self.store.get(key).await {
+            Ok(entry) => entry.is_some(),
+            Err(e) => {
+                tracing::error!(error = %e, "Denylist lookup failed, rejecting token");
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth::codes::now_secs;
+    use crate::storage::MemoryStore;
+
+    fn denylist() -> Denylist {
+        Denylist::new(Arc::new(MemoryStore::default()))
+    }
+
+    #[tokio::test]
+    async fn test_tokens_and_grants_are_separate() {
+        let denylist = denylist();
+        let until = now_secs().unwrap() + 60;
+
+        denylist.revoke_token("abc", until).await.unwrap();
+        assert!(denylist.is_token_revoked("abc").await);
+        assert!(!denylist.is_grant_revoked("abc").await);
+
+        denylist.revoke_grant("g1", until).await.unwrap();
+        assert!(denylist.is_grant_revoked("g1").await);
+        assert!(!denylist.is_token_revoked("g1").await);
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_ignored() {
+        let denylist = denylist();
+        denylist.revoke_token("old", 0).await.unwrap();
+        denylist
+            .revoke_token("new", now_secs().unwrap() + 60)
+            .await
+            .unwrap();
+
+        assert!(!denylist.is_token_revoked("old").await);
+        assert!(denylist.is_token_revoked("new").await);
+    }
+}
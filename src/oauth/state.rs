@@ -4,32 +4,49 @@ use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use super::keys::{Key, KeyPurpose, Keyring};
+
 type HmacSha256 = Hmac<Sha256>;
 
-/// Sign a JSON payload with HMAC-SHA256, returning `base64url(json).base64url(hmac)`.
-pub fn sign_state(payload: &serde_json::Value, secret: &[u8]) -> String {
+/// HMAC-SHA256 over `signed` with `key`'s subkey for `purpose`.
+fn mac(key: &Key, purpose: KeyPurpose, signed: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(&key.derive(purpose)).expect("HMAC accepts any key length");
+    mac.update(signed.as_bytes());
+    mac
+}
+
+/// Sign a JSON payload with HMAC-SHA256 under the current key, returning
+/// `v1.kid.base64url(json).base64url(hmac)`. The MAC covers everything
+/// before the signature, header included.
+pub fn sign_state(payload: &serde_json::Value, purpose: KeyPurpose, keys: &Keyring) -> String {
     let json = serde_json::to_string(payload).expect("failed to serialize state payload");
-    let payload_b64 = URL_SAFE_NO_PAD.encode(json.as_bytes());
+    let key = keys.current();
+    let signed = format!(
+        "{}.{}",
+        key.header(),
+        URL_SAFE_NO_PAD.encode(json.as_bytes())
+    );
 
-    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
-    mac.update(json.as_bytes());
-    let sig = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    let sig = URL_SAFE_NO_PAD.encode(mac(key, purpose, &signed).finalize().into_bytes());
 
-    format!("{payload_b64}.{sig}")
+    format!("{signed}.{sig}")
 }
 
-/// Verify an HMAC-signed state string produced by `sign_state`.
+/// Verify an HMAC-signed state string produced by `sign_state` for the same
+/// `purpose`.
 ///
-/// Returns `None` if the HMAC is invalid, the payload is not valid JSON,
-/// or the embedded `exp` field (if present) has passed.
-pub fn verify_state(state: &str, secret: &[u8]) -> Option<serde_json::Value> {
-    let (payload_b64, sig_b64) = state.rsplit_once('.')?;
-    let payload_bytes = URL_SAFE_NO_PAD.decode(payload_b64).ok()?;
-
-    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
-    mac.update(&payload_bytes);
+/// Returns `None` if the format or key ID is not recognised, the HMAC is
+/// invalid, the payload is not valid JSON, or the embedded `exp` field (if
+/// present) has passed.
+pub fn verify_state(state: &str, purpose: KeyPurpose, keys: &Keyring) -> Option<serde_json::Value> {
+    let (key, body) = keys.open(state, 2).ok()?;
+    let (payload_b64, sig_b64) = body.split_once('.')?;
+    let (signed, _) = state.rsplit_once('.')?;
     let expected_sig = URL_SAFE_NO_PAD.decode(sig_b64).ok()?;
-    mac.verify_slice(&expected_sig).ok()?;
+    mac(key, purpose, signed).verify_slice(&expected_sig).ok()?;
+
+    let payload_bytes = URL_SAFE_NO_PAD.decode(payload_b64).ok()?;
 
     let value: serde_json::Value = serde_json::from_slice(&payload_bytes).ok()?;
 
@@ -49,7 +66,11 @@ mod tests {
     use super::*;
     use serde_json::json;
 
-    const SECRET: &[u8] = &[0xCC; 32];
+    const PURPOSE: KeyPurpose<'static> = KeyPurpose::State("github");
+
+    fn secret() -> Keyring {
+        Keyring::single(&[0xCC; 32])
+    }
 
     #[test]
     fn test_round_trip() {
@@ -58,24 +79,54 @@ mod tests {
             "code_challenge": "abc123",
             "client_state": "xyz"
         });
-        let signed = sign_state(&payload, SECRET);
-        assert_eq!(verify_state(&signed, SECRET).unwrap(), payload);
+        let signed = sign_state(&payload, PURPOSE, &secret());
+        assert_eq!(verify_state(&signed, PURPOSE, &secret()).unwrap(), payload);
     }
 
     #[test]
     fn test_integrity_checks() {
         let payload = json!({"key": "value"});
-        let signed = sign_state(&payload, SECRET);
+        let signed = sign_state(&payload, PURPOSE, &secret());
 
         // Tampered payload
-        let dot_pos = signed.find('.').unwrap();
+        let payload_start = signed.match_indices('.').nth(1).unwrap().0 + 1;
         let mut tampered = signed.into_bytes();
-        tampered[dot_pos / 2] ^= 1;
-        assert!(verify_state(&String::from_utf8(tampered).unwrap(), SECRET).is_none());
+        tampered[payload_start + 2] ^= 1;
+        assert!(verify_state(&String::from_utf8(tampered).unwrap(), PURPOSE, &secret()).is_none());
 
         // Wrong secret
-        let signed = sign_state(&payload, &[0xAA; 32]);
-        assert!(verify_state(&signed, &[0xBB; 32]).is_none());
+        let signed = sign_state(&payload, PURPOSE, &Keyring::single(&[0xAA; 32]));
+        assert!(verify_state(&signed, PURPOSE, &Keyring::single(&[0xBB; 32])).is_none());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let key = |id: &str, byte: u8| Key {
+            id: id.to_string(),
+            secret: vec![byte; 32],
+        };
+        let old = Keyring::new(vec![key("k1", 0xAA)]).unwrap();
+        let rotated = Keyring::new(vec![key("k2", 0xBB), key("k1", 0xAA)]).unwrap();
+        let payload = json!({"key": "value"});
+
+        let signed = sign_state(&payload, PURPOSE, &old);
+        assert!(signed.starts_with("v1.k1."));
+        assert_eq!(verify_state(&signed, PURPOSE, &rotated).unwrap(), payload);
+
+        // A relabelled key ID does not let another key's signature through.
+        let relabelled = signed.replacen("k1.", "k2.", 1);
+        assert!(verify_state(&relabelled, PURPOSE, &rotated).is_none());
+
+        // Unversioned states from older releases are rejected.
+        let legacy = signed.strip_prefix("v1.").unwrap();
+        assert!(verify_state(legacy, PURPOSE, &rotated).is_none());
+    }
+
+    #[test]
+    fn test_purposes_do_not_verify_each_other() {
+        let signed = sign_state(&json!({"key": "value"}), PURPOSE, &secret());
+        assert!(verify_state(&signed, KeyPurpose::State("linear"), &secret()).is_none());
+        assert!(verify_state(&signed, KeyPurpose::ClientId, &secret()).is_none());
     }
 
     #[test]
@@ -85,17 +136,17 @@ mod tests {
             .unwrap()
             .as_secs();
 
-        let expired = sign_state(&json!({"exp": 0}), SECRET);
-        assert!(verify_state(&expired, SECRET).is_none());
+        let expired = sign_state(&json!({"exp": 0}), PURPOSE, &secret());
+        assert!(verify_state(&expired, PURPOSE, &secret()).is_none());
 
-        let valid = sign_state(&json!({"exp": now + 3600}), SECRET);
-        assert!(verify_state(&valid, SECRET).is_some());
+        let valid = sign_state(&json!({"exp": now + 3600}), PURPOSE, &secret());
+        assert!(verify_state(&valid, PURPOSE, &secret()).is_some());
     }
 
     #[test]
     fn test_malformed_input_returns_none() {
-        assert!(verify_state("", SECRET).is_none());
-        assert!(verify_state("nodothere", SECRET).is_none());
-        assert!(verify_state("!!!.!!!", SECRET).is_none());
+        assert!(verify_state("", PURPOSE, &secret()).is_none());
+        assert!(verify_state("nodothere", PURPOSE, &secret()).is_none());
+        assert!(verify_state("!!!.!!!", PURPOSE, &secret()).is_none());
     }
 }
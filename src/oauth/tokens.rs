@@ -0,0 +1,281 @@
+//! Proxy-issued access and refresh tokens.
+//!
+//! The token endpoint never hands the downstream credential to the client.
+//! Instead it mints an opaque access token: an AES-256-GCM encrypted blob,
+//! produced with the same machinery as authorization codes, that wraps the
+//! downstream credential together with the downstream name and an expiry.
+//! The MCP proxy decrypts it on every request before remapping the auth
+//! header, so a leaked token is useless anywhere except through this proxy.
+//!
+//! When a chained OAuth downstream uses proxy-managed refresh, the upstream
+//! refresh token is likewise wrapped in a proxy refresh token, and both tokens
+//! carry a grant ID that ties them to the proxy's view of the latest upstream
+//! tokens (see [`crate::auth::sessions`]).
+//!
+//! The plaintext is JSON:
+//! ```json
+//! {
+//!   "typ": "access",
+//!   "jti": "...",
+//!   "downstream": "github",
+//!   "client_id": "...",
+//!   "resource": "https://proxy.example.com/mcp/github",
+//!   "user": { "sub": "...", "email": "..." },
+//!   "downstream_tokens": { ... },
+//!   "grant_id": "...",
+//!   "downstream_exp": 1234567000,
+//!   "exp": 1234567890
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use super::codes::{self, DecryptError, DownstreamTokens, GrantBinding};
+use super::keys::{KeyPurpose, Keyring};
+
+/// Distinguishes token payloads from each other and from authorization code
+/// payloads, which are sealed under the same key.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum TokenType {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenPayload<T> {
+    typ: TokenType,
+    #[serde(flatten)]
+    claims: T,
+}
+
+/// Contents of a proxy access token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessTokenClaims {
+    /// Unique token ID, recorded in the revocation denylist.
+    pub jti: String,
+    /// Downstream and client the token was issued to.
+    #[serde(flatten)]
+    pub binding: GrantBinding,
+    pub downstream_tokens: DownstreamTokens,
+    /// Grant this token belongs to, when the proxy manages upstream refresh.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grant_id: Option<String>,
+    /// When the wrapped downstream access token expires, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downstream_exp: Option<u64>,
+    pub exp: u64,
+}
+
+/// Contents of a proxy refresh token (proxy-managed refresh only).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenClaims {
+    /// Unique token ID, recorded when the token is used: each proxy refresh
+    /// token is good for one refresh (see [`super::replay`]).
+    pub jti: String,
+    #[serde(flatten)]
+    pub binding: GrantBinding,
+    pub grant_id: String,
+    /// The upstream refresh token as of when this token was issued. The proxy
+    /// prefers a newer one from its session cache if the provider rotated it.
+    pub upstream_refresh_token: String,
+    pub exp: u64,
+}
+
+/// Mint an encrypted access token from `claims`, sealed under the token key
+/// of the downstream it is bound to.
+pub fn issue_access_token(claims: AccessTokenClaims, keys: &Keyring) -> Result<String, String> {
+    let purpose = KeyPurpose::Token(&claims.binding.downstream);
+    codes::encrypt_payload(
+        &TokenPayload {
+            typ: TokenType::Access,
+            claims: &claims,
+        },
+        purpose,
+        keys,
+    )
+}
+
+/// Mint an encrypted refresh token from `claims`.
+pub fn issue_refresh_token(claims: RefreshTokenClaims, keys: &Keyring) -> Result<String, String> {
+    let purpose = KeyPurpose::Token(&claims.binding.downstream);
+    codes::encrypt_payload(
+        &TokenPayload {
+            typ: TokenType::Refresh,
+            claims: &claims,
+        },
+        purpose,
+        keys,
+    )
+}
+
+/// Decrypt and validate a proxy access token presented at `downstream`.
+pub fn validate_access_token(
+    token: &str,
+    downstream: &str,
+    keys: &Keyring,
+) -> Result<AccessTokenClaims, &'static str> {
+    let claims: AccessTokenClaims = open_token(token, TokenType::Access, downstream, keys)
+        .map_err(|e| e.unwrap_or("access token is invalid"))?;
+
+    if codes::now_secs().map_err(|_| "system time error")? > claims.exp {
+        return Err("access token expired");
+    }
+
+    Ok(claims)
+}
+
+/// Decrypt and validate a proxy refresh token presented at `downstream`.
+pub fn validate_refresh_token(
+    token: &str,
+    downstream: &str,
+    keys: &Keyring,
+) -> Result<RefreshTokenClaims, &'static str> {
+    let claims: RefreshTokenClaims = open_token(token, TokenType::Refresh, downstream, keys)
+        .map_err(|e| e.unwrap_or("refresh token is invalid"))?;
+
+    if codes::now_secs().map_err(|_| "system time error")? > claims.exp {
+        return Err("refresh token expired");
+    }
+
+    Ok(claims)
+}
+
+/// Decrypt a token and check its type. `Err(None)` means the blob could not
+/// be opened at all; the caller supplies the message for that case.
+fn open_token<T: serde::de::DeserializeOwned>(
+    token: &str,
+    expected: TokenType,
+    downstream: &str,
+    keys: &Keyring,
+) -> Result<T, Option<&'static str>> {
+    let payload: TokenPayload<serde_json::Value> =
+        codes::decrypt_payload(token, KeyPurpose::Token(downstream), keys).map_err(
+            |e| match e {
+                DecryptError::Version => Some("token format is not supported"),
+                DecryptError::Malformed | DecryptError::Tampered => None,
+                DecryptError::Corrupt => Some("token payload corrupt"),
+            },
+        )?;
+
+    if payload.typ != expected {
+        return Err(Some("wrong token type"));
+    }
+
+    serde_json::from_value(payload.claims).map_err(|_| Some("token payload corrupt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_secret() -> Keyring {
+        Keyring::single(&[0xAA; 32])
+    }
+
+    fn passthrough_claims(ttl: u64) -> AccessTokenClaims {
+        AccessTokenClaims {
+            jti: codes::random_id(),
+            binding: GrantBinding {
+                downstream: "linear".to_string(),
+                client_id: Some("client-1".to_string()),
+                resource: None,
+                user: None,
+            },
+            downstream_tokens: DownstreamTokens::Passthrough {
+                access_token: "lin_api_key".to_string(),
+            },
+            grant_id: None,
+            downstream_exp: None,
+            exp: codes::now_secs().unwrap() + ttl,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let secret = test_secret();
+        let token = issue_access_token(passthrough_claims(3600), &secret).unwrap();
+        assert!(!token.contains("lin_api_key"));
+
+        let claims = validate_access_token(&token, "linear", &secret).unwrap();
+        assert_eq!(claims.binding.downstream, "linear");
+        assert_eq!(claims.binding.client_id.as_deref(), Some("client-1"));
+        assert_eq!(claims.downstream_tokens.access_token(), "lin_api_key");
+    }
+
+    #[test]
+    fn test_token_types_are_not_interchangeable() {
+        let secret = test_secret();
+        let code = codes::create_auth_code(
+            DownstreamTokens::Passthrough {
+                access_token: "key".to_string(),
+            },
+            GrantBinding {
+                downstream: "linear".to_string(),
+                client_id: None,
+                resource: None,
+                user: None,
+            },
+            "challenge",
+            "http://localhost/cb",
+            300,
+            &secret,
+        )
+        .unwrap();
+        assert!(validate_access_token(&code, "linear", &secret).is_err());
+        assert!(validate_refresh_token(&code, "linear", &secret).is_err());
+
+        let refresh = issue_refresh_token(
+            RefreshTokenClaims {
+                jti: codes::random_id(),
+                binding: GrantBinding {
+                    downstream: "github".to_string(),
+                    client_id: None,
+                    resource: None,
+                    user: None,
+                },
+                grant_id: "g1".to_string(),
+                upstream_refresh_token: "gh-refresh".to_string(),
+                exp: codes::now_secs().unwrap() + 3600,
+            },
+            &secret,
+        )
+        .unwrap();
+        assert_eq!(
+            validate_access_token(&refresh, "github", &secret).unwrap_err(),
+            "wrong token type"
+        );
+        let claims = validate_refresh_token(&refresh, "github", &secret).unwrap();
+        assert_eq!(claims.grant_id, "g1");
+        assert_eq!(claims.upstream_refresh_token, "gh-refresh");
+
+        let access = issue_access_token(passthrough_claims(3600), &secret).unwrap();
+        assert!(validate_refresh_token(&access, "linear", &secret).is_err());
+    }
+
+    #[test]
+    fn test_rejects_expired_and_foreign_tokens() {
+        let secret = test_secret();
+
+        let token = issue_access_token(passthrough_claims(3600), &secret).unwrap();
+        assert_eq!(
+            validate_access_token(&token, "linear", &Keyring::single(&[0xBB; 32])).unwrap_err(),
+            "access token is invalid"
+        );
+        assert_eq!(
+            validate_access_token(&token, "github", &secret).unwrap_err(),
+            "access token is invalid"
+        );
+        assert!(validate_access_token("raw-downstream-api-key", "linear", &secret).is_err());
+
+        let expired = AccessTokenClaims {
+            exp: codes::now_secs().unwrap() - 1,
+            ..passthrough_claims(0)
+        };
+        let token = issue_access_token(expired, &secret).unwrap();
+        assert_eq!(
+            validate_access_token(&token, "linear", &secret).unwrap_err(),
+            "access token expired"
+        );
+    }
+}
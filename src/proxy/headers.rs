@@ -1,3 +1,40 @@
+use std::collections::BTreeMap;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::oauth::codes::now_secs;
+use crate::proxy::sigv4::{self, AwsCredentials, SigningScope};
+
+/// How requests to a downstream are authenticated.
+pub enum DownstreamAuth<'a> {
+    /// Headers from [`remap_auth_header`] or rendered [`HeaderTemplate`]s.
+    Headers(Vec<(String, String)>),
+    /// An AWS Signature Version 4 over each whole request.
+    Sigv4(AwsCredentials, SigningScope<'a>),
+}
+
+impl DownstreamAuth<'_> {
+    /// Add the headers or signature to a built request.
+    pub fn apply(&self, request: &mut reqwest::Request) -> Result<(), String> {
+        match self {
+            DownstreamAuth::Headers(headers) => {
+                for (name, value) in headers {
+                    let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|e| format!("invalid auth header name: {e}"))?;
+                    let value = reqwest::header::HeaderValue::from_str(value)
+                        .map_err(|e| format!("invalid auth header value: {e}"))?;
+                    request.headers_mut().insert(name, value);
+                }
+                Ok(())
+            }
+            DownstreamAuth::Sigv4(credentials, scope) => {
+                sigv4::sign_request(request, credentials, scope, now_secs()?)
+            }
+        }
+    }
+}
+
 /// Remap a bearer token into the downstream auth header format.
 ///
 /// Given a downstream's `auth_header_format` config and the user's token,
@@ -19,6 +56,125 @@ pub fn remap_auth_header(auth_header_format: &str, token: &str) -> (String, Stri
     }
 }
 
+/// A header value built from the fields of a multi-field passthrough form,
+/// such as `Basic {base64({user}:{password})}` or `{workspace}`.
+///
+/// `{name}` is replaced by the field's value (empty if an optional field was
+/// left blank) and `{base64(...)}` by the standard base64 encoding of the
+/// template inside it. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderTemplate(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(String),
+    Base64(Vec<Part>),
+}
+
+impl HeaderTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut chars = template.chars().peekable();
+        let parts = parse_parts(&mut chars, false)?;
+        Ok(HeaderTemplate(parts))
+    }
+
+    /// The field names the template refers to.
+    pub fn fields(&self) -> Vec<&str> {
+        fn collect<'a>(parts: &'a [Part], out: &mut Vec<&'a str>) {
+            for part in parts {
+                match part {
+                    Part::Literal(_) => {}
+                    Part::Field(name) => out.push(name),
+                    Part::Base64(inner) => collect(inner, out),
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.0, &mut out);
+        out
+    }
+
+    pub fn render(&self, fields: &BTreeMap<String, String>) -> String {
+        fn render_parts(parts: &[Part], fields: &BTreeMap<String, String>) -> String {
+            parts
+                .iter()
+                .map(|part| match part {
+                    Part::Literal(text) => text.clone(),
+                    Part::Field(name) => fields.get(name).cloned().unwrap_or_default(),
+                    Part::Base64(inner) => STANDARD.encode(render_parts(inner, fields)),
+                })
+                .collect()
+        }
+        render_parts(&self.0, fields)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for HeaderTemplate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let template = String::deserialize(deserializer)?;
+        HeaderTemplate::parse(&template).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parse up to the end of the template or, inside a function, its `)`.
+fn parse_parts(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    in_function: bool,
+) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    loop {
+        match chars.next() {
+            None if in_function => return Err("unclosed base64(".to_string()),
+            None => break,
+            Some(')') if in_function => break,
+            Some('{') if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            Some('}') if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            Some('}') => return Err("unmatched '}' (write '}}' for a literal brace)".to_string()),
+            Some('{') => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                if name.is_empty() {
+                    return Err("expected a field name after '{'".to_string());
+                }
+                if chars.peek() == Some(&'(') {
+                    chars.next();
+                    if name != "base64" {
+                        return Err(format!("unknown function '{name}'"));
+                    }
+                    parts.push(Part::Base64(parse_parts(chars, true)?));
+                } else {
+                    parts.push(Part::Field(name));
+                }
+                if chars.next() != Some('}') {
+                    return Err("expected '}'".to_string());
+                }
+            }
+            Some(c) => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,4 +203,45 @@ mod tests {
             ("X-Custom-Auth", "my-secret")
         );
     }
+
+    #[test]
+    fn test_header_templates() {
+        let fields = BTreeMap::from([
+            ("user".to_string(), "ada@example.com".to_string()),
+            ("password".to_string(), "s3cret".to_string()),
+            ("workspace".to_string(), "acme".to_string()),
+        ]);
+        let render = |t: &str| HeaderTemplate::parse(t).unwrap().render(&fields);
+
+        assert_eq!(
+            render("Basic {base64({user}:{password})}"),
+            format!("Basic {}", STANDARD.encode("ada@example.com:s3cret"))
+        );
+        assert_eq!(render("{workspace}"), "acme");
+        assert_eq!(
+            render("Token {password}; ws={workspace}"),
+            "Token s3cret; ws=acme"
+        );
+        assert_eq!(render("{{literal}} {missing}"), "{literal} ");
+
+        let template = HeaderTemplate::parse("Basic {base64({user}:{password})}").unwrap();
+        assert_eq!(template.fields(), ["user", "password"]);
+    }
+
+    #[test]
+    fn test_invalid_header_templates() {
+        for (template, error) in [
+            ("{user", "expected '}'"),
+            ("user}", "unmatched '}'"),
+            ("{}", "expected a field name"),
+            ("{base64({user}", "unclosed base64("),
+            ("{hex({user})}", "unknown function 'hex'"),
+            ("{user name}", "expected '}'"),
+        ] {
+            assert!(
+                HeaderTemplate::parse(template).unwrap_err().contains(error),
+                "{template}"
+            );
+        }
+    }
 }
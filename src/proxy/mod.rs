@@ -1,2 +1,3 @@
 pub mod headers;
+pub mod sigv4;
 pub mod sse;
@@ -2,27 +2,41 @@ use axum::body::Body;
 use axum::http::StatusCode;
 use axum::response::Response;
 
+use crate::proxy::headers::DownstreamAuth;
+
+/// Authenticate a request to `downstream_url` and send it.
+async fn send(
+    downstream_url: &str,
+    request: reqwest::RequestBuilder,
+    auth: &DownstreamAuth<'_>,
+    client: &reqwest::Client,
+) -> Result<reqwest::Response, StatusCode> {
+    let connect_failed = |e: reqwest::Error| {
+        tracing::error!(url = %downstream_url, error = %e, "Failed to connect to downstream");
+        StatusCode::BAD_GATEWAY
+    };
+    let mut request = request.build().map_err(connect_failed)?;
+    auth.apply(&mut request).map_err(|e| {
+        tracing::error!(url = %downstream_url, error = %e, "Failed to authenticate downstream request");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    client.execute(request).await.map_err(connect_failed)
+}
+
 /// Proxy an SSE connection to a downstream MCP server using raw byte passthrough.
 ///
-/// Opens a streaming GET to `downstream_url` with the given auth header and
+/// Opens a streaming GET to `downstream_url`, authenticated by `auth`, with
 /// `Accept: text/event-stream`. Returns the raw byte stream as an SSE response,
 /// preserving the exact framing from downstream.
 pub async fn proxy_sse(
     downstream_url: &str,
-    auth_header_name: &str,
-    auth_header_value: &str,
+    auth: &DownstreamAuth<'_>,
     client: &reqwest::Client,
 ) -> Result<Response, StatusCode> {
-    let resp = client
+    let request = client
         .get(downstream_url)
-        .header(auth_header_name, auth_header_value)
-        .header("Accept", "text/event-stream")
-        .send()
-        .await
-        .map_err(|e| {
-            tracing::error!(url = %downstream_url, error = %e, "Failed to connect to downstream");
-            StatusCode::BAD_GATEWAY
-        })?;
+        .header("Accept", "text/event-stream");
+    let resp = send(downstream_url, request, auth, client).await?;
 
     if !resp.status().is_success() {
         let status = resp.status();
@@ -48,22 +62,15 @@ pub async fn proxy_sse(
 /// Forward a POST request body to a downstream MCP server and return the response.
 pub async fn proxy_post(
     downstream_url: &str,
-    auth_header_name: &str,
-    auth_header_value: &str,
+    auth: &DownstreamAuth<'_>,
     body: axum::body::Bytes,
     client: &reqwest::Client,
 ) -> Result<Response, StatusCode> {
-    let resp = client
+    let request = client
         .post(downstream_url)
-        .header(auth_header_name, auth_header_value)
         .header("Content-Type", "application/json")
-        .body(body)
-        .send()
-        .await
-        .map_err(|e| {
-            tracing::error!(url = %downstream_url, error = %e, "Failed to connect to downstream");
-            StatusCode::BAD_GATEWAY
-        })?;
+        .body(body);
+    let resp = send(downstream_url, request, auth, client).await?;
 
     if !resp.status().is_success() {
         let status = resp.status();
@@ -1,23 +1,42 @@
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse, Redirect};
+use axum::extract::{Path, Query, RawQuery, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::Form;
 use serde::Deserialize;
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::auth::chained_oauth;
-use crate::config::StrategyConfig;
-use crate::oauth::codes::{self, DownstreamTokens};
-use crate::oauth::state;
+use crate::auth::login::{self, Access};
+use crate::auth::{chained_oauth, github_app, oidc, vault};
+use crate::config::{CredentialField, FieldKind, Gate, StrategyConfig};
+use crate::oauth::codes::{self, DownstreamTokens, GrantBinding, UserIdentity};
+use crate::oauth::keys::KeyPurpose;
+use crate::oauth::{pkce, registration, state};
+use crate::proxy::sigv4::AwsCredentials;
+use crate::routes::login::redirect_to_login;
 use crate::AppState;
 
 const OAUTH_STATE_TTL_SECS: u64 = 600;
 
+/// Stylesheet shared by the proxy's HTML pages.
+pub(crate) const PAGE_STYLE: &str = r#"
+    body { font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, sans-serif; max-width: 480px; margin: 60px auto; padding: 0 20px; background: #f5f5f5; }
+    .card { background: white; border-radius: 8px; padding: 32px; box-shadow: 0 2px 8px rgba(0,0,0,0.1); }
+    h1 { font-size: 1.4em; margin: 0 0 8px 0; }
+    .hint { color: #666; margin: 0 0 20px 0; }
+    label { display: block; font-weight: 600; margin-bottom: 6px; }
+    input[type="password"], input[type="text"] { width: 100%; padding: 10px; border: 1px solid #ccc; border-radius: 4px; font-size: 1em; box-sizing: border-box; }
+    input + label, .field-hint + label { margin-top: 16px; }
+    .field-hint { color: #666; font-size: 0.85em; margin: 4px 0 0 0; }
+    button { margin-top: 16px; width: 100%; padding: 12px; background: #2563eb; color: white; border: none; border-radius: 4px; font-size: 1em; cursor: pointer; }
+    button:hover { background: #1d4ed8; }
+"#;
+
 #[derive(Deserialize)]
 pub struct AuthorizeQuery {
     response_type: Option<String>,
-    #[allow(dead_code)]
     client_id: Option<String>,
     redirect_uri: Option<String>,
     state: Option<String>,
@@ -25,6 +44,8 @@ pub struct AuthorizeQuery {
     code_challenge_method: Option<String>,
     #[allow(dead_code)]
     scope: Option<String>,
+    /// RFC 8707 resource indicator; must be this downstream's MCP URL.
+    resource: Option<String>,
 }
 
 /// GET /authorize/mcp/:name — show authorization page
@@ -32,6 +53,8 @@ pub async fn authorize_get(
     State(state): State<AppState>,
     Path(name): Path<String>,
     Query(params): Query<AuthorizeQuery>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     let Some(ds) = state.find_downstream(&name) else {
         return (StatusCode::NOT_FOUND, "Unknown downstream").into_response();
@@ -61,15 +84,190 @@ pub async fn authorize_get(
             .into_response();
     }
 
-    tracing::info!(downstream = %name, strategy = ?ds.strategy, "Authorize request");
+    let client = match registration::check_client(
+        params.client_id.as_deref(),
+        &name,
+        redirect_uri,
+        state.config.server.require_client_registration,
+        state.keys(),
+    ) {
+        Ok(c) => c,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    if params
+        .resource
+        .as_deref()
+        .is_some_and(|r| r != state.resource_url(&name))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            "resource does not match this downstream",
+        )
+            .into_response();
+    }
+
+    tracing::info!(downstream = %name, strategy = ds.strategy.name(), "Authorize request");
 
     match &ds.strategy {
-        StrategyConfig::Passthrough { auth_hint } => {
-            let auth_hint = match auth_hint {
-                Some(hint) => html_escape(hint),
-                None => "Enter your API token or key for this service.".to_string(),
+        StrategyConfig::Passthrough { auth_hint, .. }
+        | StrategyConfig::Vault { auth_hint }
+        | StrategyConfig::StaticCredential { auth_hint, .. }
+        | StrategyConfig::ClientCredentials { auth_hint, .. }
+        | StrategyConfig::GithubApp {
+            auth_hint,
+            gate: Gate::Sso | Gate::Password,
+            ..
+        }
+        | StrategyConfig::JwtBearer { auth_hint, .. }
+        | StrategyConfig::AwsSigv4 { auth_hint, .. } => {
+            let password_gate = ds.strategy.gate() == Some(Gate::Password);
+            let user = match login::check(&state, ds, &headers) {
+                _ if password_gate => None,
+                Access::Anonymous => None,
+                Access::User(user) => Some(user),
+                Access::LoginRequired => {
+                    let return_to = format!(
+                        "{}/authorize/mcp/{}?{}",
+                        state.config.server.public_url,
+                        name,
+                        raw_query.unwrap_or_default()
+                    );
+                    return redirect_to_login(&state, &return_to);
+                }
+                Access::Denied(e) => {
+                    tracing::warn!(downstream = %name, reason = %e, "Sign-in not allowed");
+                    return (StatusCode::FORBIDDEN, e).into_response();
+                }
             };
 
+            let is_vault = matches!(ds.strategy, StrategyConfig::Vault { .. });
+            let aws_credentials = matches!(ds.strategy, StrategyConfig::AwsSigv4 { .. })
+                && ds.strategy.gate().is_none();
+            let sso_gate = ds.strategy.gate() == Some(Gate::Sso);
+            let binding = |user: &UserIdentity| GrantBinding {
+                downstream: name.clone(),
+                client_id: params.client_id.clone(),
+                resource: params.resource.clone(),
+                user: Some(user.clone()),
+            };
+
+            // Without a form to fill in, a code may go out on this GET alone,
+            // but only to a redirect_uri the operator trusts. Anyone can
+            // register a client or craft a link with their own redirect_uri,
+            // and the session cookie comes along, so the user confirms on a
+            // page instead.
+            let trusted = state
+                .config
+                .server
+                .trusted_redirect_uris
+                .iter()
+                .any(|u| u == redirect_uri);
+
+            if sso_gate && trusted {
+                // The proxy holds the credential; signing in is all it takes.
+                let Some(user) = &user else {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+                };
+                return issue_code(
+                    &state,
+                    DownstreamTokens::ProxyCredential,
+                    binding(user),
+                    code_challenge,
+                    redirect_uri,
+                    oauth_state,
+                );
+            }
+
+            let mut on_file = false;
+            if is_vault {
+                // Validation guarantees [server.login] for vault downstreams.
+                let Some(user) = &user else {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+                };
+                // A credential on file is not asked for again, but only the
+                // consent POST releases it.
+                match vault::get(state.store.as_ref(), state.keys(), &name, user).await {
+                    Ok(Some(_)) => on_file = true,
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::error!(downstream = %name, error = %e, "Vault unavailable");
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "Credential storage unavailable",
+                        )
+                            .into_response();
+                    }
+                }
+            }
+
+            let credential_fields = match &ds.strategy {
+                StrategyConfig::Passthrough {
+                    credential_fields, ..
+                } => credential_fields.as_slice(),
+                _ => &[],
+            };
+            // Only a confirmation is asked for when nothing needs entering.
+            let consent = sso_gate || on_file;
+            let (fields_html, default_hint) = if consent {
+                (
+                    String::new(),
+                    "Allow this client to access the service on your behalf.",
+                )
+            } else if !credential_fields.is_empty() {
+                (
+                    credential_fields_html(credential_fields),
+                    "Enter your credentials for this service.",
+                )
+            } else if aws_credentials {
+                (
+                    r#"<label for="aws_access_key_id">Access Key ID</label>
+      <input type="text" id="aws_access_key_id" name="aws_access_key_id" required autofocus autocomplete="off" placeholder="AKIA...">
+      <label for="aws_secret_access_key">Secret Access Key</label>
+      <input type="password" id="aws_secret_access_key" name="aws_secret_access_key" required>
+      <label for="aws_session_token">Session Token (temporary credentials only)</label>
+      <input type="password" id="aws_session_token" name="aws_session_token">"#
+                        .to_string(),
+                    "Enter the AWS credentials to sign requests to this service with.",
+                )
+            } else {
+                let (label, placeholder, default_hint) = if password_gate {
+                    (
+                        "Password",
+                        "Enter the access password",
+                        "Enter the access password for this service.",
+                    )
+                } else {
+                    (
+                        "API Token",
+                        "Paste your token here",
+                        "Enter your API token or key for this service.",
+                    )
+                };
+                (
+                    format!(
+                        r#"<label for="token">{label}</label>
+      <input type="password" id="token" name="token" required autofocus placeholder="{placeholder}">"#
+                    ),
+                    default_hint,
+                )
+            };
+            let mut auth_hint = match auth_hint {
+                Some(hint) if !consent => html_escape(hint),
+                _ => default_hint.to_string(),
+            };
+            if on_file {
+                auth_hint.push_str(&format!(
+                    r#" Your stored credential is used; <a href="{}/vault/mcp/{}">manage it here</a>."#,
+                    state.config.server.public_url, name
+                ));
+            } else if is_vault {
+                auth_hint.push_str(&format!(
+                    r#" It is stored encrypted and reused next time; <a href="{}/vault/mcp/{}">manage it here</a>."#,
+                    state.config.server.public_url, name
+                ));
+            }
+
             let scopes_html = match &ds.scopes {
                 Some(scopes) => format!(
                     r#"<p style="color:#666;font-size:0.9em">Required scopes: <code>{}</code></p>"#,
@@ -78,6 +276,28 @@ pub async fn authorize_get(
                 None => String::new(),
             };
 
+            // A registered name is whatever the registrant chose, so where
+            // the code goes is always shown too.
+            let mut client_html = match client.as_ref().and_then(|c| c.client_name.as_deref()) {
+                Some(client_name) => format!(
+                    r#"<p style="color:#666;font-size:0.9em">Requested by <strong>{}</strong></p>"#,
+                    html_escape(client_name)
+                ),
+                None => String::new(),
+            };
+            client_html.push_str(&format!(
+                r#"<p style="color:#666;font-size:0.9em">Returns to <strong>{}</strong></p>"#,
+                html_escape(redirect_uri)
+            ));
+
+            let user_html = match &user {
+                Some(user) => format!(
+                    r#"<p style="color:#666;font-size:0.9em">Signed in as <strong>{}</strong></p>"#,
+                    html_escape(user.email.as_deref().unwrap_or(&user.sub))
+                ),
+                None => String::new(),
+            };
+
             let html = format!(
                 r#"<!DOCTYPE html>
 <html>
@@ -85,72 +305,111 @@ pub async fn authorize_get(
   <meta charset="utf-8">
   <meta name="viewport" content="width=device-width, initial-scale=1">
   <title>Authorize — {display_name}</title>
-  <style>
-    body {{ font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, sans-serif; max-width: 480px; margin: 60px auto; padding: 0 20px; background: #f5f5f5; }}
-    .card {{ background: white; border-radius: 8px; padding: 32px; box-shadow: 0 2px 8px rgba(0,0,0,0.1); }}
-    h1 {{ font-size: 1.4em; margin: 0 0 8px 0; }}
-    .hint {{ color: #666; margin: 0 0 20px 0; }}
-    label {{ display: block; font-weight: 600; margin-bottom: 6px; }}
-    input[type="password"] {{ width: 100%; padding: 10px; border: 1px solid #ccc; border-radius: 4px; font-size: 1em; box-sizing: border-box; }}
-    button {{ margin-top: 16px; width: 100%; padding: 12px; background: #2563eb; color: white; border: none; border-radius: 4px; font-size: 1em; cursor: pointer; }}
-    button:hover {{ background: #1d4ed8; }}
-  </style>
+  <style>{style}</style>
 </head>
 <body>
   <div class="card">
     <h1>{display_name}</h1>
     <p class="hint">{auth_hint}</p>
     {scopes_html}
+    {client_html}
+    {user_html}
     <form method="POST">
+      <input type="hidden" name="client_id" value="{client_id_val}">
+      <input type="hidden" name="resource" value="{resource_val}">
       <input type="hidden" name="state" value="{state_val}">
       <input type="hidden" name="redirect_uri" value="{redirect_uri_val}">
       <input type="hidden" name="code_challenge" value="{code_challenge_val}">
       <input type="hidden" name="code_challenge_method" value="S256">
-      <label for="token">API Token</label>
-      <input type="password" id="token" name="token" required autofocus placeholder="Paste your token here">
+      {fields_html}
       <button type="submit">Authorize</button>
     </form>
   </div>
 </body>
 </html>"#,
+                style = PAGE_STYLE,
                 display_name = html_escape(&ds.display_name),
                 auth_hint = auth_hint,
                 scopes_html = scopes_html,
+                client_html = client_html,
+                user_html = user_html,
+                client_id_val = html_escape(params.client_id.as_deref().unwrap_or("")),
+                resource_val = html_escape(params.resource.as_deref().unwrap_or("")),
                 state_val = html_escape(oauth_state),
                 redirect_uri_val = html_escape(redirect_uri),
                 code_challenge_val = html_escape(code_challenge),
+                fields_html = fields_html,
             );
 
             Html(html).into_response()
         }
-        StrategyConfig::ChainedOauth { oauth } => {
+        StrategyConfig::ChainedOauth { oauth }
+        | StrategyConfig::GithubApp {
+            oauth,
+            gate: Gate::Github,
+            ..
+        } => {
             let now = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
 
+            // PKCE towards the provider. The verifier rides along in the state,
+            // encrypted so the provider and the browser never see it.
+            let upstream_verifier = pkce::generate_verifier();
+            let sealed_verifier = match codes::encrypt_payload(
+                &upstream_verifier,
+                KeyPurpose::UpstreamPkce(&name),
+                state.keys(),
+            ) {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!("Failed to seal upstream PKCE verifier: {e}");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+                }
+            };
+
+            // OIDC: the ID token must echo this nonce back.
+            let oidc_nonce = oauth.oauth_oidc.then(codes::random_id);
+
             let state_blob = json!({
                 "claude_state": oauth_state,
                 "claude_redirect_uri": redirect_uri,
                 "pkce_challenge": code_challenge,
                 "pkce_method": "S256",
+                "downstream": name,
+                "client_id": params.client_id,
+                "resource": params.resource,
+                "upstream_verifier": sealed_verifier,
+                "oidc_nonce": oidc_nonce,
                 "exp": now + OAUTH_STATE_TTL_SECS,
             });
 
-            let signed_state = state::sign_state(&state_blob, state.state_secret());
+            let signed_state =
+                state::sign_state(&state_blob, KeyPurpose::State(&name), state.keys());
+
+            let Some(authorize_url) = oauth.authorize_url() else {
+                tracing::error!(downstream = %name, "No authorization endpoint configured or discovered");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+            };
 
             let callback_url = format!("{}/callback/mcp/{}", state.config.server.public_url, name);
 
             let mut redirect_url = format!(
-                "{}?response_type=code&client_id={}&redirect_uri={}&state={}",
-                oauth.oauth_authorize_url,
+                "{}?response_type=code&client_id={}&redirect_uri={}&state={}\
+                 &code_challenge={}&code_challenge_method=S256",
+                authorize_url,
                 urlencoding::encode(&oauth.oauth_client_id),
                 urlencoding::encode(&callback_url),
                 urlencoding::encode(&signed_state),
+                pkce::s256_challenge(&upstream_verifier),
             );
 
-            if let Some(scopes) = &oauth.oauth_scopes {
-                redirect_url.push_str(&format!("&scope={}", urlencoding::encode(scopes)));
+            if let Some(scopes) = oidc::request_scope(oauth) {
+                redirect_url.push_str(&format!("&scope={}", urlencoding::encode(&scopes)));
+            }
+            if let Some(nonce) = &oidc_nonce {
+                redirect_url.push_str(&format!("&nonce={nonce}"));
             }
 
             Redirect::to(&redirect_url).into_response()
@@ -158,9 +417,89 @@ pub async fn authorize_get(
     }
 }
 
+/// Inputs for a multi-field passthrough form, named `field_<name>`.
+fn credential_fields_html(fields: &[CredentialField]) -> String {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let id = format!("field_{}", field.name);
+            let kind = match field.kind {
+                FieldKind::Password => "password",
+                FieldKind::Text => "text",
+            };
+            let optional = if field.required { "" } else { " (optional)" };
+            let required = if field.required { " required" } else { "" };
+            let autofocus = if i == 0 { " autofocus" } else { "" };
+            let hint = match &field.hint {
+                Some(hint) => format!(r#"<p class="field-hint">{}</p>"#, html_escape(hint)),
+                None => String::new(),
+            };
+            format!(
+                r#"<label for="{id}">{label}{optional}</label>
+      <input type="{kind}" id="{id}" name="{id}"{required}{autofocus} autocomplete="off">{hint}"#,
+                label = html_escape(&field.label),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n      ")
+}
+
+/// The submitted values of a multi-field passthrough form, by field name.
+/// Optional fields left blank are omitted.
+fn credential_values(
+    fields: &[CredentialField],
+    submitted: &HashMap<String, String>,
+) -> Result<BTreeMap<String, String>, String> {
+    let mut values = BTreeMap::new();
+    for field in fields {
+        let value = submitted
+            .get(&format!("field_{}", field.name))
+            .map(String::as_str)
+            .unwrap_or_default();
+        if value.is_empty() {
+            if field.required {
+                return Err(format!("{} is required", field.label));
+            }
+            continue;
+        }
+        // Values end up in header values.
+        if value.chars().any(char::is_control) {
+            return Err(format!(
+                "{} must not contain control characters",
+                field.label
+            ));
+        }
+        if let Some(regex) = &field.regex {
+            // The whole value must match, not just part of it.
+            let regex = regex_lite::Regex::new(&format!("^(?:{regex})$"))
+                .map_err(|e| format!("invalid regex for {}: {e}", field.name))?;
+            if !regex.is_match(value) {
+                return Err(format!("{} is not in the expected format", field.label));
+            }
+        }
+        values.insert(field.name.clone(), value.to_string());
+    }
+    Ok(values)
+}
+
 #[derive(Deserialize)]
 pub struct AuthorizeForm {
+    #[serde(default)]
     token: String,
+    #[serde(default)]
+    aws_access_key_id: String,
+    #[serde(default)]
+    aws_secret_access_key: String,
+    #[serde(default)]
+    aws_session_token: String,
+    /// Multi-field passthrough inputs (`field_<name>`) and anything else.
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    resource: Option<String>,
     state: String,
     redirect_uri: String,
     code_challenge: String,
@@ -168,36 +507,261 @@ pub struct AuthorizeForm {
     code_challenge_method: String,
 }
 
-/// POST /authorize/mcp/:name — submit credentials (passthrough)
+/// What the form holds, by strategy.
+enum Submission<'a> {
+    /// The downstream credential in `token`, carried in the code.
+    Passthrough,
+    /// AWS credentials in the `aws_*` fields, carried in the code.
+    AwsCredentials,
+    /// The configured `field_<name>` inputs, carried in the code.
+    CredentialFields(&'a [CredentialField]),
+    /// The downstream credential, stored in the user's vault.
+    Vault,
+    /// The shared password of a password-gated downstream.
+    GatePassword(&'a str),
+    /// Nothing: the signed-in user confirms a downstream with `gate = "sso"`.
+    Consent,
+}
+
+/// POST /authorize/mcp/:name — submit credentials (passthrough, vault and AWS
+/// without configured credentials), the gate password, or the user's consent
+/// (strategies where the proxy holds the credential). A vault form posted
+/// without a token confirms the credential on file.
 pub async fn authorize_post(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    headers: HeaderMap,
     Form(form): Form<AuthorizeForm>,
 ) -> impl IntoResponse {
     let Some(ds) = state.find_downstream(&name) else {
         return (StatusCode::NOT_FOUND, "Unknown downstream").into_response();
     };
 
-    if !matches!(ds.strategy, StrategyConfig::Passthrough { .. }) {
+    let submission = match &ds.strategy {
+        StrategyConfig::Passthrough {
+            credential_fields, ..
+        } if !credential_fields.is_empty() => Submission::CredentialFields(credential_fields),
+        StrategyConfig::Passthrough { .. } => Submission::Passthrough,
+        StrategyConfig::Vault { .. } => Submission::Vault,
+        StrategyConfig::AwsSigv4 { aws, .. } if aws.aws_access_key_id.is_none() => {
+            Submission::AwsCredentials
+        }
+        StrategyConfig::StaticCredential {
+            gate: Gate::Password,
+            gate_password: Some(password),
+            ..
+        }
+        | StrategyConfig::ClientCredentials {
+            gate: Gate::Password,
+            gate_password: Some(password),
+            ..
+        }
+        | StrategyConfig::GithubApp {
+            gate: Gate::Password,
+            gate_password: Some(password),
+            ..
+        }
+        | StrategyConfig::JwtBearer {
+            gate: Gate::Password,
+            gate_password: Some(password),
+            ..
+        }
+        | StrategyConfig::AwsSigv4 {
+            gate: Some(Gate::Password),
+            gate_password: Some(password),
+            ..
+        } => Submission::GatePassword(password),
+        StrategyConfig::StaticCredential {
+            gate: Gate::Sso, ..
+        }
+        | StrategyConfig::ClientCredentials {
+            gate: Gate::Sso, ..
+        }
+        | StrategyConfig::GithubApp {
+            gate: Gate::Sso, ..
+        }
+        | StrategyConfig::JwtBearer {
+            gate: Gate::Sso, ..
+        }
+        | StrategyConfig::AwsSigv4 {
+            gate: Some(Gate::Sso),
+            ..
+        } => Submission::Consent,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "POST authorize only supported for passthrough, vault, AWS and gated strategies",
+            )
+                .into_response();
+        }
+    };
+
+    match submission {
+        Submission::AwsCredentials
+            if form.aws_access_key_id.is_empty() || form.aws_secret_access_key.is_empty() =>
+        {
+            return (
+                StatusCode::BAD_REQUEST,
+                "aws_access_key_id and aws_secret_access_key are required",
+            )
+                .into_response();
+        }
+        Submission::AwsCredentials => {}
+        // Checked field by field below.
+        Submission::CredentialFields(_) => {}
+        // Without a token, the vault must already hold one; checked below.
+        Submission::Vault | Submission::Consent => {}
+        _ if form.token.is_empty() => {
+            return (StatusCode::BAD_REQUEST, "token is required").into_response();
+        }
+        _ => {}
+    }
+
+    // The session is checked again: the form may outlive it.
+    let user = match login::check(&state, ds, &headers) {
+        _ if matches!(submission, Submission::GatePassword(_)) => None,
+        Access::Anonymous => None,
+        Access::User(user) => Some(user),
+        Access::LoginRequired => {
+            return (StatusCode::UNAUTHORIZED, "Sign-in required").into_response();
+        }
+        Access::Denied(e) => {
+            tracing::warn!(downstream = %name, reason = %e, "Sign-in not allowed");
+            return (StatusCode::FORBIDDEN, e).into_response();
+        }
+    };
+
+    // The form is re-validated: its hidden fields are client-controlled.
+    let client_id = form.client_id.filter(|id| !id.is_empty());
+    if let Err(e) = registration::check_client(
+        client_id.as_deref(),
+        &name,
+        &form.redirect_uri,
+        state.config.server.require_client_registration,
+        state.keys(),
+    ) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let resource = form.resource.filter(|r| !r.is_empty());
+    if resource
+        .as_deref()
+        .is_some_and(|r| r != state.resource_url(&name))
+    {
         return (
             StatusCode::BAD_REQUEST,
-            "POST authorize only supported for passthrough strategy",
+            "resource does not match this downstream",
         )
             .into_response();
     }
 
-    if form.token.is_empty() {
-        return (StatusCode::BAD_REQUEST, "token is required").into_response();
-    }
-
-    let code = match codes::create_auth_code(
-        DownstreamTokens::Passthrough {
+    let tokens = match submission {
+        Submission::Passthrough => DownstreamTokens::Passthrough {
             access_token: form.token,
         },
+        Submission::CredentialFields(fields) => match credential_values(fields, &form.fields) {
+            Ok(fields) => DownstreamTokens::CredentialFields { fields },
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        Submission::AwsCredentials => DownstreamTokens::AwsSigv4(AwsCredentials {
+            access_key_id: form.aws_access_key_id,
+            secret_access_key: form.aws_secret_access_key,
+            session_token: Some(form.aws_session_token).filter(|t| !t.is_empty()),
+        }),
+        Submission::Vault if form.token.is_empty() => {
+            let Some(user) = &user else {
+                return (StatusCode::UNAUTHORIZED, "Sign-in required").into_response();
+            };
+            match vault::get(state.store.as_ref(), state.keys(), &name, user).await {
+                Ok(Some(_)) => DownstreamTokens::Vault,
+                Ok(None) => return (StatusCode::BAD_REQUEST, "token is required").into_response(),
+                Err(e) => {
+                    tracing::error!(downstream = %name, error = %e, "Vault unavailable");
+                    return (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Credential storage unavailable",
+                    )
+                        .into_response();
+                }
+            }
+        }
+        Submission::Vault => {
+            let Some(user) = &user else {
+                return (StatusCode::UNAUTHORIZED, "Sign-in required").into_response();
+            };
+            if let Err(e) =
+                vault::put(state.store.as_ref(), state.keys(), &name, user, &form.token).await
+            {
+                tracing::error!(downstream = %name, error = %e, "Failed to store vault credential");
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Credential storage unavailable",
+                )
+                    .into_response();
+            }
+            tracing::info!(downstream = %name, user = %user.sub, "Vault credential saved");
+            DownstreamTokens::Vault
+        }
+        Submission::GatePassword(password) => {
+            // Compare digests so the comparison time does not depend on
+            // where the inputs differ.
+            if Sha256::digest(form.token.as_bytes()) != Sha256::digest(password.as_bytes()) {
+                tracing::warn!(downstream = %name, "Incorrect gate password");
+                return (StatusCode::UNAUTHORIZED, "Incorrect password").into_response();
+            }
+            DownstreamTokens::ProxyCredential
+        }
+        Submission::Consent => {
+            // The sign-in cookie is not sent with a cross-site POST, so the
+            // user really did confirm on the proxy's page.
+            if user.is_none() {
+                return (StatusCode::UNAUTHORIZED, "Sign-in required").into_response();
+            }
+            DownstreamTokens::ProxyCredential
+        }
+    };
+
+    let binding = GrantBinding {
+        downstream: name.clone(),
+        client_id,
+        resource,
+        user,
+    };
+
+    issue_code(
+        &state,
+        tokens,
+        binding,
         &form.code_challenge,
         &form.redirect_uri,
+        &form.state,
+    )
+}
+
+/// Seal an authorization code and send the browser back to the client.
+fn issue_code(
+    state: &AppState,
+    tokens: DownstreamTokens,
+    binding: GrantBinding,
+    code_challenge: &str,
+    redirect_uri: &str,
+    client_state: &str,
+) -> Response {
+    let strategy = match tokens {
+        DownstreamTokens::Vault => "vault",
+        DownstreamTokens::ProxyCredential => "proxy credential",
+        DownstreamTokens::AwsSigv4(_) => "AWS credentials",
+        DownstreamTokens::ChainedOAuth(_) => "chained OAuth callback",
+        _ => "passthrough",
+    };
+    let downstream = binding.downstream.clone();
+    let code = match codes::create_auth_code(
+        tokens,
+        binding,
+        code_challenge,
+        redirect_uri,
         state.config.server.auth_code_ttl,
-        state.state_secret(),
+        state.keys(),
     ) {
         Ok(c) => c,
         Err(e) => {
@@ -206,13 +770,13 @@ pub async fn authorize_post(
         }
     };
 
-    tracing::info!(downstream = %name, "Auth code issued (passthrough)");
+    tracing::info!(downstream = %downstream, "Auth code issued ({strategy})");
 
     let redirect_url = format!(
         "{}?code={}&state={}",
-        form.redirect_uri,
+        redirect_uri,
         urlencoding::encode(&code),
-        urlencoding::encode(&form.state),
+        urlencoding::encode(client_state),
     );
 
     Redirect::to(&redirect_url).into_response()
@@ -226,7 +790,8 @@ pub struct CallbackQuery {
     error_description: Option<String>,
 }
 
-/// GET /callback/mcp/:name — OAuth provider callback (chained OAuth)
+/// GET /callback/mcp/:name — OAuth provider callback (chained OAuth, and
+/// GitHub sign-in for `gate = "github"`)
 pub async fn callback(
     State(app): State<AppState>,
     Path(name): Path<String>,
@@ -236,12 +801,20 @@ pub async fn callback(
         return (StatusCode::NOT_FOUND, "Unknown downstream").into_response();
     };
 
-    let StrategyConfig::ChainedOauth { oauth } = &ds.strategy else {
-        return (
-            StatusCode::BAD_REQUEST,
-            "Callback only supported for chained_oauth strategy",
-        )
-            .into_response();
+    let oauth = match &ds.strategy {
+        StrategyConfig::ChainedOauth { oauth }
+        | StrategyConfig::GithubApp {
+            oauth,
+            gate: Gate::Github,
+            ..
+        } => oauth,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Callback only supported for chained_oauth and GitHub-gated github_app",
+            )
+                .into_response();
+        }
     };
 
     if let Some(error) = &params.error {
@@ -255,11 +828,7 @@ pub async fn callback(
             description = %desc,
             "Downstream OAuth provider returned an error"
         );
-        return (
-            StatusCode::BAD_GATEWAY,
-            format!("Downstream authorization failed: {desc}"),
-        )
-            .into_response();
+        return (StatusCode::BAD_GATEWAY, "Downstream authorization failed").into_response();
     }
 
     let Some(downstream_code) = &params.code else {
@@ -270,7 +839,9 @@ pub async fn callback(
         return (StatusCode::BAD_REQUEST, "Missing state parameter").into_response();
     };
 
-    let Some(state_payload) = state::verify_state(signed_state, app.state_secret()) else {
+    let Some(state_payload) =
+        state::verify_state(signed_state, KeyPurpose::State(&name), app.keys())
+    else {
         return (StatusCode::BAD_REQUEST, "Invalid or expired state").into_response();
     };
 
@@ -282,6 +853,27 @@ pub async fn callback(
         return (StatusCode::BAD_REQUEST, "Malformed state payload").into_response();
     };
 
+    // The state must come from this downstream's own authorize request.
+    let mut binding = match serde_json::from_value::<GrantBinding>(state_payload.clone()) {
+        Ok(b) if b.downstream == name => b,
+        Ok(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "State was issued for a different downstream",
+            )
+                .into_response();
+        }
+        Err(_) => {
+            return (StatusCode::BAD_REQUEST, "Malformed state payload").into_response();
+        }
+    };
+
+    let Some(upstream_verifier) = state_payload["upstream_verifier"].as_str().and_then(|v| {
+        codes::decrypt_payload::<String>(v, KeyPurpose::UpstreamPkce(&name), app.keys()).ok()
+    }) else {
+        return (StatusCode::BAD_REQUEST, "Malformed state payload").into_response();
+    };
+
     let callback_url = format!("{}/callback/mcp/{}", app.config.server.public_url, name);
 
     let body = match chained_oauth::post_downstream_token(
@@ -289,10 +881,9 @@ pub async fn callback(
         oauth,
         &[
             ("grant_type", "authorization_code"),
-            ("client_id", oauth.oauth_client_id.as_str()),
-            ("client_secret", oauth.oauth_client_secret.as_str()),
             ("code", downstream_code),
             ("redirect_uri", callback_url.as_str()),
+            ("code_verifier", upstream_verifier.as_str()),
         ],
     )
     .await
@@ -304,58 +895,100 @@ pub async fn callback(
                 error = %e,
                 "Failed to exchange downstream authorization code"
             );
-            return (
-                StatusCode::BAD_GATEWAY,
-                format!("Token exchange failed: {e}"),
-            )
-                .into_response();
+            return (StatusCode::BAD_GATEWAY, "Token exchange failed").into_response();
         }
     };
 
-    let access_token = match body["access_token"].as_str() {
-        Some(t) => t.to_string(),
-        None => {
+    if let StrategyConfig::GithubApp { app: github, .. } = &ds.strategy {
+        // The user-to-server token only proves who signed in.
+        let user = match github_app::user(&app.http_client, github, &body.access_token).await {
+            Ok(user) => user,
+            Err(e) => {
+                tracing::error!(downstream = %name, error = %e, "GitHub user lookup failed");
+                return (StatusCode::BAD_GATEWAY, "GitHub user lookup failed").into_response();
+            }
+        };
+        match github_app::can_access_installation(&app.http_client, github, &body.access_token)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!(downstream = %name, user = %user.sub, "GitHub user cannot access the app installation");
+                return (
+                    StatusCode::FORBIDDEN,
+                    format!("Your GitHub account cannot access {}", ds.display_name),
+                )
+                    .into_response();
+            }
+            Err(e) => {
+                tracing::error!(downstream = %name, error = %e, "GitHub installation lookup failed");
+                return (StatusCode::BAD_GATEWAY, "GitHub installation lookup failed")
+                    .into_response();
+            }
+        }
+        tracing::info!(downstream = %name, user = %user.sub, "GitHub user verified");
+        binding.user = Some(user);
+        return issue_code(
+            &app,
+            DownstreamTokens::ProxyCredential,
+            binding,
+            pkce_challenge,
+            claude_redirect_uri,
+            claude_state,
+        );
+    }
+
+    if let Err(e) = chained_oauth::check_granted_scope(&name, oauth, body.scope.as_deref()) {
+        tracing::warn!(downstream = %name, error = %e, "Refused grant");
+        return (StatusCode::FORBIDDEN, format!("Authorization failed: {e}")).into_response();
+    }
+
+    if oauth.oauth_oidc {
+        let Some(id_token) = body.id_token.as_deref() else {
+            tracing::error!(downstream = %name, "Provider returned no ID token");
             return (
                 StatusCode::BAD_GATEWAY,
-                "Missing access_token in downstream response",
+                "Downstream did not return an ID token",
             )
                 .into_response();
+        };
+        let nonce = state_payload["oidc_nonce"].as_str();
+        match oidc::validate_id_token(&app.http_client, &app.jwks, oauth, id_token, nonce).await {
+            Ok(user) => {
+                tracing::info!(
+                    downstream = %name,
+                    user = %user.sub,
+                    email = user.email.as_deref().unwrap_or_default(),
+                    "ID token validated"
+                );
+                binding.user = Some(user);
+            }
+            Err(e) => {
+                tracing::warn!(downstream = %name, error = %e, "Rejected ID token");
+                return (StatusCode::BAD_GATEWAY, "ID token validation failed").into_response();
+            }
         }
-    };
-
-    let tokens = DownstreamTokens::ChainedOAuth {
-        access_token,
-        refresh_token: body["refresh_token"].as_str().map(String::from),
-        expires_in: body["expires_in"].as_u64(),
-    };
+    }
 
-    let code = match codes::create_auth_code(
-        tokens,
-        pkce_challenge,
-        claude_redirect_uri,
-        app.config.server.auth_code_ttl,
-        app.state_secret(),
-    ) {
-        Ok(c) => c,
+    let body = match chained_oauth::apply_token_exchange(&app.http_client, oauth, body).await {
+        Ok(b) => b,
         Err(e) => {
-            tracing::error!("Failed to create auth code: {e}");
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+            tracing::error!(downstream = %name, error = %e, "Token exchange failed");
+            return (StatusCode::BAD_GATEWAY, "Token exchange failed").into_response();
         }
     };
 
-    tracing::info!(downstream = %name, "Auth code issued (chained OAuth callback)");
-
-    let redirect_url = format!(
-        "{}?code={}&state={}",
+    issue_code(
+        &app,
+        DownstreamTokens::ChainedOAuth(body),
+        binding,
+        pkce_challenge,
         claude_redirect_uri,
-        urlencoding::encode(&code),
-        urlencoding::encode(claude_state),
-    );
-
-    Redirect::to(&redirect_url).into_response()
+        claude_state,
+    )
 }
 
-fn html_escape(s: &str) -> String {
+pub(crate) fn html_escape(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
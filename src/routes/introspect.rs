@@ -0,0 +1,155 @@
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Form;
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::token::oauth_error;
+use crate::oauth::codes::UserIdentity;
+use crate::oauth::replay;
+use crate::oauth::tokens;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct IntrospectForm {
+    token: Option<String>,
+    #[allow(dead_code)]
+    token_type_hint: Option<String>,
+}
+
+/// Whether the request carries the configured introspection credential.
+/// Comparing digests keeps the comparison time independent of where the
+/// presented secret first differs.
+fn is_authorized(headers: &HeaderMap, secret: &str) -> bool {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|presented| {
+            Sha256::digest(presented.as_bytes()) == Sha256::digest(secret.as_bytes())
+        })
+}
+
+/// POST /introspect/mcp/:name — token introspection (RFC 7662)
+///
+/// Requires `Authorization: Bearer <server.introspection_secret>`. Only
+/// proxy-issued tokens for this downstream can be active; anything else
+/// (including revoked and expired tokens) is reported as `{"active": false}`.
+///
+/// `token_type` is the RFC 6749 type of access tokens; refresh tokens have
+/// none, so the non-standard `token_use` tells the two apart.
+pub async fn introspect(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<IntrospectForm>,
+) -> impl IntoResponse {
+    let Some(ds) = state.find_downstream(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "unknown downstream"})),
+        )
+            .into_response();
+    };
+
+    let Some(secret) = &state.config.server.introspection_secret else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !is_authorized(&headers, secret) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [("WWW-Authenticate", "Bearer")],
+            Json(json!({
+                "error": "invalid_client",
+                "error_description": "introspection requires the configured admin credential"
+            })),
+        )
+            .into_response();
+    }
+
+    let Some(token) = form.token.as_deref().filter(|t| !t.is_empty()) else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "token is required",
+        )
+        .into_response();
+    };
+
+    let strategy = ds.strategy.name();
+    let inactive = || Json(json!({ "active": false })).into_response();
+    let keys = state.keys();
+    let revoked = &state.revoked;
+
+    let resp = if let Ok(claims) = tokens::validate_access_token(token, &name, keys) {
+        if claims.binding.downstream != name
+            || revoked
+                .is_access_token_revoked(&claims.jti, claims.grant_id.as_deref())
+                .await
+        {
+            return inactive();
+        }
+        let mut resp = json!({
+            "active": true,
+            "token_type": "Bearer",
+            "token_use": "access",
+            "exp": claims.exp,
+            "jti": claims.jti,
+            "downstream": claims.binding.downstream,
+            "strategy": strategy,
+        });
+        if let Some(grant_id) = claims.grant_id {
+            resp["grant_id"] = json!(grant_id);
+        }
+        if let Some(client_id) = claims.binding.client_id {
+            resp["client_id"] = json!(client_id);
+        }
+        add_user(&mut resp, claims.binding.user);
+        resp
+    } else if let Ok(claims) = tokens::validate_refresh_token(token, &name, keys) {
+        // A used refresh token cannot be refreshed with again; if the store
+        // cannot tell, it counts as used.
+        let used = replay::is_refresh_used(state.store.as_ref(), &claims.jti)
+            .await
+            .unwrap_or(true);
+        if claims.binding.downstream != name
+            || used
+            || revoked.is_grant_revoked(&claims.grant_id).await
+        {
+            return inactive();
+        }
+        let mut resp = json!({
+            "active": true,
+            "token_use": "refresh",
+            "exp": claims.exp,
+            "downstream": claims.binding.downstream,
+            "strategy": strategy,
+            "grant_id": claims.grant_id,
+        });
+        if let Some(client_id) = claims.binding.client_id {
+            resp["client_id"] = json!(client_id);
+        }
+        add_user(&mut resp, claims.binding.user);
+        resp
+    } else {
+        return inactive();
+    };
+
+    tracing::info!(downstream = %name, "Token introspected");
+
+    Json(resp).into_response()
+}
+
+/// Report the OIDC identity behind a grant as `sub` and `email` (RFC 7662 §2.2).
+fn add_user(resp: &mut serde_json::Value, user: Option<UserIdentity>) {
+    if let Some(user) = user {
+        resp["sub"] = json!(user.sub);
+        if let Some(email) = user.email {
+            resp["email"] = json!(email);
+        }
+    }
+}
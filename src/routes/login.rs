@@ -0,0 +1,156 @@
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{AppendHeaders, IntoResponse, Redirect, Response};
+use serde::Deserialize;
+
+use crate::auth::login::{self, LoginSession};
+use crate::auth::{chained_oauth, oidc};
+use crate::oauth::codes;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct LoginCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Send the browser to the login provider, returning to `return_to` once
+/// signed in.
+pub(crate) fn redirect_to_login(app: &AppState, return_to: &str) -> Response {
+    match login::login_url(app, return_to) {
+        Ok((url, binding)) => ([(header::SET_COOKIE, binding)], Redirect::to(&url)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to start sign-in: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+        }
+    }
+}
+
+/// GET /login/callback — `[server.login]` provider callback
+pub async fn callback(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<LoginCallbackQuery>,
+) -> impl IntoResponse {
+    let Some(login) = &app.config.server.login else {
+        return (StatusCode::NOT_FOUND, "Login is not configured").into_response();
+    };
+
+    if let Some(error) = &params.error {
+        let desc = params
+            .error_description
+            .as_deref()
+            .unwrap_or("Unknown error");
+        tracing::error!(error = %error, description = %desc, "Login provider returned an error");
+        return (StatusCode::BAD_GATEWAY, "Sign-in failed").into_response();
+    }
+
+    let Some(code) = &params.code else {
+        return (StatusCode::BAD_REQUEST, "Missing code parameter").into_response();
+    };
+
+    let Some(login_state) = params
+        .state
+        .as_deref()
+        .and_then(|s| login::open_state(s, app.keys()))
+    else {
+        return (StatusCode::BAD_REQUEST, "Invalid or expired state").into_response();
+    };
+    if !login::started_here(&headers, &login_state) {
+        tracing::warn!("Login callback arrived in a browser that did not start the sign-in");
+        return (
+            StatusCode::BAD_REQUEST,
+            "Sign-in was not started in this browser",
+        )
+            .into_response();
+    }
+
+    let callback_url = login::callback_url(&app.config.server.public_url);
+    let body = match chained_oauth::post_downstream_token(
+        &app.http_client,
+        &login.oauth,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", callback_url.as_str()),
+            ("code_verifier", login_state.verifier.as_str()),
+        ],
+    )
+    .await
+    {
+        Ok(b) => b,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to exchange login authorization code");
+            return (StatusCode::BAD_GATEWAY, "Sign-in failed").into_response();
+        }
+    };
+
+    let Some(id_token) = body.id_token.as_deref() else {
+        tracing::error!("Login provider returned no ID token");
+        return (
+            StatusCode::BAD_GATEWAY,
+            "Login provider did not return an ID token",
+        )
+            .into_response();
+    };
+    let id_token = match oidc::verify_id_token(
+        &app.http_client,
+        &app.jwks,
+        &login.oauth,
+        id_token,
+        Some(&login_state.nonce),
+    )
+    .await
+    {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::warn!(error = %e, "Rejected login ID token");
+            return (StatusCode::BAD_GATEWAY, "Sign-in failed").into_response();
+        }
+    };
+
+    let now = match codes::now_secs() {
+        Ok(now) => now,
+        Err(e) => {
+            tracing::error!("{e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+        }
+    };
+    let session = LoginSession {
+        user: id_token.user,
+        groups: id_token.groups,
+        exp: now + login.session_ttl,
+    };
+    let cookie = match login::session_cookie(
+        &session,
+        login.session_ttl,
+        &app.config.server.public_url,
+        app.keys(),
+    ) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("Failed to seal login session: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response();
+        }
+    };
+
+    tracing::info!(
+        user = %session.user.sub,
+        email = session.user.email.as_deref().unwrap_or_default(),
+        "User signed in"
+    );
+
+    (
+        AppendHeaders([
+            (header::SET_COOKIE, cookie),
+            (
+                header::SET_COOKIE,
+                login::clear_binding_cookie(&app.config.server.public_url),
+            ),
+        ]),
+        Redirect::to(&login_state.return_to),
+    )
+        .into_response()
+}
@@ -3,7 +3,14 @@ use axum::extract::{Path, State};
 use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 
-use crate::proxy::{headers, sse};
+use crate::auth::chained_oauth::{self, TokenError};
+use crate::auth::sessions::UpstreamTokens;
+use crate::auth::{client_credentials, github_app, jwt_bearer, vault};
+use crate::config::{DownstreamConfig, StrategyConfig};
+use crate::oauth::codes::DownstreamTokens;
+use crate::oauth::tokens::{self, AccessTokenClaims};
+use crate::proxy::headers::{self, DownstreamAuth};
+use crate::proxy::sse;
 use crate::AppState;
 
 fn unauthorized() -> Response {
@@ -17,6 +24,229 @@ fn extract_bearer_token(headers: &HeaderMap) -> Option<&str> {
         .and_then(|v| v.strip_prefix("Bearer "))
 }
 
+/// Decrypt the proxy-issued access token. Tokens minted for a different
+/// downstream and revoked tokens are rejected.
+async fn access_claims(
+    state: &AppState,
+    name: &str,
+    headers: &HeaderMap,
+) -> Option<AccessTokenClaims> {
+    let token = extract_bearer_token(headers)?;
+
+    let claims = match tokens::validate_access_token(token, name, state.keys()) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::debug!(downstream = %name, reason = e, "Rejected access token");
+            return None;
+        }
+    };
+
+    if claims.binding.downstream != name {
+        tracing::warn!(
+            downstream = %name,
+            issued_for = %claims.binding.downstream,
+            "Access token presented to the wrong downstream"
+        );
+        return None;
+    }
+
+    if state
+        .revoked
+        .is_access_token_revoked(&claims.jti, claims.grant_id.as_deref())
+        .await
+    {
+        tracing::debug!(downstream = %name, "Rejected revoked access token");
+        return None;
+    }
+
+    if let Some(user) = &claims.binding.user {
+        let span = tracing::Span::current();
+        span.record("user", user.sub.as_str());
+        if let Some(email) = &user.email {
+            span.record("email", email.as_str());
+        }
+    }
+
+    Some(claims)
+}
+
+/// How to authenticate requests to the downstream for a proxy access token:
+/// AWS downstreams sign each request with the user's or the configured
+/// credentials, multi-field passthrough downstreams get the headers built
+/// from the user's form fields, and all others get the credential header.
+///
+/// An invalid token, or one that no longer fits the downstream, is a `401`
+/// so the client re-authorizes; failing to obtain the credential is not the
+/// client's fault and is a `502` or `503`.
+async fn downstream_auth<'a>(
+    state: &AppState,
+    name: &str,
+    ds: &'a DownstreamConfig,
+    headers: &HeaderMap,
+) -> Result<DownstreamAuth<'a>, Response> {
+    let claims = access_claims(state, name, headers)
+        .await
+        .ok_or_else(unauthorized)?;
+
+    if let StrategyConfig::AwsSigv4 { aws, .. } = &ds.strategy {
+        let credentials = match (aws.credentials(), claims.downstream_tokens) {
+            (Some(configured), DownstreamTokens::ProxyCredential) => configured,
+            (None, DownstreamTokens::AwsSigv4(entered)) => entered,
+            _ => return Err(unauthorized()),
+        };
+        return Ok(DownstreamAuth::Sigv4(credentials, aws.scope()));
+    }
+
+    let templates = match &ds.strategy {
+        StrategyConfig::Passthrough {
+            credential_headers, ..
+        } if !credential_headers.is_empty() => Some(credential_headers),
+        _ => None,
+    };
+    match (&claims.downstream_tokens, templates) {
+        (DownstreamTokens::CredentialFields { fields }, Some(templates)) => {
+            // Headers built only from optional fields left blank are skipped.
+            let rendered = templates
+                .iter()
+                .map(|(header, template)| (header.clone(), template.render(fields)))
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            return Ok(DownstreamAuth::Headers(rendered));
+        }
+        // A token from before the form changed; the client re-authorizes.
+        (DownstreamTokens::CredentialFields { .. }, None) | (_, Some(_)) => {
+            return Err(unauthorized())
+        }
+        _ => {}
+    }
+
+    let token = downstream_credential(state, name, ds, claims).await?;
+    let header = headers::remap_auth_header(&ds.auth_header_format, &token);
+    Ok(DownstreamAuth::Headers(vec![header]))
+}
+
+/// The response when the proxy could not obtain a token for the downstream.
+fn token_request_failed() -> Response {
+    (StatusCode::BAD_GATEWAY, "Downstream token request failed").into_response()
+}
+
+/// The downstream credential a validated access token stands for. For
+/// proxy-managed chained OAuth grants, an expired upstream access token is
+/// refreshed transparently; for vault grants, the credential is read from the
+/// user's vault, static credential downstreams get their configured one,
+/// client credentials downstreams the proxy's cached service token, GitHub App
+/// downstreams the cached installation token, and JWT bearer downstreams the
+/// cached token for the assertion's subject.
+async fn downstream_credential(
+    state: &AppState,
+    name: &str,
+    ds: &DownstreamConfig,
+    claims: AccessTokenClaims,
+) -> Result<String, Response> {
+    // The configured credential is sent instead of anything the token holds.
+    match &ds.strategy {
+        StrategyConfig::StaticCredential { credential, .. } => {
+            return matches!(claims.downstream_tokens, DownstreamTokens::ProxyCredential)
+                .then(|| credential.clone())
+                .ok_or_else(unauthorized);
+        }
+        StrategyConfig::ClientCredentials { oauth, .. } => {
+            if !matches!(claims.downstream_tokens, DownstreamTokens::ProxyCredential) {
+                return Err(unauthorized());
+            }
+            return client_credentials::service_token(state, name, oauth)
+                .await
+                .map_err(|e| {
+                    tracing::warn!(downstream = %name, error = %e, "Service token request failed");
+                    token_request_failed()
+                });
+        }
+        StrategyConfig::GithubApp { oauth, app, .. } => {
+            if !matches!(claims.downstream_tokens, DownstreamTokens::ProxyCredential) {
+                return Err(unauthorized());
+            }
+            return github_app::installation_token(state, name, oauth, app)
+                .await
+                .map_err(|e| {
+                    tracing::warn!(downstream = %name, error = %e, "Installation token request failed");
+                    token_request_failed()
+                });
+        }
+        StrategyConfig::JwtBearer {
+            oauth, assertion, ..
+        } => {
+            if !matches!(claims.downstream_tokens, DownstreamTokens::ProxyCredential) {
+                return Err(unauthorized());
+            }
+            let user = claims.binding.user.as_ref();
+            return jwt_bearer::access_token(state, name, oauth, assertion, user)
+                .await
+                .map_err(|e| {
+                    tracing::warn!(downstream = %name, error = %e, "JWT bearer token request failed");
+                    token_request_failed()
+                });
+        }
+        _ => {}
+    }
+
+    if let DownstreamTokens::Vault = claims.downstream_tokens {
+        let Some(user) = &claims.binding.user else {
+            tracing::warn!(downstream = %name, "Vault token without a user");
+            return Err(unauthorized());
+        };
+        return match vault::get(state.store.as_ref(), state.keys(), name, user).await {
+            Ok(Some(credential)) => Ok(credential),
+            Ok(None) => {
+                tracing::debug!(downstream = %name, "No vault credential for user");
+                Err(unauthorized())
+            }
+            Err(e) => {
+                tracing::error!(downstream = %name, error = %e, "Vault unavailable");
+                Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Credential storage unavailable",
+                )
+                    .into_response())
+            }
+        };
+    }
+
+    if let (
+        Some(grant_id),
+        StrategyConfig::ChainedOauth { oauth },
+        DownstreamTokens::ChainedOAuth(tokens),
+    ) = (&claims.grant_id, &ds.strategy, &claims.downstream_tokens)
+    {
+        let known = UpstreamTokens {
+            access_token: tokens.access_token.clone(),
+            refresh_token: tokens.refresh_token.clone(),
+            expires_at: claims.downstream_exp,
+            scope: tokens.scope.clone(),
+        };
+        return match chained_oauth::fresh_upstream_tokens(state, name, oauth, grant_id, known).await
+        {
+            Ok(upstream) => Ok(upstream.access_token),
+            Err(e) => {
+                tracing::warn!(downstream = %name, error = %e, "Upstream refresh failed");
+                // Only a refused refresh means the user must re-authorize.
+                Err(match e {
+                    TokenError::Rejected(_) => unauthorized(),
+                    TokenError::Invalid(_) => {
+                        (StatusCode::BAD_GATEWAY, "Upstream refresh failed").into_response()
+                    }
+                    TokenError::Unavailable(_) => (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Upstream refresh unavailable",
+                    )
+                        .into_response(),
+                })
+            }
+        };
+    }
+
+    Ok(claims.downstream_tokens.access_token().to_string())
+}
+
 /// GET /mcp/:name — SSE streaming proxy
 pub async fn mcp_sse(
     State(state): State<AppState>,
@@ -27,19 +257,13 @@ pub async fn mcp_sse(
         .find_downstream(&name)
         .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
 
-    let token = extract_bearer_token(&headers).ok_or_else(unauthorized)?;
-    let (header_name, header_value) = headers::remap_auth_header(&ds.auth_header_format, token);
+    let auth = downstream_auth(&state, &name, ds, &headers).await?;
 
     tracing::debug!(downstream = %name, downstream_url = %ds.downstream_url, "SSE proxy");
 
-    sse::proxy_sse(
-        &ds.downstream_url,
-        &header_name,
-        &header_value,
-        &state.http_client,
-    )
-    .await
-    .map_err(IntoResponse::into_response)
+    sse::proxy_sse(&ds.downstream_url, &auth, &state.http_client)
+        .await
+        .map_err(IntoResponse::into_response)
 }
 
 /// POST /mcp/:name — JSON-RPC proxy
@@ -53,18 +277,11 @@ pub async fn mcp_post(
         .find_downstream(&name)
         .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
 
-    let token = extract_bearer_token(&headers).ok_or_else(unauthorized)?;
-    let (header_name, header_value) = headers::remap_auth_header(&ds.auth_header_format, token);
+    let auth = downstream_auth(&state, &name, ds, &headers).await?;
 
     tracing::debug!(downstream = %name, downstream_url = %ds.downstream_url, "POST proxy");
 
-    sse::proxy_post(
-        &ds.downstream_url,
-        &header_name,
-        &header_value,
-        body,
-        &state.http_client,
-    )
-    .await
-    .map_err(IntoResponse::into_response)
+    sse::proxy_post(&ds.downstream_url, &auth, body, &state.http_client)
+        .await
+        .map_err(IntoResponse::into_response)
 }
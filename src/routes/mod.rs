@@ -1,4 +1,9 @@
 pub mod authorize;
+pub mod introspect;
+pub mod login;
 pub mod mcp_proxy;
+pub mod register;
+pub mod revoke;
 pub mod token;
+pub mod vault;
 pub mod well_known;
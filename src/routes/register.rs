@@ -0,0 +1,137 @@
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::StrategyConfig;
+use crate::oauth::registration;
+use crate::AppState;
+
+/// `client_name` is signed into the client ID along with the redirect URIs.
+const MAX_CLIENT_NAME_LEN: usize = 200;
+
+/// RFC 7591 §2 client metadata. Fields the proxy does not use are ignored.
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+    client_name: Option<String>,
+    token_endpoint_auth_method: Option<String>,
+    grant_types: Option<Vec<String>>,
+    response_types: Option<Vec<String>>,
+}
+
+/// RFC 7591 §3.2.2 error response.
+fn registration_error(error: &str, description: &str) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": error, "error_description": description })),
+    )
+        .into_response()
+}
+
+/// POST /register/mcp/:name — dynamic client registration
+pub async fn register(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: Result<Json<RegisterRequest>, JsonRejection>,
+) -> impl IntoResponse {
+    let Some(ds) = state.find_downstream(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "unknown downstream"})),
+        )
+            .into_response();
+    };
+
+    let Json(req) = match body {
+        Ok(b) => b,
+        Err(e) => return registration_error("invalid_client_metadata", &e.body_text()),
+    };
+
+    if let Some(method) = &req.token_endpoint_auth_method {
+        if method != "none" {
+            return registration_error(
+                "invalid_client_metadata",
+                "token_endpoint_auth_method must be 'none'",
+            );
+        }
+    }
+
+    if req
+        .client_name
+        .as_ref()
+        .is_some_and(|n| n.len() > MAX_CLIENT_NAME_LEN)
+    {
+        return registration_error(
+            "invalid_client_metadata",
+            &format!("client_name must be at most {MAX_CLIENT_NAME_LEN} bytes"),
+        );
+    }
+
+    let supports_refresh = matches!(
+        &ds.strategy,
+        StrategyConfig::ChainedOauth { oauth } if oauth.oauth_supports_refresh
+    );
+    let supported_grants: &[&str] = if supports_refresh {
+        &["authorization_code", "refresh_token"]
+    } else {
+        &["authorization_code"]
+    };
+
+    let grant_types = req
+        .grant_types
+        .unwrap_or_else(|| vec!["authorization_code".to_string()]);
+    if let Some(g) = grant_types
+        .iter()
+        .find(|g| !supported_grants.contains(&g.as_str()))
+    {
+        return registration_error(
+            "invalid_client_metadata",
+            &format!("unsupported grant_type: {g}"),
+        );
+    }
+
+    let response_types = req
+        .response_types
+        .unwrap_or_else(|| vec!["code".to_string()]);
+    if response_types.iter().any(|r| r != "code") {
+        return registration_error(
+            "invalid_client_metadata",
+            "response_types must only contain 'code'",
+        );
+    }
+
+    let (client_id, client) = match registration::register_client(
+        &name,
+        req.client_name,
+        req.redirect_uris,
+        state.keys(),
+    ) {
+        Ok(r) => r,
+        Err(e) => return registration_error("invalid_redirect_uri", &e),
+    };
+
+    tracing::info!(
+        downstream = %name,
+        client_name = client.client_name.as_deref().unwrap_or(""),
+        "Client registered"
+    );
+
+    let mut resp = json!({
+        "client_id": client_id,
+        "client_id_issued_at": client.iat,
+        "redirect_uris": client.redirect_uris,
+        "grant_types": grant_types,
+        "response_types": response_types,
+        "token_endpoint_auth_method": "none"
+    });
+    if let Some(client_name) = client.client_name {
+        resp["client_name"] = json!(client_name);
+    }
+
+    (StatusCode::CREATED, Json(resp)).into_response()
+}
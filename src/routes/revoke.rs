@@ -0,0 +1,184 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Form;
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::token::oauth_error;
+use crate::auth::chained_oauth;
+use crate::config::{OAuthConfig, RefreshMode, StrategyConfig};
+use crate::oauth::codes::DownstreamTokens;
+use crate::oauth::tokens;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct RevokeForm {
+    token: Option<String>,
+    token_type_hint: Option<String>,
+    #[allow(dead_code)]
+    client_id: Option<String>,
+}
+
+/// POST /revoke/mcp/:name — token revocation (RFC 7009)
+///
+/// Proxy-issued tokens go on the denylist; upstream tokens are forwarded to
+/// the provider's revocation endpoint when one is configured. Unknown or
+/// already-expired tokens are not an error (RFC 7009 §2.2).
+pub async fn revoke(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Form(form): Form<RevokeForm>,
+) -> impl IntoResponse {
+    let Some(ds) = state.find_downstream(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "unknown downstream"})),
+        )
+            .into_response();
+    };
+
+    let Some(token) = form.token.as_deref().filter(|t| !t.is_empty()) else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "token is required",
+        )
+        .into_response();
+    };
+
+    let oauth = match &ds.strategy {
+        StrategyConfig::ChainedOauth { oauth } => Some(oauth),
+        StrategyConfig::Passthrough { .. }
+        | StrategyConfig::Vault { .. }
+        | StrategyConfig::StaticCredential { .. }
+        | StrategyConfig::ClientCredentials { .. }
+        | StrategyConfig::GithubApp { .. }
+        | StrategyConfig::JwtBearer { .. }
+        | StrategyConfig::AwsSigv4 { .. } => None,
+    };
+
+    let upstream_ok = if let Ok(claims) = tokens::validate_access_token(token, &name, state.keys())
+    {
+        if claims.binding.downstream != name {
+            tracing::warn!(downstream = %name, "Revocation for a token of another downstream ignored");
+            return StatusCode::OK.into_response();
+        }
+        if let Err(e) = state.revoked.revoke_token(&claims.jti, claims.exp).await {
+            return storage_unavailable(&name, &e).into_response();
+        }
+        tracing::info!(downstream = %name, "Access token revoked");
+
+        // A proxy-managed grant shares its upstream access token between all
+        // of the grant's proxy tokens, so only client-mode tokens are revoked
+        // upstream. An exchanged token was not issued by the provider.
+        match (oauth, claims.grant_id, &claims.downstream_tokens) {
+            (Some(oauth), None, DownstreamTokens::ChainedOAuth(tokens))
+                if oauth.oauth_token_exchange_url.is_none() =>
+            {
+                revoke_upstream(&state, &name, oauth, &tokens.access_token, "access_token").await
+            }
+            _ => true,
+        }
+    } else if let Ok(claims) = tokens::validate_refresh_token(token, &name, state.keys()) {
+        if claims.binding.downstream != name {
+            tracing::warn!(downstream = %name, "Revocation for a token of another downstream ignored");
+            return StatusCode::OK.into_response();
+        }
+        if let Err(e) = state
+            .revoked
+            .revoke_grant(&claims.grant_id, claims.exp)
+            .await
+        {
+            return storage_unavailable(&name, &e).into_response();
+        }
+        let latest = state.upstream_sessions.remove(&claims.grant_id).await;
+        tracing::info!(downstream = %name, "Grant revoked");
+
+        let upstream_refresh_token = latest
+            .and_then(|t| t.refresh_token)
+            .unwrap_or(claims.upstream_refresh_token);
+        match oauth {
+            Some(oauth) => {
+                revoke_upstream(
+                    &state,
+                    &name,
+                    oauth,
+                    &upstream_refresh_token,
+                    "refresh_token",
+                )
+                .await
+            }
+            None => true,
+        }
+    } else if is_foreign_proxy_token(&state, &name, token) {
+        tracing::warn!(downstream = %name, "Revocation for a token of another downstream ignored");
+        true
+    } else {
+        // Not a proxy token. In client refresh mode the client holds the raw
+        // upstream refresh token, so pass it on to the provider.
+        match oauth {
+            Some(oauth) if oauth.oauth_refresh_mode == RefreshMode::Client => {
+                let hint = form.token_type_hint.as_deref().unwrap_or("refresh_token");
+                revoke_upstream(&state, &name, oauth, token, hint).await
+            }
+            _ => true,
+        }
+    };
+
+    if !upstream_ok {
+        return oauth_error(
+            StatusCode::BAD_GATEWAY,
+            "server_error",
+            "Downstream revocation failed",
+        )
+        .into_response();
+    }
+
+    StatusCode::OK.into_response()
+}
+
+/// The denylist could not be written; the client should retry (RFC 7009 §2.2.1).
+fn storage_unavailable(ds_name: &str, error: &str) -> impl IntoResponse {
+    tracing::error!(downstream = %ds_name, error = %error, "Failed to record revocation");
+    oauth_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "temporarily_unavailable",
+        "revocation could not be recorded",
+    )
+}
+
+/// Whether `token` is a live proxy token of another downstream. Those are
+/// sealed under that downstream's key, so they look opaque here.
+fn is_foreign_proxy_token(state: &AppState, name: &str, token: &str) -> bool {
+    state
+        .config
+        .downstream
+        .keys()
+        .filter(|ds| *ds != name)
+        .any(|ds| {
+            tokens::validate_access_token(token, ds, state.keys()).is_ok()
+                || tokens::validate_refresh_token(token, ds, state.keys()).is_ok()
+        })
+}
+
+/// Forward a revocation to the provider, logging failures. Returns whether it
+/// succeeded.
+async fn revoke_upstream(
+    state: &AppState,
+    ds_name: &str,
+    oauth: &OAuthConfig,
+    token: &str,
+    token_type_hint: &str,
+) -> bool {
+    match chained_oauth::revoke_upstream_token(&state.http_client, oauth, token, token_type_hint)
+        .await
+    {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!(downstream = %ds_name, error = %e, "Upstream revocation failed");
+            false
+        }
+    }
+}
@@ -3,13 +3,21 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::Form;
 use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use serde::Deserialize;
 use serde_json::json;
+use sha2::{Digest, Sha256};
 
-use crate::auth::chained_oauth;
-use crate::config::{OAuthConfig, StrategyConfig};
-use crate::oauth::codes::{self, DownstreamTokens};
+use crate::auth::chained_oauth::{self, TokenError, TokenResponse};
+use crate::auth::oidc;
+use crate::auth::sessions::UpstreamTokens;
+use crate::config::{DownstreamConfig, OAuthConfig, RefreshMode, StrategyConfig};
+use crate::oauth::codes::{self, DownstreamTokens, GrantBinding};
 use crate::oauth::pkce;
+use crate::oauth::registration;
+use crate::oauth::replay;
+use crate::oauth::tokens::{self, AccessTokenClaims, RefreshTokenClaims};
 use crate::AppState;
 
 #[derive(Deserialize)]
@@ -18,12 +26,13 @@ pub struct TokenForm {
     code: Option<String>,
     code_verifier: Option<String>,
     redirect_uri: Option<String>,
-    #[allow(dead_code)]
     client_id: Option<String>,
     refresh_token: Option<String>,
+    /// RFC 8707 resource indicator.
+    resource: Option<String>,
 }
 
-fn oauth_error(status: StatusCode, error: &str, description: &str) -> impl IntoResponse {
+pub(crate) fn oauth_error(status: StatusCode, error: &str, description: &str) -> impl IntoResponse {
     (
         status,
         Json(json!({ "error": error, "error_description": description })),
@@ -47,9 +56,38 @@ pub async fn token(
 
     tracing::info!(downstream = %name, grant_type = %form.grant_type, "Token request");
 
+    if form
+        .resource
+        .as_deref()
+        .is_some_and(|r| r != state.resource_url(&name))
+    {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_target",
+            "resource does not match this downstream",
+        )
+        .into_response();
+    }
+
     match form.grant_type.as_str() {
-        "authorization_code" => handle_authorization_code(&state, form).into_response(),
+        "authorization_code" => handle_authorization_code(&state, &name, ds, form)
+            .await
+            .into_response(),
         "refresh_token" => {
+            if state.config.server.require_client_registration
+                && form
+                    .client_id
+                    .as_deref()
+                    .and_then(|id| registration::verify_client_id(id, state.keys()))
+                    .is_none_or(|client| client.downstream != name)
+            {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_client",
+                    "client_id is not registered",
+                )
+                .into_response();
+            }
             let StrategyConfig::ChainedOauth { oauth } = &ds.strategy else {
                 return oauth_error(
                     StatusCode::BAD_REQUEST,
@@ -66,9 +104,14 @@ pub async fn token(
                 )
                 .into_response();
             }
-            handle_refresh_token(&state, &name, oauth, form)
-                .await
-                .into_response()
+            match oauth.oauth_refresh_mode {
+                RefreshMode::Client => handle_refresh_token(&state, &name, oauth, form)
+                    .await
+                    .into_response(),
+                RefreshMode::Proxy => handle_proxy_refresh(&state, &name, oauth, form)
+                    .await
+                    .into_response(),
+            }
         }
         _ => oauth_error(
             StatusCode::BAD_REQUEST,
@@ -79,7 +122,12 @@ pub async fn token(
     }
 }
 
-fn handle_authorization_code(state: &AppState, form: TokenForm) -> impl IntoResponse {
+async fn handle_authorization_code(
+    state: &AppState,
+    ds_name: &str,
+    ds: &DownstreamConfig,
+    form: TokenForm,
+) -> impl IntoResponse {
     let Some(code) = &form.code else {
         return oauth_error(
             StatusCode::BAD_REQUEST,
@@ -107,13 +155,28 @@ fn handle_authorization_code(state: &AppState, form: TokenForm) -> impl IntoResp
         .into_response();
     };
 
-    let grant = match codes::validate_auth_code(code, state.state_secret()) {
+    if let Err(e) = registration::check_client(
+        form.client_id.as_deref(),
+        ds_name,
+        redirect_uri,
+        state.config.server.require_client_registration,
+        state.keys(),
+    ) {
+        return oauth_error(StatusCode::BAD_REQUEST, "invalid_client", e).into_response();
+    }
+
+    let grant = match codes::validate_auth_code(code, ds_name, state.keys()) {
         Ok(g) => g,
         Err(e) => {
             return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", e).into_response();
         }
     };
 
+    if let Err(e) = grant.binding.check(ds_name, form.client_id.as_deref()) {
+        tracing::warn!(downstream = %ds_name, reason = e, "Rejected authorization code");
+        return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", e).into_response();
+    }
+
     if grant.redirect_uri != *redirect_uri {
         return oauth_error(
             StatusCode::BAD_REQUEST,
@@ -132,32 +195,118 @@ fn handle_authorization_code(state: &AppState, form: TokenForm) -> impl IntoResp
         .into_response();
     }
 
-    tracing::info!("Auth code exchanged for tokens");
-
-    match grant.downstream_tokens {
-        DownstreamTokens::Passthrough { access_token } => Json(json!({
-            "access_token": access_token,
-            "token_type": "Bearer"
-        }))
-        .into_response(),
-        DownstreamTokens::ChainedOAuth {
-            access_token,
-            refresh_token,
-            expires_in,
-        } => {
-            let mut resp = json!({
-                "access_token": access_token,
-                "token_type": "Bearer"
-            });
-            if let Some(rt) = refresh_token {
-                resp["refresh_token"] = json!(rt);
+    if state.config.server.single_use_auth_codes {
+        match replay::first_use(state.store.as_ref(), &grant.nonce, grant.exp).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!(downstream = %ds_name, "Rejected reused authorization code");
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_grant",
+                    "authorization code has already been used",
+                )
+                .into_response();
             }
-            if let Some(ei) = expires_in {
-                resp["expires_in"] = json!(ei);
+            Err(e) => {
+                tracing::error!(downstream = %ds_name, error = %e, "Storage unavailable");
+                return server_error("could not verify authorization code").into_response();
             }
-            Json(resp).into_response()
         }
     }
+
+    tracing::info!(downstream = %ds_name, "Auth code exchanged for tokens");
+
+    match (&ds.strategy, grant.downstream_tokens) {
+        (StrategyConfig::ChainedOauth { oauth }, DownstreamTokens::ChainedOAuth(tokens))
+            if oauth.oauth_refresh_mode == RefreshMode::Proxy && tokens.refresh_token.is_some() =>
+        {
+            let Ok(now) = codes::now_secs() else {
+                return server_error("system time error").into_response();
+            };
+            let upstream = UpstreamTokens {
+                access_token: tokens.access_token,
+                refresh_token: tokens.refresh_token,
+                expires_at: tokens.expires_in.map(|ei| now + ei),
+                scope: tokens.scope,
+            };
+            proxy_managed_response(state, grant.binding, &codes::random_id(), upstream)
+                .into_response()
+        }
+        (_, downstream_tokens) => {
+            let refresh_token = match &downstream_tokens {
+                DownstreamTokens::Passthrough { .. }
+                | DownstreamTokens::Vault
+                | DownstreamTokens::ProxyCredential
+                | DownstreamTokens::AwsSigv4(_)
+                | DownstreamTokens::CredentialFields { .. } => None,
+                DownstreamTokens::ChainedOAuth(tokens) => tokens.refresh_token.clone(),
+            };
+            if let Some(rt) = &refresh_token {
+                remember_binding(state, rt, &grant.binding).await;
+            }
+            token_response(state, grant.binding, downstream_tokens, refresh_token).into_response()
+        }
+    }
+}
+
+fn server_error(description: &str) -> impl IntoResponse {
+    oauth_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        description,
+    )
+}
+
+/// Wrap `downstream_tokens` in a proxy-issued access token and build the
+/// RFC 6749 §5.1 response body. The access token lifetime is capped at the
+/// downstream token's own `expires_in`, if it reported one.
+fn token_response(
+    state: &AppState,
+    binding: GrantBinding,
+    downstream_tokens: DownstreamTokens,
+    refresh_token: Option<String>,
+) -> impl IntoResponse {
+    let mut expires_in = state.config.server.access_token_ttl;
+    let mut scope = None;
+    if let DownstreamTokens::ChainedOAuth(tokens) = &downstream_tokens {
+        if let Some(downstream_ttl) = tokens.expires_in {
+            expires_in = expires_in.min(downstream_ttl);
+        }
+        scope = tokens.scope.as_deref().map(chained_oauth::normalize_scope);
+    }
+
+    let Ok(now) = codes::now_secs() else {
+        return server_error("system time error").into_response();
+    };
+
+    let claims = AccessTokenClaims {
+        jti: codes::random_id(),
+        binding,
+        downstream_tokens,
+        grant_id: None,
+        downstream_exp: None,
+        exp: now + expires_in,
+    };
+    let access_token = match tokens::issue_access_token(claims, state.keys()) {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("Failed to issue access token: {e}");
+            return server_error("Failed to issue access token").into_response();
+        }
+    };
+
+    let mut resp = json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": expires_in
+    });
+    if let Some(rt) = refresh_token {
+        resp["refresh_token"] = json!(rt);
+    }
+    if let Some(scope) = scope {
+        resp["scope"] = json!(scope);
+    }
+    Json(resp).into_response()
 }
 
 async fn handle_refresh_token(
@@ -175,14 +324,34 @@ async fn handle_refresh_token(
         .into_response();
     };
 
+    // The grant this token was issued under, unless it predates the cache or
+    // the store lost it; the new tokens are then bound to whoever presents it.
+    let original = state.upstream_sessions.binding(refresh_token).await;
+    if let Some(original) = &original {
+        if let Err(e) = original.check(ds_name, form.client_id.as_deref()) {
+            tracing::warn!(downstream = %ds_name, reason = e, "Rejected refresh token");
+            return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", e).into_response();
+        }
+    }
+
+    // When a refresh went through at the provider but its token exchange
+    // failed, the rotated refresh token waits here under the one the client
+    // still holds, and is used in its place.
+    let sessions = &state.upstream_sessions;
+    let pending_key = pending_refresh_key(refresh_token);
+    let _guard = sessions.lock(&pending_key).await;
+    let upstream_refresh = sessions
+        .get(&pending_key)
+        .await
+        .and_then(|t| t.refresh_token)
+        .unwrap_or_else(|| refresh_token.clone());
+
     let body = match chained_oauth::post_downstream_token(
         &state.http_client,
         oauth,
         &[
             ("grant_type", "refresh_token"),
-            ("refresh_token", refresh_token.as_str()),
-            ("client_id", &oauth.oauth_client_id),
-            ("client_secret", &oauth.oauth_client_secret),
+            ("refresh_token", upstream_refresh.as_str()),
         ],
     )
     .await
@@ -194,40 +363,358 @@ async fn handle_refresh_token(
                 error = %e,
                 "Downstream refresh token request failed"
             );
-            return oauth_error(
-                StatusCode::BAD_REQUEST,
-                "invalid_grant",
-                "Refresh token invalid or expired. User must re-authorize.",
-            )
+            return refresh_error(&e).into_response();
+        }
+    };
+
+    if let Err(e) = chained_oauth::check_granted_scope(ds_name, oauth, body.scope.as_deref()) {
+        return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", &e).into_response();
+    }
+
+    // Refreshed ID tokens carry no nonce (OIDC Core §12.2); when the provider
+    // sends one, it still identifies the user.
+    let user = match body.id_token.as_deref().filter(|_| oauth.oauth_oidc) {
+        Some(id_token) => {
+            match oidc::validate_id_token(&state.http_client, &state.jwks, oauth, id_token, None)
+                .await
+            {
+                Ok(user) => Some(user),
+                Err(e) => {
+                    tracing::error!(
+                        downstream = %ds_name,
+                        error = %e,
+                        "Refreshed ID token validation failed"
+                    );
+                    return oauth_error(
+                        StatusCode::BAD_GATEWAY,
+                        "server_error",
+                        "Downstream returned an invalid ID token",
+                    )
+                    .into_response();
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Providers that rotate return a new refresh token; others keep the one
+    // just used valid, which the client only lacks if it came from the
+    // pending entry.
+    let next_refresh = body
+        .refresh_token
+        .clone()
+        .or_else(|| (upstream_refresh != *refresh_token).then_some(upstream_refresh));
+
+    let body = match chained_oauth::apply_token_exchange(&state.http_client, oauth, body).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!(downstream = %ds_name, error = %e, "Token exchange failed");
+            if let (Some(rotated), Ok(now)) = (next_refresh, codes::now_secs()) {
+                let pending = UpstreamTokens {
+                    access_token: String::new(),
+                    refresh_token: Some(rotated),
+                    expires_at: Some(0),
+                    scope: None,
+                };
+                let until = now + state.config.server.refresh_token_ttl;
+                sessions.put(&pending_key, pending, until).await;
+            }
+            return match e {
+                TokenError::Unavailable(_) => oauth_error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "temporarily_unavailable",
+                    "Token exchange is unavailable. Try again later.",
+                ),
+                _ => oauth_error(
+                    StatusCode::BAD_GATEWAY,
+                    "server_error",
+                    "Token exchange failed",
+                ),
+            }
             .into_response();
         }
     };
+    sessions.remove(&pending_key).await;
 
-    let Some(access_token) = body["access_token"].as_str() else {
-        tracing::error!(
-            downstream = %ds_name,
-            "Downstream refresh response missing access_token"
-        );
-        return oauth_error(
+    tracing::info!(downstream = %ds_name, "Refresh token proxied");
+
+    // Keep the original grant's client, resource and user. A refreshed ID
+    // token may update the user's details, but not who they are.
+    let binding = match original {
+        Some(original) => {
+            if let (Some(new), Some(old)) = (&user, &original.user) {
+                if new.sub != old.sub {
+                    tracing::warn!(downstream = %ds_name, "Refreshed ID token names another user");
+                    return oauth_error(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_grant",
+                        "refreshed ID token names another user",
+                    )
+                    .into_response();
+                }
+            }
+            GrantBinding {
+                user: user.or(original.user),
+                ..original
+            }
+        }
+        None => GrantBinding {
+            downstream: ds_name.to_string(),
+            client_id: form.client_id,
+            resource: form.resource,
+            user,
+        },
+    };
+
+    // The client's next refresh presents the rotated token, or this one again.
+    remember_binding(
+        state,
+        next_refresh.as_deref().unwrap_or(refresh_token),
+        &binding,
+    )
+    .await;
+
+    let tokens = DownstreamTokens::ChainedOAuth(body);
+    token_response(state, binding, tokens, next_refresh).into_response()
+}
+
+/// Remember the grant behind a client-mode upstream refresh token for as long
+/// as a proxy refresh token would live.
+async fn remember_binding(state: &AppState, refresh_token: &str, binding: &GrantBinding) {
+    let Ok(now) = codes::now_secs() else {
+        return;
+    };
+    let until = now + state.config.server.refresh_token_ttl;
+    state
+        .upstream_sessions
+        .put_binding(refresh_token, binding, until)
+        .await;
+}
+
+/// Session cache key for the rotated refresh token of a client-mode refresh
+/// whose token exchange failed, derived from the refresh token the client
+/// holds.
+fn pending_refresh_key(refresh_token: &str) -> String {
+    format!(
+        "refresh:{}",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(refresh_token.as_bytes()))
+    )
+}
+
+/// The response to a failed upstream refresh. Only a refusal by the provider
+/// means the client must re-authorize; if the provider is down, the client
+/// keeps its refresh token and tries again later.
+fn refresh_error(e: &TokenError) -> impl IntoResponse {
+    match e {
+        TokenError::Rejected(_) => oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Refresh token invalid or expired. User must re-authorize.",
+        ),
+        TokenError::Invalid(_) => oauth_error(
             StatusCode::BAD_GATEWAY,
             "server_error",
             "Downstream returned invalid token response",
+        ),
+        TokenError::Unavailable(_) => oauth_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "temporarily_unavailable",
+            "Downstream token endpoint is unavailable. Try again later.",
+        ),
+    }
+}
+
+/// Proxy-managed refresh: unwrap the proxy refresh token, obtain fresh upstream
+/// tokens (from the session cache or the provider), and issue a new pair.
+async fn handle_proxy_refresh(
+    state: &AppState,
+    ds_name: &str,
+    oauth: &OAuthConfig,
+    form: TokenForm,
+) -> impl IntoResponse {
+    let Some(refresh_token) = &form.refresh_token else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "refresh_token is required",
         )
         .into_response();
     };
 
-    tracing::info!(downstream = %ds_name, "Refresh token proxied");
+    let claims = match tokens::validate_refresh_token(refresh_token, ds_name, state.keys()) {
+        Ok(c) => c,
+        Err(e) => {
+            return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", e).into_response();
+        }
+    };
 
-    let mut result = json!({
-        "access_token": access_token,
-        "token_type": "Bearer"
-    });
-    if let Some(rt) = body["refresh_token"].as_str() {
-        result["refresh_token"] = json!(rt);
+    if let Err(e) = claims.binding.check(ds_name, form.client_id.as_deref()) {
+        tracing::warn!(downstream = %ds_name, reason = e, "Rejected refresh token");
+        return oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", e).into_response();
+    }
+
+    if state.revoked.is_grant_revoked(&claims.grant_id).await {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "refresh token has been revoked",
+        )
+        .into_response();
+    }
+
+    // Catch a reused token before refreshing upstream on its behalf; the
+    // check after the refresh settles races.
+    match replay::is_refresh_used(state.store.as_ref(), &claims.jti).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return reused_refresh_token(state, ds_name, &claims)
+                .await
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!(downstream = %ds_name, error = %e, "Storage unavailable");
+            return refresh_store_unavailable().into_response();
+        }
     }
-    if let Some(ei) = body["expires_in"].as_u64() {
-        result["expires_in"] = json!(ei);
+
+    // Treat the embedded upstream access token as expired: the client only
+    // refreshes when it needs a new one.
+    let known = UpstreamTokens {
+        access_token: String::new(),
+        refresh_token: Some(claims.upstream_refresh_token.clone()),
+        expires_at: Some(0),
+        scope: None,
+    };
+
+    let upstream =
+        match chained_oauth::fresh_upstream_tokens(state, ds_name, oauth, &claims.grant_id, known)
+            .await
+        {
+            Ok(t) => t,
+            Err(e) => {
+                tracing::error!(
+                    downstream = %ds_name,
+                    error = %e,
+                    "Proxy-managed upstream refresh failed"
+                );
+                return refresh_error(&e).into_response();
+            }
+        };
+
+    // Consume the presented token only once the refresh succeeded, so a
+    // client whose refresh failed upstream can retry with it.
+    match replay::first_refresh(state.store.as_ref(), &claims.jti, claims.exp).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return reused_refresh_token(state, ds_name, &claims)
+                .await
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!(downstream = %ds_name, error = %e, "Storage unavailable");
+            return refresh_store_unavailable().into_response();
+        }
     }
 
-    Json(result).into_response()
+    tracing::info!(downstream = %ds_name, "Refresh handled by proxy");
+
+    proxy_managed_response(state, claims.binding, &claims.grant_id, upstream).into_response()
+}
+
+/// A proxy refresh token was presented again after it was used, so more than
+/// one party holds it. Revoke its whole grant, including the tokens issued in
+/// exchange for it, and have the client re-authorize.
+async fn reused_refresh_token(
+    state: &AppState,
+    ds_name: &str,
+    claims: &RefreshTokenClaims,
+) -> impl IntoResponse {
+    tracing::warn!(
+        downstream = %ds_name,
+        grant_id = %claims.grant_id,
+        "Refresh token reused, revoking its grant"
+    );
+    // Tokens issued under the grant expire at most a refresh token lifetime
+    // from now.
+    let until = codes::now_secs().unwrap_or(0) + state.config.server.refresh_token_ttl;
+    if let Err(e) = state.revoked.revoke_grant(&claims.grant_id, until).await {
+        tracing::error!(downstream = %ds_name, error = %e, "Failed to revoke grant");
+    }
+    state.upstream_sessions.remove(&claims.grant_id).await;
+    oauth_error(
+        StatusCode::BAD_REQUEST,
+        "invalid_grant",
+        "refresh token has already been used",
+    )
+}
+
+fn refresh_store_unavailable() -> impl IntoResponse {
+    oauth_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "temporarily_unavailable",
+        "could not verify refresh token",
+    )
+}
+
+/// Issue a proxy access token and proxy refresh token for a proxy-managed
+/// grant. The access token outlives the upstream one: the MCP proxy refreshes
+/// upstream on demand, so its lifetime is simply `access_token_ttl`.
+fn proxy_managed_response(
+    state: &AppState,
+    binding: GrantBinding,
+    grant_id: &str,
+    upstream: UpstreamTokens,
+) -> impl IntoResponse {
+    let Ok(now) = codes::now_secs() else {
+        return server_error("system time error").into_response();
+    };
+    let Some(upstream_refresh_token) = upstream.refresh_token.clone() else {
+        return server_error("missing upstream refresh token").into_response();
+    };
+
+    let server = &state.config.server;
+    let access = AccessTokenClaims {
+        jti: codes::random_id(),
+        binding: binding.clone(),
+        downstream_tokens: DownstreamTokens::ChainedOAuth(TokenResponse {
+            access_token: upstream.access_token,
+            refresh_token: upstream.refresh_token,
+            expires_in: upstream.expires_at.map(|exp| exp.saturating_sub(now)),
+            scope: upstream.scope.clone(),
+            ..Default::default()
+        }),
+        grant_id: Some(grant_id.to_string()),
+        downstream_exp: upstream.expires_at,
+        exp: now + server.access_token_ttl,
+    };
+    let refresh = RefreshTokenClaims {
+        jti: codes::random_id(),
+        binding,
+        grant_id: grant_id.to_string(),
+        upstream_refresh_token,
+        exp: now + server.refresh_token_ttl,
+    };
+
+    let keys = state.keys();
+    let (access_token, refresh_token) = match (
+        tokens::issue_access_token(access, keys),
+        tokens::issue_refresh_token(refresh, keys),
+    ) {
+        (Ok(a), Ok(r)) => (a, r),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Failed to issue tokens: {e}");
+            return server_error("Failed to issue tokens").into_response();
+        }
+    };
+
+    let mut resp = json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": server.access_token_ttl,
+        "refresh_token": refresh_token
+    });
+    if let Some(scope) = &upstream.scope {
+        resp["scope"] = json!(chained_oauth::normalize_scope(scope));
+    }
+    Json(resp).into_response()
 }
@@ -0,0 +1,155 @@
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Form;
+use serde::Deserialize;
+
+use crate::auth::login::{self, Access};
+use crate::auth::vault;
+use crate::config::{DownstreamConfig, StrategyConfig};
+use crate::routes::authorize::{html_escape, PAGE_STYLE};
+use crate::routes::login::redirect_to_login;
+use crate::AppState;
+
+fn find_vault<'a>(state: &'a AppState, name: &str) -> Option<&'a DownstreamConfig> {
+    state
+        .find_downstream(name)
+        .filter(|ds| matches!(ds.strategy, StrategyConfig::Vault { .. }))
+}
+
+/// The response when the request is not from an allowed, signed-in user.
+/// Without a session, `GET` goes to sign-in and `POST` fails.
+fn refuse(state: &AppState, name: &str, access: Access, sign_in: bool) -> Response {
+    match access {
+        Access::LoginRequired if sign_in => {
+            let return_to = format!("{}/vault/mcp/{}", state.config.server.public_url, name);
+            redirect_to_login(state, &return_to)
+        }
+        Access::Denied(e) => {
+            tracing::warn!(downstream = %name, reason = %e, "Sign-in not allowed");
+            (StatusCode::FORBIDDEN, e).into_response()
+        }
+        _ => (StatusCode::UNAUTHORIZED, "Sign-in required").into_response(),
+    }
+}
+
+fn storage_unavailable(name: &str, e: String) -> Response {
+    tracing::error!(downstream = %name, error = %e, "Vault unavailable");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Credential storage unavailable",
+    )
+        .into_response()
+}
+
+/// GET /vault/mcp/:name — show and manage the user's stored credential
+pub async fn manage_get(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(ds) = find_vault(&state, &name) else {
+        return (StatusCode::NOT_FOUND, "Unknown downstream").into_response();
+    };
+    let user = match login::check(&state, ds, &headers) {
+        Access::User(user) => user,
+        access => return refuse(&state, &name, access, true),
+    };
+
+    let stored = match vault::get(state.store.as_ref(), state.keys(), &name, &user).await {
+        Ok(credential) => credential.is_some(),
+        Err(e) => return storage_unavailable(&name, e),
+    };
+
+    let (status, delete_html) = if stored {
+        (
+            "A token is saved. Saving a new one replaces it; deleting it stops access until you authorize again.",
+            r#"<form method="POST">
+      <input type="hidden" name="action" value="delete">
+      <button type="submit" style="background:#dc2626">Delete saved token</button>
+    </form>"#,
+        )
+    } else {
+        ("No token is saved.", "")
+    };
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>Saved token — {display_name}</title>
+  <style>{style}</style>
+</head>
+<body>
+  <div class="card">
+    <h1>{display_name}</h1>
+    <p class="hint">{status}</p>
+    <p style="color:#666;font-size:0.9em">Signed in as <strong>{who}</strong></p>
+    <form method="POST">
+      <input type="hidden" name="action" value="save">
+      <label for="token">API Token</label>
+      <input type="password" id="token" name="token" required placeholder="Paste your token here">
+      <button type="submit">Save</button>
+    </form>
+    {delete_html}
+  </div>
+</body>
+</html>"#,
+        style = PAGE_STYLE,
+        display_name = html_escape(&ds.display_name),
+        who = html_escape(user.email.as_deref().unwrap_or(&user.sub)),
+    );
+
+    Html(html).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ManageForm {
+    action: String,
+    #[serde(default)]
+    token: String,
+}
+
+/// POST /vault/mcp/:name — save or delete the user's stored credential
+pub async fn manage_post(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<ManageForm>,
+) -> Response {
+    let Some(ds) = find_vault(&state, &name) else {
+        return (StatusCode::NOT_FOUND, "Unknown downstream").into_response();
+    };
+    let user = match login::check(&state, ds, &headers) {
+        Access::User(user) => user,
+        access => return refuse(&state, &name, access, false),
+    };
+
+    let store = state.store.as_ref();
+    match form.action.as_str() {
+        "save" => {
+            if form.token.is_empty() {
+                return (StatusCode::BAD_REQUEST, "token is required").into_response();
+            }
+            if let Err(e) = vault::put(store, state.keys(), &name, &user, &form.token).await {
+                return storage_unavailable(&name, e);
+            }
+            tracing::info!(downstream = %name, user = %user.sub, "Vault credential saved");
+        }
+        "delete" => {
+            if let Err(e) = vault::delete(store, &name, &user).await {
+                return storage_unavailable(&name, e);
+            }
+            tracing::info!(downstream = %name, user = %user.sub, "Vault credential deleted");
+        }
+        _ => return (StatusCode::BAD_REQUEST, "action must be 'save' or 'delete'").into_response(),
+    }
+
+    Redirect::to(&format!(
+        "{}/vault/mcp/{}",
+        state.config.server.public_url, name
+    ))
+    .into_response()
+}
@@ -20,7 +20,7 @@ pub async fn protected_resource(
             .into_response();
     }
 
-    let resource = format!("{}/mcp/{}", state.config.server.public_url, name);
+    let resource = state.resource_url(&name);
 
     Json(json!({
         "resource": resource,
@@ -46,6 +46,8 @@ pub async fn authorization_server(
     let issuer = format!("{public}/mcp/{}", name);
     let authorization_endpoint = format!("{public}/authorize/mcp/{}", name);
     let token_endpoint = format!("{public}/token/mcp/{}", name);
+    let registration_endpoint = format!("{public}/register/mcp/{}", name);
+    let revocation_endpoint = format!("{public}/revoke/mcp/{}", name);
 
     let supports_refresh = matches!(
         &ds.strategy,
@@ -57,14 +59,22 @@ pub async fn authorization_server(
         json!(["authorization_code"])
     };
 
-    Json(json!({
+    let mut metadata = json!({
         "issuer": issuer,
         "authorization_endpoint": authorization_endpoint,
         "token_endpoint": token_endpoint,
+        "registration_endpoint": registration_endpoint,
+        "revocation_endpoint": revocation_endpoint,
         "response_types_supported": ["code"],
         "grant_types_supported": grant_types,
         "code_challenge_methods_supported": ["S256"],
-        "token_endpoint_auth_methods_supported": ["none"]
-    }))
-    .into_response()
+        "token_endpoint_auth_methods_supported": ["none"],
+        "revocation_endpoint_auth_methods_supported": ["none"]
+    });
+
+    if state.config.server.introspection_secret.is_some() {
+        metadata["introspection_endpoint"] = json!(format!("{public}/introspect/mcp/{}", name));
+    }
+
+    Json(metadata).into_response()
 }
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::Store;
+use crate::oauth::codes::now_secs;
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn is_live(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|exp| exp >= now)
+    }
+}
+
+/// In-process store. Nothing is shared between replicas or kept across
+/// restarts.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryStore {
+    fn insert(&self, key: &str, value: &[u8], expires_at: Option<u64>, replace: bool) -> bool {
+        let now = now_secs().unwrap_or(0);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| e.is_live(now));
+        if !replace && entries.contains_key(key) {
+            return false;
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: value.to_vec(),
+                expires_at,
+            },
+        );
+        true
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let now = now_secs()?;
+        let entries = self.entries.lock().unwrap();
+        Ok(entries
+            .get(key)
+            .filter(|e| e.is_live(now))
+            .map(|e| e.value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: &[u8], expires_at: Option<u64>) -> Result<(), String> {
+        self.insert(key, value, expires_at, true);
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<bool, String> {
+        Ok(self.insert(key, value, expires_at, false))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        super::super::check_store(&MemoryStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_purged() {
+        let store = MemoryStore::default();
+        store.set("old", b"", Some(0)).await.unwrap();
+        store.set("new", b"", None).await.unwrap();
+
+        let entries = store.entries.lock().unwrap();
+        assert!(!entries.contains_key("old"));
+        assert!(entries.contains_key("new"));
+    }
+}
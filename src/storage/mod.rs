@@ -0,0 +1,86 @@
+//! Shared key-value storage for the little state the proxy keeps.
+//!
+//! Codes and tokens are self-contained, but a few features need to remember
+//! things across requests: the revocation denylist, the authorization code
+//! replay cache, vault credentials and rotated upstream tokens. They go through the [`Store`] trait so a deployment can pick
+//! where that state lives with the `[storage]` config section:
+//!
+//! - [`MemoryStore`] (default): per-process, lost on restart.
+//! - [`SqliteStore`]: an embedded SQLite file, shared by processes on one host.
+//! - [`RedisStore`]: a Redis server, shared by every replica.
+//!
+//! Every entry may carry an expiry (UNIX seconds). Expired entries read as
+//! absent and are purged by the backend.
+
+mod memory;
+mod redis;
+mod sqlite;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::config::StorageConfig;
+
+pub use self::memory::MemoryStore;
+pub use self::redis::RedisStore;
+pub use self::sqlite::SqliteStore;
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// The value at `key`, unless it is missing or expired.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    /// Store `value` at `key`, replacing any existing value. The entry is
+    /// dropped after `expires_at` if given.
+    async fn set(&self, key: &str, value: &[u8], expires_at: Option<u64>) -> Result<(), String>;
+
+    /// Store `value` at `key` only if no live entry exists. Returns whether it
+    /// was stored. Atomic, so concurrent callers agree on a single winner.
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<bool, String>;
+
+    async fn delete(&self, key: &str) -> Result<(), String>;
+}
+
+/// Open the backend selected by `[storage]`.
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Store>, String> {
+    Ok(match config {
+        StorageConfig::Memory => Arc::new(MemoryStore::default()),
+        StorageConfig::Sqlite { path } => Arc::new(SqliteStore::open(path)?),
+        StorageConfig::Redis { url, key_prefix } => Arc::new(RedisStore::open(url, key_prefix)?),
+    })
+}
+
+/// Behaviour every backend must share, run against each of them.
+#[cfg(test)]
+pub(crate) async fn check_store(store: &dyn Store) {
+    use crate::oauth::codes::now_secs;
+
+    let later = Some(now_secs().unwrap() + 60);
+
+    assert_eq!(store.get("k").await.unwrap(), None);
+    store.set("k", b"one", later).await.unwrap();
+    assert_eq!(store.get("k").await.unwrap().as_deref(), Some(&b"one"[..]));
+    store.set("k", b"two", None).await.unwrap();
+    assert_eq!(store.get("k").await.unwrap().as_deref(), Some(&b"two"[..]));
+
+    assert!(!store.set_if_absent("k", b"three", later).await.unwrap());
+    assert_eq!(store.get("k").await.unwrap().as_deref(), Some(&b"two"[..]));
+    assert!(store.set_if_absent("fresh", b"", later).await.unwrap());
+    assert!(!store.set_if_absent("fresh", b"", later).await.unwrap());
+
+    store.delete("k").await.unwrap();
+    assert_eq!(store.get("k").await.unwrap(), None);
+    store.delete("k").await.unwrap();
+
+    // Expired entries read as absent and can be claimed again.
+    store.set("old", b"x", Some(1)).await.unwrap();
+    assert_eq!(store.get("old").await.unwrap(), None);
+    assert!(store.set_if_absent("old", b"y", later).await.unwrap());
+    assert_eq!(store.get("old").await.unwrap().as_deref(), Some(&b"y"[..]));
+}
@@ -0,0 +1,114 @@
+use ::redis::aio::ConnectionManager;
+use ::redis::{AsyncCommands, Client};
+use async_trait::async_trait;
+use tokio::sync::OnceCell;
+
+use super::Store;
+
+/// Store backed by a Redis server, shared by every replica pointing at it.
+/// Expiry uses Redis' own key expiry.
+pub struct RedisStore {
+    client: Client,
+    key_prefix: String,
+    /// Connected on first use, so startup does not wait for Redis.
+    conn: OnceCell<ConnectionManager>,
+}
+
+impl RedisStore {
+    /// `url` is a `redis://` or `rediss://` URL; every key is prefixed with
+    /// `key_prefix`.
+    pub fn open(url: &str, key_prefix: &str) -> Result<Self, String> {
+        let client = Client::open(url).map_err(|e| format!("invalid Redis URL: {e}"))?;
+        Ok(Self {
+            client,
+            key_prefix: key_prefix.to_string(),
+            conn: OnceCell::new(),
+        })
+    }
+
+    async fn conn(&self) -> Result<ConnectionManager, String> {
+        self.conn
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .map_err(|e| format!("failed to connect to Redis: {e}"))
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.key_prefix)
+    }
+
+    fn set_cmd(&self, key: &str, value: &[u8], expires_at: Option<u64>) -> ::redis::Cmd {
+        let mut cmd = ::redis::cmd("SET");
+        cmd.arg(self.key(key)).arg(value);
+        if let Some(exp) = expires_at {
+            // Redis drops a key at its EXAT time; other backends keep an entry
+            // through its `expires_at` second.
+            cmd.arg("EXAT").arg(exp + 1);
+        }
+        cmd
+    }
+}
+
+fn redis_error(e: ::redis::RedisError) -> String {
+    format!("Redis error: {e}")
+}
+
+#[async_trait]
+impl Store for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut conn = self.conn().await?;
+        conn.get(self.key(key)).await.map_err(redis_error)
+    }
+
+    async fn set(&self, key: &str, value: &[u8], expires_at: Option<u64>) -> Result<(), String> {
+        let mut conn = self.conn().await?;
+        self.set_cmd(key, value, expires_at)
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<bool, String> {
+        let mut conn = self.conn().await?;
+        let reply: Option<String> = self
+            .set_cmd(key, value, expires_at)
+            .arg("NX")
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error)?;
+        Ok(reply.is_some())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let mut conn = self.conn().await?;
+        conn.del(self.key(key)).await.map_err(redis_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against the server in `MCP_PROXY_TEST_REDIS_URL`
+    /// (e.g. `redis://127.0.0.1/` for a local `redis-server`), with
+    /// `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs a Redis server in MCP_PROXY_TEST_REDIS_URL"]
+    async fn test_redis_store() {
+        let url =
+            std::env::var("MCP_PROXY_TEST_REDIS_URL").expect("MCP_PROXY_TEST_REDIS_URL is not set");
+        let prefix = format!("mcp-oauth-proxy-test:{}:", crate::oauth::codes::random_id());
+        super::super::check_store(&RedisStore::open(&url, &prefix).unwrap()).await;
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        assert!(RedisStore::open("not a url", "p:").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::Store;
+use crate::oauth::codes::now_secs;
+
+/// Store backed by an SQLite database file. Several processes on one host can
+/// share the file; SQLite serializes their writes.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Open (creating if needed) the database at `path`.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("failed to open SQLite store '{}': {e}", path.display()))?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))
+            .and_then(|_| {
+                conn.execute_batch(
+                    "PRAGMA journal_mode = WAL;
+                     CREATE TABLE IF NOT EXISTS kv (
+                         key TEXT PRIMARY KEY,
+                         value BLOB NOT NULL,
+                         expires_at INTEGER
+                     );
+                     CREATE INDEX IF NOT EXISTS kv_expires_at ON kv (expires_at);",
+                )
+            })
+            .map_err(|e| format!("failed to initialize SQLite store: {e}"))?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run `f` against the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection, u64) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let now = now_secs()?;
+        tokio::task::spawn_blocking(move || f(&conn.lock().unwrap(), now))
+            .await
+            .map_err(|e| format!("SQLite task failed: {e}"))?
+            .map_err(|e| format!("SQLite error: {e}"))
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let key = key.to_string();
+        self.with_conn(move |conn, now| {
+            conn.query_row(
+                "SELECT value FROM kv WHERE key = ?1 AND (expires_at IS NULL OR expires_at >= ?2)",
+                params![key, now],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, value: &[u8], expires_at: Option<u64>) -> Result<(), String> {
+        let (key, value) = (key.to_string(), value.to_vec());
+        self.with_conn(move |conn, now| {
+            conn.execute("DELETE FROM kv WHERE expires_at < ?1", params![now])?;
+            conn.execute(
+                "INSERT OR REPLACE INTO kv (key, value, expires_at) VALUES (?1, ?2, ?3)",
+                params![key, value, expires_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<bool, String> {
+        let (key, value) = (key.to_string(), value.to_vec());
+        self.with_conn(move |conn, now| {
+            conn.execute("DELETE FROM kv WHERE expires_at < ?1", params![now])?;
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO kv (key, value, expires_at) VALUES (?1, ?2, ?3)",
+                params![key, value, expires_at],
+            )?;
+            Ok(inserted == 1)
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let key = key.to_string();
+        self.with_conn(move |conn, _| {
+            conn.execute("DELETE FROM kv WHERE key = ?1", params![key])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let path = std::env::temp_dir().join(format!(
+            "mcp-oauth-proxy-test-{}.db",
+            crate::oauth::codes::random_id()
+        ));
+        let store = SqliteStore::open(&path).unwrap();
+        super::super::check_store(&store).await;
+
+        // Entries survive reopening the file.
+        store.set("kept", b"v", None).await.unwrap();
+        drop(store);
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.get("kept").await.unwrap().as_deref(), Some(&b"v"[..]));
+
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}
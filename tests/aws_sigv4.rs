@@ -0,0 +1,248 @@
+mod common;
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::routing::any;
+use axum::{Json, Router};
+use common::*;
+use mcp_oauth_proxy::proxy::sigv4::{self, AwsCredentials, SigningScope};
+use serde_json::json;
+use std::net::SocketAddr;
+
+// ---------------------------------------------------------------------------
+// Mock API Gateway
+// ---------------------------------------------------------------------------
+
+const REGION: &str = "eu-west-1";
+const SECRET: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+const CONFIGURED_SECRET: &str = "configured/secret+KEY";
+
+/// Recomputes the signature over what arrived, like API Gateway with IAM
+/// authorization, and echoes the access key and session token it was made
+/// with. Keys starting with `AKIACONFIG` use [`CONFIGURED_SECRET`].
+async fn mock_gateway(
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let Some(authorization) = header("authorization") else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
+    let Some(credential) = authorization
+        .split("Credential=")
+        .nth(1)
+        .and_then(|c| c.split('/').next())
+    else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
+    let Some(signed) = authorization
+        .split("SignedHeaders=")
+        .nth(1)
+        .and_then(|s| s.split(',').next())
+    else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
+    let signed_headers: Vec<(String, String)> = signed
+        .split(';')
+        .map(|name| {
+            (
+                name.to_string(),
+                header(name).unwrap_or_default().to_string(),
+            )
+        })
+        .collect();
+
+    let credentials = AwsCredentials {
+        access_key_id: credential.to_string(),
+        secret_access_key: if credential.starts_with("AKIACONFIG") {
+            CONFIGURED_SECRET
+        } else {
+            SECRET
+        }
+        .to_string(),
+        session_token: None,
+    };
+    let url = reqwest::Url::parse(&format!("http://{}{uri}", header("host").unwrap())).unwrap();
+    let expected = sigv4::authorization(
+        &credentials,
+        &SigningScope {
+            region: REGION,
+            service: "execute-api",
+        },
+        method.as_str(),
+        &url,
+        &signed_headers,
+        &body,
+        header("x-amz-date").unwrap_or_default(),
+    );
+    if expected != authorization {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    Json(json!({
+        "access_key_id": credential,
+        "session_token": header("x-amz-security-token"),
+        "signed_headers": signed,
+    }))
+    .into_response()
+}
+
+async fn start_mock_gateway() -> SocketAddr {
+    serve(Router::new().route("/prod/mcp", any(mock_gateway))).await
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// Start a proxy with two AWS downstreams in front of the mock gateway:
+/// `user-keys`, where users enter their own credentials, and `team`, which
+/// signs with configured ones behind a password gate.
+async fn start_proxy(gateway: &SocketAddr) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[downstream.user-keys]
+display_name = "Internal API"
+strategy = "aws_sigv4"
+downstream_url = "http://{gateway}/prod/mcp"
+aws_region = "{REGION}"
+
+[downstream.team]
+display_name = "Team API"
+strategy = "aws_sigv4"
+downstream_url = "http://{gateway}/prod/mcp"
+aws_region = "{REGION}"
+aws_access_key_id = "AKIACONFIGEXAMPLE"
+aws_secret_access_key = "{CONFIGURED_SECRET}"
+gate = "password"
+gate_password = "{GATE_PASSWORD}"
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Submit the authorize form and redeem the code, returning a proxy access
+/// token.
+async fn obtain_token(proxy: &SocketAddr, name: &str, fields: &[(&str, &str)]) -> String {
+    let resp = submit(proxy, name, fields, None).await;
+    let body = redeem(proxy, name, &resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_entered_credentials_sign_each_request() {
+    let gateway = start_mock_gateway().await;
+    let proxy = start_proxy(&gateway).await;
+
+    let page = client()
+        .get(authorize_url(&proxy, "user-keys"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains(r#"name="aws_access_key_id""#));
+    assert!(page.contains(r#"name="aws_secret_access_key""#));
+    assert!(page.contains(r#"name="aws_session_token""#));
+    assert!(!page.contains(r#"name="token""#));
+
+    let access_token = obtain_token(
+        &proxy,
+        "user-keys",
+        &[
+            ("aws_access_key_id", "AKIDEXAMPLE"),
+            ("aws_secret_access_key", SECRET),
+            ("aws_session_token", "session-token"),
+        ],
+    )
+    .await;
+
+    let resp = mcp_ping(&proxy, "user-keys", &access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["access_key_id"], "AKIDEXAMPLE");
+    assert_eq!(body["session_token"], "session-token");
+    assert_eq!(
+        body["signed_headers"],
+        "content-type;host;x-amz-date;x-amz-security-token"
+    );
+
+    // The SSE stream is signed too, without a body.
+    let resp = client()
+        .get(format!("http://{proxy}/mcp/user-keys"))
+        .bearer_auth(&access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(body["access_key_id"], "AKIDEXAMPLE");
+}
+
+#[tokio::test]
+async fn test_incomplete_credentials_are_rejected() {
+    let gateway = start_mock_gateway().await;
+    let proxy = start_proxy(&gateway).await;
+
+    let resp = submit(
+        &proxy,
+        "user-keys",
+        &[("aws_access_key_id", "AKIDEXAMPLE")],
+        None,
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+    // A plain token is not a credential for an AWS downstream.
+    let resp = submit(&proxy, "user-keys", &[("token", SECRET)], None).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_configured_credentials_behind_password_gate() {
+    let gateway = start_mock_gateway().await;
+    let proxy = start_proxy(&gateway).await;
+
+    let resp = submit(
+        &proxy,
+        "team",
+        &[("token", "wrong password, sixteen+")],
+        None,
+    )
+    .await;
+    assert_eq!(resp.status(), 401);
+
+    let access_token = obtain_token(&proxy, "team", &[("token", GATE_PASSWORD)]).await;
+    let resp = mcp_ping(&proxy, "team", &access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["access_key_id"], "AKIACONFIGEXAMPLE");
+    assert!(body["session_token"].is_null());
+
+    // A token for the other AWS downstream is not accepted here.
+    let user_token = obtain_token(
+        &proxy,
+        "user-keys",
+        &[
+            ("aws_access_key_id", "AKIDEXAMPLE"),
+            ("aws_secret_access_key", SECRET),
+        ],
+    )
+    .await;
+    let resp = mcp_ping(&proxy, "team", &user_token).await;
+    assert_eq!(resp.status(), 401);
+}
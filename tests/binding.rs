@@ -0,0 +1,186 @@
+mod common;
+
+use common::*;
+use mcp_oauth_proxy::oauth::keys::KeyPurpose;
+use mcp_oauth_proxy::oauth::state::sign_state;
+use serde_json::json;
+use std::net::SocketAddr;
+
+async fn start_proxy() -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[downstream.test]
+display_name = "Test Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+
+[downstream.other]
+display_name = "Other Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+
+[downstream.chained]
+display_name = "Chained Service"
+strategy = "chained_oauth"
+downstream_url = "http://127.0.0.1:1/mcp"
+oauth_authorize_url = "http://127.0.0.1:1/authorize"
+oauth_token_url = "http://127.0.0.1:1/token"
+oauth_client_id = "test-client-id"
+oauth_client_secret = "test-client-secret"
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Submit the passthrough form for `test` and return the issued code.
+async fn obtain_code(addr: &SocketAddr, client_id: &str, resource: &str) -> String {
+    let challenge = pkce_challenge(VERIFIER);
+    let resp = client()
+        .post(format!("http://{addr}/authorize/mcp/test"))
+        .form(&[
+            ("token", "secret-key"),
+            ("client_id", client_id),
+            ("resource", resource),
+            ("state", "s"),
+            ("redirect_uri", CLAUDE_REDIRECT),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    query_param(location(&resp).as_str(), "code")
+}
+
+async fn exchange(
+    addr: &SocketAddr,
+    downstream: &str,
+    code: &str,
+    extra: &[(&str, &str)],
+) -> reqwest::Response {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("code_verifier", VERIFIER),
+        ("redirect_uri", CLAUDE_REDIRECT),
+    ];
+    form.extend_from_slice(extra);
+    client()
+        .post(format!("http://{addr}/token/mcp/{downstream}"))
+        .form(&form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn assert_oauth_error(resp: reqwest::Response, error: &str) {
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], error);
+}
+
+#[tokio::test]
+async fn test_code_rejected_at_other_downstream() {
+    let addr = start_proxy().await;
+    let code = obtain_code(&addr, "client-1", "").await;
+
+    let resp = exchange(&addr, "other", &code, &[("client_id", "client-1")]).await;
+    assert_oauth_error(resp, "invalid_grant").await;
+
+    // The code is still good where it was issued.
+    let resp = exchange(&addr, "test", &code, &[("client_id", "client-1")]).await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn test_code_rejected_for_other_client() {
+    let addr = start_proxy().await;
+    let code = obtain_code(&addr, "client-1", "").await;
+
+    let resp = exchange(&addr, "test", &code, &[("client_id", "client-2")]).await;
+    assert_oauth_error(resp, "invalid_grant").await;
+}
+
+#[tokio::test]
+async fn test_access_token_rejected_at_other_downstream() {
+    let addr = start_proxy().await;
+    let code = obtain_code(&addr, "client-1", "").await;
+    let body: serde_json::Value = exchange(&addr, "test", &code, &[("client_id", "client-1")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let access_token = body["access_token"].as_str().unwrap();
+
+    let resp = mcp_ping(&addr, "other", access_token).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn test_resource_indicator_must_match_downstream() {
+    let addr = start_proxy().await;
+    let own = format!("http://127.0.0.1:{}/mcp/test", addr.port());
+    let foreign = format!("http://127.0.0.1:{}/mcp/other", addr.port());
+
+    let url = url::Url::parse_with_params(
+        &format!("http://{addr}/authorize/mcp/test"),
+        &[
+            ("response_type", "code"),
+            ("redirect_uri", CLAUDE_REDIRECT),
+            ("state", "s"),
+            ("code_challenge", pkce_challenge(VERIFIER).as_str()),
+            ("code_challenge_method", "S256"),
+            ("resource", foreign.as_str()),
+        ],
+    )
+    .unwrap();
+    let resp = client().get(url).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+
+    let code = obtain_code(&addr, "", &own).await;
+    let resp = exchange(&addr, "test", &code, &[("resource", foreign.as_str())]).await;
+    assert_oauth_error(resp, "invalid_target").await;
+
+    let resp = exchange(&addr, "test", &code, &[("resource", own.as_str())]).await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn test_callback_rejects_state_from_other_downstream() {
+    let addr = start_proxy().await;
+    let exp = now() + 600;
+    let payload = json!({
+        "claude_state": "s",
+        "claude_redirect_uri": CLAUDE_REDIRECT,
+        "pkce_challenge": pkce_challenge(VERIFIER),
+        "pkce_method": "S256",
+        "downstream": "test",
+        "exp": exp,
+    });
+    let keys = keyring();
+    let callback = |signed: String| {
+        client()
+            .get(format!(
+                "http://{addr}/callback/mcp/chained?code=x&state={signed}"
+            ))
+            .send()
+    };
+
+    // Signed under another downstream's state key.
+    let signed = sign_state(&payload, KeyPurpose::State("test"), &keys);
+    assert_eq!(callback(signed).await.unwrap().status(), 400);
+
+    // Even under the right key, the payload must name this downstream.
+    let signed = sign_state(&payload, KeyPurpose::State("chained"), &keys);
+    let resp = callback(signed).await.unwrap();
+    assert_eq!(resp.status(), 400);
+    assert!(resp.text().await.unwrap().contains("different downstream"));
+}
@@ -4,6 +4,7 @@ use axum::routing::{get, post};
 use axum::{Json, Router};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use mcp_oauth_proxy::oauth::keys::{KeyPurpose, Keyring};
 use serde::Deserialize;
 use serde_json::json;
 use std::future::IntoFuture;
@@ -21,6 +22,8 @@ struct MockState {
     refresh_count: Arc<Mutex<u32>>,
     fail_token_exchange: Arc<Mutex<bool>>,
     fail_refresh: Arc<Mutex<bool>>,
+    /// Answer refreshes with a bare 503, as during an outage.
+    refresh_outage: Arc<Mutex<bool>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -37,6 +40,8 @@ struct TokenRequest {
     redirect_uri: Option<String>,
     #[serde(default)]
     refresh_token: Option<String>,
+    #[serde(default)]
+    code_verifier: Option<String>,
 }
 
 async fn mock_authorize() -> impl IntoResponse {
@@ -71,6 +76,13 @@ async fn mock_token(
             .into_response()
         }
         "refresh_token" => {
+            if *state.refresh_outage.lock().await {
+                return (
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                    "Service Unavailable",
+                )
+                    .into_response();
+            }
             if *state.fail_refresh.lock().await {
                 return (
                     axum::http::StatusCode::BAD_REQUEST,
@@ -108,6 +120,7 @@ async fn start_mock_downstream() -> (SocketAddr, MockState) {
         refresh_count: Arc::new(Mutex::new(0)),
         fail_token_exchange: Arc::new(Mutex::new(false)),
         fail_refresh: Arc::new(Mutex::new(false)),
+        refresh_outage: Arc::new(Mutex::new(false)),
     };
 
     let app = Router::new()
@@ -133,6 +146,18 @@ fn test_secret() -> String {
     STANDARD.encode([0xAA_u8; 32])
 }
 
+/// Decrypt a proxy-issued access token and return the downstream token inside.
+fn unwrap_access_token(token: &str) -> String {
+    let claims = mcp_oauth_proxy::oauth::tokens::validate_access_token(
+        token,
+        "test-oauth",
+        &Keyring::single(&[0xAA_u8; 32]),
+    )
+    .unwrap();
+    assert_eq!(claims.binding.downstream, "test-oauth");
+    claims.downstream_tokens.access_token().to_string()
+}
+
 fn no_redirect_client() -> reqwest::Client {
     reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -178,7 +203,7 @@ async fn start_proxy(mock_addr: &SocketAddr) -> SocketAddr {
 
     let toml_str = make_config_toml(mock_addr, &proxy_addr);
     let config: mcp_oauth_proxy::config::Config = toml::from_str(&toml_str).unwrap();
-    let state = mcp_oauth_proxy::AppState::new(config, reqwest::Client::new());
+    let state = mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap();
 
     let app = mcp_oauth_proxy::build_router(state);
     tokio::spawn(axum::serve(listener, app).into_future());
@@ -313,12 +338,76 @@ async fn test_full_chained_oauth_flow() {
 
     assert_eq!(resp.status(), 200);
     let body: serde_json::Value = resp.json().await.unwrap();
-    assert_eq!(body["access_token"], "downstream-access-token-abc");
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_ne!(access_token, "downstream-access-token-abc");
+    assert_eq!(
+        unwrap_access_token(access_token),
+        "downstream-access-token-abc"
+    );
     assert_eq!(body["token_type"], "Bearer");
     assert_eq!(body["refresh_token"], "downstream-refresh-token-xyz");
     assert_eq!(body["expires_in"], 28800);
 }
 
+#[tokio::test]
+async fn test_upstream_pkce() {
+    let (mock_addr, mock_state) = start_mock_downstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+    let client = no_redirect_client();
+
+    let resp = client
+        .get(format!(
+            "http://{proxy_addr}/authorize/mcp/test-oauth\
+             ?response_type=code\
+             &client_id=claude-client\
+             &redirect_uri={CLAUDE_REDIRECT}\
+             &state=s\
+             &code_challenge={}\
+             &code_challenge_method=S256",
+            pkce_challenge(VERIFIER)
+        ))
+        .send()
+        .await
+        .unwrap();
+    let location = resp.headers()["location"].to_str().unwrap();
+    let params: std::collections::HashMap<String, String> = url::Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect();
+
+    // The provider gets the proxy's own S256 challenge, not Claude's.
+    assert_eq!(params["code_challenge_method"], "S256");
+    let upstream_challenge = &params["code_challenge"];
+    assert_ne!(upstream_challenge, &pkce_challenge(VERIFIER));
+
+    // The verifier is not readable from the state.
+    let signed_state = &params["state"];
+    let state_payload = mcp_oauth_proxy::oauth::state::verify_state(
+        signed_state,
+        KeyPurpose::State("test-oauth"),
+        &Keyring::single(&[0xAA_u8; 32]),
+    )
+    .unwrap();
+    let sealed = state_payload["upstream_verifier"].as_str().unwrap();
+    assert!(sealed.starts_with("v1."));
+
+    let resp = client
+        .get(format!(
+            "http://{proxy_addr}/callback/mcp/test-oauth?code=c&state={signed_state}"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+
+    // The token exchange presents the matching verifier.
+    let token_reqs = mock_state.token_requests.lock().await;
+    let verifier = token_reqs[0].code_verifier.as_deref().unwrap();
+    assert!(!sealed.contains(verifier));
+    assert_eq!(&pkce_challenge(verifier), upstream_challenge);
+}
+
 #[tokio::test]
 async fn test_refresh_token_flow() {
     let (mock_addr, _mock_state) = start_mock_downstream().await;
@@ -339,7 +428,10 @@ async fn test_refresh_token_flow() {
 
     assert_eq!(resp.status(), 200);
     let body: serde_json::Value = resp.json().await.unwrap();
-    assert_eq!(body["access_token"], "refreshed-access-token-1");
+    assert_eq!(
+        unwrap_access_token(body["access_token"].as_str().unwrap()),
+        "refreshed-access-token-1"
+    );
     assert_eq!(body["refresh_token"], "refreshed-refresh-token-1");
     assert_eq!(body["token_type"], "Bearer");
     assert_eq!(body["expires_in"], 28800);
@@ -362,7 +454,7 @@ async fn test_invalid_callback_rejected() {
     assert_eq!(resp.status(), 400);
 
     // Expired state
-    let secret = vec![0xAA_u8; 32];
+    let secret = Keyring::single(&[0xAA_u8; 32]);
     let expired_payload = json!({
         "claude_state": "s",
         "claude_redirect_uri": "http://localhost/cb",
@@ -370,7 +462,11 @@ async fn test_invalid_callback_rejected() {
         "pkce_method": "S256",
         "exp": 0,
     });
-    let expired_signed = mcp_oauth_proxy::oauth::state::sign_state(&expired_payload, &secret);
+    let expired_signed = mcp_oauth_proxy::oauth::state::sign_state(
+        &expired_payload,
+        KeyPurpose::State("test-oauth"),
+        &secret,
+    );
     let resp = client
         .get(format!(
             "http://{proxy_addr}/callback/mcp/test-oauth?code=x&state={expired_signed}"
@@ -380,6 +476,30 @@ async fn test_invalid_callback_rejected() {
         .unwrap();
     assert_eq!(resp.status(), 400);
 
+    // Valid state without the sealed upstream PKCE verifier
+    let unsealed_payload = json!({
+        "claude_state": "s",
+        "claude_redirect_uri": "http://localhost/cb",
+        "pkce_challenge": "c",
+        "pkce_method": "S256",
+        "downstream": "test-oauth",
+        "upstream_verifier": "plain-verifier",
+        "exp": 4_000_000_000u64,
+    });
+    let unsealed_signed = mcp_oauth_proxy::oauth::state::sign_state(
+        &unsealed_payload,
+        KeyPurpose::State("test-oauth"),
+        &secret,
+    );
+    let resp = client
+        .get(format!(
+            "http://{proxy_addr}/callback/mcp/test-oauth?code=x&state={unsealed_signed}"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
     // Missing code param
     let resp = client
         .get(format!(
@@ -474,6 +594,29 @@ async fn test_downstream_refresh_failure() {
         .contains("re-authorize"));
 }
 
+#[tokio::test]
+async fn test_downstream_refresh_outage_is_temporary() {
+    let (mock_addr, mock_state) = start_mock_downstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+    *mock_state.refresh_outage.lock().await = true;
+
+    let resp = no_redirect_client()
+        .post(format!("http://{proxy_addr}/token/mcp/test-oauth"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", "downstream-refresh-token-xyz"),
+            ("client_id", "claude-client"),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    // The client keeps its refresh token and retries later.
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "temporarily_unavailable");
+}
+
 #[tokio::test]
 async fn test_well_known_advertises_refresh_for_chained() {
     let (mock_addr, _mock_state) = start_mock_downstream().await;
@@ -513,6 +656,7 @@ async fn test_downstream_error_in_callback() {
         .unwrap();
 
     assert_eq!(resp.status(), 502);
+    // The provider's text is logged, not shown.
     let body = resp.text().await.unwrap();
-    assert!(body.contains("User denied access"));
+    assert_eq!(body, "Downstream authorization failed");
 }
@@ -0,0 +1,307 @@
+mod common;
+
+use axum::extract::{Form, State};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock provider recording how the proxy authenticates
+// ---------------------------------------------------------------------------
+
+/// One request to the token or revocation endpoint.
+#[derive(Debug, Clone)]
+struct Recorded {
+    path: &'static str,
+    authorization: Option<String>,
+    form: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+struct MockState {
+    requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+fn record(
+    state: &MockState,
+    path: &'static str,
+    headers: &HeaderMap,
+    form: HashMap<String, String>,
+) {
+    state.requests.lock().unwrap().push(Recorded {
+        path,
+        authorization: headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+        form,
+    });
+}
+
+async fn mock_token(
+    State(state): State<MockState>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    record(&state, "/token", &headers, form);
+    Json(json!({
+        "access_token": "upstream-access",
+        "token_type": "bearer",
+        "expires_in": 3600,
+    }))
+}
+
+async fn mock_revoke(
+    State(state): State<MockState>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    record(&state, "/revoke", &headers, form);
+}
+
+async fn start_mock_provider() -> (SocketAddr, MockState) {
+    let state = MockState::default();
+    let app = Router::new()
+        .route("/token", post(mock_token))
+        .route("/revoke", post(mock_revoke))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// A config for one chained downstream authenticating with `auth`.
+fn config(proxy_port: u16, mock: &SocketAddr, auth: &str) -> String {
+    format!(
+        r#"
+[server]
+public_url = "http://127.0.0.1:{proxy_port}"
+state_secret = "{secret}"
+
+[downstream.idp]
+display_name = "Corporate IdP"
+strategy = "chained_oauth"
+downstream_url = "http://{mock}/mcp"
+oauth_authorize_url = "http://{mock}/authorize"
+oauth_token_url = "http://{mock}/token"
+oauth_revocation_url = "http://{mock}/revoke"
+oauth_client_id = "test-client-id"
+{auth}
+"#,
+        secret = state_secret(),
+    )
+}
+
+/// Start a proxy loading [`config`], so `private_key_jwt` keys are read like
+/// in production.
+async fn start_proxy(mock: &SocketAddr, auth: &str) -> SocketAddr {
+    start_loaded_proxy_with(|proxy| config(proxy.port(), mock, auth)).await
+}
+
+/// Run an authorization through the callback (a code exchange) and revoke an
+/// upstream refresh token, returning what the provider received.
+async fn exchange_and_revoke(proxy: &SocketAddr, mock_state: &MockState) -> Vec<Recorded> {
+    let client = client();
+
+    let resp = client
+        .get(authorize_url(proxy, "idp"))
+        .send()
+        .await
+        .unwrap();
+    let state = query_param(location(&resp).as_str(), "state");
+
+    let resp = client
+        .get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/callback/mcp/idp"),
+                &[("code", "upstream-code"), ("state", state.as_str())],
+            )
+            .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+
+    // In client refresh mode an opaque token is forwarded to the provider.
+    let resp = client
+        .post(format!("http://{proxy}/revoke/mcp/idp"))
+        .form(&[("token", "upstream-refresh")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let requests = mock_state.requests.lock().unwrap().clone();
+    assert_eq!(
+        requests.iter().map(|r| r.path).collect::<Vec<_>>(),
+        ["/token", "/revoke"]
+    );
+    requests
+}
+
+/// Check a `private_key_jwt` request and return its assertion's header.
+fn verify_assertion(
+    req: &Recorded,
+    mock: &SocketAddr,
+    public_key: jsonwebtoken::DecodingKey,
+    alg: jsonwebtoken::Algorithm,
+) -> jsonwebtoken::Header {
+    assert_eq!(req.authorization, None);
+    assert_eq!(req.form["client_id"], "test-client-id");
+    assert!(!req.form.contains_key("client_secret"));
+    assert_eq!(
+        req.form["client_assertion_type"],
+        "urn:ietf:params:oauth:client-assertion-type:jwt-bearer"
+    );
+
+    let audience = format!("http://{mock}{}", req.path);
+    let mut validation = jsonwebtoken::Validation::new(alg);
+    validation.set_audience(&[audience.as_str()]);
+    validation.set_issuer(&["test-client-id"]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let data = jsonwebtoken::decode::<serde_json::Value>(
+        &req.form["client_assertion"],
+        &public_key,
+        &validation,
+    )
+    .unwrap();
+    assert_eq!(data.claims["sub"], "test-client-id");
+    assert!(data.claims["jti"].as_str().is_some_and(|j| !j.is_empty()));
+    data.header
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_client_secret_post_is_the_default() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "oauth_client_secret = \"s3cret\"").await;
+
+    for req in exchange_and_revoke(&proxy, &mock_state).await {
+        assert_eq!(req.authorization, None);
+        assert_eq!(req.form["client_id"], "test-client-id");
+        assert_eq!(req.form["client_secret"], "s3cret");
+    }
+}
+
+#[tokio::test]
+async fn test_client_secret_basic() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(
+        &mock,
+        "oauth_client_secret = \"s3cret:with/specials\"\noauth_client_auth_method = \"client_secret_basic\"",
+    )
+    .await;
+
+    let expected = format!(
+        "Basic {}",
+        STANDARD.encode("test-client-id:s3cret%3Awith%2Fspecials")
+    );
+    for req in exchange_and_revoke(&proxy, &mock_state).await {
+        assert_eq!(req.authorization.as_deref(), Some(expected.as_str()));
+        assert!(!req.form.contains_key("client_id"));
+        assert!(!req.form.contains_key("client_secret"));
+    }
+}
+
+#[tokio::test]
+async fn test_private_key_jwt_rs256() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(
+        &mock,
+        &format!(
+            "oauth_client_auth_method = \"private_key_jwt\"\noauth_client_key_path = \"{}\"\noauth_client_key_id = \"key-1\"",
+            fixture("client_rs256.pem").display()
+        ),
+    )
+    .await;
+
+    let public_pem = std::fs::read(fixture("client_rs256.pub.pem")).unwrap();
+    for req in exchange_and_revoke(&proxy, &mock_state).await {
+        let header = verify_assertion(
+            &req,
+            &mock,
+            jsonwebtoken::DecodingKey::from_rsa_pem(&public_pem).unwrap(),
+            jsonwebtoken::Algorithm::RS256,
+        );
+        assert_eq!(header.kid.as_deref(), Some("key-1"));
+    }
+}
+
+#[tokio::test]
+async fn test_private_key_jwt_es256() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(
+        &mock,
+        &format!(
+            "oauth_client_auth_method = \"private_key_jwt\"\noauth_client_key_path = \"{}\"\noauth_client_key_alg = \"ES256\"",
+            fixture("client_es256.pem").display()
+        ),
+    )
+    .await;
+
+    let public_pem = std::fs::read(fixture("client_es256.pub.pem")).unwrap();
+    for req in exchange_and_revoke(&proxy, &mock_state).await {
+        let header = verify_assertion(
+            &req,
+            &mock,
+            jsonwebtoken::DecodingKey::from_ec_pem(&public_pem).unwrap(),
+            jsonwebtoken::Algorithm::ES256,
+        );
+        assert_eq!(header.kid, None);
+    }
+}
+
+#[tokio::test]
+async fn test_public_client_sends_only_client_id() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "oauth_client_auth_method = \"none\"").await;
+
+    for req in exchange_and_revoke(&proxy, &mock_state).await {
+        assert_eq!(req.authorization, None);
+        assert_eq!(req.form["client_id"], "test-client-id");
+        assert!(!req.form.contains_key("client_secret"));
+        assert!(!req.form.contains_key("client_assertion"));
+    }
+}
+
+#[tokio::test]
+async fn test_unusable_client_key_fails_load_config() {
+    let (mock, _) = start_mock_provider().await;
+
+    let err = load_config(&config(
+        1,
+        &mock,
+        "oauth_client_auth_method = \"private_key_jwt\"\noauth_client_key_path = \"/nonexistent/key.pem\"",
+    ))
+    .await
+    .unwrap_err();
+    assert!(err.contains("downstream 'idp'"), "{err}");
+    assert!(err.contains("/nonexistent/key.pem"), "{err}");
+
+    // An RSA key where an EC key is expected.
+    let err = load_config(&config(
+        2,
+        &mock,
+        &format!(
+            "oauth_client_auth_method = \"private_key_jwt\"\noauth_client_key_path = \"{}\"\noauth_client_key_alg = \"ES256\"",
+            fixture("client_rs256.pem").display()
+        ),
+    ))
+    .await
+    .unwrap_err();
+    assert!(err.contains("Invalid ES256 client key"), "{err}");
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Form, Json, Router};
+use common::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock identity provider and MCP server
+// ---------------------------------------------------------------------------
+
+#[derive(Clone)]
+struct MockState {
+    /// Forms received by the token endpoint, in order.
+    token_requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    /// `expires_in` of the tokens the endpoint issues.
+    expires_in: Arc<Mutex<u64>>,
+}
+
+async fn mock_token(
+    State(state): State<MockState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let n = {
+        let mut requests = state.token_requests.lock().unwrap();
+        requests.push(form);
+        requests.len()
+    };
+    // Slow enough for concurrent proxy requests to pile up behind this one.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    Json(json!({
+        "access_token": format!("service-token-{n}"),
+        "token_type": "Bearer",
+        "expires_in": *state.expires_in.lock().unwrap(),
+        "scope": "mcp:read",
+    }))
+}
+
+/// Echoes the Authorization header the proxy forwarded.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    Json(json!({ "authorization": auth }))
+}
+
+async fn start_mock_provider(expires_in: u64) -> (SocketAddr, MockState) {
+    let state = MockState {
+        token_requests: Arc::default(),
+        expires_in: Arc::new(Mutex::new(expires_in)),
+    };
+    let app = Router::new()
+        .route("/token", post(mock_token))
+        .route("/mcp", post(mock_mcp))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// Start a proxy with a password-gated client credentials downstream `svc`
+/// whose provider token endpoint is `token_url`.
+async fn start_proxy(mock: &SocketAddr, token_url: &str) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[downstream.svc]
+display_name = "Service"
+strategy = "client_credentials"
+downstream_url = "http://{mock}/mcp"
+oauth_token_url = "{token_url}"
+oauth_client_id = "proxy-service"
+oauth_client_secret = "service-secret"
+oauth_scopes = "mcp:read"
+gate = "password"
+gate_password = "{GATE_PASSWORD}"
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Pass the password gate and redeem the code, returning a proxy access token.
+async fn proxy_token(proxy: &SocketAddr) -> String {
+    let resp = submit(proxy, "svc", &[("token", GATE_PASSWORD)], None).await;
+    let body = redeem(proxy, "svc", &resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Call the MCP endpoint, returning the Authorization header the downstream
+/// received.
+async fn forwarded_auth(proxy: &SocketAddr, access_token: &str) -> String {
+    let resp = mcp_ping(proxy, "svc", access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["authorization"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_service_token_is_fetched_once_and_shared() {
+    let (mock, mock_state) = start_mock_provider(3600).await;
+    let proxy = start_proxy(&mock, &format!("http://{mock}/token")).await;
+    let access_token = proxy_token(&proxy).await;
+
+    // The proxy token carries no downstream credential.
+    let claims =
+        mcp_oauth_proxy::oauth::tokens::validate_access_token(&access_token, "svc", &keyring())
+            .unwrap();
+    assert_eq!(claims.downstream_tokens.access_token(), "");
+
+    let calls: Vec<_> = (0..8)
+        .map(|_| {
+            let access_token = access_token.clone();
+            tokio::spawn(async move { forwarded_auth(&proxy, &access_token).await })
+        })
+        .collect();
+    for call in calls {
+        assert_eq!(call.await.unwrap(), "Bearer service-token-1");
+    }
+
+    // Another user's proxy token gets the same service token.
+    let other = proxy_token(&proxy).await;
+    assert_eq!(
+        forwarded_auth(&proxy, &other).await,
+        "Bearer service-token-1"
+    );
+
+    let requests = mock_state.token_requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["grant_type"], "client_credentials");
+    assert_eq!(requests[0]["client_id"], "proxy-service");
+    assert_eq!(requests[0]["client_secret"], "service-secret");
+    assert_eq!(requests[0]["scope"], "mcp:read");
+}
+
+#[tokio::test]
+async fn test_service_token_is_replaced_before_expiry() {
+    // Tokens that expire immediately are replaced on every request.
+    let (mock, mock_state) = start_mock_provider(0).await;
+    let proxy = start_proxy(&mock, &format!("http://{mock}/token")).await;
+    let access_token = proxy_token(&proxy).await;
+
+    assert_eq!(
+        forwarded_auth(&proxy, &access_token).await,
+        "Bearer service-token-1"
+    );
+    assert_eq!(
+        forwarded_auth(&proxy, &access_token).await,
+        "Bearer service-token-2"
+    );
+
+    // Once the provider issues long-lived tokens, the next one is kept.
+    *mock_state.expires_in.lock().unwrap() = 3600;
+    assert_eq!(
+        forwarded_auth(&proxy, &access_token).await,
+        "Bearer service-token-3"
+    );
+    assert_eq!(
+        forwarded_auth(&proxy, &access_token).await,
+        "Bearer service-token-3"
+    );
+    assert_eq!(mock_state.token_requests.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_unavailable_provider_fails_requests() {
+    let (mock, _) = start_mock_provider(3600).await;
+    let (listener, dead) = bind().await;
+    drop(listener);
+    let proxy = start_proxy(&mock, &format!("http://{dead}/token")).await;
+
+    // The gate does not involve the provider...
+    let access_token = proxy_token(&proxy).await;
+
+    // ...but without a service token there is nothing to send downstream.
+    // The client's token is fine, so it is not told to re-authorize.
+    let resp = mcp_ping(&proxy, "svc", &access_token).await;
+    assert_eq!(resp.status(), 502);
+}
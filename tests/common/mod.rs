@@ -0,0 +1,423 @@
+//! Scaffolding shared by the integration tests: the PKCE verifier and client
+//! redirect every flow uses, proxy and mock server startup, the authorize and
+//! token round trip, and a mock login provider for `[server.login]`.
+
+// Each test crate uses a different subset.
+#![allow(dead_code)]
+
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use mcp_oauth_proxy::config::Config;
+use mcp_oauth_proxy::oauth::keys::Keyring;
+use mcp_oauth_proxy::storage::Store;
+use mcp_oauth_proxy::AppState;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+pub const CLAUDE_REDIRECT: &str = "http://localhost:9999/callback";
+pub const GATE_PASSWORD: &str = "correct horse battery staple";
+
+const SECRET: [u8; 32] = [0xAA; 32];
+
+/// The `state_secret` of every test proxy.
+pub fn state_secret() -> String {
+    STANDARD.encode(SECRET)
+}
+
+/// The keys derived from [`state_secret`], to open proxy codes and tokens.
+pub fn keyring() -> Keyring {
+    Keyring::single(&SECRET)
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// An HTTP client that does not follow redirects.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+pub fn location(resp: &reqwest::Response) -> url::Url {
+    url::Url::parse(resp.headers()["location"].to_str().unwrap()).unwrap()
+}
+
+pub fn query_param(url: &str, name: &str) -> String {
+    url::Url::parse(url)
+        .unwrap()
+        .query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.to_string())
+        .unwrap()
+}
+
+/// Serve a mock on a free local port.
+pub async fn serve(app: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, app).into_future());
+    addr
+}
+
+/// Start a proxy with the TOML `config` builds for the proxy's own address.
+pub async fn start_proxy_with(config: impl FnOnce(&SocketAddr) -> String) -> SocketAddr {
+    let (listener, proxy_addr) = bind().await;
+    let config: Config = toml::from_str(&config(&proxy_addr)).unwrap();
+    serve_proxy(
+        listener,
+        AppState::try_new(config, reqwest::Client::new()).unwrap(),
+    );
+    proxy_addr
+}
+
+/// [`start_proxy_with`] over `store`, as another replica or a restarted
+/// process sharing it would run.
+pub async fn start_proxy_on(
+    store: Arc<dyn Store>,
+    config: impl FnOnce(&SocketAddr) -> String,
+) -> SocketAddr {
+    let (listener, proxy_addr) = bind().await;
+    let config: Config = toml::from_str(&config(&proxy_addr)).unwrap();
+    serve_proxy(
+        listener,
+        AppState::with_store(config, reqwest::Client::new(), store),
+    );
+    proxy_addr
+}
+
+/// [`start_proxy_with`], loading the config as the binary does (see
+/// [`load_config`]).
+pub async fn start_loaded_proxy_with(config: impl FnOnce(&SocketAddr) -> String) -> SocketAddr {
+    let (listener, proxy_addr) = bind().await;
+    let config = load_config(&config(&proxy_addr)).await.unwrap();
+    serve_proxy(
+        listener,
+        AppState::try_new(config, reqwest::Client::new()).unwrap(),
+    );
+    proxy_addr
+}
+
+/// A listener on a free local port, for a proxy whose config needs its
+/// address.
+pub async fn bind() -> (tokio::net::TcpListener, SocketAddr) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+pub fn serve_proxy(listener: tokio::net::TcpListener, state: AppState) {
+    tokio::spawn(axum::serve(listener, mcp_oauth_proxy::build_router(state)).into_future());
+}
+
+/// Load `config` from a file like the binary does, so key files are read
+/// and issuers discovered.
+pub async fn load_config(config: &str) -> Result<Config, String> {
+    static FILES: AtomicU32 = AtomicU32::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "mcp-oauth-proxy-test-{}-{}.toml",
+        std::process::id(),
+        FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, config).unwrap();
+    let result = mcp_oauth_proxy::config::load_config(&path).await;
+    std::fs::remove_file(&path).unwrap();
+    result
+}
+
+/// `/authorize` for client `c` with the test PKCE challenge and state `s`.
+pub fn authorize_url(proxy: &SocketAddr, name: &str) -> String {
+    authorize_url_for(proxy, name, "c")
+}
+
+/// [`authorize_url`] for another `client_id`.
+pub fn authorize_url_for(proxy: &SocketAddr, name: &str, client_id: &str) -> String {
+    authorize_url_to(proxy, name, client_id, CLAUDE_REDIRECT)
+}
+
+/// [`authorize_url_for`] with another `redirect_uri`.
+pub fn authorize_url_to(
+    proxy: &SocketAddr,
+    name: &str,
+    client_id: &str,
+    redirect_uri: &str,
+) -> String {
+    url::Url::parse_with_params(
+        &format!("http://{proxy}/authorize/mcp/{name}"),
+        &[
+            ("response_type", "code"),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+            ("state", "s"),
+            ("code_challenge", pkce_challenge(VERIFIER).as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .unwrap()
+    .to_string()
+}
+
+/// Register a client for `name` with [`CLAUDE_REDIRECT`], returning its ID.
+pub async fn register(proxy: &SocketAddr, name: &str) -> String {
+    register_redirect(proxy, name, CLAUDE_REDIRECT).await
+}
+
+/// [`register`] with another `redirect_uri`.
+pub async fn register_redirect(proxy: &SocketAddr, name: &str, redirect_uri: &str) -> String {
+    let resp = client()
+        .post(format!("http://{proxy}/register/mcp/{name}"))
+        .json(&json!({"redirect_uris": [redirect_uri]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["client_id"].as_str().unwrap().to_string()
+}
+
+/// Post the authorize form of `name` with `fields` and the hidden fields
+/// [`authorize_url`] would carry.
+pub async fn submit(
+    proxy: &SocketAddr,
+    name: &str,
+    fields: &[(&str, &str)],
+    cookie: Option<&str>,
+) -> reqwest::Response {
+    let challenge = pkce_challenge(VERIFIER);
+    let mut form = vec![
+        ("client_id", "c"),
+        ("state", "s"),
+        ("redirect_uri", CLAUDE_REDIRECT),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ];
+    form.extend_from_slice(fields);
+    let mut req = client()
+        .post(format!("http://{proxy}/authorize/mcp/{name}"))
+        .form(&form);
+    if let Some(cookie) = cookie {
+        req = req.header("Cookie", cookie);
+    }
+    req.send().await.unwrap()
+}
+
+/// Redeem the code in a redirect to Claude, returning the `/token` response.
+pub async fn redeem(proxy: &SocketAddr, name: &str, resp: &reqwest::Response) -> serde_json::Value {
+    redeem_for(proxy, name, "c", resp).await
+}
+
+/// [`redeem`] for another `client_id`.
+pub async fn redeem_for(
+    proxy: &SocketAddr,
+    name: &str,
+    client_id: &str,
+    resp: &reqwest::Response,
+) -> serde_json::Value {
+    assert_eq!(resp.status(), 303);
+    let redirect = location(resp);
+    assert!(redirect.as_str().starts_with(CLAUDE_REDIRECT), "{redirect}");
+    let code = query_param(redirect.as_str(), "code");
+
+    let resp = client()
+        .post(format!("http://{proxy}/token/mcp/{name}"))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("code_verifier", VERIFIER),
+            ("redirect_uri", CLAUDE_REDIRECT),
+            ("client_id", client_id),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    resp.json().await.unwrap()
+}
+
+/// Send a JSON-RPC ping to `/mcp/<name>`.
+pub async fn mcp_ping(proxy: &SocketAddr, name: &str, access_token: &str) -> reqwest::Response {
+    client()
+        .post(format!("http://{proxy}/mcp/{name}"))
+        .bearer_auth(access_token)
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": "ping"}))
+        .send()
+        .await
+        .unwrap()
+}
+
+// ---------------------------------------------------------------------------
+// Mock login provider
+// ---------------------------------------------------------------------------
+
+/// The `[server.login]` section for a login provider mock at `mock`.
+pub fn login_config(mock: &SocketAddr) -> String {
+    format!(
+        r#"
+[server.login]
+oauth_issuer = "http://{mock}"
+oauth_authorize_url = "http://{mock}/authorize"
+oauth_token_url = "http://{mock}/token"
+oauth_jwks_url = "http://{mock}/jwks"
+oauth_client_id = "proxy-login"
+oauth_client_secret = "login-secret"
+"#
+    )
+}
+
+#[derive(Clone, Default)]
+pub struct LoginProvider {
+    /// ID token the token endpoint returns next.
+    pub id_token: Arc<Mutex<Option<String>>>,
+}
+
+async fn login_jwks() -> impl IntoResponse {
+    let jwks: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(fixture("idp_jwks.json")).unwrap()).unwrap();
+    Json(jwks)
+}
+
+async fn login_token(State(state): State<LoginProvider>) -> impl IntoResponse {
+    let mut body = json!({
+        "access_token": "login-access",
+        "token_type": "Bearer",
+        "expires_in": 3600,
+    });
+    if let Some(id_token) = state.id_token.lock().unwrap().clone() {
+        body["id_token"] = json!(id_token);
+    }
+    Json(body)
+}
+
+impl LoginProvider {
+    /// `/jwks` and `/token` of the provider, to merge into a mock.
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route("/jwks", get(login_jwks))
+            .route("/token", post(login_token))
+            .with_state(self.clone())
+    }
+
+    /// Complete the sign-in the proxy started with the redirect `started`,
+    /// with an ID token carrying `claims` plus the issuer, audience, nonce and
+    /// expiry. Returns the `/login/callback` response.
+    pub async fn callback(
+        &self,
+        proxy: &SocketAddr,
+        mock: &SocketAddr,
+        started: &reqwest::Response,
+        claims: serde_json::Value,
+    ) -> reqwest::Response {
+        let binding = binding_cookie(started);
+        self.callback_from(proxy, mock, &location(started), Some(&binding), claims)
+            .await
+    }
+
+    /// Like [`LoginProvider::callback`], for the provider redirect `upstream`
+    /// and the browser's `cookie`, if any.
+    pub async fn callback_from(
+        &self,
+        proxy: &SocketAddr,
+        mock: &SocketAddr,
+        upstream: &url::Url,
+        cookie: Option<&str>,
+        claims: serde_json::Value,
+    ) -> reqwest::Response {
+        let mut claims = claims;
+        claims["iss"] = json!(format!("http://{mock}"));
+        claims["aud"] = json!("proxy-login");
+        claims["nonce"] = json!(query_param(upstream.as_str(), "nonce"));
+        claims["iat"] = json!(now());
+        claims["exp"] = json!(now() + 300);
+        *self.id_token.lock().unwrap() = Some(sign_id_token(&claims));
+
+        let mut req = client().get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/login/callback"),
+                &[
+                    ("code", "login-code"),
+                    ("state", query_param(upstream.as_str(), "state").as_str()),
+                ],
+            )
+            .unwrap(),
+        );
+        if let Some(cookie) = cookie {
+            req = req.header("Cookie", cookie);
+        }
+        req.send().await.unwrap()
+    }
+
+    /// Sign in through `/authorize/mcp/<name>` as a user with `claims`,
+    /// returning the session cookie.
+    pub async fn sign_in(
+        &self,
+        proxy: &SocketAddr,
+        mock: &SocketAddr,
+        name: &str,
+        claims: serde_json::Value,
+    ) -> String {
+        let resp = client()
+            .get(authorize_url(proxy, name))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 303);
+        assert_eq!(location(&resp).path(), "/authorize");
+        let resp = self.callback(proxy, mock, &resp, claims).await;
+        assert_eq!(resp.status(), 303);
+        session_cookie(&resp)
+    }
+}
+
+/// Sign `claims` with the login provider's key.
+pub fn sign_id_token(claims: &serde_json::Value) -> String {
+    let key = EncodingKey::from_rsa_pem(&std::fs::read(fixture("idp_rs256.pem")).unwrap()).unwrap();
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("idp-rsa".to_string());
+    jsonwebtoken::encode(&header, claims, &key).unwrap()
+}
+
+/// The `name=value` part of the sign-in binding cookie set by the redirect
+/// to the login provider.
+pub fn binding_cookie(resp: &reqwest::Response) -> String {
+    let set_cookie = resp.headers()["set-cookie"].to_str().unwrap();
+    assert!(
+        set_cookie.starts_with("mcp_proxy_login_state="),
+        "{set_cookie}"
+    );
+    assert!(set_cookie.contains("HttpOnly"), "{set_cookie}");
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+/// The `name=value` part of the session cookie set by `resp`.
+pub fn session_cookie(resp: &reqwest::Response) -> String {
+    let set_cookie = resp.headers()["set-cookie"].to_str().unwrap();
+    assert!(set_cookie.contains("HttpOnly"), "{set_cookie}");
+    assert!(set_cookie.contains("SameSite=Lax"), "{set_cookie}");
+    set_cookie.split(';').next().unwrap().to_string()
+}
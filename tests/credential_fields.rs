@@ -0,0 +1,184 @@
+mod common;
+
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::*;
+use serde_json::json;
+use std::net::SocketAddr;
+
+// ---------------------------------------------------------------------------
+// Mock MCP server
+// ---------------------------------------------------------------------------
+
+/// Echoes the auth headers the proxy sent.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    Json(json!({
+        "authorization": header("authorization"),
+        "workspace": header("x-workspace"),
+    }))
+}
+
+async fn start_mock() -> SocketAddr {
+    serve(Router::new().route("/mcp", post(mock_mcp))).await
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// Start a proxy with a passthrough downstream that asks for an email, an
+/// API token and an optional workspace ID.
+async fn start_proxy(mock: &SocketAddr) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[downstream.jira]
+display_name = "Jira"
+strategy = "passthrough"
+downstream_url = "http://{mock}/mcp"
+
+[[downstream.jira.credential_fields]]
+name = "email"
+label = "Email"
+type = "text"
+regex = "[^@ ]+@[^@ ]+"
+
+[[downstream.jira.credential_fields]]
+name = "token"
+label = "API token"
+hint = "Create one under Account settings > Security."
+
+[[downstream.jira.credential_fields]]
+name = "workspace"
+label = "Workspace ID"
+type = "text"
+required = false
+
+[downstream.jira.credential_headers]
+Authorization = "Basic {{base64({{email}}:{{token}})}}"
+X-Workspace = "{{workspace}}"
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Submit the form, redeem the code and make an MCP request, returning the
+/// headers the downstream received.
+async fn forwarded_headers(proxy: &SocketAddr, fields: &[(&str, &str)]) -> serde_json::Value {
+    let resp = submit(proxy, "jira", fields, None).await;
+    let body = redeem(proxy, "jira", &resp).await;
+
+    let resp = mcp_ping(proxy, "jira", body["access_token"].as_str().unwrap()).await;
+    assert_eq!(resp.status(), 200);
+    resp.json().await.unwrap()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_form_shows_configured_fields() {
+    let mock = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    let page = client()
+        .get(authorize_url(&proxy, "jira"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(page
+        .contains(r#"<input type="text" id="field_email" name="field_email" required autofocus"#));
+    assert!(page.contains(
+        r#"<input type="password" id="field_token" name="field_token" required autocomplete"#
+    ));
+    assert!(page.contains("Create one under Account settings &gt; Security."));
+    assert!(page.contains("Workspace ID (optional)"));
+    assert!(page.contains(
+        r#"<input type="text" id="field_workspace" name="field_workspace" autocomplete"#
+    ));
+    assert!(!page.contains(r#"name="token""#));
+}
+
+#[tokio::test]
+async fn test_fields_are_rendered_into_headers() {
+    let mock = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    let headers = forwarded_headers(
+        &proxy,
+        &[
+            ("field_email", "ada@example.com"),
+            ("field_token", "tok:en"),
+            ("field_workspace", "acme"),
+        ],
+    )
+    .await;
+    assert_eq!(
+        headers["authorization"],
+        format!("Basic {}", STANDARD.encode("ada@example.com:tok:en"))
+    );
+    assert_eq!(headers["workspace"], "acme");
+
+    // A header built only from a blank optional field is left out.
+    let headers = forwarded_headers(
+        &proxy,
+        &[("field_email", "ada@example.com"), ("field_token", "t")],
+    )
+    .await;
+    assert_eq!(
+        headers["authorization"],
+        format!("Basic {}", STANDARD.encode("ada@example.com:t"))
+    );
+    assert!(headers["workspace"].is_null());
+}
+
+#[tokio::test]
+async fn test_invalid_submissions_are_rejected() {
+    let mock = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    for (fields, error) in [
+        (
+            &[("field_email", "ada@example.com")][..],
+            "API token is required",
+        ),
+        (
+            &[("field_email", "not an email"), ("field_token", "t")][..],
+            "Email is not in the expected format",
+        ),
+        // The regex must match the whole value.
+        (
+            &[("field_email", "ada@example.com x"), ("field_token", "t")][..],
+            "Email is not in the expected format",
+        ),
+        (
+            &[
+                ("field_email", "ada@example.com"),
+                ("field_token", "t\r\nX-Evil: 1"),
+            ][..],
+            "API token must not contain control characters",
+        ),
+        // A single token is not what this form asks for.
+        (&[("token", "t")][..], "Email is required"),
+    ] {
+        let resp = submit(&proxy, "jira", fields, None).await;
+        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.text().await.unwrap(), error);
+    }
+}
@@ -0,0 +1,207 @@
+mod common;
+
+use axum::extract::State;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use common::*;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock provider publishing its metadata
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Default)]
+struct MockState {
+    /// Served at the OIDC location for the root issuer.
+    oidc: Arc<Mutex<Option<serde_json::Value>>>,
+    /// Served at the RFC 8414 location for the `/tenant` issuer.
+    rfc8414: Arc<Mutex<Option<serde_json::Value>>>,
+}
+
+fn published(doc: &Mutex<Option<serde_json::Value>>) -> axum::response::Response {
+    use axum::response::IntoResponse;
+    match doc.lock().unwrap().clone() {
+        Some(doc) => Json(doc).into_response(),
+        None => axum::http::StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn start_mock_provider() -> (SocketAddr, MockState) {
+    let state = MockState::default();
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(|State(s): State<MockState>| async move { published(&s.oidc) }),
+        )
+        .route(
+            "/.well-known/oauth-authorization-server/tenant",
+            get(|State(s): State<MockState>| async move { published(&s.rfc8414) }),
+        )
+        .route(
+            "/token",
+            post(|| async {
+                Json(json!({
+                    "access_token": "discovered-access-token",
+                    "token_type": "bearer",
+                }))
+            }),
+        )
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+fn metadata(issuer: &str, base: &str, authorize_path: &str) -> serde_json::Value {
+    json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{base}{authorize_path}"),
+        "token_endpoint": format!("{base}/token"),
+        "jwks_uri": format!("{base}/jwks"),
+        "response_types_supported": ["code"],
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// A config for one chained downstream using `issuer`.
+fn config(proxy_port: u16, issuer: &str, extra: &str) -> String {
+    format!(
+        r#"
+[server]
+public_url = "http://127.0.0.1:{proxy_port}"
+state_secret = "{secret}"
+{extra}
+
+[downstream.oidc]
+display_name = "OIDC Provider"
+strategy = "chained_oauth"
+downstream_url = "http://127.0.0.1:1/mcp"
+oauth_issuer = "{issuer}"
+oauth_client_id = "test-client-id"
+oauth_client_secret = "test-client-secret"
+"#,
+        secret = state_secret(),
+    )
+}
+
+async fn start_proxy(issuer: &str, extra: &str) -> SocketAddr {
+    let (listener, addr) = bind().await;
+    let config = load_config(&config(addr.port(), issuer, extra))
+        .await
+        .unwrap();
+    let state = mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap();
+    state.spawn_discovery_refresh();
+    serve_proxy(listener, state);
+    addr
+}
+
+/// Start an authorization and return where the proxy sends the browser.
+async fn authorize_redirect(proxy: &SocketAddr) -> url::Url {
+    let resp = client()
+        .get(authorize_url(proxy, "oidc"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    location(&resp)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_oidc_discovery_fills_endpoints() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let issuer = format!("http://{mock}");
+    *mock_state.oidc.lock().unwrap() = Some(metadata(&issuer, &issuer, "/authorize"));
+
+    let proxy = start_proxy(&issuer, "").await;
+    let redirect = authorize_redirect(&proxy).await;
+    assert_eq!(redirect.path(), "/authorize");
+    assert_eq!(redirect.port(), Some(mock.port()));
+
+    // The callback exchanges the code at the discovered token endpoint.
+    let state = query_param(redirect.as_str(), "state");
+    let resp = client()
+        .get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/callback/mcp/oidc"),
+                &[("code", "upstream-code"), ("state", state.as_str())],
+            )
+            .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+}
+
+#[tokio::test]
+async fn test_rfc8414_discovery_for_issuer_with_path() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let base = format!("http://{mock}");
+    let issuer = format!("{base}/tenant");
+    *mock_state.rfc8414.lock().unwrap() = Some(metadata(&issuer, &base, "/tenant/authorize"));
+
+    let config = load_config(&config(1, &issuer, "")).await.unwrap();
+    let mcp_oauth_proxy::config::StrategyConfig::ChainedOauth { oauth } =
+        &config.downstream["oidc"].strategy
+    else {
+        panic!("expected chained_oauth");
+    };
+    assert_eq!(
+        oauth.authorize_url(),
+        Some(format!("{base}/tenant/authorize"))
+    );
+    assert_eq!(oauth.token_url(), Some(format!("{base}/token")));
+    assert_eq!(oauth.jwks_url(), Some(format!("{base}/jwks")));
+    assert_eq!(oauth.revocation_url(), None);
+}
+
+#[tokio::test]
+async fn test_discovery_failure_fails_load_config() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let issuer = format!("http://{mock}");
+
+    // Nothing published.
+    let err = load_config(&config(2, &issuer, "")).await.unwrap_err();
+    assert!(err.contains("downstream 'oidc'"), "{err}");
+    assert!(err.contains("discovery"), "{err}");
+
+    // A document for another issuer is not trusted.
+    *mock_state.oidc.lock().unwrap() =
+        Some(metadata("https://evil.example.com", &issuer, "/authorize"));
+    let err = load_config(&config(3, &issuer, "")).await.unwrap_err();
+    assert!(err.contains("does not match"), "{err}");
+
+    // The proxy needs a token endpoint.
+    let mut doc = metadata(&issuer, &issuer, "/authorize");
+    doc.as_object_mut().unwrap().remove("token_endpoint");
+    *mock_state.oidc.lock().unwrap() = Some(doc);
+    let err = load_config(&config(4, &issuer, "")).await.unwrap_err();
+    assert!(err.contains("token_endpoint"), "{err}");
+}
+
+#[tokio::test]
+async fn test_metadata_is_refreshed() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let issuer = format!("http://{mock}");
+    *mock_state.oidc.lock().unwrap() = Some(metadata(&issuer, &issuer, "/authorize"));
+
+    let proxy = start_proxy(&issuer, "discovery_refresh_interval = 1").await;
+    assert_eq!(authorize_redirect(&proxy).await.path(), "/authorize");
+
+    // The provider moves its endpoint; the proxy follows after a refresh.
+    *mock_state.oidc.lock().unwrap() = Some(metadata(&issuer, &issuer, "/v2/authorize"));
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert_eq!(authorize_redirect(&proxy).await.path(), "/v2/authorize");
+
+    // A failed refresh keeps the cached metadata.
+    *mock_state.oidc.lock().unwrap() = None;
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert_eq!(authorize_redirect(&proxy).await.path(), "/v2/authorize");
+}
@@ -0,0 +1,337 @@
+mod common;
+
+use axum::extract::{Form, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use common::*;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock GitHub (OAuth and REST API) and MCP server
+// ---------------------------------------------------------------------------
+
+const APP_CLIENT_ID: &str = "Iv23liTestApp";
+
+#[derive(Clone)]
+struct MockState {
+    /// Installation IDs of the installation token requests, in order.
+    token_requests: Arc<Mutex<Vec<u64>>>,
+    /// `expires_at` of the installation tokens issued.
+    expires_at: Arc<Mutex<String>>,
+    /// Forms received by the user-to-server token endpoint.
+    user_token_requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+}
+
+/// Issues `ghs-<installation>-<n>` for a valid app JWT.
+async fn mock_installation_token(
+    State(state): State<MockState>,
+    Path(installation): Path<u64>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+    if header("accept") != "application/vnd.github+json" || header("user-agent").is_empty() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let Some(jwt) = header("authorization").strip_prefix("Bearer ") else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let key = DecodingKey::from_rsa_pem(&std::fs::read(fixture("client_rs256.pub.pem")).unwrap())
+        .unwrap();
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_issuer(&[APP_CLIENT_ID]);
+    validation.set_required_spec_claims(&["exp", "iat", "iss"]);
+    let Ok(jwt) = jsonwebtoken::decode::<serde_json::Value>(jwt, &key, &validation) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let (iat, exp) = (
+        jwt.claims["iat"].as_u64().unwrap(),
+        jwt.claims["exp"].as_u64().unwrap(),
+    );
+    if exp - iat > 600 {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let n = {
+        let mut requests = state.token_requests.lock().unwrap();
+        requests.push(installation);
+        requests.len()
+    };
+    // Slow enough for concurrent proxy requests to pile up behind this one.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    (
+        StatusCode::CREATED,
+        Json(json!({
+            "token": format!("ghs-{installation}-{n}"),
+            "expires_at": *state.expires_at.lock().unwrap(),
+            "permissions": {"contents": "read"},
+        })),
+    )
+        .into_response()
+}
+
+async fn mock_user_token(
+    State(state): State<MockState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    state.user_token_requests.lock().unwrap().push(form);
+    Json(json!({
+        "access_token": "ghu-user",
+        "token_type": "bearer",
+        "scope": "",
+    }))
+}
+
+fn is_user(headers: &HeaderMap) -> bool {
+    headers
+        .get("authorization")
+        .is_some_and(|v| v == "Bearer ghu-user")
+}
+
+async fn mock_user(headers: HeaderMap) -> impl IntoResponse {
+    if !is_user(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(json!({"login": "octocat", "id": 583231, "email": "octocat@example.com"})).into_response()
+}
+
+/// The user can access installation 42 only.
+async fn mock_user_installations(headers: HeaderMap) -> impl IntoResponse {
+    if !is_user(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(json!({
+        "total_count": 1,
+        "installations": [{"id": 42, "account": {"login": "acme"}}],
+    }))
+    .into_response()
+}
+
+/// Echoes the Authorization header the proxy forwarded.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    Json(json!({ "authorization": auth }))
+}
+
+async fn start_mock_github(expires_at: &str) -> (SocketAddr, MockState) {
+    let state = MockState {
+        token_requests: Arc::default(),
+        expires_at: Arc::new(Mutex::new(expires_at.to_string())),
+        user_token_requests: Arc::default(),
+    };
+    let app = Router::new()
+        .route(
+            "/api/app/installations/{id}/access_tokens",
+            post(mock_installation_token),
+        )
+        .route("/api/user", get(mock_user))
+        .route("/api/user/installations", get(mock_user_installations))
+        .route("/login/oauth/access_token", post(mock_user_token))
+        .route("/mcp", post(mock_mcp))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// A GitHub App downstream for installation `installation`.
+fn downstream(name: &str, mock: &SocketAddr, installation: u64, gate: &str) -> String {
+    format!(
+        r#"
+[downstream.{name}]
+display_name = "GitHub"
+strategy = "github_app"
+downstream_url = "http://{mock}/mcp"
+oauth_client_id = "{APP_CLIENT_ID}"
+github_installation_id = {installation}
+github_private_key_path = "{key}"
+github_api_url = "http://{mock}/api"
+{gate}
+"#,
+        key = fixture("client_rs256.pem").display(),
+    )
+}
+
+/// Start a proxy with three GitHub App downstreams: `repo` behind a gate
+/// password, and `hub` (installation 42) and `other` (installation 7) behind
+/// GitHub sign-in. The config is loaded from a file so the app key is read
+/// like in production.
+async fn start_proxy(mock: &SocketAddr) -> SocketAddr {
+    let github_gate = format!(
+        "gate = \"github\"\n\
+         oauth_authorize_url = \"http://{mock}/login/oauth/authorize\"\n\
+         oauth_token_url = \"http://{mock}/login/oauth/access_token\"\n\
+         oauth_client_secret = \"app-secret\""
+    );
+    start_loaded_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+{repo}{hub}{other}"#,
+            secret = state_secret(),
+            repo = downstream(
+                "repo",
+                mock,
+                42,
+                &format!("gate = \"password\"\ngate_password = \"{GATE_PASSWORD}\"")
+            ),
+            hub = downstream("hub", mock, 42, &github_gate),
+            other = downstream("other", mock, 7, &github_gate),
+        )
+    })
+    .await
+}
+
+/// Redeem the code in a redirect to Claude, returning a proxy access token.
+async fn access_token(proxy: &SocketAddr, name: &str, resp: &reqwest::Response) -> String {
+    let body = redeem(proxy, name, resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Pass the password gate of `repo`, returning a proxy access token.
+async fn password_token(proxy: &SocketAddr) -> String {
+    let resp = submit(proxy, "repo", &[("token", GATE_PASSWORD)], None).await;
+    access_token(proxy, "repo", &resp).await
+}
+
+/// Sign in with GitHub at `name`, returning the callback response.
+async fn github_callback(proxy: &SocketAddr, mock: &SocketAddr, name: &str) -> reqwest::Response {
+    let resp = client()
+        .get(authorize_url(proxy, name))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    let location = resp.headers()["location"].to_str().unwrap();
+    assert!(location.starts_with(&format!("http://{mock}/login/oauth/authorize?")));
+    assert_eq!(query_param(location, "client_id"), APP_CLIENT_ID);
+    let signed_state = query_param(location, "state");
+
+    client()
+        .get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/callback/mcp/{name}"),
+                &[("code", "github-code"), ("state", signed_state.as_str())],
+            )
+            .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Call the MCP endpoint, returning the Authorization header the downstream
+/// received.
+async fn forwarded_auth(proxy: &SocketAddr, name: &str, access_token: &str) -> String {
+    let resp = mcp_ping(proxy, name, access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["authorization"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_installation_token_is_fetched_once_and_injected() {
+    let (mock, mock_state) = start_mock_github("2099-01-01T00:00:00Z").await;
+    let proxy = start_proxy(&mock).await;
+    let access_token = password_token(&proxy).await;
+
+    // The proxy token carries no GitHub credential.
+    let claims =
+        mcp_oauth_proxy::oauth::tokens::validate_access_token(&access_token, "repo", &keyring())
+            .unwrap();
+    assert_eq!(claims.downstream_tokens.access_token(), "");
+
+    let calls: Vec<_> = (0..8)
+        .map(|_| {
+            let access_token = access_token.clone();
+            tokio::spawn(async move { forwarded_auth(&proxy, "repo", &access_token).await })
+        })
+        .collect();
+    for call in calls {
+        assert_eq!(call.await.unwrap(), "Bearer ghs-42-1");
+    }
+    assert_eq!(*mock_state.token_requests.lock().unwrap(), vec![42]);
+}
+
+#[tokio::test]
+async fn test_expiring_installation_token_is_replaced() {
+    let (mock, mock_state) = start_mock_github("2001-01-01T00:00:00Z").await;
+    let proxy = start_proxy(&mock).await;
+    let access_token = password_token(&proxy).await;
+
+    // Already expired: replaced on every request.
+    assert_eq!(
+        forwarded_auth(&proxy, "repo", &access_token).await,
+        "Bearer ghs-42-1"
+    );
+    assert_eq!(
+        forwarded_auth(&proxy, "repo", &access_token).await,
+        "Bearer ghs-42-2"
+    );
+
+    *mock_state.expires_at.lock().unwrap() = "2099-01-01T00:00:00Z".to_string();
+    assert_eq!(
+        forwarded_auth(&proxy, "repo", &access_token).await,
+        "Bearer ghs-42-3"
+    );
+    assert_eq!(
+        forwarded_auth(&proxy, "repo", &access_token).await,
+        "Bearer ghs-42-3"
+    );
+    assert_eq!(mock_state.token_requests.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_github_sign_in_requires_installation_access() {
+    let (mock, mock_state) = start_mock_github("2099-01-01T00:00:00Z").await;
+    let proxy = start_proxy(&mock).await;
+
+    let resp = github_callback(&proxy, &mock, "hub").await;
+    let access_token = access_token(&proxy, "hub", &resp).await;
+    {
+        let forms = mock_state.user_token_requests.lock().unwrap();
+        assert_eq!(forms[0]["code"], "github-code");
+        assert_eq!(forms[0]["client_id"], APP_CLIENT_ID);
+        assert!(forms[0].contains_key("code_verifier"));
+    }
+
+    // The grant records the GitHub user but not the user-to-server token.
+    let claims =
+        mcp_oauth_proxy::oauth::tokens::validate_access_token(&access_token, "hub", &keyring())
+            .unwrap();
+    let user = claims.binding.user.as_ref().unwrap();
+    assert_eq!(user.sub, "583231");
+    assert_eq!(user.email.as_deref(), Some("octocat@example.com"));
+    assert_eq!(claims.downstream_tokens.access_token(), "");
+
+    assert_eq!(
+        forwarded_auth(&proxy, "hub", &access_token).await,
+        "Bearer ghs-42-1"
+    );
+
+    // Installation 7 is not among the user's.
+    let resp = github_callback(&proxy, &mock, "other").await;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(*mock_state.token_requests.lock().unwrap(), vec![42]);
+}
@@ -30,7 +30,7 @@ async fn start_proxy() -> SocketAddr {
 
     let toml_str = make_config_toml(&addr);
     let config: mcp_oauth_proxy::config::Config = toml::from_str(&toml_str).unwrap();
-    let state = mcp_oauth_proxy::AppState::new(config, reqwest::Client::new());
+    let state = mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap();
 
     let app = mcp_oauth_proxy::build_router(state);
     tokio::spawn(axum::serve(listener, app).into_future());
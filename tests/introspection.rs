@@ -0,0 +1,184 @@
+mod common;
+
+use common::*;
+use mcp_oauth_proxy::oauth::codes::GrantBinding;
+use mcp_oauth_proxy::oauth::tokens::{self, RefreshTokenClaims};
+use std::net::SocketAddr;
+
+const ADMIN_SECRET: &str = "introspection-admin-secret";
+
+async fn start_proxy(introspection: bool) -> SocketAddr {
+    let introspection_line = if introspection {
+        format!("introspection_secret = \"{ADMIN_SECRET}\"")
+    } else {
+        String::new()
+    };
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+{introspection_line}
+
+[downstream.test]
+display_name = "Test Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+
+[downstream.other]
+display_name = "Other Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Run the passthrough flow and return a proxy access token.
+async fn obtain_access_token(addr: &SocketAddr) -> String {
+    let resp = submit(addr, "test", &[("token", "secret-key")], None).await;
+    let body = redeem(addr, "test", &resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+async fn introspect(
+    addr: &SocketAddr,
+    downstream: &str,
+    credential: Option<&str>,
+    token: &str,
+) -> reqwest::Response {
+    let mut req = reqwest::Client::new()
+        .post(format!("http://{addr}/introspect/mcp/{downstream}"))
+        .form(&[("token", token)]);
+    if let Some(credential) = credential {
+        req = req.bearer_auth(credential);
+    }
+    req.send().await.unwrap()
+}
+
+async fn metadata(addr: &SocketAddr) -> serde_json::Value {
+    reqwest::get(format!(
+        "http://{addr}/.well-known/oauth-authorization-server/mcp/test"
+    ))
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_introspection_disabled_by_default() {
+    let addr = start_proxy(false).await;
+    assert!(metadata(&addr)
+        .await
+        .get("introspection_endpoint")
+        .is_none());
+
+    let token = obtain_access_token(&addr).await;
+    let resp = introspect(&addr, "test", Some(ADMIN_SECRET), &token).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn test_introspection_requires_admin_credential() {
+    let addr = start_proxy(true).await;
+    assert_eq!(
+        metadata(&addr).await["introspection_endpoint"],
+        format!("http://127.0.0.1:{}/introspect/mcp/test", addr.port())
+    );
+
+    let token = obtain_access_token(&addr).await;
+
+    let resp = introspect(&addr, "test", None, &token).await;
+    assert_eq!(resp.status(), 401);
+
+    // A proxy access token is not an admin credential.
+    let resp = introspect(&addr, "test", Some(&token), &token).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn test_introspect_access_token() {
+    let addr = start_proxy(true).await;
+    let token = obtain_access_token(&addr).await;
+
+    let resp = introspect(&addr, "test", Some(ADMIN_SECRET), &token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["active"], true);
+    assert_eq!(body["token_type"], "Bearer");
+    assert_eq!(body["token_use"], "access");
+    assert_eq!(body["downstream"], "test");
+    assert_eq!(body["strategy"], "passthrough");
+    assert!(body["exp"].as_u64().is_some());
+    // The wrapped credential is never disclosed.
+    assert!(!body.to_string().contains("secret-key"));
+
+    // Introspecting through another downstream's endpoint reports inactive.
+    let body: serde_json::Value = introspect(&addr, "other", Some(ADMIN_SECRET), &token)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body, serde_json::json!({"active": false}));
+
+    // Revoked tokens are inactive.
+    reqwest::Client::new()
+        .post(format!("http://{addr}/revoke/mcp/test"))
+        .form(&[("token", token.as_str())])
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = introspect(&addr, "test", Some(ADMIN_SECRET), &token)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body, serde_json::json!({"active": false}));
+}
+
+#[tokio::test]
+async fn test_introspect_refresh_and_unknown_tokens() {
+    let addr = start_proxy(true).await;
+    let exp = now() + 3600;
+    let refresh = tokens::issue_refresh_token(
+        RefreshTokenClaims {
+            jti: "refresh-1".to_string(),
+            binding: GrantBinding {
+                downstream: "test".to_string(),
+                client_id: Some("client-1".to_string()),
+                resource: None,
+                user: None,
+            },
+            grant_id: "grant-1".to_string(),
+            upstream_refresh_token: "upstream-refresh".to_string(),
+            exp,
+        },
+        &keyring(),
+    )
+    .unwrap();
+
+    let body: serde_json::Value = introspect(&addr, "test", Some(ADMIN_SECRET), &refresh)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["active"], true);
+    assert!(body.get("token_type").is_none());
+    assert_eq!(body["token_use"], "refresh");
+    assert_eq!(body["exp"], exp);
+    assert_eq!(body["grant_id"], "grant-1");
+    assert_eq!(body["client_id"], "client-1");
+    assert!(!body.to_string().contains("upstream-refresh"));
+
+    let body: serde_json::Value = introspect(&addr, "test", Some(ADMIN_SECRET), "garbage")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body, serde_json::json!({"active": false}));
+}
@@ -0,0 +1,286 @@
+mod common;
+
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use common::*;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock login provider, service account token endpoint and MCP server
+// ---------------------------------------------------------------------------
+
+/// One JWT bearer grant received by the token endpoint.
+#[derive(Debug, Clone)]
+struct Grant {
+    form: HashMap<String, String>,
+    kid: Option<String>,
+    claims: serde_json::Value,
+}
+
+#[derive(Clone, Default)]
+struct MockState {
+    login: LoginProvider,
+    grants: Arc<Mutex<Vec<Grant>>>,
+}
+
+/// Discovery for the login provider, which lives under `/login`.
+async fn mock_metadata(headers: HeaderMap) -> impl IntoResponse {
+    let base = format!("http://{}", headers["host"].to_str().unwrap());
+    Json(json!({
+        "issuer": base,
+        "authorization_endpoint": format!("{base}/authorize"),
+        "token_endpoint": format!("{base}/login/token"),
+        "jwks_uri": format!("{base}/login/jwks"),
+    }))
+}
+
+/// Verifies the assertion with the fixture key for its algorithm and issues
+/// `jwt-<sub>-<n>`.
+async fn mock_token(
+    State(state): State<MockState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if form.get("grant_type").map(String::as_str)
+        != Some("urn:ietf:params:oauth:grant-type:jwt-bearer")
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let Some(assertion) = form.get("assertion") else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let header = jsonwebtoken::decode_header(assertion).unwrap();
+    let key = match header.alg {
+        Algorithm::RS256 => {
+            DecodingKey::from_rsa_pem(&std::fs::read(fixture("client_rs256.pub.pem")).unwrap())
+        }
+        _ => DecodingKey::from_ec_pem(&std::fs::read(fixture("client_es256.pub.pem")).unwrap()),
+    }
+    .unwrap();
+    let mut validation = Validation::new(header.alg);
+    validation.validate_aud = false;
+    let Ok(jwt) = jsonwebtoken::decode::<serde_json::Value>(assertion, &key, &validation) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        )
+            .into_response();
+    };
+
+    let sub = jwt.claims["sub"].as_str().unwrap().to_string();
+    let n = {
+        let mut grants = state.grants.lock().unwrap();
+        grants.push(Grant {
+            form: form.clone(),
+            kid: header.kid,
+            claims: jwt.claims,
+        });
+        grants.len()
+    };
+    // Slow enough for concurrent proxy requests to pile up behind this one.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    Json(json!({
+        "access_token": format!("jwt-{sub}-{n}"),
+        "token_type": "Bearer",
+        "expires_in": 3600,
+    }))
+    .into_response()
+}
+
+/// Echoes the Authorization header the proxy forwarded.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    Json(json!({ "authorization": auth }))
+}
+
+async fn start_mock_provider() -> (SocketAddr, MockState) {
+    let state = MockState::default();
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(mock_metadata))
+        .route("/token", post(mock_token))
+        .route("/mcp", post(mock_mcp))
+        .with_state(state.clone())
+        .nest("/login", state.login.routes());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+const SCOPE: &str = "https://www.googleapis.com/auth/drive.readonly";
+
+/// Start a proxy with `[server.login]` and two JWT bearer downstreams:
+/// `svc`, a password-gated service account with the scope in the assertion,
+/// and `drive`, which impersonates the signed-in user by email with an ES256
+/// key. The config is loaded from a file so the keys are read like in
+/// production.
+async fn start_proxy(mock: &SocketAddr) -> SocketAddr {
+    start_loaded_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[server.login]
+oauth_issuer = "http://{mock}"
+oauth_client_id = "proxy-login"
+oauth_client_secret = "login-secret"
+
+[downstream.svc]
+display_name = "Service Account"
+strategy = "jwt_bearer"
+downstream_url = "http://{mock}/mcp"
+oauth_token_url = "http://{mock}/token"
+oauth_client_id = "1234567890"
+oauth_client_auth_method = "none"
+oauth_scopes = "{SCOPE}"
+jwt_issuer = "mcp@project.iam.gserviceaccount.com"
+jwt_scope_claim = true
+jwt_key_path = "{rsa_key}"
+gate = "password"
+gate_password = "{GATE_PASSWORD}"
+
+[downstream.drive]
+display_name = "Drive"
+strategy = "jwt_bearer"
+downstream_url = "http://{mock}/mcp"
+oauth_token_url = "http://{mock}/token"
+oauth_client_id = "drive-delegate"
+oauth_client_auth_method = "none"
+oauth_scopes = "{SCOPE}"
+jwt_audience = "https://oauth2.example.com/token"
+jwt_key_path = "{ec_key}"
+jwt_key_alg = "ES256"
+jwt_key_id = "delegate-1"
+jwt_impersonate = "email"
+"#,
+            secret = state_secret(),
+            rsa_key = fixture("client_rs256.pem").display(),
+            ec_key = fixture("client_es256.pem").display(),
+        )
+    })
+    .await
+}
+
+/// A proxy access token for the password-gated `svc` downstream.
+async fn password_token(proxy: &SocketAddr) -> String {
+    let resp = submit(proxy, "svc", &[("token", GATE_PASSWORD)], None).await;
+    let body = redeem(proxy, "svc", &resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Sign in as `email` and confirm the `drive` page, returning a proxy access
+/// token. The client is not registered, so the user confirms on a page.
+async fn sso_token(
+    proxy: &SocketAddr,
+    mock: &SocketAddr,
+    mock_state: &MockState,
+    email: &str,
+) -> String {
+    let claims = json!({
+        "sub": format!("sub-{email}"),
+        "email": email,
+        "email_verified": true,
+    });
+    let cookie = mock_state.login.sign_in(proxy, mock, "drive", claims).await;
+
+    let resp = client()
+        .get(authorize_url(proxy, "drive"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let resp = submit(proxy, "drive", &[], Some(&cookie)).await;
+    let body = redeem(proxy, "drive", &resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Call the MCP endpoint, returning the Authorization header the downstream
+/// received.
+async fn forwarded_auth(proxy: &SocketAddr, name: &str, access_token: &str) -> String {
+    let resp = mcp_ping(proxy, name, access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["authorization"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_service_account_token_is_fetched_once_and_shared() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    let access_token = password_token(&proxy).await;
+
+    let calls: Vec<_> = (0..8)
+        .map(|_| {
+            let access_token = access_token.clone();
+            tokio::spawn(async move { forwarded_auth(&proxy, "svc", &access_token).await })
+        })
+        .collect();
+    let subject = "mcp@project.iam.gserviceaccount.com";
+    for call in calls {
+        assert_eq!(call.await.unwrap(), format!("Bearer jwt-{subject}-1"));
+    }
+
+    let grants = mock_state.grants.lock().unwrap();
+    assert_eq!(grants.len(), 1);
+    let grant = &grants[0];
+    assert_eq!(grant.claims["iss"], subject);
+    assert_eq!(grant.claims["sub"], subject);
+    assert_eq!(grant.claims["aud"], format!("http://{mock}/token"));
+    assert_eq!(grant.claims["scope"], SCOPE);
+    assert!(grant.claims["jti"].is_string());
+    assert!(grant.kid.is_none());
+    // The scope travels in the assertion only.
+    assert!(!grant.form.contains_key("scope"));
+    assert_eq!(grant.form["client_id"], "1234567890");
+}
+
+#[tokio::test]
+async fn test_impersonation_fetches_a_token_per_user() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    let ada = sso_token(&proxy, &mock, &mock_state, "ada@example.com").await;
+    let bob = sso_token(&proxy, &mock, &mock_state, "bob@example.com").await;
+
+    assert_eq!(
+        forwarded_auth(&proxy, "drive", &ada).await,
+        "Bearer jwt-ada@example.com-1"
+    );
+    assert_eq!(
+        forwarded_auth(&proxy, "drive", &bob).await,
+        "Bearer jwt-bob@example.com-2"
+    );
+    // Each user's token is cached on its own.
+    assert_eq!(
+        forwarded_auth(&proxy, "drive", &ada).await,
+        "Bearer jwt-ada@example.com-1"
+    );
+
+    let grants = mock_state.grants.lock().unwrap();
+    assert_eq!(grants.len(), 2);
+    let grant = &grants[0];
+    assert_eq!(grant.claims["iss"], "drive-delegate");
+    assert_eq!(grant.claims["sub"], "ada@example.com");
+    assert_eq!(grant.claims["aud"], "https://oauth2.example.com/token");
+    assert!(grant.claims.get("scope").is_none());
+    assert_eq!(grant.kid.as_deref(), Some("delegate-1"));
+    assert_eq!(grant.form["scope"], SCOPE);
+}
@@ -0,0 +1,273 @@
+mod common;
+
+use common::*;
+use mcp_oauth_proxy::oauth::codes::UserIdentity;
+use serde_json::json;
+use std::net::SocketAddr;
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+async fn start_mock_provider() -> (SocketAddr, LoginProvider) {
+    let login = LoginProvider::default();
+    (serve(login.routes()).await, login)
+}
+
+/// Start a proxy with `[server.login]` and a passthrough downstream with the
+/// given access rules.
+async fn start_proxy(mock: &SocketAddr, rules: &str) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+{login}oauth_scopes = "email groups"
+
+[downstream.linear]
+display_name = "Linear"
+strategy = "passthrough"
+downstream_url = "http://{mock}/mcp"
+{rules}
+"#,
+            secret = state_secret(),
+            login = login_config(mock),
+        )
+    })
+    .await
+}
+
+/// Sign in at the mock provider as a user with `email` and `groups`,
+/// returning the `/login/callback` response.
+async fn sign_in(
+    proxy: &SocketAddr,
+    mock: &SocketAddr,
+    login: &LoginProvider,
+    email: &str,
+    groups: &[&str],
+) -> reqwest::Response {
+    let resp = client()
+        .get(authorize_url(proxy, "linear"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    let upstream = location(&resp);
+    assert_eq!(upstream.path(), "/authorize");
+    assert_eq!(
+        query_param(upstream.as_str(), "scope"),
+        "openid email groups"
+    );
+    assert_eq!(
+        query_param(upstream.as_str(), "redirect_uri"),
+        format!("http://{proxy}/login/callback")
+    );
+
+    let claims = json!({
+        "sub": "user-123",
+        "email": email,
+        "email_verified": true,
+        "groups": groups,
+    });
+    login.callback(proxy, mock, &resp, claims).await
+}
+
+async fn submit_form(proxy: &SocketAddr, cookie: Option<&str>) -> reqwest::Response {
+    submit(proxy, "linear", &[("token", "lin_api_key")], cookie).await
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_sign_in_before_passthrough_form() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "allowed_email_domains = [\"example.com\"]").await;
+
+    let resp = sign_in(&proxy, &mock, &login, "ada@example.com", &[]).await;
+    assert_eq!(resp.status(), 303);
+    let cookie = session_cookie(&resp);
+    assert_eq!(location(&resp).as_str(), authorize_url(&proxy, "linear"));
+    // The binding cookie has done its job.
+    assert!(resp
+        .headers()
+        .get_all("set-cookie")
+        .iter()
+        .any(|v| v.to_str().unwrap().starts_with("mcp_proxy_login_state=;")));
+
+    // Back at /authorize, the form is shown to the signed-in user.
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", format!("other=1; {cookie}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("Signed in as <strong>ada@example.com</strong>"));
+
+    // The code issued by the form carries the user into the proxy token.
+    let body = redeem(&proxy, "linear", &submit_form(&proxy, Some(&cookie)).await).await;
+    let claims = mcp_oauth_proxy::oauth::tokens::validate_access_token(
+        body["access_token"].as_str().unwrap(),
+        "linear",
+        &keyring(),
+    )
+    .unwrap();
+    assert_eq!(
+        claims.binding.user,
+        Some(UserIdentity {
+            sub: "user-123".to_string(),
+            email: Some("ada@example.com".to_string()),
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_access_rules() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(
+        &mock,
+        "allowed_email_domains = [\"example.com\"]\nallowed_groups = [\"contractors\"]",
+    )
+    .await;
+
+    // Outside the domain, but in an allowed group.
+    let resp = sign_in(&proxy, &mock, &login, "bob@partner.com", &["contractors"]).await;
+    let cookie = session_cookie(&resp);
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    // Neither: the form and its submission are refused.
+    let resp = sign_in(&proxy, &mock, &login, "eve@partner.com", &["sales"]).await;
+    let cookie = session_cookie(&resp);
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text().await.unwrap().contains("eve@partner.com"));
+    assert_eq!(submit_form(&proxy, Some(&cookie)).await.status(), 403);
+}
+
+#[tokio::test]
+async fn test_form_requires_session() {
+    let (mock, _) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "").await;
+
+    assert_eq!(submit_form(&proxy, None).await.status(), 401);
+
+    // A cookie the proxy did not seal is ignored.
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", "mcp_proxy_login=v1.default.forged")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    assert_eq!(location(&resp).path(), "/authorize");
+    assert_eq!(
+        submit_form(&proxy, Some("mcp_proxy_login=v1.default.forged"))
+            .await
+            .status(),
+        401
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_login_is_rejected() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "").await;
+
+    // A state the proxy did not issue.
+    let resp = client()
+        .get(format!(
+            "http://{proxy}/login/callback?code=login-code&state=v1.default.forged"
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
+    // An ID token for someone else's sign-in (wrong nonce).
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .send()
+        .await
+        .unwrap();
+    let upstream = location(&resp);
+    let binding = binding_cookie(&resp);
+    *login.id_token.lock().unwrap() = Some(sign_id_token(&json!({
+        "iss": format!("http://{mock}"),
+        "aud": "proxy-login",
+        "sub": "user-123",
+        "nonce": "replayed",
+        "exp": now() + 300,
+    })));
+    let resp = client()
+        .get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/login/callback"),
+                &[
+                    ("code", "login-code"),
+                    ("state", query_param(upstream.as_str(), "state").as_str()),
+                ],
+            )
+            .unwrap(),
+        )
+        .header("Cookie", &binding)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 502);
+    assert!(resp.headers().get("set-cookie").is_none());
+}
+
+#[tokio::test]
+async fn test_callback_in_another_browser_is_refused() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "").await;
+
+    // The attacker starts a sign-in and hands their provider redirect to a
+    // victim, whose browser never received the binding cookie.
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .send()
+        .await
+        .unwrap();
+    let upstream = location(&resp);
+    let claims = json!({"sub": "attacker", "email": "eve@example.com"});
+
+    let resp = login
+        .callback_from(&proxy, &mock, &upstream, None, claims.clone())
+        .await;
+    assert_eq!(resp.status(), 400);
+    assert!(resp.headers().get("set-cookie").is_none());
+
+    // Nor does a binding cookie from another sign-in match.
+    let other = client()
+        .get(authorize_url(&proxy, "linear"))
+        .send()
+        .await
+        .unwrap();
+    let resp = login
+        .callback_from(
+            &proxy,
+            &mock,
+            &upstream,
+            Some(&binding_cookie(&other)),
+            claims,
+        )
+        .await;
+    assert_eq!(resp.status(), 400);
+    assert!(resp.headers().get("set-cookie").is_none());
+}
@@ -1,5 +1,6 @@
 use axum::routing::{get, post};
 use axum::Router;
+use mcp_oauth_proxy::oauth::keys::Keyring;
 use tokio::net::TcpListener;
 
 async fn start_mock_downstream() -> String {
@@ -63,6 +64,38 @@ async fn mock_post_handler(
         .unwrap()
 }
 
+/// Mint a proxy access token wrapping `credential`, as `/token` would.
+fn access_token(downstream: &str, credential: &str) -> String {
+    let exp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 3600;
+    mcp_oauth_proxy::oauth::tokens::issue_access_token(
+        mcp_oauth_proxy::oauth::tokens::AccessTokenClaims {
+            jti: "test-token".to_string(),
+            binding: mcp_oauth_proxy::oauth::codes::GrantBinding {
+                downstream: downstream.to_string(),
+                client_id: None,
+                resource: None,
+                user: None,
+            },
+            downstream_tokens: mcp_oauth_proxy::oauth::codes::DownstreamTokens::Passthrough {
+                access_token: credential.to_string(),
+            },
+            grant_id: None,
+            downstream_exp: None,
+            exp,
+        },
+        &Keyring::single(&[0xAA; 32]),
+    )
+    .unwrap()
+}
+
+fn bearer(downstream: &str, credential: &str) -> String {
+    format!("Bearer {}", access_token(downstream, credential))
+}
+
 fn build_proxy_app(downstream_url: &str) -> Router {
     use base64::Engine;
 
@@ -95,7 +128,7 @@ auth_header_format = "X-API-Key"
     );
 
     let config: mcp_oauth_proxy::config::Config = toml::from_str(&toml_str).unwrap();
-    let state = mcp_oauth_proxy::AppState::new(config, reqwest::Client::new());
+    let state = mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap();
 
     Router::new()
         .route(
@@ -126,7 +159,7 @@ async fn test_sse_proxy_streams_events() {
     let client = reqwest::Client::new();
     let resp = client
         .get(format!("{proxy}/mcp/test-sse"))
-        .header("Authorization", "Bearer test-token-123")
+        .header("Authorization", bearer("test-sse", "test-token-123"))
         .header("Accept", "text/event-stream")
         .send()
         .await
@@ -155,7 +188,7 @@ async fn test_sse_proxy_bearer_format() {
     let client = reqwest::Client::new();
     let resp = client
         .get(format!("{proxy}/mcp/test-bearer"))
-        .header("Authorization", "Bearer some-token")
+        .header("Authorization", bearer("test-bearer", "some-token"))
         .header("Accept", "text/event-stream")
         .send()
         .await
@@ -180,7 +213,7 @@ async fn test_post_proxy_forwards_body_and_remaps_auth() {
     let client = reqwest::Client::new();
     let resp = client
         .post(format!("{proxy}/mcp/test-rpc"))
-        .header("Authorization", "Bearer test-token-123")
+        .header("Authorization", bearer("test-rpc", "test-token-123"))
         .header("Content-Type", "application/json")
         .json(&rpc_body)
         .send()
@@ -234,6 +267,24 @@ async fn test_auth_errors_return_401() {
             .unwrap(),
         "Bearer"
     );
+
+    // Raw downstream credential instead of a proxy-issued token
+    let resp = client
+        .get(format!("{proxy}/mcp/test-sse"))
+        .header("Authorization", "Bearer test-token-123")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+
+    // Token issued for a different downstream
+    let resp = client
+        .get(format!("{proxy}/mcp/test-sse"))
+        .header("Authorization", bearer("test-bearer", "test-token-123"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
 }
 
 #[tokio::test]
@@ -256,7 +307,7 @@ async fn test_unreachable_downstream_returns_502() {
 
     let resp = reqwest::Client::new()
         .get(format!("{proxy}/mcp/test-sse"))
-        .header("Authorization", "Bearer some-token")
+        .header("Authorization", bearer("test-sse", "some-token"))
         .send()
         .await
         .unwrap();
@@ -0,0 +1,361 @@
+mod common;
+
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use common::*;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use mcp_oauth_proxy::oauth::codes::UserIdentity;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock OpenID provider
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Default)]
+struct MockState {
+    /// ID token the token endpoint returns next, if any.
+    id_token: Arc<Mutex<Option<String>>>,
+    jwks_fetches: Arc<AtomicUsize>,
+}
+
+async fn mock_jwks(State(state): State<MockState>) -> impl IntoResponse {
+    state.jwks_fetches.fetch_add(1, Ordering::SeqCst);
+    let jwks: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(fixture("idp_jwks.json")).unwrap()).unwrap();
+    Json(jwks)
+}
+
+async fn mock_token(State(state): State<MockState>) -> impl IntoResponse {
+    let mut body = json!({
+        "access_token": "upstream-access",
+        "token_type": "Bearer",
+        "expires_in": 3600,
+        "refresh_token": "upstream-refresh",
+    });
+    if let Some(id_token) = state.id_token.lock().unwrap().clone() {
+        body["id_token"] = json!(id_token);
+    }
+    Json(body)
+}
+
+async fn start_mock_provider() -> (SocketAddr, MockState) {
+    let state = MockState::default();
+    let app = Router::new()
+        .route("/jwks", get(mock_jwks))
+        .route("/token", post(mock_token))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+const ADMIN_SECRET: &str = "introspection-admin-secret";
+const CLIENT_ID: &str = "test-client-id";
+
+async fn start_proxy(mock: &SocketAddr) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+introspection_secret = "{ADMIN_SECRET}"
+
+[downstream.idp]
+display_name = "Corporate IdP"
+strategy = "chained_oauth"
+downstream_url = "http://{mock}/mcp"
+oauth_issuer = "http://{mock}"
+oauth_authorize_url = "http://{mock}/authorize"
+oauth_token_url = "http://{mock}/token"
+oauth_jwks_url = "http://{mock}/jwks"
+oauth_client_id = "{CLIENT_ID}"
+oauth_client_secret = "test-client-secret"
+oauth_scopes = "email"
+oauth_supports_refresh = true
+oauth_oidc = true
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Start an authorization, returning the provider authorize URL.
+async fn authorize(proxy: &SocketAddr) -> url::Url {
+    let resp = client()
+        .get(authorize_url(proxy, "idp"))
+        .send()
+        .await
+        .unwrap();
+    location(&resp)
+}
+
+async fn callback(proxy: &SocketAddr, state: &str) -> reqwest::Response {
+    client()
+        .get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/callback/mcp/idp"),
+                &[("code", "upstream-code"), ("state", state)],
+            )
+            .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Claims a well-behaved provider would put in the ID token.
+fn claims(mock: &SocketAddr, nonce: &str) -> serde_json::Value {
+    json!({
+        "iss": format!("http://{mock}"),
+        "aud": CLIENT_ID,
+        "sub": "user-123",
+        "email": "ada@example.com",
+        "email_verified": true,
+        "nonce": nonce,
+        "iat": now(),
+        "exp": now() + 300,
+    })
+}
+
+fn sign(kid: &str, claims: &serde_json::Value) -> String {
+    let (alg, key) = match kid {
+        "idp-ec" => (
+            Algorithm::ES256,
+            EncodingKey::from_ec_pem(&std::fs::read(fixture("idp_es256.pem")).unwrap()).unwrap(),
+        ),
+        _ => (
+            Algorithm::RS256,
+            EncodingKey::from_rsa_pem(&std::fs::read(fixture("idp_rs256.pem")).unwrap()).unwrap(),
+        ),
+    };
+    let mut header = Header::new(alg);
+    header.kid = Some(kid.to_string());
+    jsonwebtoken::encode(&header, claims, &key).unwrap()
+}
+
+fn token_user(proxy_token: &str) -> Option<UserIdentity> {
+    mcp_oauth_proxy::oauth::tokens::validate_access_token(proxy_token, "idp", &keyring())
+        .unwrap()
+        .binding
+        .user
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_id_token_identifies_user() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    let upstream = authorize(&proxy).await;
+    assert_eq!(query_param(upstream.as_str(), "scope"), "openid email");
+    let nonce = query_param(upstream.as_str(), "nonce");
+    assert!(!nonce.is_empty());
+
+    *mock_state.id_token.lock().unwrap() = Some(sign("idp-rsa", &claims(&mock, &nonce)));
+    let resp = callback(&proxy, &query_param(upstream.as_str(), "state")).await;
+    let body = redeem(&proxy, "idp", &resp).await;
+
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_eq!(
+        token_user(access_token),
+        Some(UserIdentity {
+            sub: "user-123".to_string(),
+            email: Some("ada@example.com".to_string()),
+        })
+    );
+
+    let introspection: serde_json::Value = client()
+        .post(format!("http://{proxy}/introspect/mcp/idp"))
+        .bearer_auth(ADMIN_SECRET)
+        .form(&[("token", access_token)])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(introspection["active"], true);
+    assert_eq!(introspection["sub"], "user-123");
+    assert_eq!(introspection["email"], "ada@example.com");
+}
+
+#[tokio::test]
+async fn test_unverified_email_is_dropped() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    let upstream = authorize(&proxy).await;
+    let mut claims = claims(&mock, &query_param(upstream.as_str(), "nonce"));
+    claims["email_verified"] = json!("false");
+    *mock_state.id_token.lock().unwrap() = Some(sign("idp-ec", &claims));
+
+    let resp = callback(&proxy, &query_param(upstream.as_str(), "state")).await;
+    let body = redeem(&proxy, "idp", &resp).await;
+    assert_eq!(
+        token_user(body["access_token"].as_str().unwrap()),
+        Some(UserIdentity {
+            sub: "user-123".to_string(),
+            email: None,
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_id_tokens_fail_callback() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    type Tamper = fn(&mut serde_json::Value);
+    let cases: [(&str, &str, Tamper); 6] = [
+        ("wrong nonce", "idp-rsa", |c| c["nonce"] = json!("other")),
+        ("wrong audience", "idp-rsa", |c| {
+            c["aud"] = json!("someone-else")
+        }),
+        ("wrong issuer", "idp-rsa", |c| {
+            c["iss"] = json!("https://evil.example.com")
+        }),
+        ("expired", "idp-rsa", |c| c["exp"] = json!(now() - 3600)),
+        ("unknown key", "idp-unknown", |_| {}),
+        ("foreign azp", "idp-ec", |c| {
+            c["aud"] = json!([CLIENT_ID, "another-client"]);
+            c["azp"] = json!("another-client");
+        }),
+    ];
+
+    for (case, kid, tamper) in cases {
+        let upstream = authorize(&proxy).await;
+        let mut claims = claims(&mock, &query_param(upstream.as_str(), "nonce"));
+        tamper(&mut claims);
+        *mock_state.id_token.lock().unwrap() = Some(sign(kid, &claims));
+
+        let resp = callback(&proxy, &query_param(upstream.as_str(), "state")).await;
+        assert_eq!(resp.status(), 502, "{case}");
+        let body = resp.text().await.unwrap();
+        assert!(
+            body.contains("ID token validation failed"),
+            "{case}: {body}"
+        );
+    }
+
+    // The JWKS is cached, and an unknown key ID does not refetch it again
+    // straight away.
+    assert_eq!(mock_state.jwks_fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_missing_id_token_fails_callback() {
+    let (mock, _) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    let upstream = authorize(&proxy).await;
+    let resp = callback(&proxy, &query_param(upstream.as_str(), "state")).await;
+    assert_eq!(resp.status(), 502);
+    let body = resp.text().await.unwrap();
+    assert!(body.contains("did not return an ID token"), "{body}");
+}
+
+#[tokio::test]
+async fn test_refreshed_id_token_identifies_user() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    // Refreshed ID tokens need not carry a nonce.
+    let mut claims = claims(&mock, "");
+    claims.as_object_mut().unwrap().remove("nonce");
+    *mock_state.id_token.lock().unwrap() = Some(sign("idp-rsa", &claims));
+
+    let refresh = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", "upstream-refresh"),
+    ];
+    let resp = client()
+        .post(format!("http://{proxy}/token/mcp/idp"))
+        .form(&refresh)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        token_user(body["access_token"].as_str().unwrap()).map(|u| u.sub),
+        Some("user-123".to_string())
+    );
+
+    // A forged one is not accepted.
+    claims["aud"] = json!("someone-else");
+    *mock_state.id_token.lock().unwrap() = Some(sign("idp-rsa", &claims));
+    let resp = client()
+        .post(format!("http://{proxy}/token/mcp/idp"))
+        .form(&refresh)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 502);
+}
+
+#[tokio::test]
+async fn test_refresh_keeps_the_grant_identity() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    let upstream = authorize(&proxy).await;
+    let mut claims = claims(&mock, &query_param(upstream.as_str(), "nonce"));
+    *mock_state.id_token.lock().unwrap() = Some(sign("idp-rsa", &claims));
+    let resp = callback(&proxy, &query_param(upstream.as_str(), "state")).await;
+    let body = redeem(&proxy, "idp", &resp).await;
+    assert_eq!(body["refresh_token"], "upstream-refresh");
+
+    let refresh = |client_id: &'static str| {
+        client()
+            .post(format!("http://{proxy}/token/mcp/idp"))
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", "upstream-refresh"),
+                ("client_id", client_id),
+            ])
+            .send()
+    };
+
+    // The provider sends no ID token with the refresh; the user carries over
+    // from the original grant.
+    *mock_state.id_token.lock().unwrap() = None;
+    let resp = refresh("c").await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let claims_out = mcp_oauth_proxy::oauth::tokens::validate_access_token(
+        body["access_token"].as_str().unwrap(),
+        "idp",
+        &keyring(),
+    )
+    .unwrap();
+    assert_eq!(claims_out.binding.client_id.as_deref(), Some("c"));
+    assert_eq!(
+        claims_out.binding.user.map(|u| u.sub),
+        Some("user-123".to_string())
+    );
+
+    // Another client cannot refresh the grant.
+    let resp = refresh("other").await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let err: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(err["error"], "invalid_grant");
+
+    // Nor can a refreshed ID token swap in another user.
+    claims.as_object_mut().unwrap().remove("nonce");
+    claims["sub"] = json!("user-456");
+    *mock_state.id_token.lock().unwrap() = Some(sign("idp-rsa", &claims));
+    let resp = refresh("c").await.unwrap();
+    assert_eq!(resp.status(), 400);
+}
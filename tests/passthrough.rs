@@ -1,5 +1,6 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use mcp_oauth_proxy::oauth::keys::Keyring;
 use std::future::IntoFuture;
 use std::net::SocketAddr;
 
@@ -48,7 +49,7 @@ scopes = "read write"
     );
 
     let config: mcp_oauth_proxy::config::Config = toml::from_str(&toml_str).unwrap();
-    let state = mcp_oauth_proxy::AppState::new(config, reqwest::Client::new());
+    let state = mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap();
 
     let app = mcp_oauth_proxy::build_router(state);
     tokio::spawn(axum::serve(listener, app).into_future());
@@ -93,7 +94,7 @@ async fn test_passthrough_authorize_and_token_exchange() {
     assert!(location.starts_with(CLAUDE_REDIRECT));
     let code = extract_code_from_redirect(location);
 
-    // Token exchange: decrypt code, verify PKCE, return original token
+    // Token exchange: decrypt code, verify PKCE, return a proxy-wrapped token
     let resp = client
         .post(format!("http://{proxy_addr}/token/mcp/test-pt"))
         .header("Content-Type", "application/x-www-form-urlencoded")
@@ -109,7 +110,18 @@ async fn test_passthrough_authorize_and_token_exchange() {
         .unwrap();
     assert_eq!(resp.status(), 200);
     let body: serde_json::Value = resp.json().await.unwrap();
-    assert_eq!(body["access_token"], "my-secret-api-key");
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_ne!(access_token, "my-secret-api-key");
     assert_eq!(body["token_type"], "Bearer");
+    assert!(body["expires_in"].as_u64().unwrap() > 0);
     assert!(body.get("refresh_token").is_none());
+
+    let claims = mcp_oauth_proxy::oauth::tokens::validate_access_token(
+        access_token,
+        "test-pt",
+        &Keyring::single(&[0xAA_u8; 32]),
+    )
+    .unwrap();
+    assert_eq!(claims.binding.downstream, "test-pt");
+    assert_eq!(claims.downstream_tokens.access_token(), "my-secret-api-key");
 }
@@ -0,0 +1,341 @@
+mod common;
+
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::*;
+use mcp_oauth_proxy::storage::{MemoryStore, Store};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// ---------------------------------------------------------------------------
+// Mock upstream provider that rotates refresh tokens
+// ---------------------------------------------------------------------------
+
+#[derive(Clone)]
+struct MockState {
+    /// The only refresh token the provider currently accepts.
+    valid_refresh_token: Arc<Mutex<String>>,
+    refresh_count: Arc<Mutex<u32>>,
+    /// Whether refresh requests fail with a bare `503`.
+    outage: Arc<Mutex<bool>>,
+    /// `expires_in` reported for the initial access token.
+    initial_expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    grant_type: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+async fn mock_token(
+    State(state): State<MockState>,
+    Form(form): Form<TokenRequest>,
+) -> impl IntoResponse {
+    match form.grant_type.as_str() {
+        "authorization_code" => Json(json!({
+            "access_token": "upstream-access-0",
+            "token_type": "bearer",
+            "expires_in": state.initial_expires_in,
+            "refresh_token": "upstream-refresh-0"
+        }))
+        .into_response(),
+        "refresh_token" => {
+            if *state.outage.lock().await {
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+            let mut valid = state.valid_refresh_token.lock().await;
+            if form.refresh_token.as_deref() != Some(valid.as_str()) {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error": "invalid_grant"})),
+                )
+                    .into_response();
+            }
+
+            let mut count = state.refresh_count.lock().await;
+            *count += 1;
+            *valid = format!("upstream-refresh-{count}");
+
+            Json(json!({
+                "access_token": format!("upstream-access-{count}"),
+                "token_type": "bearer",
+                "expires_in": 3600,
+                "refresh_token": *valid
+            }))
+            .into_response()
+        }
+        _ => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+/// Echo the Authorization header the proxy sent.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    Json(json!({ "authorization": auth }))
+}
+
+async fn start_mock_upstream(initial_expires_in: u64) -> (SocketAddr, MockState) {
+    let state = MockState {
+        valid_refresh_token: Arc::new(Mutex::new("upstream-refresh-0".to_string())),
+        refresh_count: Arc::new(Mutex::new(0)),
+        outage: Arc::new(Mutex::new(false)),
+        initial_expires_in,
+    };
+
+    let app = Router::new()
+        .route("/token", post(mock_token))
+        .route("/mcp", post(mock_mcp))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+async fn start_proxy(mock_addr: &SocketAddr) -> SocketAddr {
+    start_proxy_on(Arc::new(MemoryStore::default()), mock_addr).await
+}
+
+/// Start a proxy whose shared state lives in `store`.
+async fn start_proxy_on(store: Arc<dyn Store>, mock_addr: &SocketAddr) -> SocketAddr {
+    common::start_proxy_on(store, |proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[downstream.gh]
+display_name = "Proxy Refresh"
+strategy = "chained_oauth"
+downstream_url = "http://127.0.0.1:{mock_port}/mcp"
+oauth_authorize_url = "http://127.0.0.1:{mock_port}/authorize"
+oauth_token_url = "http://127.0.0.1:{mock_port}/token"
+oauth_client_id = "test-client-id"
+oauth_client_secret = "test-client-secret"
+oauth_supports_refresh = true
+oauth_refresh_mode = "proxy"
+"#,
+            mock_port = mock_addr.port(),
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Run authorize → callback → token and return the token response body.
+async fn obtain_tokens(proxy_addr: &SocketAddr) -> serde_json::Value {
+    let client = client();
+
+    let resp = client
+        .get(authorize_url(proxy_addr, "gh"))
+        .send()
+        .await
+        .unwrap();
+    let signed_state = query_param(location(&resp).as_str(), "state");
+
+    let resp = client
+        .get(format!(
+            "http://{proxy_addr}/callback/mcp/gh?code=upstream-code&state={signed_state}"
+        ))
+        .send()
+        .await
+        .unwrap();
+    redeem(proxy_addr, "gh", &resp).await
+}
+
+async fn refresh(proxy_addr: &SocketAddr, refresh_token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{proxy_addr}/token/mcp/gh"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", "c"),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Call the MCP endpoint and return the Authorization header the upstream saw.
+async fn mcp_authorization(proxy_addr: &SocketAddr, access_token: &str) -> String {
+    let resp = mcp_ping(proxy_addr, "gh", access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["authorization"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_refresh_token_is_proxy_wrapped() {
+    let (mock_addr, _mock) = start_mock_upstream(3600).await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+    assert_ne!(refresh_token, "upstream-refresh-0");
+
+    // The proxy token outlives the upstream access token.
+    assert!(body["expires_in"].as_u64().unwrap() > 3600);
+
+    // A raw upstream refresh token is not accepted.
+    let resp = refresh(&proxy_addr, "upstream-refresh-0").await;
+    assert_eq!(resp.status(), 400);
+    let err: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(err["error"], "invalid_grant");
+
+    let resp = refresh(&proxy_addr, refresh_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_ne!(body["refresh_token"], refresh_token);
+}
+
+#[tokio::test]
+async fn test_mcp_path_refreshes_expired_upstream_token() {
+    // The initial upstream access token is already inside the refresh window.
+    let (mock_addr, mock) = start_mock_upstream(1).await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let access_token = body["access_token"].as_str().unwrap();
+
+    assert_eq!(
+        mcp_authorization(&proxy_addr, access_token).await,
+        "Bearer upstream-access-1"
+    );
+    // The refreshed token is cached; no second upstream refresh.
+    assert_eq!(
+        mcp_authorization(&proxy_addr, access_token).await,
+        "Bearer upstream-access-1"
+    );
+    assert_eq!(*mock.refresh_count.lock().await, 1);
+}
+
+#[tokio::test]
+async fn test_rotated_refresh_token_survives_client_refresh() {
+    let (mock_addr, mock) = start_mock_upstream(1).await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let access_token = body["access_token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+
+    // The MCP path refreshes first, rotating upstream-refresh-0 away.
+    mcp_authorization(&proxy_addr, access_token).await;
+    assert_eq!(*mock.valid_refresh_token.lock().await, "upstream-refresh-1");
+
+    // The client's refresh token still embeds upstream-refresh-0, but the proxy
+    // uses the rotated one it remembered.
+    let resp = refresh(&proxy_addr, refresh_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        mcp_authorization(&proxy_addr, body["access_token"].as_str().unwrap()).await,
+        "Bearer upstream-access-1"
+    );
+}
+
+#[tokio::test]
+async fn test_rotated_refresh_token_survives_restart() {
+    let (mock_addr, mock) = start_mock_upstream(1).await;
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::default());
+    let proxy_addr = start_proxy_on(store.clone(), &mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let access_token = body["access_token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+    mcp_authorization(&proxy_addr, access_token).await;
+    assert_eq!(*mock.valid_refresh_token.lock().await, "upstream-refresh-1");
+
+    // A fresh process over the same store still knows the rotated token.
+    let restarted = start_proxy_on(store, &mock_addr).await;
+    let resp = refresh(&restarted, refresh_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        mcp_authorization(&restarted, body["access_token"].as_str().unwrap()).await,
+        "Bearer upstream-access-1"
+    );
+    assert_eq!(*mock.refresh_count.lock().await, 1);
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_refresh_once() {
+    let (mock_addr, mock) = start_mock_upstream(1).await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let access_token = body["access_token"].as_str().unwrap().to_string();
+
+    let requests = (0..5).map(|_| {
+        let token = access_token.clone();
+        tokio::spawn(async move { mcp_authorization(&proxy_addr, &token).await })
+    });
+    for handle in requests {
+        assert_eq!(handle.await.unwrap(), "Bearer upstream-access-1");
+    }
+    assert_eq!(*mock.refresh_count.lock().await, 1);
+}
+
+#[tokio::test]
+async fn test_reused_refresh_token_revokes_grant() {
+    let (mock_addr, mock) = start_mock_upstream(3600).await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let first_refresh = body["refresh_token"].as_str().unwrap().to_string();
+
+    let resp = refresh(&proxy_addr, &first_refresh).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let second_refresh = body["refresh_token"].as_str().unwrap().to_string();
+    let access_token = body["access_token"].as_str().unwrap().to_string();
+
+    // The used token is rejected without another upstream refresh.
+    let resp = refresh(&proxy_addr, &first_refresh).await;
+    assert_eq!(resp.status(), 400);
+    let err: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(err["error"], "invalid_grant");
+    assert_eq!(*mock.refresh_count.lock().await, 1);
+
+    // The reuse revokes everything issued under the grant since.
+    let resp = refresh(&proxy_addr, &second_refresh).await;
+    assert_eq!(resp.status(), 400);
+    let resp = mcp_ping(&proxy_addr, "gh", &access_token).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn test_failed_mcp_path_refresh_status() {
+    let (mock_addr, mock) = start_mock_upstream(1).await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr).await;
+    let access_token = body["access_token"].as_str().unwrap();
+    let mcp_status = || async { mcp_ping(&proxy_addr, "gh", access_token).await.status() };
+
+    // An outage is temporary; the client keeps its tokens.
+    *mock.outage.lock().await = true;
+    assert_eq!(mcp_status().await, 503);
+
+    // A refused refresh token means the user must re-authorize.
+    *mock.outage.lock().await = false;
+    *mock.valid_refresh_token.lock().await = "revoked".to_string();
+    assert_eq!(mcp_status().await, 401);
+}
@@ -0,0 +1,265 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use std::net::SocketAddr;
+
+async fn start_proxy(require_registration: bool) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+require_client_registration = {require_registration}
+
+[downstream.test]
+display_name = "Test Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+
+[downstream.other]
+display_name = "Other Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+"#,
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+async fn register_metadata(
+    addr: &SocketAddr,
+    downstream: &str,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    client()
+        .post(format!("http://{addr}/register/mcp/{downstream}"))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn register_client_id(addr: &SocketAddr) -> String {
+    let resp = register_metadata(
+        addr,
+        "test",
+        json!({"client_name": "Test Client", "redirect_uris": [CLAUDE_REDIRECT]}),
+    )
+    .await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["client_id"].as_str().unwrap().to_string()
+}
+
+async fn authorize(addr: &SocketAddr, client_id: &str, redirect_uri: &str) -> reqwest::Response {
+    let url = url::Url::parse_with_params(
+        &format!("http://{addr}/authorize/mcp/test"),
+        &[
+            ("response_type", "code"),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+            ("state", "s"),
+            ("code_challenge", pkce_challenge(VERIFIER).as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .unwrap();
+    client().get(url).send().await.unwrap()
+}
+
+/// Submit the passthrough form and return the issued authorization code.
+async fn obtain_code(addr: &SocketAddr, client_id: &str) -> String {
+    let challenge = pkce_challenge(VERIFIER);
+    let resp = client()
+        .post(format!("http://{addr}/authorize/mcp/test"))
+        .form(&[
+            ("token", "secret-key"),
+            ("client_id", client_id),
+            ("state", "s"),
+            ("redirect_uri", CLAUDE_REDIRECT),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    query_param(location(&resp).as_str(), "code")
+}
+
+async fn exchange(
+    addr: &SocketAddr,
+    code: &str,
+    client_id: &str,
+    redirect_uri: &str,
+) -> reqwest::Response {
+    client()
+        .post(format!("http://{addr}/token/mcp/test"))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", VERIFIER),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_registration_endpoint_advertised() {
+    let addr = start_proxy(false).await;
+    let body: serde_json::Value = client()
+        .get(format!(
+            "http://{addr}/.well-known/oauth-authorization-server/mcp/test"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        body["registration_endpoint"],
+        format!("http://127.0.0.1:{}/register/mcp/test", addr.port())
+    );
+}
+
+#[tokio::test]
+async fn test_register_returns_client_metadata() {
+    let addr = start_proxy(false).await;
+    let resp = register_metadata(
+        &addr,
+        "test",
+        json!({
+            "client_name": "Test Client",
+            "redirect_uris": [CLAUDE_REDIRECT],
+            "token_endpoint_auth_method": "none",
+            "grant_types": ["authorization_code"],
+            "response_types": ["code"]
+        }),
+    )
+    .await;
+    assert_eq!(resp.status(), 201);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert!(body["client_id"].as_str().unwrap().len() > 20);
+    assert!(body["client_id_issued_at"].as_u64().is_some());
+    assert_eq!(body["client_name"], "Test Client");
+    assert_eq!(body["redirect_uris"], json!([CLAUDE_REDIRECT]));
+    assert_eq!(body["token_endpoint_auth_method"], "none");
+    assert!(body.get("client_secret").is_none());
+}
+
+#[tokio::test]
+async fn test_register_rejects_invalid_metadata() {
+    let addr = start_proxy(false).await;
+
+    let cases = [
+        (json!({"redirect_uris": []}), "invalid_redirect_uri"),
+        (
+            json!({"redirect_uris": ["http://evil.example.com/cb"]}),
+            "invalid_redirect_uri",
+        ),
+        (
+            json!({"redirect_uris": [CLAUDE_REDIRECT], "token_endpoint_auth_method": "client_secret_basic"}),
+            "invalid_client_metadata",
+        ),
+        (
+            // Passthrough downstreams do not issue refresh tokens
+            json!({"redirect_uris": [CLAUDE_REDIRECT], "grant_types": ["refresh_token"]}),
+            "invalid_client_metadata",
+        ),
+        (json!({"redirect_uris": "nope"}), "invalid_client_metadata"),
+        (
+            json!({"redirect_uris": vec![CLAUDE_REDIRECT; 11]}),
+            "invalid_redirect_uri",
+        ),
+        (
+            json!({"redirect_uris": [format!("{CLAUDE_REDIRECT}?{}", "a".repeat(4096))]}),
+            "invalid_redirect_uri",
+        ),
+        (
+            json!({"redirect_uris": [CLAUDE_REDIRECT], "client_name": "x".repeat(1000)}),
+            "invalid_client_metadata",
+        ),
+    ];
+
+    for (body, expected) in cases {
+        let resp = register_metadata(&addr, "test", body.clone()).await;
+        assert_eq!(resp.status(), 400, "for {body}");
+        let err: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(err["error"], expected, "for {body}");
+    }
+}
+
+#[tokio::test]
+async fn test_registered_redirect_uris_enforced() {
+    let addr = start_proxy(false).await;
+    let client_id = register_client_id(&addr).await;
+
+    let resp = authorize(&addr, &client_id, "http://localhost:9999/other").await;
+    assert_eq!(resp.status(), 400);
+
+    let resp = authorize(&addr, &client_id, CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("Test Client"));
+    assert!(html.contains(&client_id));
+
+    // A client ID registered with another downstream is rejected.
+    let resp = register_metadata(&addr, "other", json!({"redirect_uris": [CLAUDE_REDIRECT]})).await;
+    let other: serde_json::Value = resp.json().await.unwrap();
+    let resp = authorize(&addr, other["client_id"].as_str().unwrap(), CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_registered_client_full_flow() {
+    let addr = start_proxy(false).await;
+    let client_id = register_client_id(&addr).await;
+
+    let code = obtain_code(&addr, &client_id).await;
+    let resp = exchange(&addr, &code, &client_id, CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert!(body["access_token"].is_string());
+}
+
+#[tokio::test]
+async fn test_token_rejects_unregistered_redirect() {
+    let addr = start_proxy(false).await;
+    let client_id = register_client_id(&addr).await;
+    let code = obtain_code(&addr, "unregistered").await;
+
+    let resp = exchange(&addr, &code, &client_id, "http://localhost:9999/other").await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_client");
+}
+
+#[tokio::test]
+async fn test_require_client_registration() {
+    let addr = start_proxy(true).await;
+
+    let resp = authorize(&addr, "claude-desktop", CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 400);
+
+    let client_id = register_client_id(&addr).await;
+    let resp = authorize(&addr, &client_id, CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 200);
+
+    let code = obtain_code(&addr, &client_id).await;
+    let resp = exchange(&addr, &code, "claude-desktop", CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_client");
+
+    let resp = exchange(&addr, &code, &client_id, CLAUDE_REDIRECT).await;
+    assert_eq!(resp.status(), 200);
+}
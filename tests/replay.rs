@@ -0,0 +1,111 @@
+mod common;
+
+use common::*;
+use mcp_oauth_proxy::storage::{MemoryStore, Store};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn config(port: u16, single_use: bool) -> mcp_oauth_proxy::config::Config {
+    let toml_str = format!(
+        r#"
+[server]
+public_url = "http://127.0.0.1:{port}"
+state_secret = "{secret}"
+single_use_auth_codes = {single_use}
+
+[downstream.test]
+display_name = "Test Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+"#,
+        secret = state_secret(),
+    );
+    toml::from_str(&toml_str).unwrap()
+}
+
+/// Start a proxy replica, optionally sharing `store` with other replicas.
+async fn start_proxy(single_use: bool, store: Option<Arc<dyn Store>>) -> SocketAddr {
+    let (listener, addr) = bind().await;
+    let config = config(addr.port(), single_use);
+    let state = match store {
+        Some(store) => mcp_oauth_proxy::AppState::with_store(config, reqwest::Client::new(), store),
+        None => mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap(),
+    };
+    serve_proxy(listener, state);
+    addr
+}
+
+async fn obtain_code(addr: &SocketAddr) -> String {
+    let resp = submit(addr, "test", &[("token", "secret-key")], None).await;
+    assert_eq!(resp.status(), 303);
+    query_param(location(&resp).as_str(), "code")
+}
+
+async fn exchange(addr: &SocketAddr, code: &str, verifier: &str) -> reqwest::Response {
+    client()
+        .post(format!("http://{addr}/token/mcp/test"))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", verifier),
+            ("redirect_uri", CLAUDE_REDIRECT),
+            ("client_id", "c"),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn assert_reused(resp: reqwest::Response) {
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_grant");
+    assert_eq!(
+        body["error_description"],
+        "authorization code has already been used"
+    );
+}
+
+#[tokio::test]
+async fn test_code_can_only_be_redeemed_once() {
+    let addr = start_proxy(true, None).await;
+    let code = obtain_code(&addr).await;
+
+    assert_eq!(exchange(&addr, &code, VERIFIER).await.status(), 200);
+    assert_reused(exchange(&addr, &code, VERIFIER).await).await;
+
+    // A fresh code is unaffected.
+    let code = obtain_code(&addr).await;
+    assert_eq!(exchange(&addr, &code, VERIFIER).await.status(), 200);
+}
+
+#[tokio::test]
+async fn test_failed_redemption_does_not_consume_code() {
+    let addr = start_proxy(true, None).await;
+    let code = obtain_code(&addr).await;
+
+    let resp = exchange(&addr, &code, "wrong-verifier-wrong-verifier-wrong-verifier").await;
+    assert_eq!(resp.status(), 400);
+
+    assert_eq!(exchange(&addr, &code, VERIFIER).await.status(), 200);
+}
+
+#[tokio::test]
+async fn test_shared_store_spans_replicas() {
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::default());
+    let first = start_proxy(true, Some(store.clone())).await;
+    let second = start_proxy(true, Some(store)).await;
+
+    let code = obtain_code(&first).await;
+    assert_eq!(exchange(&first, &code, VERIFIER).await.status(), 200);
+    assert_reused(exchange(&second, &code, VERIFIER).await).await;
+}
+
+#[tokio::test]
+async fn test_reuse_allowed_when_disabled() {
+    let addr = start_proxy(false, None).await;
+    let code = obtain_code(&addr).await;
+
+    assert_eq!(exchange(&addr, &code, VERIFIER).await.status(), 200);
+    assert_eq!(exchange(&addr, &code, VERIFIER).await.status(), 200);
+}
@@ -0,0 +1,312 @@
+mod common;
+
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// ---------------------------------------------------------------------------
+// Mock upstream provider with a revocation endpoint
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Default)]
+struct MockState {
+    /// `(token, token_type_hint)` pairs received by the revocation endpoint.
+    revoked: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+async fn mock_token() -> impl IntoResponse {
+    Json(json!({
+        "access_token": "upstream-access",
+        "token_type": "bearer",
+        "expires_in": 3600,
+        "refresh_token": "upstream-refresh"
+    }))
+}
+
+async fn mock_revoke(
+    State(state): State<MockState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if form.get("client_secret").map(String::as_str) != Some("test-client-secret") {
+        return StatusCode::UNAUTHORIZED;
+    }
+    state.revoked.lock().await.push((
+        form.get("token").cloned().unwrap_or_default(),
+        form.get("token_type_hint").cloned().unwrap_or_default(),
+    ));
+    StatusCode::OK
+}
+
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    Json(json!({ "authorization": auth }))
+}
+
+async fn start_mock_upstream() -> (SocketAddr, MockState) {
+    let state = MockState::default();
+    let app = Router::new()
+        .route("/token", post(mock_token))
+        .route("/revoke", post(mock_revoke))
+        .route("/mcp", post(mock_mcp))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+async fn start_proxy(mock_addr: &SocketAddr) -> SocketAddr {
+    let chained = |mode: &str| {
+        format!(
+            r#"
+display_name = "Chained ({mode})"
+strategy = "chained_oauth"
+downstream_url = "http://127.0.0.1:{mock_port}/mcp"
+oauth_authorize_url = "http://127.0.0.1:{mock_port}/authorize"
+oauth_token_url = "http://127.0.0.1:{mock_port}/token"
+oauth_revocation_url = "http://127.0.0.1:{mock_port}/revoke"
+oauth_client_id = "test-client-id"
+oauth_client_secret = "test-client-secret"
+oauth_supports_refresh = true
+oauth_refresh_mode = "{mode}"
+"#,
+            mock_port = mock_addr.port(),
+        )
+    };
+
+    start_proxy_with(|proxy_addr| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy_addr}"
+state_secret = "{secret}"
+
+[downstream.pt]
+display_name = "Passthrough"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:{mock_port}/mcp"
+
+[downstream.client]
+{client}
+
+[downstream.proxy]
+{proxy}
+"#,
+            mock_port = mock_addr.port(),
+            secret = state_secret(),
+            client = chained("client"),
+            proxy = chained("proxy"),
+        )
+    })
+    .await
+}
+
+/// Obtain tokens for `downstream` through the full authorization flow.
+async fn obtain_tokens(proxy_addr: &SocketAddr, downstream: &str) -> serde_json::Value {
+    let resp = if downstream == "pt" {
+        submit(proxy_addr, "pt", &[("token", "pt-api-key")], None).await
+    } else {
+        let resp = client()
+            .get(authorize_url(proxy_addr, downstream))
+            .send()
+            .await
+            .unwrap();
+        let signed_state = query_param(location(&resp).as_str(), "state");
+        client()
+            .get(format!(
+                "http://{proxy_addr}/callback/mcp/{downstream}?code=upstream-code&state={signed_state}"
+            ))
+            .send()
+            .await
+            .unwrap()
+    };
+    redeem(proxy_addr, downstream, &resp).await
+}
+
+async fn revoke(proxy_addr: &SocketAddr, downstream: &str, form: &[(&str, &str)]) -> StatusCode {
+    client()
+        .post(format!("http://{proxy_addr}/revoke/mcp/{downstream}"))
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn mcp_status(proxy_addr: &SocketAddr, downstream: &str, access_token: &str) -> StatusCode {
+    mcp_ping(proxy_addr, downstream, access_token)
+        .await
+        .status()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_revocation_endpoint_advertised() {
+    let (mock_addr, _) = start_mock_upstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body: serde_json::Value = client()
+        .get(format!(
+            "http://{proxy_addr}/.well-known/oauth-authorization-server/mcp/pt"
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        body["revocation_endpoint"],
+        format!("http://127.0.0.1:{}/revoke/mcp/pt", proxy_addr.port())
+    );
+}
+
+#[tokio::test]
+async fn test_revoke_request_validation() {
+    let (mock_addr, mock) = start_mock_upstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let resp = client()
+        .post(format!("http://{proxy_addr}/revoke/mcp/pt"))
+        .form(&[("token_type_hint", "access_token")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_request");
+
+    // Unknown tokens are not an error (RFC 7009 §2.2).
+    assert_eq!(
+        revoke(&proxy_addr, "pt", &[("token", "garbage")]).await,
+        200
+    );
+    assert!(mock.revoked.lock().await.is_empty());
+
+    assert_eq!(
+        revoke(&proxy_addr, "nope", &[("token", "garbage")]).await,
+        404
+    );
+}
+
+#[tokio::test]
+async fn test_revoked_passthrough_token_rejected() {
+    let (mock_addr, mock) = start_mock_upstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr, "pt").await;
+    let access_token = body["access_token"].as_str().unwrap();
+    let other = obtain_tokens(&proxy_addr, "pt").await;
+    let other_token = other["access_token"].as_str().unwrap();
+
+    assert_eq!(mcp_status(&proxy_addr, "pt", access_token).await, 200);
+
+    // Revoking through another downstream's endpoint has no effect.
+    assert_eq!(
+        revoke(&proxy_addr, "client", &[("token", access_token)]).await,
+        200
+    );
+    assert_eq!(mcp_status(&proxy_addr, "pt", access_token).await, 200);
+
+    assert_eq!(
+        revoke(&proxy_addr, "pt", &[("token", access_token)]).await,
+        200
+    );
+    assert_eq!(mcp_status(&proxy_addr, "pt", access_token).await, 401);
+
+    // Other tokens are unaffected.
+    assert_eq!(mcp_status(&proxy_addr, "pt", other_token).await, 200);
+    assert!(mock.revoked.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_client_mode_revocation_forwarded_upstream() {
+    let (mock_addr, mock) = start_mock_upstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr, "client").await;
+    let access_token = body["access_token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+    assert_eq!(refresh_token, "upstream-refresh");
+
+    assert_eq!(
+        revoke(&proxy_addr, "client", &[("token", access_token)]).await,
+        200
+    );
+    assert_eq!(mcp_status(&proxy_addr, "client", access_token).await, 401);
+
+    assert_eq!(
+        revoke(
+            &proxy_addr,
+            "client",
+            &[
+                ("token", refresh_token),
+                ("token_type_hint", "refresh_token")
+            ]
+        )
+        .await,
+        200
+    );
+
+    assert_eq!(
+        *mock.revoked.lock().await,
+        vec![
+            ("upstream-access".to_string(), "access_token".to_string()),
+            ("upstream-refresh".to_string(), "refresh_token".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_mode_refresh_token_revokes_grant() {
+    let (mock_addr, mock) = start_mock_upstream().await;
+    let proxy_addr = start_proxy(&mock_addr).await;
+
+    let body = obtain_tokens(&proxy_addr, "proxy").await;
+    let access_token = body["access_token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+
+    assert_eq!(mcp_status(&proxy_addr, "proxy", access_token).await, 200);
+    assert_eq!(
+        revoke(&proxy_addr, "proxy", &[("token", refresh_token)]).await,
+        200
+    );
+
+    // The access token belongs to the revoked grant.
+    assert_eq!(mcp_status(&proxy_addr, "proxy", access_token).await, 401);
+
+    let resp = client()
+        .post(format!("http://{proxy_addr}/token/mcp/proxy"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", "c"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let err: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(err["error"], "invalid_grant");
+
+    assert_eq!(
+        *mock.revoked.lock().await,
+        vec![("upstream-refresh".to_string(), "refresh_token".to_string())]
+    );
+}
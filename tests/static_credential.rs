@@ -0,0 +1,211 @@
+mod common;
+
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::*;
+use serde_json::json;
+use std::net::SocketAddr;
+
+// ---------------------------------------------------------------------------
+// Mock login provider and MCP server
+// ---------------------------------------------------------------------------
+
+/// Echoes the credential the proxy forwarded.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let key = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    Json(json!({ "key": key }))
+}
+
+async fn start_mock_provider() -> (SocketAddr, LoginProvider) {
+    let login = LoginProvider::default();
+    let app = Router::new()
+        .route("/mcp", post(mock_mcp))
+        .merge(login.routes());
+    (serve(app).await, login)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// Start a proxy with `[server.login]` and two static credential downstreams:
+/// `sso` behind sign-in and `password` behind a gate password. `server` is
+/// added to `[server]`.
+async fn start_proxy(mock: &SocketAddr, server: &str) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+{server}
+{login}
+[downstream.sso]
+display_name = "Team Tool"
+strategy = "static_credential"
+downstream_url = "http://{mock}/mcp"
+auth_header_format = "X-API-Key"
+credential = "team-key-sso"
+
+[downstream.password]
+display_name = "Team Tool"
+strategy = "static_credential"
+downstream_url = "http://{mock}/mcp"
+auth_header_format = "X-API-Key"
+credential = "team-key-password"
+gate = "password"
+gate_password = "{GATE_PASSWORD}"
+"#,
+            secret = state_secret(),
+            login = login_config(mock),
+        )
+    })
+    .await
+}
+
+/// Call the MCP endpoint, returning the credential the downstream received.
+async fn forwarded_key(proxy: &SocketAddr, name: &str, access_token: &str) -> String {
+    let resp = mcp_ping(proxy, name, access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["key"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_password_gate() {
+    let (mock, _) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "").await;
+
+    // No sign-in: the page asks for the gate password.
+    let resp = client()
+        .get(authorize_url(&proxy, "password"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(r#"<label for="token">Password</label>"#));
+    assert!(!html.contains("team-key"));
+
+    let resp = submit(&proxy, "password", &[("token", "not the password")], None).await;
+    assert_eq!(resp.status(), 401);
+
+    let body = redeem(
+        &proxy,
+        "password",
+        &submit(&proxy, "password", &[("token", GATE_PASSWORD)], None).await,
+    )
+    .await;
+    let access_token = body["access_token"].as_str().unwrap();
+    assert!(body.get("refresh_token").is_none());
+
+    // The proxy token does not contain the team credential...
+    let claims =
+        mcp_oauth_proxy::oauth::tokens::validate_access_token(access_token, "password", &keyring())
+            .unwrap();
+    assert_eq!(claims.downstream_tokens.access_token(), "");
+
+    // ...the proxy injects it.
+    assert_eq!(
+        forwarded_key(&proxy, "password", access_token).await,
+        "team-key-password"
+    );
+}
+
+#[tokio::test]
+async fn test_sso_gate() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "").await;
+
+    // Not signed in: off to the login provider.
+    let cookie = login
+        .sign_in(&proxy, &mock, "sso", json!({ "sub": "user-123" }))
+        .await;
+
+    // Signed in: an unregistered client could have crafted the link, so the
+    // user confirms where the code goes. There is nothing to fill in.
+    let resp = client()
+        .get(authorize_url(&proxy, "sso"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(&format!("Returns to <strong>{CLAUDE_REDIRECT}</strong>")));
+    assert!(!html.contains(r#"name="token""#));
+
+    // The confirmation needs the sign-in cookie, which a cross-site POST
+    // does not carry.
+    assert_eq!(submit(&proxy, "sso", &[], None).await.status(), 401);
+
+    let body = redeem(
+        &proxy,
+        "sso",
+        &submit(&proxy, "sso", &[], Some(&cookie)).await,
+    )
+    .await;
+    assert_eq!(
+        forwarded_key(&proxy, "sso", body["access_token"].as_str().unwrap()).await,
+        "team-key-sso"
+    );
+}
+
+#[tokio::test]
+async fn test_sso_gate_self_registered_client_gets_consent() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(&mock, "").await;
+    let cookie = login
+        .sign_in(&proxy, &mock, "sso", json!({ "sub": "user-123" }))
+        .await;
+
+    // Registration is open: a matching redirect_uri proves nothing about
+    // who the client is.
+    let attacker = "https://attacker.example/callback";
+    let client_id = register_redirect(&proxy, "sso", attacker).await;
+    let resp = client()
+        .get(authorize_url_to(&proxy, "sso", &client_id, attacker))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("location").is_none());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("Allow this client to access the service on your behalf."));
+}
+
+#[tokio::test]
+async fn test_sso_gate_trusted_redirect_skips_consent() {
+    let (mock, login) = start_mock_provider().await;
+    let proxy = start_proxy(
+        &mock,
+        &format!("trusted_redirect_uris = [\"{CLAUDE_REDIRECT}\"]"),
+    )
+    .await;
+    let cookie = login
+        .sign_in(&proxy, &mock, "sso", json!({ "sub": "user-123" }))
+        .await;
+
+    // The operator trusts this redirect_uri: no page.
+    let resp = client()
+        .get(authorize_url(&proxy, "sso"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    let body = redeem(&proxy, "sso", &resp).await;
+    assert_eq!(
+        forwarded_key(&proxy, "sso", body["access_token"].as_str().unwrap()).await,
+        "team-key-sso"
+    );
+}
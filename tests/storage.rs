@@ -0,0 +1,124 @@
+mod common;
+
+use common::*;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+const ADMIN_SECRET: &str = "introspection-admin-secret";
+
+fn db_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mcp-oauth-proxy-{test}-{}.db", std::process::id()))
+}
+
+/// Start a replica whose shared state lives in the SQLite file at `db`.
+async fn start_replica(db: &Path) -> SocketAddr {
+    let (listener, addr) = bind().await;
+    let toml_str = format!(
+        r#"
+[server]
+public_url = "http://127.0.0.1:{port}"
+state_secret = "{secret}"
+introspection_secret = "{ADMIN_SECRET}"
+
+[storage]
+backend = "sqlite"
+path = "{db}"
+
+[downstream.test]
+display_name = "Test Service"
+strategy = "passthrough"
+downstream_url = "http://127.0.0.1:1/mcp"
+"#,
+        port = addr.port(),
+        secret = state_secret(),
+        db = db.display(),
+    );
+
+    let config: mcp_oauth_proxy::config::Config = toml::from_str(&toml_str).unwrap();
+    let state = mcp_oauth_proxy::AppState::try_new(config, reqwest::Client::new()).unwrap();
+    serve_proxy(listener, state);
+    addr
+}
+
+async fn obtain_code(addr: &SocketAddr) -> String {
+    let resp = submit(addr, "test", &[("token", "secret-key")], None).await;
+    query_param(location(&resp).as_str(), "code")
+}
+
+async fn exchange(addr: &SocketAddr, code: &str) -> reqwest::Response {
+    client()
+        .post(format!("http://{addr}/token/mcp/test"))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", VERIFIER),
+            ("redirect_uri", CLAUDE_REDIRECT),
+            ("client_id", "c"),
+        ])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn is_active(addr: &SocketAddr, token: &str) -> bool {
+    let body: serde_json::Value = client()
+        .post(format!("http://{addr}/introspect/mcp/test"))
+        .bearer_auth(ADMIN_SECRET)
+        .form(&[("token", token)])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["active"] == true
+}
+
+fn remove_db(db: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", db.display()));
+    }
+}
+
+#[tokio::test]
+async fn test_sqlite_state_is_shared_between_replicas() {
+    let db = db_path("shared");
+    remove_db(&db);
+    let first = start_replica(&db).await;
+    let second = start_replica(&db).await;
+
+    // A code redeemed on one replica cannot be redeemed on the other.
+    let code = obtain_code(&first).await;
+    let resp = exchange(&first, &code).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let token = body["access_token"].as_str().unwrap().to_string();
+    assert_eq!(exchange(&second, &code).await.status(), 400);
+
+    // A revocation on one replica is honoured by the other.
+    assert!(is_active(&second, &token).await);
+    let resp = client()
+        .post(format!("http://{first}/revoke/mcp/test"))
+        .form(&[("token", token.as_str())])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(!is_active(&second, &token).await);
+
+    remove_db(&db);
+}
+
+#[tokio::test]
+async fn test_sqlite_state_survives_restart() {
+    let db = db_path("restart");
+    remove_db(&db);
+    let before = start_replica(&db).await;
+    let code = obtain_code(&before).await;
+    assert_eq!(exchange(&before, &code).await.status(), 200);
+
+    let after = start_replica(&db).await;
+    assert_eq!(exchange(&after, &code).await.status(), 400);
+
+    remove_db(&db);
+}
@@ -0,0 +1,370 @@
+mod common;
+
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::*;
+use mcp_oauth_proxy::storage::{MemoryStore, Store};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock upstream provider, security token service and MCP server
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Default)]
+struct MockState {
+    refresh_count: Arc<Mutex<u32>>,
+    /// Forms received by the STS, in order.
+    exchanges: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    /// The Authorization header of each STS request.
+    exchange_auth: Arc<Mutex<Vec<Option<String>>>>,
+    /// Refresh tokens already used; the provider rotates them.
+    used_refresh_tokens: Arc<Mutex<HashSet<String>>>,
+    /// Answer exchanges with a 503, as during an STS outage.
+    sts_down: Arc<Mutex<bool>>,
+}
+
+async fn mock_token(
+    State(state): State<MockState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let n = match form["grant_type"].as_str() {
+        "authorization_code" => 0,
+        "refresh_token" => {
+            if !state
+                .used_refresh_tokens
+                .lock()
+                .unwrap()
+                .insert(form["refresh_token"].clone())
+            {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error": "invalid_grant"})),
+                )
+                    .into_response();
+            }
+            let mut count = state.refresh_count.lock().unwrap();
+            *count += 1;
+            *count
+        }
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    Json(json!({
+        "access_token": format!("upstream-access-{n}"),
+        "token_type": "bearer",
+        "expires_in": 3600,
+        "refresh_token": format!("upstream-refresh-{n}"),
+    }))
+    .into_response()
+}
+
+/// Issues `sts-<subject token>`. The `short-lived` audience gets an already
+/// expired token for the first upstream token; `denied` is refused.
+async fn mock_sts(
+    State(state): State<MockState>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    state.exchanges.lock().unwrap().push(form.clone());
+    state.exchange_auth.lock().unwrap().push(
+        headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+    );
+    if *state.sts_down.lock().unwrap() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    let subject = &form["subject_token"];
+    let audience = form.get("audience").map(String::as_str);
+    if audience == Some("denied") {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_target"})),
+        )
+            .into_response();
+    }
+    let expires_in = if audience == Some("short-lived") && subject == "upstream-access-0" {
+        0
+    } else {
+        900
+    };
+    Json(json!({
+        "access_token": format!("sts-{subject}"),
+        "issued_token_type": "urn:ietf:params:oauth:token-type:access_token",
+        "token_type": "Bearer",
+        "expires_in": expires_in,
+    }))
+    .into_response()
+}
+
+/// Echo the Authorization header the proxy sent.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    Json(json!({ "authorization": auth }))
+}
+
+async fn start_mock() -> (SocketAddr, MockState) {
+    let state = MockState::default();
+    let app = Router::new()
+        .route("/token", post(mock_token))
+        .route("/sts", post(mock_sts))
+        .route("/mcp", post(mock_mcp))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// A chained OAuth downstream exchanging at the mock STS for `audience`.
+fn downstream(name: &str, mock: &SocketAddr, refresh_mode: &str, audience: &str) -> String {
+    format!(
+        r#"
+[downstream.{name}]
+display_name = "Internal"
+strategy = "chained_oauth"
+downstream_url = "http://{mock}/mcp"
+oauth_authorize_url = "http://{mock}/authorize"
+oauth_token_url = "http://{mock}/token"
+oauth_client_id = "proxy-client"
+oauth_client_secret = "proxy-secret"
+oauth_supports_refresh = true
+oauth_refresh_mode = "{refresh_mode}"
+oauth_token_exchange_url = "http://{mock}/sts"
+oauth_token_exchange_audience = "{audience}"
+oauth_token_exchange_scopes = "mcp:tools"
+"#
+    )
+}
+
+async fn start_proxy(mock: &SocketAddr) -> SocketAddr {
+    start_proxy_on(Arc::new(MemoryStore::default()), mock).await
+}
+
+/// Start a proxy whose shared state lives in `store`.
+async fn start_proxy_on(store: Arc<dyn Store>, mock: &SocketAddr) -> SocketAddr {
+    common::start_proxy_on(store, |proxy_addr| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy_addr}"
+state_secret = "{secret}"
+{client}{proxy}{denied}{sts_client}
+oauth_token_exchange_client_id = "proxy-at-sts"
+oauth_token_exchange_client_secret = "sts-secret"
+oauth_token_exchange_client_auth_method = "client_secret_basic"
+"#,
+            secret = state_secret(),
+            client = downstream("client", mock, "client", "internal-mcp"),
+            proxy = downstream("proxy", mock, "proxy", "short-lived"),
+            denied = downstream("denied", mock, "client", "denied"),
+            sts_client = downstream("sts-client", mock, "client", "internal-mcp"),
+        )
+    })
+    .await
+}
+
+/// Run authorize → callback, returning the callback response.
+async fn callback(proxy: &SocketAddr, name: &str) -> reqwest::Response {
+    let resp = client()
+        .get(authorize_url(proxy, name))
+        .send()
+        .await
+        .unwrap();
+    let signed_state = query_param(location(&resp).as_str(), "state");
+
+    client()
+        .get(format!(
+            "http://{proxy}/callback/mcp/{name}?code=upstream-code&state={signed_state}"
+        ))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Run authorize → callback → token and return the token response body.
+async fn obtain_tokens(proxy: &SocketAddr, name: &str) -> serde_json::Value {
+    let resp = callback(proxy, name).await;
+    redeem(proxy, name, &resp).await
+}
+
+async fn refresh(proxy: &SocketAddr, name: &str, refresh_token: &str) -> serde_json::Value {
+    let resp = client()
+        .post(format!("http://{proxy}/token/mcp/{name}"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", "c"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    resp.json().await.unwrap()
+}
+
+/// Call the MCP endpoint, returning the Authorization header the downstream
+/// received.
+async fn forwarded_auth(proxy: &SocketAddr, name: &str, access_token: &str) -> String {
+    let resp = mcp_ping(proxy, name, access_token).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    body["authorization"].as_str().unwrap().to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_exchanged_token_is_sent_and_reexchanged_on_refresh() {
+    let (mock, mock_state) = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    let body = obtain_tokens(&proxy, "client").await;
+    assert_eq!(
+        forwarded_auth(&proxy, "client", body["access_token"].as_str().unwrap()).await,
+        "Bearer sts-upstream-access-0"
+    );
+    // The STS token's lifetime caps the proxy token's.
+    assert_eq!(body["expires_in"], 900);
+
+    {
+        let exchanges = mock_state.exchanges.lock().unwrap();
+        assert_eq!(exchanges.len(), 1);
+        let form = &exchanges[0];
+        assert_eq!(
+            form["grant_type"],
+            "urn:ietf:params:oauth:grant-type:token-exchange"
+        );
+        assert_eq!(form["subject_token"], "upstream-access-0");
+        assert_eq!(
+            form["subject_token_type"],
+            "urn:ietf:params:oauth:token-type:access_token"
+        );
+        assert_eq!(form["audience"], "internal-mcp");
+        assert_eq!(form["scope"], "mcp:tools");
+        // The STS is not the provider: without its own client settings the
+        // request is not authenticated at all.
+        assert!(!form.contains_key("client_id"));
+        assert!(!form.contains_key("client_secret"));
+        assert_eq!(mock_state.exchange_auth.lock().unwrap()[0], None);
+    }
+
+    // The client holds the provider's refresh token; refreshing exchanges the
+    // new upstream access token.
+    assert_eq!(body["refresh_token"], "upstream-refresh-0");
+    let body = refresh(&proxy, "client", "upstream-refresh-0").await;
+    assert_eq!(body["refresh_token"], "upstream-refresh-1");
+    assert_eq!(
+        forwarded_auth(&proxy, "client", body["access_token"].as_str().unwrap()).await,
+        "Bearer sts-upstream-access-1"
+    );
+    assert_eq!(mock_state.exchanges.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_rotated_refresh_token_survives_failed_exchange() {
+    let (mock, mock_state) = start_mock().await;
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::default());
+    let proxy = start_proxy_on(store.clone(), &mock).await;
+
+    let body = obtain_tokens(&proxy, "client").await;
+    assert_eq!(body["refresh_token"], "upstream-refresh-0");
+
+    // The provider rotates upstream-refresh-0 to upstream-refresh-1, then the
+    // exchange fails.
+    *mock_state.sts_down.lock().unwrap() = true;
+    let resp = client()
+        .post(format!("http://{proxy}/token/mcp/client"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", "upstream-refresh-0"),
+            ("client_id", "c"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 503);
+    let err: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(err["error"], "temporarily_unavailable");
+
+    // The client retries with the token it holds, and the proxy refreshes
+    // with the rotated one instead, even after a restart.
+    *mock_state.sts_down.lock().unwrap() = false;
+    let restarted = start_proxy_on(store, &mock).await;
+    let body = refresh(&restarted, "client", "upstream-refresh-0").await;
+    assert_eq!(body["refresh_token"], "upstream-refresh-2");
+    assert_eq!(
+        forwarded_auth(&restarted, "client", body["access_token"].as_str().unwrap()).await,
+        "Bearer sts-upstream-access-2"
+    );
+}
+
+#[tokio::test]
+async fn test_sts_client_credentials_are_separate() {
+    let (mock, mock_state) = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    obtain_tokens(&proxy, "sts-client").await;
+
+    let form = mock_state.exchanges.lock().unwrap()[0].clone();
+    assert!(!form.contains_key("client_id"));
+    assert!(!form.values().any(|v| v.contains("proxy-secret")));
+    assert_eq!(
+        mock_state.exchange_auth.lock().unwrap()[0].as_deref(),
+        Some(format!("Basic {}", STANDARD.encode("proxy-at-sts:sts-secret")).as_str())
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_mode_reexchanges_expired_tokens() {
+    let (mock, mock_state) = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    // The first exchanged token expires at once, so the MCP request refreshes
+    // upstream and exchanges again.
+    let body = obtain_tokens(&proxy, "proxy").await;
+    let access_token = body["access_token"].as_str().unwrap();
+    assert_eq!(
+        forwarded_auth(&proxy, "proxy", access_token).await,
+        "Bearer sts-upstream-access-1"
+    );
+    assert_eq!(
+        forwarded_auth(&proxy, "proxy", access_token).await,
+        "Bearer sts-upstream-access-1"
+    );
+    assert_eq!(*mock_state.refresh_count.lock().unwrap(), 1);
+    assert_eq!(mock_state.exchanges.lock().unwrap().len(), 2);
+
+    // A proxy refresh reuses the grant's current exchanged token.
+    let body = refresh(&proxy, "proxy", body["refresh_token"].as_str().unwrap()).await;
+    assert_eq!(
+        forwarded_auth(&proxy, "proxy", body["access_token"].as_str().unwrap()).await,
+        "Bearer sts-upstream-access-1"
+    );
+    assert_eq!(mock_state.exchanges.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_refused_exchange_fails_authorization() {
+    let (mock, _) = start_mock().await;
+    let proxy = start_proxy(&mock).await;
+
+    let resp = callback(&proxy, "denied").await;
+    assert_eq!(resp.status(), 502);
+    assert!(resp.text().await.unwrap().contains("Token exchange"));
+}
@@ -0,0 +1,274 @@
+mod common;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::Router;
+use common::*;
+use mcp_oauth_proxy::oauth::codes::DownstreamTokens;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Mock provider answering with a canned token response
+// ---------------------------------------------------------------------------
+
+/// Status, Content-Type and body the token endpoint answers with.
+type Reply = (u16, &'static str, &'static str);
+
+#[derive(Clone)]
+struct MockState {
+    reply: Arc<Mutex<Reply>>,
+}
+
+async fn mock_token(State(state): State<MockState>) -> impl IntoResponse {
+    let (status, content_type, body) = *state.reply.lock().unwrap();
+    (
+        StatusCode::from_u16(status).unwrap(),
+        [("Content-Type", content_type)],
+        body,
+    )
+}
+
+async fn start_mock_provider(reply: Reply) -> (SocketAddr, MockState) {
+    let state = MockState {
+        reply: Arc::new(Mutex::new(reply)),
+    };
+    let app = Router::new()
+        .route("/token", post(mock_token))
+        .with_state(state.clone());
+    (serve(app).await, state)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+const FORM: &str = "application/x-www-form-urlencoded";
+
+async fn start_proxy(mock_addr: &SocketAddr, extra: &str) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+
+[downstream.legacy]
+display_name = "Legacy Provider"
+strategy = "chained_oauth"
+downstream_url = "http://127.0.0.1:{mock_port}/mcp"
+oauth_authorize_url = "http://127.0.0.1:{mock_port}/authorize"
+oauth_token_url = "http://127.0.0.1:{mock_port}/token"
+oauth_client_id = "test-client-id"
+oauth_client_secret = "test-client-secret"
+oauth_supports_refresh = true
+{extra}
+"#,
+            mock_port = mock_addr.port(),
+            secret = state_secret(),
+        )
+    })
+    .await
+}
+
+/// Run authorize → callback, returning the callback response.
+async fn callback(proxy: &SocketAddr) -> reqwest::Response {
+    let resp = client()
+        .get(authorize_url(proxy, "legacy"))
+        .send()
+        .await
+        .unwrap();
+    let state = query_param(location(&resp).as_str(), "state");
+
+    client()
+        .get(
+            url::Url::parse_with_params(
+                &format!("http://{proxy}/callback/mcp/legacy"),
+                &[("code", "upstream-code"), ("state", state.as_str())],
+            )
+            .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn token(proxy: &SocketAddr, form: &[(&str, &str)]) -> reqwest::Response {
+    client()
+        .post(format!("http://{proxy}/token/mcp/legacy"))
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn upstream_tokens(proxy_token: &str) -> DownstreamTokens {
+    mcp_oauth_proxy::oauth::tokens::validate_access_token(proxy_token, "legacy", &keyring())
+        .unwrap()
+        .downstream_tokens
+}
+
+fn upstream_access_token(proxy_token: &str) -> String {
+    upstream_tokens(proxy_token).access_token().to_string()
+}
+
+/// Complete the flow and return the `/token` response body.
+async fn exchange(proxy: &SocketAddr) -> serde_json::Value {
+    let resp = callback(proxy).await;
+    redeem(proxy, "legacy", &resp).await
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_form_encoded_code_exchange() {
+    let (mock, _) = start_mock_provider((
+        200,
+        FORM,
+        "access_token=gho_abc%2B123&token_type=bearer&scope=repo%2Cuser\
+         &expires_in=28800&refresh_token=ghr_xyz",
+    ))
+    .await;
+    let proxy = start_proxy(&mock, "").await;
+
+    let body = exchange(&proxy).await;
+    assert_eq!(
+        upstream_access_token(body["access_token"].as_str().unwrap()),
+        "gho_abc+123"
+    );
+    assert_eq!(body["refresh_token"], "ghr_xyz");
+    assert_eq!(body["expires_in"], 28800);
+}
+
+#[tokio::test]
+async fn test_error_with_http_200_fails_callback() {
+    let (mock, _) = start_mock_provider((
+        200,
+        FORM,
+        "error=bad_verification_code\
+         &error_description=The+code+passed+is+incorrect+or+expired.",
+    ))
+    .await;
+    let proxy = start_proxy(&mock, "").await;
+
+    let resp = callback(&proxy).await;
+    assert_eq!(resp.status(), 502);
+    // The provider's error is logged, not shown.
+    let body = resp.text().await.unwrap();
+    assert_eq!(body, "Token exchange failed");
+}
+
+#[tokio::test]
+async fn test_form_encoded_refresh() {
+    let (mock, mock_state) = start_mock_provider((
+        200,
+        "text/plain",
+        "access_token=refreshed&token_type=bearer&expires_in=60",
+    ))
+    .await;
+    let proxy = start_proxy(&mock, "").await;
+    let refresh = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", "upstream-refresh"),
+    ];
+
+    // An unlabelled form body is still understood.
+    let resp = token(&proxy, &refresh).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        upstream_access_token(body["access_token"].as_str().unwrap()),
+        "refreshed"
+    );
+    assert_eq!(body["expires_in"], 60);
+    assert!(body.get("refresh_token").is_none());
+
+    // A provider error reported with HTTP 200 means the grant is gone.
+    *mock_state.reply.lock().unwrap() = (200, "application/json", r#"{"error":"invalid_grant"}"#);
+    let resp = token(&proxy, &refresh).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_grant");
+
+    // A success that carries no token is the provider's fault, not the grant's.
+    *mock_state.reply.lock().unwrap() = (200, FORM, "token_type=bearer");
+    let resp = token(&proxy, &refresh).await;
+    assert_eq!(resp.status(), 502);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "server_error");
+}
+
+#[tokio::test]
+async fn test_token_response_fields_are_preserved() {
+    let (mock, _) = start_mock_provider((
+        200,
+        "application/json",
+        r#"{"access_token":"at","token_type":"Bearer","expires_in":3600,
+            "scope":"openid email","id_token":"header.payload.signature"}"#,
+    ))
+    .await;
+    let proxy = start_proxy(&mock, "oauth_scopes = \"openid email\"").await;
+
+    let body = exchange(&proxy).await;
+    assert_eq!(body["scope"], "openid email");
+    let DownstreamTokens::ChainedOAuth(tokens) =
+        upstream_tokens(body["access_token"].as_str().unwrap())
+    else {
+        panic!("expected chained OAuth tokens");
+    };
+    assert_eq!(tokens.token_type.as_deref(), Some("Bearer"));
+    assert_eq!(tokens.scope.as_deref(), Some("openid email"));
+    assert_eq!(tokens.id_token.as_deref(), Some("header.payload.signature"));
+}
+
+#[tokio::test]
+async fn test_narrower_scope_warns_by_default() {
+    let (mock, _) = start_mock_provider((
+        200,
+        FORM,
+        "access_token=at&token_type=bearer&scope=user%2Cgist",
+    ))
+    .await;
+    let proxy = start_proxy(&mock, "oauth_scopes = \"repo user\"").await;
+
+    // GitHub-style comma-separated scopes come back space-delimited.
+    let body = exchange(&proxy).await;
+    assert_eq!(body["scope"], "user gist");
+}
+
+#[tokio::test]
+async fn test_narrower_scope_rejected_by_policy() {
+    let (mock, mock_state) =
+        start_mock_provider((200, FORM, "access_token=at&token_type=bearer&scope=user")).await;
+    let proxy = start_proxy(
+        &mock,
+        "oauth_scopes = \"repo user\"\noauth_scope_policy = \"reject\"",
+    )
+    .await;
+
+    let resp = callback(&proxy).await;
+    assert_eq!(resp.status(), 403);
+    let body = resp.text().await.unwrap();
+    assert!(body.contains("repo"), "{body}");
+
+    // A refresh that drops a required scope fails too.
+    let refresh = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", "upstream-refresh"),
+    ];
+    let resp = token(&proxy, &refresh).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"], "invalid_grant");
+
+    // Omitting scope means everything requested was granted (RFC 6749 §5.1).
+    *mock_state.reply.lock().unwrap() = (200, FORM, "access_token=at&token_type=bearer");
+    let resp = token(&proxy, &refresh).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert!(body.get("scope").is_none());
+}
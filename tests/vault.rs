@@ -0,0 +1,245 @@
+mod common;
+
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::*;
+use serde_json::json;
+use std::net::SocketAddr;
+
+// ---------------------------------------------------------------------------
+// Mock login provider and MCP server
+// ---------------------------------------------------------------------------
+
+/// Echoes the credential the proxy forwarded.
+async fn mock_mcp(headers: HeaderMap) -> impl IntoResponse {
+    let auth = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    Json(json!({ "auth": auth }))
+}
+
+async fn start_mock_provider() -> (SocketAddr, LoginProvider) {
+    let login = LoginProvider::default();
+    let app = Router::new()
+        .route("/mcp", post(mock_mcp))
+        .merge(login.routes());
+    (serve(app).await, login)
+}
+
+// ---------------------------------------------------------------------------
+// Test helpers
+// ---------------------------------------------------------------------------
+
+/// Start a proxy with `[server.login]` and a vault downstream.
+async fn start_proxy(mock: &SocketAddr) -> SocketAddr {
+    start_proxy_with(|proxy| {
+        format!(
+            r#"
+[server]
+public_url = "http://{proxy}"
+state_secret = "{secret}"
+{login}
+[downstream.linear]
+display_name = "Linear"
+strategy = "vault"
+downstream_url = "http://{mock}/mcp"
+"#,
+            secret = state_secret(),
+            login = login_config(mock),
+        )
+    })
+    .await
+}
+
+/// Sign in at the mock provider as `sub`, returning the session cookie.
+async fn sign_in(
+    proxy: &SocketAddr,
+    mock: &SocketAddr,
+    login: &LoginProvider,
+    sub: &str,
+) -> String {
+    login
+        .sign_in(proxy, mock, "linear", json!({ "sub": sub }))
+        .await
+}
+
+async fn submit_form(proxy: &SocketAddr, cookie: &str, token: &str) -> reqwest::Response {
+    submit(proxy, "linear", &[("token", token)], Some(cookie)).await
+}
+
+/// Redeem the code in a redirect to Claude, returning the proxy access token.
+async fn redeem_token(proxy: &SocketAddr, resp: &reqwest::Response) -> String {
+    let body = redeem(proxy, "linear", resp).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Call the MCP endpoint, returning the status and the forwarded credential.
+async fn call_mcp(proxy: &SocketAddr, access_token: &str) -> (u16, String) {
+    let resp = mcp_ping(proxy, "linear", access_token).await;
+    let status = resp.status().as_u16();
+    if status != 200 {
+        return (status, String::new());
+    }
+    let body: serde_json::Value = resp.json().await.unwrap();
+    (status, body["auth"].as_str().unwrap().to_string())
+}
+
+async fn manage(proxy: &SocketAddr, cookie: &str, form: &[(&str, &str)]) -> reqwest::Response {
+    client()
+        .post(format!("http://{proxy}/vault/mcp/linear"))
+        .header("Cookie", cookie)
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn test_credential_is_asked_for_once() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+    let cookie = sign_in(&proxy, &mock, &mock_state, "ada").await;
+
+    // Nothing stored yet: the form is shown.
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(&format!("http://{proxy}/vault/mcp/linear")));
+
+    let access_token = redeem_token(&proxy, &submit_form(&proxy, &cookie, "lin_api_1").await).await;
+    assert_eq!(
+        call_mcp(&proxy, &access_token).await,
+        (200, "Bearer lin_api_1".to_string())
+    );
+
+    // Next time, the stored credential is used without asking for it, but
+    // the user still confirms where the code goes.
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(!html.contains(r#"name="token""#));
+    assert!(html.contains(&format!("Returns to <strong>{CLAUDE_REDIRECT}</strong>")));
+
+    let access_token = redeem_token(&proxy, &submit_form(&proxy, &cookie, "").await).await;
+    assert_eq!(
+        call_mcp(&proxy, &access_token).await,
+        (200, "Bearer lin_api_1".to_string())
+    );
+
+    // Another user has their own vault.
+    let cookie = sign_in(&proxy, &mock, &mock_state, "bob").await;
+    let resp = client()
+        .get(authorize_url(&proxy, "linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(r#"name="token""#));
+    assert_eq!(submit_form(&proxy, &cookie, "").await.status(), 400);
+}
+
+#[tokio::test]
+async fn test_self_registered_client_gets_the_page() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+    let cookie = sign_in(&proxy, &mock, &mock_state, "ada").await;
+    redeem_token(&proxy, &submit_form(&proxy, &cookie, "lin_api_1").await).await;
+
+    // Registration is open: anyone can register their own redirect_uri and
+    // send a signed-in user the link. The stored credential is not released
+    // on the GET.
+    let attacker = "https://attacker.example/callback";
+    let client_id = register_redirect(&proxy, "linear", attacker).await;
+    let resp = client()
+        .get(authorize_url_to(&proxy, "linear", &client_id, attacker))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("location").is_none());
+    let html = resp.text().await.unwrap();
+    assert!(html.contains(&format!("Returns to <strong>{attacker}</strong>")));
+}
+
+#[tokio::test]
+async fn test_manage_page() {
+    let (mock, mock_state) = start_mock_provider().await;
+    let proxy = start_proxy(&mock).await;
+
+    // Without a session the page starts a sign-in; its POST is refused.
+    let resp = client()
+        .get(format!("http://{proxy}/vault/mcp/linear"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 303);
+    assert_eq!(location(&resp).path(), "/authorize");
+    let resp = client()
+        .post(format!("http://{proxy}/vault/mcp/linear"))
+        .form(&[("action", "delete")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let cookie = sign_in(&proxy, &mock, &mock_state, "ada").await;
+    let access_token = redeem_token(&proxy, &submit_form(&proxy, &cookie, "lin_api_1").await).await;
+
+    let page = client()
+        .get(format!("http://{proxy}/vault/mcp/linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(page.status(), 200);
+    assert!(page.text().await.unwrap().contains("A token is saved."));
+
+    // An update applies to tokens already issued.
+    let resp = manage(
+        &proxy,
+        &cookie,
+        &[("action", "save"), ("token", "lin_api_2")],
+    )
+    .await;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(location(&resp).path(), "/vault/mcp/linear");
+    assert_eq!(
+        call_mcp(&proxy, &access_token).await,
+        (200, "Bearer lin_api_2".to_string())
+    );
+
+    // So does deleting it.
+    let resp = manage(&proxy, &cookie, &[("action", "delete")]).await;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(call_mcp(&proxy, &access_token).await.0, 401);
+    let page = client()
+        .get(format!("http://{proxy}/vault/mcp/linear"))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert!(page.text().await.unwrap().contains("No token is saved."));
+
+    let resp = manage(&proxy, &cookie, &[("action", "save"), ("token", "")]).await;
+    assert_eq!(resp.status(), 400);
+}